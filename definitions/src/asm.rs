@@ -3,6 +3,8 @@ use crate::{
     RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY, RISCV_PAGES, RISCV_PAGESIZE,
 };
 use std::alloc::{alloc, Layout};
+use std::sync::atomic::AtomicU8;
+use std::sync::Arc;
 
 // The number of trace items to keep
 pub const TRACE_SIZE: usize = 8192;
@@ -17,6 +19,13 @@ pub const RET_CYCLES_OVERFLOW: u8 = 6;
 pub const RET_OUT_OF_BOUND: u8 = 7;
 pub const RET_INVALID_PERMISSION: u8 = 8;
 pub const RET_SLOWPATH: u8 = 9;
+pub const RET_PAUSE: u8 = 10;
+pub const RET_TOUCHED_FRAME: u8 = 11;
+
+// How the load or store failing with RET_OUT_OF_BOUND or
+// RET_INVALID_PERMISSION accesses memory, see AsmCoreMachine::fault_kind.
+pub const FAULT_KIND_READ: u8 = 1;
+pub const FAULT_KIND_WRITE: u8 = 2;
 
 #[inline(always)]
 pub fn calculate_slot(addr: u64) -> usize {
@@ -28,6 +37,8 @@ pub fn calculate_slot(addr: u64) -> usize {
 pub struct Trace {
     pub address: u64,
     pub length: u8,
+    // Instructions in the trace, added to instret when the trace is entered.
+    pub instruction_count: u8,
     pub cycles: u64,
     pub instructions: [Instruction; TRACE_ITEM_LENGTH + 1],
     // We are using direct threaded code here:
@@ -59,6 +70,22 @@ pub struct AsmCoreMachine {
 
     pub last_read_frame: u64,
     pub last_write_page: u64,
+    // Polled by the asm loop at trace boundaries, when the flag is not zero,
+    // the asm loop exits with RET_PAUSE.
+    pub pause: PauseFlag,
+    // Cycles charged for each frame initialized, when not zero, the asm loop
+    // exits with RET_TOUCHED_FRAME right after initializing a frame, so the
+    // instruction touching it is executed and charged by the Rust side.
+    pub frame_cycles: u64,
+    // Frames initialized since the last time they are charged.
+    pub touched_frames: u64,
+    // Where the asm loop is reading instructions of the trace when a load or
+    // store fails, the failing instruction is the one right before.
+    pub fault_inst_args: u64,
+    // Whether the failing load or store reads or writes, FAULT_KIND_READ or
+    // FAULT_KIND_WRITE.
+    pub fault_kind: u8,
+    pub instret: u64,
 
     pub flags: [u8; RISCV_PAGES],
     pub frames: [u8; MEMORY_FRAMES],
@@ -67,6 +94,63 @@ pub struct AsmCoreMachine {
     pub memory: [u8; RISCV_MAX_MEMORY],
 }
 
+// Default pause flag for machines that never get interrupted.
+static NO_PAUSE: u8 = 0;
+
+/// The pause flag of an AsmCoreMachine, loaded by the asm loop as a byte.
+/// It points to either NO_PAUSE, or an atomic flag it holds a strong count
+/// of, see Arc::into_raw.
+#[repr(transparent)]
+pub struct PauseFlag {
+    ptr: *const u8,
+}
+
+// SAFETY: ptr is never written through, and it is valid as long as the
+// PauseFlag: it points to either a static, or an AtomicU8 kept alive by the
+// strong count, which can be shared between threads. AtomicU8 has the same
+// in-memory representation as u8, so the byte load of the asm loop is atomic.
+unsafe impl Send for PauseFlag {}
+unsafe impl Sync for PauseFlag {}
+
+impl PauseFlag {
+    pub fn new(flag: Arc<AtomicU8>) -> Self {
+        Self {
+            ptr: Arc::into_raw(flag) as *const u8,
+        }
+    }
+
+    fn shared(&self) -> Option<*const AtomicU8> {
+        if std::ptr::eq(self.ptr, &NO_PAUSE) {
+            None
+        } else {
+            Some(self.ptr as *const AtomicU8)
+        }
+    }
+}
+
+impl Default for PauseFlag {
+    fn default() -> Self {
+        Self { ptr: &NO_PAUSE }
+    }
+}
+
+impl Clone for PauseFlag {
+    fn clone(&self) -> Self {
+        if let Some(flag) = self.shared() {
+            unsafe { Arc::increment_strong_count(flag) };
+        }
+        Self { ptr: self.ptr }
+    }
+}
+
+impl Drop for PauseFlag {
+    fn drop(&mut self) {
+        if let Some(flag) = self.shared() {
+            unsafe { drop(Arc::from_raw(flag)) };
+        }
+    }
+}
+
 impl AsmCoreMachine {
     pub fn new(isa: u8, version: u32, max_cycles: u64) -> Box<AsmCoreMachine> {
         Self::new_with_memory(isa, version, max_cycles, RISCV_MAX_MEMORY)
@@ -117,7 +201,55 @@ impl AsmCoreMachine {
 
         machine.last_read_frame = u64::max_value();
         machine.last_write_page = u64::max_value();
+        // The allocation is not initialized, writing the field instead of
+        // assigning it so no garbage is dropped.
+        unsafe { std::ptr::write(&mut machine.pause, PauseFlag::default()) };
+        machine.frame_cycles = 0;
+        machine.touched_frames = 0;
+        machine.fault_inst_args = 0;
+        machine.fault_kind = 0;
+        machine.instret = 0;
 
         machine
     }
 }
+
+// Forks a machine with a deep copy of its memory. Only the memory frames that
+// have been initialized are copied, the others are initialized on their first
+// access like in a new machine. Traces are not copied since they are merely a
+// cache. The fork gets its own pause flag, which never interrupts it until
+// another one is set.
+impl Clone for Box<AsmCoreMachine> {
+    fn clone(&self) -> Self {
+        let mut machine = AsmCoreMachine::new_with_memory(
+            self.isa,
+            self.version,
+            self.max_cycles,
+            self.memory_size as usize,
+        );
+        machine.registers = self.registers;
+        machine.pc = self.pc;
+        machine.next_pc = self.next_pc;
+        machine.running = self.running;
+        machine.cycles = self.cycles;
+        machine.chaos_mode = self.chaos_mode;
+        machine.chaos_seed = self.chaos_seed;
+        machine.load_reservation_address = self.load_reservation_address;
+        machine.reset_signal = self.reset_signal;
+        machine.frame_cycles = self.frame_cycles;
+        machine.touched_frames = self.touched_frames;
+        machine.fault_inst_args = self.fault_inst_args;
+        machine.fault_kind = self.fault_kind;
+        machine.instret = self.instret;
+        machine.flags = self.flags;
+        machine.frames = self.frames;
+        for frame in 0..self.frames_size as usize {
+            if self.frames[frame] != 0 {
+                let start = frame * MEMORY_FRAMESIZE;
+                let end = start + MEMORY_FRAMESIZE;
+                machine.memory[start..end].copy_from_slice(&self.memory[start..end]);
+            }
+        }
+        machine
+    }
+}
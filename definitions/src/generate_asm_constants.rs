@@ -1,7 +1,8 @@
 use ckb_vm_definitions::{
     asm::{
-        AsmCoreMachine, Trace, RET_CYCLES_OVERFLOW, RET_DECODE_TRACE, RET_DYNAMIC_JUMP, RET_EBREAK,
-        RET_ECALL, RET_INVALID_PERMISSION, RET_MAX_CYCLES_EXCEEDED, RET_OUT_OF_BOUND, RET_SLOWPATH,
+        AsmCoreMachine, PauseFlag, Trace, FAULT_KIND_READ, FAULT_KIND_WRITE, RET_CYCLES_OVERFLOW,
+        RET_DECODE_TRACE, RET_DYNAMIC_JUMP, RET_EBREAK, RET_ECALL, RET_INVALID_PERMISSION,
+        RET_MAX_CYCLES_EXCEEDED, RET_OUT_OF_BOUND, RET_PAUSE, RET_SLOWPATH, RET_TOUCHED_FRAME,
         TRACE_ITEM_LENGTH,
     },
     instructions::{
@@ -10,8 +11,9 @@ use ckb_vm_definitions::{
     },
     memory::{FLAG_DIRTY, FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_WRITABLE, FLAG_WXORX_BIT},
     registers::{RA, SP},
-    MEMORY_FRAMES, MEMORY_FRAMESIZE, MEMORY_FRAME_PAGE_SHIFTS, MEMORY_FRAME_SHIFTS,
-    RISCV_MAX_MEMORY, RISCV_PAGES, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS,
+    CSR_CYCLE, CSR_INSTRET, CSR_TIME, MEMORY_FRAMES, MEMORY_FRAMESIZE, MEMORY_FRAME_PAGE_SHIFTS,
+    MEMORY_FRAME_SHIFTS, RISCV_MAX_MEMORY, RISCV_PAGES, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS,
+    TIME_SHIFTS,
 };
 use std::mem::{size_of, zeroed};
 
@@ -64,6 +66,12 @@ fn main() {
         RET_INVALID_PERMISSION
     );
     println!("#define CKB_VM_ASM_RET_SLOWPATH {}", RET_SLOWPATH);
+    println!("#define CKB_VM_ASM_RET_PAUSE {}", RET_PAUSE);
+    println!("#define CKB_VM_ASM_RET_TOUCHED_FRAME {}", RET_TOUCHED_FRAME);
+    println!();
+
+    println!("#define CKB_VM_ASM_FAULT_KIND_READ {}", FAULT_KIND_READ);
+    println!("#define CKB_VM_ASM_FAULT_KIND_WRITE {}", FAULT_KIND_WRITE);
     println!();
 
     println!("#define CKB_VM_ASM_REGISTER_RA {}", RA);
@@ -83,6 +91,12 @@ fn main() {
     println!("#define CKB_VM_ASM_MEMORY_FLAG_DIRTY {}", FLAG_DIRTY);
     println!();
 
+    println!("#define CKB_VM_ASM_CSR_CYCLE {}", CSR_CYCLE);
+    println!("#define CKB_VM_ASM_CSR_TIME {}", CSR_TIME);
+    println!("#define CKB_VM_ASM_CSR_INSTRET {}", CSR_INSTRET);
+    println!("#define CKB_VM_ASM_TIME_SHIFTS {}", TIME_SHIFTS);
+    println!();
+
     println!(
         "#define CKB_VM_ASM_TRACE_STRUCT_SIZE {}",
         size_of::<Trace>()
@@ -98,6 +112,10 @@ fn main() {
         "#define CKB_VM_ASM_TRACE_OFFSET_LENGTH {}",
         (&t.length as *const u8 as usize) - t_address
     );
+    println!(
+        "#define CKB_VM_ASM_TRACE_OFFSET_INSTRUCTION_COUNT {}",
+        (&t.instruction_count as *const u8 as usize) - t_address
+    );
     println!(
         "#define CKB_VM_ASM_TRACE_OFFSET_CYCLES {}",
         (&t.cycles as *const u64 as usize) - t_address
@@ -163,6 +181,26 @@ fn main() {
         "#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_LAST_WRITE_PAGE {}",
         (&m.last_write_page as *const u64 as usize) - m_address
     );
+    println!(
+        "#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_PAUSE {}",
+        (&m.pause as *const PauseFlag as usize) - m_address
+    );
+    println!(
+        "#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FRAME_CYCLES {}",
+        (&m.frame_cycles as *const u64 as usize) - m_address
+    );
+    println!(
+        "#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FAULT_INST_ARGS {}",
+        (&m.fault_inst_args as *const u64 as usize) - m_address
+    );
+    println!(
+        "#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FAULT_KIND {}",
+        (&m.fault_kind as *const u8 as usize) - m_address
+    );
+    println!(
+        "#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_INSTRET {}",
+        (&m.instret as *const u64 as usize) - m_address
+    );
 
     println!(
         "#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FLAGS {}",
@@ -197,9 +197,11 @@ pub const OP_ADD3C: InstructionOpcode = 0xa9;
 pub const OP_CUSTOM_LOAD_UIMM: InstructionOpcode = 0xaa;
 pub const OP_CUSTOM_LOAD_IMM: InstructionOpcode = 0xab;
 pub const OP_CUSTOM_TRACE_END: InstructionOpcode = 0xac;
+// Zicsr
+pub const OP_CSRR: InstructionOpcode = 0xad;
 
 pub const MINIMAL_OPCODE: InstructionOpcode = OP_UNLOADED;
-pub const MAXIMUM_OPCODE: InstructionOpcode = OP_CUSTOM_TRACE_END;
+pub const MAXIMUM_OPCODE: InstructionOpcode = OP_CSRR;
 
 pub const INSTRUCTION_OPCODE_NAMES: [&str; (MAXIMUM_OPCODE - MINIMAL_OPCODE + 1) as usize] = [
     "UNLOADED",
@@ -359,6 +361,7 @@ pub const INSTRUCTION_OPCODE_NAMES: [&str; (MAXIMUM_OPCODE - MINIMAL_OPCODE + 1)
     "CUSTOM_LOAD_UIMM",
     "CUSTOM_LOAD_IMM",
     "CUSTOM_TRACE_END",
+    "CSRR",
 ];
 
 pub fn instruction_opcode_name(i: InstructionOpcode) -> &'static str {
@@ -21,3 +21,16 @@ pub const ISA_IMC: u8 = 0b0000_0000;
 pub const ISA_B: u8 = 0b0000_0001;
 pub const ISA_MOP: u8 = 0b0000_0010;
 pub const ISA_A: u8 = 0b0000_0100;
+// Zicsr with the read-only Zicntr counters.
+pub const ISA_ZICSR: u8 = 0b0000_1000;
+
+// Zicntr counters, the high halves are only readable on 32-bit machines.
+pub const CSR_CYCLE: u32 = 0xc00;
+pub const CSR_TIME: u32 = 0xc01;
+pub const CSR_INSTRET: u32 = 0xc02;
+pub const CSR_CYCLEH: u32 = 0xc80;
+pub const CSR_TIMEH: u32 = 0xc81;
+pub const CSR_INSTRETH: u32 = 0xc82;
+// There is no wall clock in the VM, time advances once every 1 << TIME_SHIFTS
+// cycles instead so it's as deterministic as cycles.
+pub const TIME_SHIFTS: u32 = 10;
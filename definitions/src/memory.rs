@@ -5,3 +5,6 @@ pub const FLAG_EXECUTABLE: u8 = 0b10;
 pub const FLAG_WXORX_BIT: u8 = 0b10;
 pub const FLAG_WRITABLE: u8 = (!FLAG_EXECUTABLE) & FLAG_WXORX_BIT;
 pub const FLAG_DIRTY: u8 = 0b100;
+// Set instead of FLAG_DIRTY when dirty flags are cleared, so pages written
+// before are still told apart from fresh pages.
+pub const FLAG_TOUCHED: u8 = 0b1000;
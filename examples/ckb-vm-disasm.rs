@@ -0,0 +1,27 @@
+use ckb_vm::disasm::write_disassembly;
+use ckb_vm::{Bytes, Error};
+
+// Usage: ckb-vm-disasm <program> [--no-mop]
+//
+// Prints the disassembly of a program as decoded by the VM with all
+// extensions enabled, MOP fusion included unless --no-mop is given.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: ckb-vm-disasm <program> [--no-mop]");
+        std::process::exit(1);
+    }
+    let code: Bytes = std::fs::read(&args[1])?.into();
+    let mut isa = ckb_vm::ISA_IMC | ckb_vm::ISA_A | ckb_vm::ISA_B | ckb_vm::ISA_ZICSR;
+    if !args[2..].iter().any(|arg| arg == "--no-mop") {
+        isa |= ckb_vm::ISA_MOP;
+    }
+    let version = ckb_vm::machine::VERSION2;
+    let stdout = std::io::stdout();
+    let mut writer = stdout.lock();
+    match write_disassembly::<u64, _>(&code, isa, version, &mut writer) {
+        Err(Error::ElfBits) => write_disassembly::<u32, _>(&code, isa, version, &mut writer)?,
+        result => result?,
+    }
+    Ok(())
+}
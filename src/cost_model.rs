@@ -1,7 +1,63 @@
 use crate::{
-    instructions::{extract_opcode, insts},
-    Instruction,
+    debugger::MemoryAccess,
+    instructions::{blank_instruction, extract_opcode, insts, InstructionOpcode},
+    machine::InstructionCycleFunc,
+    Error, Instruction,
 };
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// What an instruction did, as told to context aware cost models.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CostContext {
+    pub pc: u64,
+    /// Whether a branch instruction jumped, None for other instructions.
+    pub branch_taken: Option<bool>,
+    /// Memory accessed by load, store and atomic instructions.
+    pub access: Option<MemoryAccess>,
+    /// Whether the page accessed was not written before the instruction,
+    /// i.e. it had neither FLAG_DIRTY nor FLAG_TOUCHED.
+    pub fresh_page: bool,
+}
+
+// Cycles of an instruction are made of two parts. The cycles returned by
+// CostModel::cycles only depend on the instruction, they are charged before
+// executing it, and traces of TraceMachine and AsmMachine sum them up when
+// the traces are built. Context aware models may charge extra cycles after
+// executing an instruction, given what it did. Machines execute instructions
+// one by one for them: DefaultMachine and TraceMachine through
+// DefaultMachine::step, AsmMachine through AsmMachine::step. The contexts
+// can't be computed from traces, so AsmMachine gives up its traces and runs
+// about as slow as DefaultMachine with such models, though all the machines
+// charge the same cycles. Context aware models suit profiling and estimating
+// costs, consensus critical code shall stick to CostModel::cycles. Machines
+// share their model with the functions returned by
+// DefaultMachine::instruction_cycle_func, so models keeping state across
+// instructions do so with interior mutability.
+pub trait CostModel: Send + Sync {
+    fn cycles(&self, instruction: Instruction) -> u64;
+
+    /// Tells if the machine shall compute contexts and call extra_cycles.
+    fn context_aware(&self) -> bool {
+        false
+    }
+
+    fn extra_cycles(&self, _instruction: Instruction, _context: &CostContext) -> u64 {
+        0
+    }
+}
+
+impl<F: Fn(Instruction) -> u64 + Send + Sync> CostModel for F {
+    fn cycles(&self, instruction: Instruction) -> u64 {
+        self(instruction)
+    }
+}
+
+impl CostModel for CostTable {
+    fn cycles(&self, instruction: Instruction) -> u64 {
+        self.get(extract_opcode(instruction))
+    }
+}
 
 // Returns the spent cycles to execute the secific instruction.
 // This function is usually used to write test cases, which can visually
@@ -68,3 +124,102 @@ pub fn estimate_cycles(i: Instruction) -> u64 {
         _ => 1,
     }
 }
+
+/// Cycles of each instruction, keyed by opcode.
+///
+/// A table is serialized as a map from opcode names, as returned by
+/// instruction_opcode_name, to cycles, so it can be loaded from and saved to
+/// JSON, TOML or any other format supported by serde. A table being loaded
+/// may have a default for the opcodes it doesn't list, names which are not
+/// in the opcode list are rejected.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "CostTableData", into = "CostTableData")]
+pub struct CostTable {
+    // Indexed by opcode - insts::MINIMAL_OPCODE.
+    cycles: Vec<u64>,
+}
+
+impl CostTable {
+    /// A table charging the same cycles for all instructions.
+    pub fn new(cycles: u64) -> Self {
+        Self {
+            cycles: vec![cycles; insts::INSTRUCTION_OPCODE_NAMES.len()],
+        }
+    }
+
+    /// A table with the cycles returned by f for instructions with each
+    /// opcode and no operands.
+    pub fn from_cycle_func<F: Fn(Instruction) -> u64>(f: F) -> Self {
+        let cycles = (insts::MINIMAL_OPCODE..=insts::MAXIMUM_OPCODE)
+            .map(|op| f(blank_instruction(op)))
+            .collect();
+        Self { cycles }
+    }
+
+    pub fn get(&self, op: InstructionOpcode) -> u64 {
+        self.cycles[(op - insts::MINIMAL_OPCODE) as usize]
+    }
+
+    pub fn set(&mut self, op: InstructionOpcode, cycles: u64) {
+        self.cycles[(op - insts::MINIMAL_OPCODE) as usize] = cycles;
+    }
+
+    pub fn cycles(&self, i: Instruction) -> u64 {
+        self.get(extract_opcode(i))
+    }
+
+    pub fn into_cycle_func(self) -> Box<InstructionCycleFunc> {
+        Box::new(move |i| self.cycles(i))
+    }
+}
+
+// The table of estimate_cycles.
+impl Default for CostTable {
+    fn default() -> Self {
+        Self::from_cycle_func(estimate_cycles)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct CostTableData {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default: Option<u64>,
+    cycles: BTreeMap<String, u64>,
+}
+
+impl From<CostTable> for CostTableData {
+    fn from(table: CostTable) -> Self {
+        let cycles = insts::INSTRUCTION_OPCODE_NAMES
+            .iter()
+            .map(|name| name.to_string())
+            .zip(table.cycles)
+            .collect();
+        Self {
+            default: None,
+            cycles,
+        }
+    }
+}
+
+impl TryFrom<CostTableData> for CostTable {
+    type Error = Error;
+
+    fn try_from(data: CostTableData) -> Result<Self, Error> {
+        let names = &insts::INSTRUCTION_OPCODE_NAMES;
+        let mut cycles = vec![data.default; names.len()];
+        for (name, value) in data.cycles {
+            let index = names.iter().position(|n| *n == name).ok_or_else(|| {
+                Error::CostTableError(format!("unknown instruction opcode {}", name))
+            })?;
+            cycles[index] = Some(value);
+        }
+        let cycles = cycles
+            .into_iter()
+            .zip(names.iter())
+            .map(|(value, name)| {
+                value.ok_or_else(|| Error::CostTableError(format!("missing cycles of {}", name)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { cycles })
+    }
+}
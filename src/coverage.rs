@@ -0,0 +1,119 @@
+use crate::symbolizer::Symbolizer;
+use crate::Error;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+// Coverage counts how many times each basic block of a program is executed.
+// Blocks are the traces built by TraceMachine and AsmMachine: a block starts
+// where a jump lands and ends at the first instruction that may jump, as
+// told by is_basic_block_end_instruction, or when the trace is full. All
+// instructions of a block run once it is entered, so a block is recorded
+// after it's executed, and a block stopped by an error is not recorded.
+//
+// Coverage is exported in the lcov tracefile format, using the DWARF line
+// tables read by a Symbolizer. Every line in the tables is reported, so
+// lines never executed show up with 0 hits.
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    // Hits keyed by block address and length in bytes.
+    blocks: BTreeMap<(u64, u64), u64>,
+    // Length of the longest block, to find the blocks containing an address.
+    max_length: u64,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an execution of the block of length bytes at address.
+    pub fn record(&mut self, address: u64, length: u64) {
+        *self.blocks.entry((address, length)).or_insert(0) += 1;
+        self.max_length = self.max_length.max(length);
+    }
+
+    /// Adds the hits of another run, e.g. of another test or fuzz input.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (block, hits) in &other.blocks {
+            *self.blocks.entry(*block).or_insert(0) += hits;
+        }
+        self.max_length = self.max_length.max(other.max_length);
+    }
+
+    /// Blocks executed as address, length and hits, sorted by address.
+    pub fn blocks(&self) -> impl Iterator<Item = (u64, u64, u64)> + '_ {
+        self.blocks
+            .iter()
+            .map(|((address, length), hits)| (*address, *length, *hits))
+    }
+
+    /// Times the instruction at address was executed. Jumping into the
+    /// middle of a block starts another block, so blocks may overlap.
+    pub fn hits(&self, address: u64) -> u64 {
+        let start = address.saturating_sub(self.max_length);
+        self.blocks
+            .range((start, 0)..=(address, u64::MAX))
+            .filter(|((block, length), _)| address < block + length)
+            .map(|(_, hits)| hits)
+            .sum()
+    }
+
+    /// Writes coverage in the lcov tracefile format, with source files named
+    /// as in the line tables of the program.
+    pub fn write_lcov<W: Write>(
+        &self,
+        symbolizer: &Symbolizer,
+        writer: &mut W,
+    ) -> Result<(), Error> {
+        #[derive(Default)]
+        struct File<'a> {
+            // Line, name and hits.
+            functions: Vec<(u64, &'a str, u64)>,
+            // Hits by line.
+            lines: BTreeMap<u64, u64>,
+        }
+
+        let mut files: BTreeMap<&str, File> = BTreeMap::new();
+        for (address, file, line) in symbolizer.lines() {
+            let hits = files
+                .entry(file)
+                .or_default()
+                .lines
+                .entry(line)
+                .or_insert(0);
+            // A line split into several rows counts its most executed one.
+            *hits = (*hits).max(self.hits(address));
+        }
+        for function in symbolizer.functions() {
+            if let Some((file, line)) = symbolizer.line(function.address) {
+                files.entry(file).or_default().functions.push((
+                    line,
+                    function.name.as_str(),
+                    self.hits(function.address),
+                ));
+            }
+        }
+        for (name, file) in &files {
+            writeln!(writer, "TN:")?;
+            writeln!(writer, "SF:{}", name)?;
+            for (line, function, _) in &file.functions {
+                writeln!(writer, "FN:{},{}", line, function)?;
+            }
+            for (_, function, hits) in &file.functions {
+                writeln!(writer, "FNDA:{},{}", hits, function)?;
+            }
+            let functions_hit = file.functions.iter().filter(|f| f.2 > 0).count();
+            writeln!(writer, "FNF:{}", file.functions.len())?;
+            writeln!(writer, "FNH:{}", functions_hit)?;
+            for (line, hits) in &file.lines {
+                writeln!(writer, "DA:{},{}", line, hits)?;
+            }
+            let lines_hit = file.lines.values().filter(|hits| **hits > 0).count();
+            writeln!(writer, "LF:{}", file.lines.len())?;
+            writeln!(writer, "LH:{}", lines_hit)?;
+            writeln!(writer, "end_of_record")?;
+        }
+        Ok(())
+    }
+}
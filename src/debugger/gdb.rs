@@ -0,0 +1,650 @@
+use super::{memory_access, Breakpoints, Debugger, WatchKind, Watchpoint};
+use crate::decoder::{build_decoder, Decoder};
+use crate::instructions::{extract_opcode, Register};
+use crate::machine::{DefaultMachine, VERSION0};
+use crate::memory::Memory;
+use crate::{CoreMachine, Error, SupportMachine, ISA_MOP};
+use ckb_vm_definitions::{instructions as insts, registers::REGISTER_ABI_NAMES};
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+
+// A stub implementing the GDB remote serial protocol, so a program running in
+// CKB-VM can be debugged with riscv64-unknown-elf-gdb:
+//
+//   (gdb) target remote 127.0.0.1:9999
+//
+// GdbStub::serve runs a DefaultMachine (or the machine field of a
+// TraceMachine) instruction by instruction under the control of GDB. It
+// supports reading and writing registers and memory, single step, continue,
+// interrupting a running program, breakpoints and watchpoints. Software and
+// hardware breakpoints are handled the same way: the program stops before
+// executing an instruction at a breakpoint address, memory is never patched.
+// Watchpoints are triggered by load, store and atomic instructions, and the
+// program stops after executing the instruction.
+//
+// GdbStub also implements Debugger. Installed in a machine running normally,
+// GDB gets the control whenever an EBREAK instruction is executed, in this
+// mode continue and single step both resume the machine, and breakpoints and
+// watchpoints are ignored.
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+// Number of instructions executed between two checks for interrupts from GDB.
+const INTERRUPT_CHECK_INTERVAL: u64 = 0x10000;
+
+/// A connection to GDB.
+pub trait Connection: Read + Write + Send + Sync {
+    // Reads a byte if one is available without blocking. It is used to detect
+    // interrupts sent by GDB when the program is running.
+    fn try_read_byte(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+impl Connection for TcpStream {
+    fn try_read_byte(&mut self) -> Option<u8> {
+        try_read_byte_nonblocking(self, TcpStream::set_nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn try_read_byte(&mut self) -> Option<u8> {
+        try_read_byte_nonblocking(self, UnixStream::set_nonblocking)
+    }
+}
+
+fn try_read_byte_nonblocking<S: Read>(
+    stream: &mut S,
+    set_nonblocking: fn(&S, bool) -> std::io::Result<()>,
+) -> Option<u8> {
+    set_nonblocking(stream, true).ok()?;
+    let mut buf = [0u8; 1];
+    let result = stream.read(&mut buf);
+    set_nonblocking(stream, false).ok()?;
+    match result {
+        Ok(1) => Some(buf[0]),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StopReason {
+    Signal(u8),
+    Breakpoint,
+    Watchpoint(WatchKind, u64),
+    Exited(i8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Resume {
+    Continue,
+    Step,
+    Detach,
+    Kill,
+}
+
+pub struct GdbStub<C: Connection> {
+    connection: C,
+    input: VecDeque<u8>,
+    breakpoints: Breakpoints,
+    // Set once GDB has queried the initial stop reason.
+    attached: bool,
+    // Set when GDB modifies memory, decoded instructions must be flushed.
+    memory_modified: bool,
+}
+
+impl GdbStub<TcpStream> {
+    /// Waits for GDB to connect to `addr`.
+    pub fn listen_tcp<A: ToSocketAddrs>(addr: A) -> Result<Self, Error> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+}
+
+#[cfg(unix)]
+impl GdbStub<UnixStream> {
+    /// Waits for GDB to connect to the unix socket at `path`.
+    pub fn listen_unix<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let listener = UnixListener::bind(path)?;
+        let (stream, _) = listener.accept()?;
+        Ok(Self::new(stream))
+    }
+}
+
+impl<C: Connection> GdbStub<C> {
+    pub fn new(connection: C) -> Self {
+        Self {
+            connection,
+            input: VecDeque::new(),
+            breakpoints: Breakpoints::new(),
+            attached: false,
+            memory_modified: false,
+        }
+    }
+
+    /// Runs the machine under the control of GDB until the program exits,
+    /// GDB kills the program or detaches. In the last case, the program
+    /// keeps running without GDB.
+    pub fn serve<Inner: SupportMachine>(
+        &mut self,
+        machine: &mut DefaultMachine<Inner>,
+    ) -> Result<i8, Error> {
+        if machine.isa() & ISA_MOP != 0 && machine.version() == VERSION0 {
+            return Err(Error::InvalidVersion);
+        }
+        let mut decoder = build_decoder::<Inner::REG>(machine.isa(), machine.version());
+        machine.set_running(true);
+        let mut stop = StopReason::Signal(SIGTRAP);
+        let mut fault = None;
+        loop {
+            let resume = self.session(machine, stop)?;
+            if self.memory_modified {
+                decoder.reset_instructions_cache();
+                self.memory_modified = false;
+            }
+            match resume {
+                Resume::Kill => return Err(Error::External("killed by gdb".to_string())),
+                Resume::Detach => {
+                    if let Some(e) = fault {
+                        return Err(e);
+                    }
+                    return machine.run();
+                }
+                Resume::Continue | Resume::Step => {
+                    if let Some(e) = fault {
+                        if let StopReason::Signal(signal) = stop {
+                            self.send_packet(format!("X{:02x}", signal).as_bytes())?;
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+            let result = if resume == Resume::Step {
+                self.step(machine, &mut decoder)
+                    .map(|stop| stop.unwrap_or(StopReason::Signal(SIGTRAP)))
+            } else {
+                self.resume(machine, &mut decoder)
+            };
+            stop = match result {
+                Ok(StopReason::Exited(exit_code)) => {
+                    self.send_packet(format!("W{:02x}", exit_code as u8).as_bytes())?;
+                    return Ok(exit_code);
+                }
+                Ok(stop) => stop,
+                Err(e) => {
+                    let signal = match e {
+                        Error::InvalidInstruction { .. } | Error::InvalidOp(_) => SIGILL,
+                        _ => SIGSEGV,
+                    };
+                    fault = Some(e);
+                    StopReason::Signal(signal)
+                }
+            };
+        }
+    }
+
+    // Executes one instruction, returns the reason to stop if any.
+    fn step<Inner: SupportMachine>(
+        &mut self,
+        machine: &mut DefaultMachine<Inner>,
+        decoder: &mut Decoder,
+    ) -> Result<Option<StopReason>, Error> {
+        if !machine.running() {
+            return Ok(Some(StopReason::Exited(machine.exit_code())));
+        }
+        if machine.reset_signal() {
+            decoder.reset_instructions_cache();
+        }
+        let pc = machine.pc().to_u64();
+        let instruction = decoder.decode(machine.memory_mut(), pc)?;
+        let access = if self.breakpoints.has_watchpoints() {
+            memory_access(instruction, machine.registers())
+        } else {
+            None
+        };
+        machine.step(decoder)?;
+        if !machine.running() {
+            return Ok(Some(StopReason::Exited(machine.exit_code())));
+        }
+        if let Some(access) = access {
+            if let Some(watchpoint) = self.breakpoints.watchpoint_hit(&access) {
+                let address = access.address.max(watchpoint.address);
+                return Ok(Some(StopReason::Watchpoint(watchpoint.kind, address)));
+            }
+        }
+        if extract_opcode(instruction) == insts::OP_EBREAK {
+            return Ok(Some(StopReason::Signal(SIGTRAP)));
+        }
+        Ok(None)
+    }
+
+    fn resume<Inner: SupportMachine>(
+        &mut self,
+        machine: &mut DefaultMachine<Inner>,
+        decoder: &mut Decoder,
+    ) -> Result<StopReason, Error> {
+        // The instruction at the current pc is executed even if there is a
+        // breakpoint, otherwise the program could never resume from one.
+        if let Some(stop) = self.step(machine, decoder)? {
+            return Ok(stop);
+        }
+        let mut steps: u64 = 0;
+        loop {
+            if self.breakpoints.is_breakpoint(machine.pc().to_u64()) {
+                return Ok(StopReason::Breakpoint);
+            }
+            steps += 1;
+            if steps % INTERRUPT_CHECK_INTERVAL == 0 && self.interrupted() {
+                return Ok(StopReason::Signal(SIGINT));
+            }
+            if let Some(stop) = self.step(machine, decoder)? {
+                return Ok(stop);
+            }
+        }
+    }
+
+    fn interrupted(&mut self) -> bool {
+        if self.input.is_empty() {
+            if let Some(byte) = self.connection.try_read_byte() {
+                self.input.push_back(byte);
+            }
+        }
+        if self.input.front() == Some(&0x03) {
+            self.input.pop_front();
+            return true;
+        }
+        false
+    }
+
+    // Reports the stop reason to GDB, then serves requests until GDB resumes
+    // the program.
+    fn session<M: SupportMachine>(
+        &mut self,
+        machine: &mut M,
+        stop: StopReason,
+    ) -> Result<Resume, Error> {
+        // GDB queries the initial stop reason by itself after connected.
+        if self.attached {
+            self.send_packet(stop_reply(stop).as_bytes())?;
+        }
+        loop {
+            let packet = self.read_packet()?;
+            let (command, args) = split_command(&packet);
+            let response = match command {
+                b'?' => {
+                    self.attached = true;
+                    stop_reply(stop)
+                }
+                b'c' | b's' => {
+                    if !args.is_empty() {
+                        match parse_hex(args) {
+                            Some(pc) => set_pc(machine, pc),
+                            None => {
+                                self.send_packet(b"E01")?;
+                                continue;
+                            }
+                        }
+                    }
+                    return Ok(if command == b'c' {
+                        Resume::Continue
+                    } else {
+                        Resume::Step
+                    });
+                }
+                b'D' => {
+                    self.send_packet(b"OK")?;
+                    return Ok(Resume::Detach);
+                }
+                b'k' => return Ok(Resume::Kill),
+                b'g' => read_registers(machine),
+                b'G' => write_registers(machine, args),
+                b'p' => read_register(machine, args),
+                b'P' => write_register(machine, args),
+                b'm' => read_memory(machine, args),
+                b'M' => {
+                    let response = write_memory(machine, args);
+                    self.memory_modified = true;
+                    response
+                }
+                b'Z' | b'z' => self.update_point(command == b'Z', args),
+                b'H' | b'T' => "OK".to_string(),
+                b'q' => query::<M::REG>(args),
+                b'v' if args.starts_with(b"Kill") => {
+                    self.send_packet(b"OK")?;
+                    return Ok(Resume::Kill);
+                }
+                _ => String::new(),
+            };
+            self.send_packet(response.as_bytes())?;
+        }
+    }
+
+    fn update_point(&mut self, insert: bool, args: &[u8]) -> String {
+        let mut parts = args.split(|c| *c == b',');
+        let (kind, address, size) = match (
+            parts.next().and_then(parse_hex),
+            parts.next().and_then(parse_hex),
+            parts.next().and_then(parse_hex),
+        ) {
+            (Some(kind), Some(address), Some(size)) => (kind, address, size),
+            _ => return "E01".to_string(),
+        };
+        let kind = match kind {
+            0 | 1 => {
+                if insert {
+                    self.breakpoints.insert_breakpoint(address);
+                } else {
+                    self.breakpoints.remove_breakpoint(address);
+                }
+                return "OK".to_string();
+            }
+            2 => WatchKind::Write,
+            3 => WatchKind::Read,
+            4 => WatchKind::Access,
+            _ => return String::new(),
+        };
+        let watchpoint = Watchpoint {
+            kind,
+            address,
+            size,
+        };
+        if insert {
+            self.breakpoints.insert_watchpoint(watchpoint);
+        } else {
+            self.breakpoints.remove_watchpoint(&watchpoint);
+        }
+        "OK".to_string()
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        if let Some(byte) = self.input.pop_front() {
+            return Ok(byte);
+        }
+        let mut buf = [0u8; 4096];
+        let n = self.connection.read(&mut buf)?;
+        if n == 0 {
+            return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "gdb disconnected").into());
+        }
+        self.input.extend(&buf[..n]);
+        Ok(self.input.pop_front().unwrap())
+    }
+
+    fn read_packet(&mut self) -> Result<Vec<u8>, Error> {
+        loop {
+            // Skips acks and interrupts received when the program is stopped.
+            while self.read_byte()? != b'$' {}
+            let mut data = Vec::new();
+            let mut checksum: u8 = 0;
+            loop {
+                let byte = self.read_byte()?;
+                if byte == b'#' {
+                    break;
+                }
+                checksum = checksum.wrapping_add(byte);
+                data.push(byte);
+            }
+            let expected = [self.read_byte()?, self.read_byte()?];
+            if parse_hex(&expected) == Some(u64::from(checksum)) {
+                self.connection.write_all(b"+")?;
+                return Ok(data);
+            }
+            self.connection.write_all(b"-")?;
+        }
+    }
+
+    fn send_packet(&mut self, data: &[u8]) -> Result<(), Error> {
+        let checksum = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(data);
+        packet.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+        loop {
+            self.connection.write_all(&packet)?;
+            self.connection.flush()?;
+            match self.read_byte()? {
+                b'-' => continue,
+                b'+' => return Ok(()),
+                // Acks are missing, keeps the byte for the next packet.
+                byte => {
+                    self.input.push_front(byte);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl<C: Connection, Mac: SupportMachine> Debugger<Mac> for GdbStub<C> {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ebreak(&mut self, machine: &mut Mac) -> Result<(), Error> {
+        match self.session(machine, StopReason::Signal(SIGTRAP))? {
+            Resume::Kill => Err(Error::External("killed by gdb".to_string())),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn stop_reply(stop: StopReason) -> String {
+    match stop {
+        StopReason::Signal(signal) => format!("S{:02x}", signal),
+        StopReason::Breakpoint => format!("T{:02x}swbreak:;", SIGTRAP),
+        StopReason::Watchpoint(kind, address) => {
+            let name = match kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, name, address)
+        }
+        StopReason::Exited(exit_code) => format!("W{:02x}", exit_code as u8),
+    }
+}
+
+fn split_command(packet: &[u8]) -> (u8, &[u8]) {
+    match packet.split_first() {
+        Some((command, args)) => (*command, args),
+        None => (0, &[]),
+    }
+}
+
+fn query<R: Register>(args: &[u8]) -> String {
+    if args.starts_with(b"Supported") {
+        "PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+".to_string()
+    } else if args == b"Attached" {
+        "1".to_string()
+    } else if args == b"C" {
+        "QC1".to_string()
+    } else if args == b"fThreadInfo" {
+        "m1".to_string()
+    } else if args == b"sThreadInfo" {
+        "l".to_string()
+    } else if let Some(annex) = args.strip_prefix(b"Xfer:features:read:target.xml:") {
+        let mut parts = annex.split(|c| *c == b',');
+        match (
+            parts.next().and_then(parse_hex),
+            parts.next().and_then(parse_hex),
+        ) {
+            (Some(offset), Some(length)) => {
+                let xml = target_xml::<R>();
+                let start = (offset as usize).min(xml.len());
+                let end = start.saturating_add(length as usize).min(xml.len());
+                let prefix = if end == xml.len() { "l" } else { "m" };
+                format!("{}{}", prefix, &xml[start..end])
+            }
+            _ => "E01".to_string(),
+        }
+    } else {
+        String::new()
+    }
+}
+
+fn target_xml<R: Register>() -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><architecture>riscv:rv{}</architecture>\
+         <feature name=\"org.gnu.gdb.riscv.cpu\">",
+        R::BITS
+    );
+    for (i, name) in REGISTER_ABI_NAMES.iter().enumerate() {
+        xml.push_str(&format!(
+            "<reg name=\"{}\" bitsize=\"{}\" type=\"int\" regnum=\"{}\"/>",
+            name,
+            R::BITS,
+            i
+        ));
+    }
+    xml.push_str(&format!(
+        "<reg name=\"pc\" bitsize=\"{}\" type=\"code_ptr\" regnum=\"32\"/></feature></target>",
+        R::BITS
+    ));
+    xml
+}
+
+fn set_pc<M: SupportMachine>(machine: &mut M, pc: u64) {
+    machine.update_pc(M::REG::from_u64(pc));
+    machine.commit_pc();
+}
+
+// Registers are transferred in target byte order, which is little endian.
+fn encode_register<R: Register>(value: &R) -> String {
+    let bytes = value.to_u64().to_le_bytes();
+    encode_hex(&bytes[..R::BITS as usize / 8])
+}
+
+fn decode_register<R: Register>(data: &[u8]) -> Option<R> {
+    let bytes = decode_hex(data)?;
+    if bytes.len() != R::BITS as usize / 8 {
+        return None;
+    }
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(&bytes);
+    Some(R::from_u64(u64::from_le_bytes(buf)))
+}
+
+fn read_registers<M: SupportMachine>(machine: &mut M) -> String {
+    let mut response: String = machine.registers().iter().map(encode_register).collect();
+    response.push_str(&encode_register(machine.pc()));
+    response
+}
+
+fn write_registers<M: SupportMachine>(machine: &mut M, args: &[u8]) -> String {
+    let width = M::REG::BITS as usize / 4;
+    if args.len() != width * 33 {
+        return "E01".to_string();
+    }
+    let mut values = Vec::with_capacity(33);
+    for chunk in args.chunks(width) {
+        match decode_register::<M::REG>(chunk) {
+            Some(value) => values.push(value),
+            None => return "E01".to_string(),
+        }
+    }
+    let pc = values.pop().unwrap();
+    for (i, value) in values.into_iter().enumerate().skip(1) {
+        machine.set_register(i, value);
+    }
+    machine.update_pc(pc);
+    machine.commit_pc();
+    "OK".to_string()
+}
+
+fn read_register<M: SupportMachine>(machine: &mut M, args: &[u8]) -> String {
+    match parse_hex(args) {
+        Some(i) if i < 32 => encode_register(&machine.registers()[i as usize]),
+        Some(32) => encode_register(machine.pc()),
+        _ => "E01".to_string(),
+    }
+}
+
+fn write_register<M: SupportMachine>(machine: &mut M, args: &[u8]) -> String {
+    let mut parts = args.splitn(2, |c| *c == b'=');
+    let (index, value) = match (
+        parts.next().and_then(parse_hex),
+        parts.next().and_then(decode_register::<M::REG>),
+    ) {
+        (Some(index), Some(value)) => (index, value),
+        _ => return "E01".to_string(),
+    };
+    match index {
+        // x0 is hardwired to zero.
+        0 => {}
+        1..=31 => machine.set_register(index as usize, value),
+        32 => {
+            machine.update_pc(value);
+            machine.commit_pc();
+        }
+        _ => return "E01".to_string(),
+    }
+    "OK".to_string()
+}
+
+fn read_memory<M: SupportMachine>(machine: &mut M, args: &[u8]) -> String {
+    let mut parts = args.split(|c| *c == b',');
+    match (
+        parts.next().and_then(parse_hex),
+        parts.next().and_then(parse_hex),
+    ) {
+        (Some(address), Some(length)) => match machine.memory_mut().load_bytes(address, length) {
+            Ok(data) => encode_hex(&data),
+            Err(_) => "E14".to_string(),
+        },
+        _ => "E01".to_string(),
+    }
+}
+
+fn write_memory<M: SupportMachine>(machine: &mut M, args: &[u8]) -> String {
+    let mut parts = args.splitn(2, |c| *c == b':');
+    let (range, data) = match (parts.next(), parts.next().and_then(decode_hex)) {
+        (Some(range), Some(data)) => (range, data),
+        _ => return "E01".to_string(),
+    };
+    let mut parts = range.split(|c| *c == b',');
+    match (
+        parts.next().and_then(parse_hex),
+        parts.next().and_then(parse_hex),
+    ) {
+        (Some(address), Some(length)) if length == data.len() as u64 => {
+            match machine.memory_mut().store_bytes(address, &data) {
+                Ok(()) => "OK".to_string(),
+                Err(_) => "E14".to_string(),
+            }
+        }
+        _ => "E01".to_string(),
+    }
+}
+
+fn parse_hex(data: &[u8]) -> Option<u64> {
+    if data.is_empty() || data.len() > 16 {
+        return None;
+    }
+    std::str::from_utf8(data)
+        .ok()
+        .and_then(|s| u64::from_str_radix(s, 16).ok())
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() % 2 != 0 {
+        return None;
+    }
+    data.chunks(2)
+        .map(|pair| parse_hex(pair).map(|byte| byte as u8))
+        .collect()
+}
@@ -0,0 +1,188 @@
+pub mod gdb;
+
+use crate::instructions::{extract_opcode, Instruction, Itype, Register, Rtype, Stype};
+use crate::{machine::SupportMachine, Error};
+use ckb_vm_definitions::instructions as insts;
+use std::collections::BTreeSet;
+
+pub trait Debugger<Mac: SupportMachine>: Send + Sync {
+    fn initialize(&mut self, machine: &mut Mac) -> Result<(), Error>;
+    fn ebreak(&mut self, machine: &mut Mac) -> Result<(), Error>;
+}
+
+/// Memory accessed by an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: u64,
+    pub size: u64,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl MemoryAccess {
+    pub fn overlaps(&self, address: u64, size: u64) -> bool {
+        self.address < address.saturating_add(size) && address < self.address + self.size
+    }
+}
+
+/// The kind of memory accesses a watchpoint is triggered by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub address: u64,
+    pub size: u64,
+}
+
+impl Watchpoint {
+    pub fn hit(&self, access: &MemoryAccess) -> bool {
+        let kind_matches = match self.kind {
+            WatchKind::Write => access.write,
+            WatchKind::Read => access.read,
+            WatchKind::Access => true,
+        };
+        kind_matches && access.overlaps(self.address, self.size)
+    }
+}
+
+/// Breakpoints and watchpoints set by the host. A machine stops before
+/// executing an instruction at a breakpoint, and after executing an
+/// instruction which accesses memory covered by a watchpoint. A stopped
+/// machine returns Error::Pause from `run`, while `run_with_budget` returns
+/// the reason as a RunOutcome.
+#[derive(Clone, Debug, Default)]
+pub struct Breakpoints {
+    breakpoints: BTreeSet<u64>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert_breakpoint(&mut self, pc: u64) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u64) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn insert_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        match self.watchpoints.iter().position(|w| w == watchpoint) {
+            Some(i) => {
+                self.watchpoints.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty() && self.watchpoints.is_empty()
+    }
+
+    pub fn has_watchpoints(&self) -> bool {
+        !self.watchpoints.is_empty()
+    }
+
+    pub fn is_breakpoint(&self, pc: u64) -> bool {
+        self.breakpoints.contains(&pc)
+    }
+
+    /// Returns the first watchpoint triggered by a memory access.
+    pub fn watchpoint_hit(&self, access: &MemoryAccess) -> Option<&Watchpoint> {
+        self.watchpoints.iter().find(|w| w.hit(access))
+    }
+}
+
+// Computes the memory accessed by load, store and atomic instructions, from
+// the registers before the instruction is executed. None is returned for
+// other instructions, including macro-op fused ones.
+pub fn memory_access<R: Register>(inst: Instruction, registers: &[R]) -> Option<MemoryAccess> {
+    let op = extract_opcode(inst);
+    let offset = |rs1: usize, immediate: i32| {
+        registers[rs1]
+            .overflowing_add(&R::from_i32(immediate))
+            .to_u64()
+    };
+    let (address, size, read, write) = match op {
+        insts::OP_LB_VERSION0
+        | insts::OP_LB_VERSION1
+        | insts::OP_LBU_VERSION0
+        | insts::OP_LBU_VERSION1 => {
+            let i = Itype(inst);
+            (offset(i.rs1(), i.immediate_s()), 1, true, false)
+        }
+        insts::OP_LH_VERSION0
+        | insts::OP_LH_VERSION1
+        | insts::OP_LHU_VERSION0
+        | insts::OP_LHU_VERSION1 => {
+            let i = Itype(inst);
+            (offset(i.rs1(), i.immediate_s()), 2, true, false)
+        }
+        insts::OP_LW_VERSION0
+        | insts::OP_LW_VERSION1
+        | insts::OP_LWU_VERSION0
+        | insts::OP_LWU_VERSION1 => {
+            let i = Itype(inst);
+            (offset(i.rs1(), i.immediate_s()), 4, true, false)
+        }
+        insts::OP_LD_VERSION0 | insts::OP_LD_VERSION1 => {
+            let i = Itype(inst);
+            (offset(i.rs1(), i.immediate_s()), 8, true, false)
+        }
+        insts::OP_SB => {
+            let i = Stype(inst);
+            (offset(i.rs1(), i.immediate_s()), 1, false, true)
+        }
+        insts::OP_SH => {
+            let i = Stype(inst);
+            (offset(i.rs1(), i.immediate_s()), 2, false, true)
+        }
+        insts::OP_SW => {
+            let i = Stype(inst);
+            (offset(i.rs1(), i.immediate_s()), 4, false, true)
+        }
+        insts::OP_SD => {
+            let i = Stype(inst);
+            (offset(i.rs1(), i.immediate_s()), 8, false, true)
+        }
+        insts::OP_LR_W => (registers[Rtype(inst).rs1()].to_u64(), 4, true, false),
+        insts::OP_LR_D => (registers[Rtype(inst).rs1()].to_u64(), 8, true, false),
+        insts::OP_SC_W..=insts::OP_AMOMAXU_W => {
+            (registers[Rtype(inst).rs1()].to_u64(), 4, true, true)
+        }
+        insts::OP_SC_D..=insts::OP_AMOMAXU_D => {
+            (registers[Rtype(inst).rs1()].to_u64(), 8, true, true)
+        }
+        _ => return None,
+    };
+    Some(MemoryAccess {
+        address,
+        size,
+        read,
+        write,
+    })
+}
+
+// Tells if an instruction accesses memory, whatever the registers are.
+pub fn accesses_memory(inst: Instruction) -> bool {
+    memory_access::<u64>(inst, &[0; 32]).is_some()
+}
@@ -1,13 +1,14 @@
 use ckb_vm_definitions::instructions::{self as insts};
 use ckb_vm_definitions::registers::{RA, ZERO};
 
+use crate::error::{AccessKind, MemoryFault};
 use crate::instructions::{
-    a, b, extract_opcode, i, instruction_length, m, rvc, set_instruction_length_n, Instruction,
-    InstructionFactory, Itype, R4type, R5type, Register, Rtype, Utype,
+    a, b, extract_opcode, i, instruction_length, m, rvc, set_instruction_length_n, zicsr,
+    Instruction, InstructionFactory, Itype, R4type, R5type, Register, Rtype, Utype,
 };
 use crate::machine::VERSION2;
 use crate::memory::Memory;
-use crate::{Error, ISA_A, ISA_B, ISA_MOP, RISCV_MAX_MEMORY, RISCV_PAGESIZE};
+use crate::{Error, ISA_A, ISA_B, ISA_MOP, ISA_ZICSR, RISCV_MAX_MEMORY, RISCV_PAGESIZE};
 
 const RISCV_PAGESIZE_MASK: u64 = RISCV_PAGESIZE as u64 - 1;
 const INSTRUCTION_CACHE_SIZE: usize = 4096;
@@ -68,7 +69,7 @@ impl Decoder {
     // Also, due to RISC-V encoding behavior, it's totally okay when we cast a 16-bit
     // RVC instruction into a 32-bit instruction, the meaning of the instruction stays
     // unchanged in the cast conversion.
-    fn decode_bits<M: Memory>(&self, memory: &mut M, pc: u64) -> Result<u32, Error> {
+    pub fn decode_bits<M: Memory>(&self, memory: &mut M, pc: u64) -> Result<u32, Error> {
         // when the address is not the last 2 bytes of an executable page,
         // use a faster path to load instruction bits
         if pc & RISCV_PAGESIZE_MASK < RISCV_PAGESIZE_MASK - 1 {
@@ -89,7 +90,11 @@ impl Decoder {
     pub fn decode_raw<M: Memory>(&mut self, memory: &mut M, pc: u64) -> Result<Instruction, Error> {
         // since we are using RISCV_MAX_MEMORY as the default key in the instruction cache, have to check out of bound error first
         if pc as usize >= RISCV_MAX_MEMORY {
-            return Err(Error::MemOutOfBound);
+            return Err(Error::MemOutOfBound(MemoryFault::new(
+                pc,
+                2,
+                AccessKind::Execute,
+            )));
         }
         let instruction_cache_key = {
             // according to RISC-V instruction encoding, the lowest bit in PC will always be zero
@@ -849,11 +854,13 @@ impl Decoder {
     }
 
     pub fn decode<M: Memory>(&mut self, memory: &mut M, pc: u64) -> Result<Instruction, Error> {
-        if self.mop {
+        let result = if self.mop {
             self.decode_mop(memory, pc)
         } else {
             self.decode_raw(memory, pc)
-        }
+        };
+        // Instructions failing to be fetched are at pc.
+        result.map_err(|e| e.with_pc(pc))
     }
 
     pub fn reset_instructions_cache(&mut self) {
@@ -872,5 +879,8 @@ pub fn build_decoder<R: Register>(isa: u8, version: u32) -> Decoder {
     if isa & ISA_A != 0 {
         decoder.add_instruction_factory(a::factory::<R>);
     }
+    if isa & ISA_ZICSR != 0 {
+        decoder.add_instruction_factory(zicsr::factory::<R>);
+    }
     decoder
 }
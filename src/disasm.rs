@@ -0,0 +1,393 @@
+use crate::decoder::build_decoder;
+use crate::instructions::{
+    extract_opcode, instruction_length, instruction_opcode_name, insts, tagged::TaggedInstruction,
+    zicsr, Instruction, InstructionOpcode, Register, REGISTER_ABI_NAMES,
+};
+use crate::machine::elf_adaptor::{ProgramHeader, SectionHeader, PF_X, PT_LOAD, SHF_EXECINSTR};
+use crate::machine::{parse_elf_sections, CoreMachine, DefaultCoreMachine, SupportMachine};
+use crate::memory::{sparse::SparseMemory, wxorx::WXorXMemory};
+use crate::symbolizer::Symbolizer;
+use crate::{Bytes, Error};
+use core::convert::TryFrom;
+use goblin_v040::elf::section_header::SHT_PROGBITS;
+use std::io::Write;
+
+// Disassembler for the executable segments of a program, decoding them the
+// way the VM does: with the Decoder built for the given ISA and version, so
+// instructions of disabled extensions are shown as raw bits, and adjacent
+// instructions fused by MOP are shown as the single instruction the VM runs,
+// annotated with the instructions they were fused from.
+//
+// Instructions are printed in their base forms, without the pseudo
+// instruction aliases of objdump: "addi a0, zero, 1" instead of "li a0, 1".
+// Compressed instructions are printed as the instructions they expand to.
+
+/// An instruction, or undecodable bits, at an address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisassembledInstruction {
+    pub address: u64,
+    // Bits of each RISC-V instruction covered, 16-bit for compressed ones.
+    pub bits: Vec<u32>,
+    // None if the bits can't be decoded.
+    pub instruction: Option<Instruction>,
+    // Instructions fused into instruction by MOP, empty if not fused.
+    pub fused: Vec<Instruction>,
+}
+
+impl DisassembledInstruction {
+    pub fn length(&self) -> u64 {
+        self.bits.iter().map(|bits| bits_length(*bits)).sum()
+    }
+}
+
+fn bits_length(bits: u32) -> u64 {
+    if bits & 0x3 == 0x3 {
+        4
+    } else {
+        2
+    }
+}
+
+// Address ranges of code: the executable sections within executable PT_LOAD
+// segments, as segments often start with the ELF headers, or the initialized
+// part of the segments for programs without section headers.
+fn code_ranges(
+    program_headers: &[ProgramHeader],
+    section_headers: &[SectionHeader],
+) -> Vec<(u64, u64)> {
+    let segments: Vec<(u64, u64)> = program_headers
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD && header.p_flags & PF_X != 0)
+        .map(|header| (header.p_vaddr, header.p_vaddr.wrapping_add(header.p_filesz)))
+        .collect();
+    let mut sections: Vec<(u64, u64)> = section_headers
+        .iter()
+        .filter(|header| {
+            header.sh_type == SHT_PROGBITS && header.sh_flags & u64::from(SHF_EXECINSTR) != 0
+        })
+        .map(|header| (header.sh_addr, header.sh_addr.wrapping_add(header.sh_size)))
+        .filter(|(start, end)| {
+            segments
+                .iter()
+                .any(|(segment_start, segment_end)| segment_start <= start && end <= segment_end)
+        })
+        .collect();
+    if sections.is_empty() {
+        return segments;
+    }
+    sections.sort_unstable();
+    sections
+}
+
+/// Disassembles the code in the executable PT_LOAD segments of a program.
+pub fn disassemble<R: Register>(
+    program: &Bytes,
+    isa: u8,
+    version: u32,
+) -> Result<Vec<DisassembledInstruction>, Error> {
+    let (_, program_headers, section_headers) = parse_elf_sections::<R>(program, version)?;
+    let mut machine =
+        DefaultCoreMachine::<R, WXorXMemory<SparseMemory<R>>>::new(isa, version, u64::MAX);
+    machine.load_elf(program, false)?;
+    let memory = machine.memory_mut();
+    let mut decoder = build_decoder::<R>(isa, version);
+    let mut instructions = Vec::new();
+    for (start, end) in code_ranges(&program_headers, &section_headers) {
+        let mut pc = start;
+        while pc < end {
+            let head_bits = match decoder.decode_bits(memory, pc) {
+                Ok(bits) => bits,
+                // Code ends within an instruction past the loaded pages.
+                Err(_) => break,
+            };
+            // A valid instruction may fail to decode as a whole when the
+            // ones MOP looks ahead at can't be decoded.
+            let instruction = decoder
+                .decode(memory, pc)
+                .or_else(|_| decoder.decode_raw(memory, pc))
+                .ok();
+            let mut disassembled = DisassembledInstruction {
+                address: pc,
+                bits: vec![head_bits],
+                instruction,
+                fused: Vec::new(),
+            };
+            if let Some(instruction) = instruction {
+                let length = u64::from(instruction_length(instruction));
+                if length > bits_length(head_bits) {
+                    let mut address = pc;
+                    disassembled.bits.clear();
+                    while address < pc + length {
+                        let bits = decoder.decode_bits(memory, address)?;
+                        disassembled.bits.push(bits);
+                        disassembled
+                            .fused
+                            .push(decoder.decode_raw(memory, address)?);
+                        address += bits_length(bits);
+                    }
+                }
+            }
+            pc += disassembled.length();
+            instructions.push(disassembled);
+        }
+    }
+    Ok(instructions)
+}
+
+/// Lowercased name of an opcode, as written in assembly.
+pub fn mnemonic(op: InstructionOpcode) -> String {
+    let name = match op {
+        insts::OP_FENCEI => "fence.i",
+        insts::OP_ADDUW => "add.uw",
+        insts::OP_SH1ADDUW => "sh1add.uw",
+        insts::OP_SH2ADDUW => "sh2add.uw",
+        insts::OP_SH3ADDUW => "sh3add.uw",
+        insts::OP_SLLIUW => "slli.uw",
+        insts::OP_ORCB => "orc.b",
+        insts::OP_SEXTB => "sext.b",
+        insts::OP_SEXTH => "sext.h",
+        insts::OP_ZEXTH => "zext.h",
+        _ => instruction_opcode_name(op)
+            .trim_end_matches("_VERSION0")
+            .trim_end_matches("_VERSION1"),
+    };
+    let name = name.to_lowercase();
+    // The A extension separates the operand width with a dot: "amoadd.w".
+    if (insts::OP_LR_W..=insts::OP_AMOMAXU_D).contains(&op) {
+        name.replace('_', ".")
+    } else {
+        name
+    }
+}
+
+fn register(index: usize) -> &'static str {
+    REGISTER_ABI_NAMES[index]
+}
+
+// Formats an absolute address like "0x11138 <check+0xc>".
+fn target(address: u64, symbolizer: Option<&Symbolizer>) -> String {
+    match symbolizer.and_then(|symbolizer| symbolizer.function(address)) {
+        Some(function) if address == function.address => {
+            format!("0x{:x} <{}>", address, function.name)
+        }
+        Some(function) => format!(
+            "0x{:x} <{}+0x{:x}>",
+            address,
+            function.name,
+            address - function.address
+        ),
+        None => format!("0x{:x}", address),
+    }
+}
+
+fn csr_name(csr: u32) -> String {
+    match csr {
+        zicsr::CSR_CYCLE => "cycle".to_string(),
+        zicsr::CSR_TIME => "time".to_string(),
+        zicsr::CSR_INSTRET => "instret".to_string(),
+        zicsr::CSR_CYCLEH => "cycleh".to_string(),
+        zicsr::CSR_TIMEH => "timeh".to_string(),
+        zicsr::CSR_INSTRETH => "instreth".to_string(),
+        _ => format!("0x{:x}", csr),
+    }
+}
+
+// Formats the predecessor or successor set of a fence like "iorw".
+fn fence_set(set: usize) -> String {
+    let set: String = "iorw"
+        .chars()
+        .enumerate()
+        .filter(|(i, _)| set & (0b1000 >> i) != 0)
+        .map(|(_, c)| c)
+        .collect();
+    if set.is_empty() {
+        "0".to_string()
+    } else {
+        set
+    }
+}
+
+/// Formats an instruction at address like "blt a0, zero, 0x11144 <check+0xc>".
+/// Branch and jump targets are printed as absolute addresses, named after
+/// the functions containing them when a symbolizer is given.
+pub fn format_instruction(
+    instruction: Instruction,
+    address: u64,
+    symbolizer: Option<&Symbolizer>,
+) -> String {
+    let op = extract_opcode(instruction);
+    let tagged = match TaggedInstruction::try_from(instruction) {
+        Ok(tagged) => tagged,
+        Err(_) => return format!("<unknown opcode 0x{:x}>", op),
+    };
+    let relative = |offset: i32| target(address.wrapping_add(offset as i64 as u64), symbolizer);
+    let operands = match tagged {
+        TaggedInstruction::Rtype(i) => match op {
+            insts::OP_ECALL
+            | insts::OP_EBREAK
+            | insts::OP_FENCEI
+            | insts::OP_UNLOADED
+            | insts::OP_CUSTOM_TRACE_END => String::new(),
+            // Fence fields are stored as fm in rd, pred in rs1 and succ in
+            // rs2.
+            insts::OP_FENCE if i.rd() == 0b1000 => return "fence.tso".to_string(),
+            insts::OP_FENCE => format!("{}, {}", fence_set(i.rs1()), fence_set(i.rs2())),
+            insts::OP_LR_W | insts::OP_LR_D => {
+                format!("{}, ({})", register(i.rd()), register(i.rs1()))
+            }
+            insts::OP_SC_W..=insts::OP_AMOMAXU_D => format!(
+                "{}, {}, ({})",
+                register(i.rd()),
+                register(i.rs2()),
+                register(i.rs1())
+            ),
+            insts::OP_CLZ
+            | insts::OP_CLZW
+            | insts::OP_CPOP
+            | insts::OP_CPOPW
+            | insts::OP_CTZ
+            | insts::OP_CTZW
+            | insts::OP_ORCB
+            | insts::OP_REV8
+            | insts::OP_SEXTB
+            | insts::OP_SEXTH
+            | insts::OP_ZEXTH => format!("{}, {}", register(i.rd()), register(i.rs1())),
+            _ => format!(
+                "{}, {}, {}",
+                register(i.rd()),
+                register(i.rs1()),
+                register(i.rs2())
+            ),
+        },
+        TaggedInstruction::Itype(i) => match op {
+            insts::OP_LB_VERSION0..=insts::OP_LWU_VERSION1
+            | insts::OP_JALR_VERSION0
+            | insts::OP_JALR_VERSION1 => format!(
+                "{}, {}({})",
+                register(i.rd()),
+                i.immediate_s(),
+                register(i.rs1())
+            ),
+            insts::OP_CSRR => format!("{}, {}", register(i.rd()), csr_name(i.immediate_u())),
+            _ => format!(
+                "{}, {}, {}",
+                register(i.rd()),
+                register(i.rs1()),
+                i.immediate_s()
+            ),
+        },
+        TaggedInstruction::Stype(i) => match op {
+            insts::OP_BEQ..=insts::OP_BNE => format!(
+                "{}, {}, {}",
+                register(i.rs1()),
+                register(i.rs2()),
+                relative(i.immediate_s())
+            ),
+            _ => format!(
+                "{}, {}({})",
+                register(i.rs2()),
+                i.immediate_s(),
+                register(i.rs1())
+            ),
+        },
+        TaggedInstruction::Utype(i) => match op {
+            insts::OP_LUI | insts::OP_AUIPC => {
+                format!("{}, 0x{:x}", register(i.rd()), i.immediate_u() >> 12)
+            }
+            insts::OP_JAL | insts::OP_FAR_JUMP_REL => {
+                format!("{}, {}", register(i.rd()), relative(i.immediate_s()))
+            }
+            insts::OP_FAR_JUMP_ABS => format!(
+                "{}, {}",
+                register(i.rd()),
+                target(i.immediate_s() as i64 as u64 & !1, symbolizer)
+            ),
+            insts::OP_CUSTOM_LOAD_UIMM => {
+                format!("{}, 0x{:x}", register(i.rd()), i.immediate_u())
+            }
+            _ => format!("{}, {}", register(i.rd()), i.immediate_s()),
+        },
+        TaggedInstruction::R4type(i) => format!(
+            "{}, {}, {}, {}",
+            register(i.rd()),
+            register(i.rs1()),
+            register(i.rs2()),
+            register(i.rs3())
+        ),
+        TaggedInstruction::R5type(i) => format!(
+            "{}, {}, {}, {}, {}",
+            register(i.rd()),
+            register(i.rs1()),
+            register(i.rs2()),
+            register(i.rs3()),
+            register(i.rs4())
+        ),
+    };
+    if operands.is_empty() {
+        mnemonic(op)
+    } else {
+        format!("{} {}", mnemonic(op), operands)
+    }
+}
+
+/// Writes the disassembly of a program in the style of objdump, with a
+/// label at the start of every function:
+///
+/// ```text
+/// 0000000000011138 <check>:
+///    11138:  00054663           blt a0, zero, 0x11144 <check+0xc>
+/// ```
+pub fn write_disassembly<R: Register, W: Write>(
+    program: &Bytes,
+    isa: u8,
+    version: u32,
+    writer: &mut W,
+) -> Result<(), Error> {
+    let instructions = disassemble::<R>(program, isa, version)?;
+    // Programs without a symbol table are still disassembled.
+    let symbolizer = Symbolizer::new(program).ok();
+    let functions = symbolizer
+        .as_ref()
+        .map_or(&[][..], |symbolizer| symbolizer.functions());
+    for (i, disassembled) in instructions.iter().enumerate() {
+        let address = disassembled.address;
+        let contiguous =
+            i > 0 && instructions[i - 1].address + instructions[i - 1].length() == address;
+        if !contiguous {
+            writeln!(writer, "\nDisassembly at 0x{:x}:", address)?;
+        }
+        // Functions starting within a fused instruction are not labelled.
+        if let Ok(index) = functions.binary_search_by_key(&address, |f| f.address) {
+            writeln!(writer, "\n{:016x} <{}>:", address, functions[index].name)?;
+        }
+        let bits: Vec<String> = disassembled
+            .bits
+            .iter()
+            .map(|bits| {
+                if bits_length(*bits) == 4 {
+                    format!("{:08x}", bits)
+                } else {
+                    format!("{:04x}", bits)
+                }
+            })
+            .collect();
+        let text = match disassembled.instruction {
+            Some(instruction) => format_instruction(instruction, address, symbolizer.as_ref()),
+            None if bits_length(disassembled.bits[0]) == 4 => {
+                format!(".4byte 0x{:x}", disassembled.bits[0])
+            }
+            None => format!(".2byte 0x{:x}", disassembled.bits[0]),
+        };
+        write!(writer, "{:>8x}:\t{:<18}\t{}", address, bits.join(" "), text)?;
+        if !disassembled.fused.is_empty() {
+            let fused: Vec<String> = disassembled
+                .fused
+                .iter()
+                .map(|i| mnemonic(extract_opcode(*i)))
+                .collect();
+            write!(writer, "\t# fused: {}", fused.join(", "))?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
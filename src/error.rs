@@ -2,10 +2,14 @@
 pub enum Error {
     #[display(fmt = "asm error: {}", "_0")]
     Asm(u8),
+    #[display(fmt = "cost table error: {}", "_0")]
+    CostTableError(String),
     #[display(fmt = "cycles error: max cycles exceeded")]
     CyclesExceeded,
     #[display(fmt = "cycles error: overflow")]
     CyclesOverflow,
+    #[display(fmt = "duplicate syscall {}", "_0")]
+    DuplicateSyscall(u64),
     #[display(fmt = "elf error: bits")]
     ElfBits,
     #[display(fmt = "elf error: {}", "_0")]
@@ -37,22 +41,116 @@ pub enum Error {
         kind: std::io::ErrorKind,
         data: String,
     },
-    #[display(fmt = "memory error: out of bound")]
-    MemOutOfBound,
+    #[display(fmt = "{} at 0x{:x} in {}", "error", "pc", "location")]
+    Located {
+        error: Box<Error>,
+        pc: u64,
+        location: String,
+    },
+    #[display(fmt = "memory error: execute on writable page, {}", "_0")]
+    MemExecuteOnWritablePage(MemoryFault),
+    #[display(fmt = "memory error: out of bound, {}", "_0")]
+    MemOutOfBound(MemoryFault),
     #[display(fmt = "memory error: out of stack")]
     MemOutOfStack,
-    #[display(fmt = "memory error: unaligned page access")]
-    MemPageUnalignedAccess,
-    #[display(fmt = "memory error: write on executable page")]
-    MemWriteOnExecutablePage,
-    #[display(fmt = "memory error: write on freezed page")]
-    MemWriteOnFreezedPage,
+    #[display(fmt = "memory error: unaligned page access, {}", "_0")]
+    MemPageUnalignedAccess(MemoryFault),
+    #[display(fmt = "memory error: write on executable page, {}", "_0")]
+    MemWriteOnExecutablePage(MemoryFault),
+    #[display(fmt = "memory error: write on freezed page, {}", "_0")]
+    MemWriteOnFreezedPage(MemoryFault),
+    #[display(fmt = "pause")]
+    Pause,
+    #[display(fmt = "snapshot data load error")]
+    SnapshotDataLoadError,
+    #[display(fmt = "snapshot decode error: {}", "_0")]
+    SnapshotDecodeError(String),
+    #[display(fmt = "spawn error: deadlock")]
+    SpawnDeadlock,
+    #[display(fmt = "trace decode error: {}", "_0")]
+    TraceDecodeError(String),
     #[display(fmt = "unexpected error")]
     Unexpected(String),
     #[display(fmt = "unimplemented")]
     Unimplemented,
 }
 
+/// How memory is accessed.
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Display)]
+pub enum AccessKind {
+    #[display(fmt = "read")]
+    Read,
+    #[display(fmt = "write")]
+    Write,
+    /// Instruction fetches.
+    #[display(fmt = "execute")]
+    Execute,
+}
+
+/// A failed memory access. Memories report the accessed bytes, the pc of the
+/// instruction doing the access is filled by machines, it is left 0 for
+/// accesses not done by an instruction, like loading a program.
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Display)]
+#[display(
+    fmt = "{} of {} bytes at 0x{:x}, pc=0x{:x}",
+    "kind",
+    "size",
+    "address",
+    "pc"
+)]
+pub struct MemoryFault {
+    pub pc: u64,
+    pub address: u64,
+    pub size: u64,
+    pub kind: AccessKind,
+}
+
+impl MemoryFault {
+    pub fn new(address: u64, size: u64, kind: AccessKind) -> Self {
+        Self {
+            pc: 0,
+            address,
+            size,
+            kind,
+        }
+    }
+}
+
+impl Error {
+    /// The fault of a memory error.
+    pub fn memory_fault(&self) -> Option<&MemoryFault> {
+        match self.unlocated() {
+            Error::MemExecuteOnWritablePage(fault)
+            | Error::MemOutOfBound(fault)
+            | Error::MemPageUnalignedAccess(fault)
+            | Error::MemWriteOnExecutablePage(fault)
+            | Error::MemWriteOnFreezedPage(fault) => Some(fault),
+            _ => None,
+        }
+    }
+
+    /// Sets the pc of the instruction failing with a memory error.
+    pub fn with_pc(mut self, pc: u64) -> Self {
+        match &mut self {
+            Error::MemExecuteOnWritablePage(fault)
+            | Error::MemOutOfBound(fault)
+            | Error::MemPageUnalignedAccess(fault)
+            | Error::MemWriteOnExecutablePage(fault)
+            | Error::MemWriteOnFreezedPage(fault) => fault.pc = pc,
+            _ => (),
+        }
+        self
+    }
+
+    /// The error without the location added by a symbolizer.
+    pub fn unlocated(&self) -> &Error {
+        match self {
+            Error::Located { error, .. } => error.unlocated(),
+            error => error,
+        }
+    }
+}
+
 impl std::error::Error for Error {}
 
 impl From<std::io::Error> for Error {
@@ -0,0 +1,43 @@
+use crate::instructions::Instruction;
+use crate::{machine::SupportMachine, Error};
+
+/// What a machine does with an instruction, as told by a hook.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HookAction {
+    Continue,
+    /// Vetoes the instruction: it's not executed nor charged, and pc moves
+    /// to the next instruction. Only honored before executing it.
+    Skip,
+    /// Stops the machine with Error::Pause, run_with_budget returns
+    /// RunOutcome::Hook with the pc it stops at. A machine stopped before
+    /// an instruction asks hooks about it again when it's run again.
+    Stop,
+}
+
+// Hooks are registered with DefaultMachineBuilder::hook, and called around
+// each instruction executed by DefaultMachine::step, after the instruction
+// is decoded. Hooks are called in the order they are registered, the first
+// one not returning Continue decides what happens. A TraceMachine with hooks
+// executes instructions one by one like DefaultMachine, AsmMachine doesn't
+// call hooks. Hooks are only available with the hooks feature, machines built
+// without it don't check for them at all.
+pub trait Hook<Mac: SupportMachine>: Send + Sync {
+    /// Called before executing an instruction, pc points to it and its
+    /// cycles are not added yet.
+    fn before(
+        &mut self,
+        _machine: &mut Mac,
+        _instruction: Instruction,
+    ) -> Result<HookAction, Error> {
+        Ok(HookAction::Continue)
+    }
+
+    /// Called after executing an instruction, pc points to the next one.
+    fn after(
+        &mut self,
+        _machine: &mut Mac,
+        _instruction: Instruction,
+    ) -> Result<HookAction, Error> {
+        Ok(HookAction::Continue)
+    }
+}
@@ -1,3 +1,4 @@
+use super::super::error::{AccessKind, MemoryFault};
 use super::super::machine::Machine;
 use super::super::memory::Memory;
 use super::super::RISCV_MAX_MEMORY;
@@ -84,9 +85,13 @@ pub fn addiw<Mac: Machine>(
 fn check_load_boundary<R: Register>(version0: bool, address: &R, bytes: u64) -> Result<(), Error> {
     if version0 {
         let address = address.to_u64();
-        let end = address.checked_add(bytes).ok_or(Error::MemOutOfBound)?;
-        if end == RISCV_MAX_MEMORY as u64 {
-            return Err(Error::MemOutOfBound);
+        let end = address.checked_add(bytes);
+        if end.is_none() || end == Some(RISCV_MAX_MEMORY as u64) {
+            return Err(Error::MemOutOfBound(MemoryFault::new(
+                address,
+                bytes,
+                AccessKind::Read,
+            )));
         }
     }
     Ok(())
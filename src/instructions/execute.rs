@@ -2,7 +2,7 @@ use super::{
     super::{machine::Machine, Error},
     common, extract_opcode, instruction_length,
     utils::update_register,
-    Instruction, Itype, R4type, R5type, Register, Rtype, Stype, Utype,
+    zicsr, Instruction, Itype, R4type, R5type, Register, Rtype, Stype, Utype,
 };
 use crate::memory::Memory;
 use ckb_vm_definitions::{instructions as insts, registers::RA};
@@ -1171,6 +1171,19 @@ pub fn execute_instruction<Mac: Machine>(
                 update_register(machine, i.rs3(), r);
             }
         }
+        insts::OP_CSRR => {
+            let i = Itype(inst);
+            let value = match i.immediate_u() {
+                zicsr::CSR_CYCLE => machine.cycle_counter(),
+                zicsr::CSR_TIME => machine.cycle_counter() >> zicsr::TIME_SHIFTS,
+                zicsr::CSR_INSTRET => machine.instret_counter(),
+                zicsr::CSR_CYCLEH => machine.cycle_counter() >> 32,
+                zicsr::CSR_TIMEH => machine.cycle_counter() >> zicsr::TIME_SHIFTS >> 32,
+                zicsr::CSR_INSTRETH => machine.instret_counter() >> 32,
+                _ => return Err(Error::InvalidOp(op)),
+            };
+            update_register(machine, i.rd(), Mac::REG::from_u64(value));
+        }
         insts::OP_CUSTOM_LOAD_UIMM => {
             let i = Utype(inst);
             update_register(machine, i.rd(), Mac::REG::from_u32(i.immediate_u()));
@@ -10,6 +10,7 @@ pub mod i;
 pub mod m;
 pub mod rvc;
 pub mod tagged;
+pub mod zicsr;
 
 pub use self::register::Register;
 use super::Error;
@@ -426,6 +427,7 @@ pub fn is_basic_block_end_instruction(i: Instruction) -> bool {
             | insts::OP_JAL
             | insts::OP_FAR_JUMP_ABS
             | insts::OP_FAR_JUMP_REL
+            | insts::OP_CSRR
     ) | is_slowpath_instruction(i)
 }
 
@@ -242,6 +242,7 @@ impl TryFrom<Instruction> for TaggedInstruction {
             insts::OP_ADD3A => R5type(i).into(),
             insts::OP_ADD3B => R5type(i).into(),
             insts::OP_ADD3C => R5type(i).into(),
+            insts::OP_CSRR => Itype(i).into(),
             insts::OP_CUSTOM_LOAD_UIMM => Utype(i).into(),
             insts::OP_CUSTOM_LOAD_IMM => Utype(i).into(),
             _ => return Err(Error::InvalidOp(op)),
@@ -257,7 +258,7 @@ mod tests {
 
     #[test]
     fn test_all_valid_opcodes_convert_to_tagged_instruction() {
-        for i in insts::OP_UNLOADED..=insts::OP_CUSTOM_TRACE_END {
+        for i in insts::OP_UNLOADED..=insts::MAXIMUM_OPCODE {
             let inst = blank_instruction(i);
             let result = TaggedInstruction::try_from(inst);
             assert!(
@@ -0,0 +1,41 @@
+use ckb_vm_definitions::instructions as insts;
+pub use ckb_vm_definitions::{
+    CSR_CYCLE, CSR_CYCLEH, CSR_INSTRET, CSR_INSTRETH, CSR_TIME, CSR_TIMEH, TIME_SHIFTS,
+};
+
+use super::utils::{funct3, opcode, rd, rs1, x};
+use super::{set_instruction_length_4, Instruction, Itype, Register};
+
+// Only reads of the Zicntr counters are supported, all of them are decoded
+// into OP_CSRR with the CSR number as the immediate. CSR instructions writing
+// a CSR, or accessing any other CSR, are illegal.
+pub fn factory<R: Register>(instruction_bits: u32, _: u32) -> Option<Instruction> {
+    let bit_length = R::BITS;
+    if bit_length != 32 && bit_length != 64 {
+        return None;
+    }
+    if opcode(instruction_bits) != 0b_1110011 {
+        return None;
+    }
+    // csrrs and csrrc only read the CSR when rs1 is zero, csrrsi and csrrci
+    // when the immediate is zero.
+    let read_only = match funct3(instruction_bits) {
+        0b_010 | 0b_011 | 0b_110 | 0b_111 => rs1(instruction_bits) == 0,
+        _ => false,
+    };
+    if !read_only {
+        return None;
+    }
+    let csr = x(instruction_bits, 20, 12, 0);
+    let valid = match csr {
+        CSR_CYCLE | CSR_TIME | CSR_INSTRET => true,
+        CSR_CYCLEH | CSR_TIMEH | CSR_INSTRETH => bit_length == 32,
+        _ => false,
+    };
+    if !valid {
+        return None;
+    }
+    Some(set_instruction_length_4(
+        Itype::new_u(insts::OP_CSRR, rd(instruction_bits), 0, csr).0,
+    ))
+}
@@ -3,34 +3,49 @@ extern crate derive_more;
 
 pub mod bits;
 pub mod cost_model;
+pub mod coverage;
 pub mod debugger;
 pub mod decoder;
+pub mod disasm;
 pub mod error;
+#[cfg(feature = "hooks")]
+pub mod hook;
 pub mod instructions;
 pub mod machine;
 pub mod memory;
+#[cfg(feature = "pprof")]
+pub mod pprof;
 pub mod snapshot;
+pub mod snapshot2;
+pub mod spawn;
+pub mod symbolizer;
 pub mod syscalls;
+pub mod time_travel;
+pub mod tracer;
 
 pub use bytes;
 pub use ckb_vm_definitions;
 
+#[cfg(feature = "hooks")]
+pub use crate::hook::{Hook, HookAction};
 pub use crate::{
     debugger::Debugger,
     instructions::{Instruction, Register},
     machine::{
         trace::TraceMachine, CoreMachine, DefaultCoreMachine, DefaultMachine,
-        DefaultMachineBuilder, InstructionCycleFunc, Machine, SupportMachine,
+        DefaultMachineBuilder, InstructionCycleFunc, Machine, Pause, RunOutcome, SupportMachine,
+    },
+    memory::{
+        flat::FlatMemory, sparse::SparseMemory, tracing::TracingMemory, wxorx::WXorXMemory, Memory,
     },
-    memory::{flat::FlatMemory, sparse::SparseMemory, wxorx::WXorXMemory, Memory},
     syscalls::Syscalls,
 };
 pub use bytes::Bytes;
 
 pub use ckb_vm_definitions::{
-    registers, DEFAULT_STACK_SIZE, ISA_A, ISA_B, ISA_IMC, ISA_MOP, MEMORY_FRAMES, MEMORY_FRAMESIZE,
-    MEMORY_FRAME_SHIFTS, RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY, RISCV_PAGES,
-    RISCV_PAGESIZE, RISCV_PAGE_SHIFTS,
+    registers, DEFAULT_STACK_SIZE, ISA_A, ISA_B, ISA_IMC, ISA_MOP, ISA_ZICSR, MEMORY_FRAMES,
+    MEMORY_FRAMESIZE, MEMORY_FRAME_SHIFTS, RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY,
+    RISCV_PAGES, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS,
 };
 
 pub use error::Error;
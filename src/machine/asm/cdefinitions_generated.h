@@ -19,6 +19,11 @@
 #define CKB_VM_ASM_RET_OUT_OF_BOUND 7
 #define CKB_VM_ASM_RET_INVALID_PERMISSION 8
 #define CKB_VM_ASM_RET_SLOWPATH 9
+#define CKB_VM_ASM_RET_PAUSE 10
+#define CKB_VM_ASM_RET_TOUCHED_FRAME 11
+
+#define CKB_VM_ASM_FAULT_KIND_READ 1
+#define CKB_VM_ASM_FAULT_KIND_WRITE 2
 
 #define CKB_VM_ASM_REGISTER_RA 1
 #define CKB_VM_ASM_REGISTER_SP 2
@@ -29,9 +34,15 @@
 #define CKB_VM_ASM_MEMORY_FLAG_WRITABLE 0
 #define CKB_VM_ASM_MEMORY_FLAG_DIRTY 4
 
+#define CKB_VM_ASM_CSR_CYCLE 3072
+#define CKB_VM_ASM_CSR_TIME 3073
+#define CKB_VM_ASM_CSR_INSTRET 3074
+#define CKB_VM_ASM_TIME_SHIFTS 10
+
 #define CKB_VM_ASM_TRACE_STRUCT_SIZE 296
 #define CKB_VM_ASM_TRACE_OFFSET_ADDRESS 0
 #define CKB_VM_ASM_TRACE_OFFSET_LENGTH 8
+#define CKB_VM_ASM_TRACE_OFFSET_INSTRUCTION_COUNT 9
 #define CKB_VM_ASM_TRACE_OFFSET_CYCLES 16
 #define CKB_VM_ASM_TRACE_OFFSET_INSTRUCTIONS 24
 #define CKB_VM_ASM_TRACE_OFFSET_THREAD 160
@@ -48,13 +59,18 @@
 #define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FLAGS_SIZE 336
 #define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_LAST_READ_FRAME 344
 #define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_LAST_WRITE_PAGE 352
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FLAGS 360
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_MEMORY 2426232
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_TRACES 1400
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FRAMES 1384
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_PAUSE 360
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FRAME_CYCLES 368
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FAULT_INST_ARGS 384
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FAULT_KIND 392
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_INSTRET 400
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FLAGS 408
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_MEMORY 2426280
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_TRACES 1448
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_FRAMES 1432
 
 #define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_MEMORY_H 2424832
-#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_MEMORY_L 1400
+#define CKB_VM_ASM_ASM_CORE_MACHINE_OFFSET_MEMORY_L 1448
 
 #define CKB_VM_ASM_OP_UNLOADED 16
 #define CKB_VM_ASM_OP_ADD 17
@@ -212,6 +228,7 @@
 #define CKB_VM_ASM_OP_ADD3C 169
 #define CKB_VM_ASM_OP_CUSTOM_LOAD_UIMM 170
 #define CKB_VM_ASM_OP_CUSTOM_LOAD_IMM 171
+#define CKB_VM_ASM_OP_CUSTOM_TRACE_END 172
 
 #ifdef CKB_VM_ASM_GENERATE_LABEL_TABLES
 #ifdef __APPLE__
@@ -395,4 +412,5 @@ ckb_vm_asm_labels:
 	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM_LOAD_UIMM - .CKB_VM_ASM_LABEL_TABLE
 	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM_LOAD_IMM - .CKB_VM_ASM_LABEL_TABLE
 	.long	.CKB_VM_ASM_LABEL_OP_CUSTOM_TRACE_END - .CKB_VM_ASM_LABEL_TABLE
+	.long	.CKB_VM_ASM_LABEL_OP_CSRR - .CKB_VM_ASM_LABEL_TABLE
 #endif /* CKB_VM_ASM_GENERATE_LABEL_TABLES */
@@ -3,8 +3,9 @@ use bytes::Bytes;
 pub use ckb_vm_definitions::asm::AsmCoreMachine;
 use ckb_vm_definitions::{
     asm::{
-        calculate_slot, Trace, RET_CYCLES_OVERFLOW, RET_DECODE_TRACE, RET_DYNAMIC_JUMP, RET_EBREAK,
-        RET_ECALL, RET_INVALID_PERMISSION, RET_MAX_CYCLES_EXCEEDED, RET_OUT_OF_BOUND, RET_SLOWPATH,
+        calculate_slot, PauseFlag, Trace, FAULT_KIND_READ, FAULT_KIND_WRITE, RET_CYCLES_OVERFLOW,
+        RET_DECODE_TRACE, RET_DYNAMIC_JUMP, RET_EBREAK, RET_ECALL, RET_INVALID_PERMISSION,
+        RET_MAX_CYCLES_EXCEEDED, RET_OUT_OF_BOUND, RET_PAUSE, RET_SLOWPATH, RET_TOUCHED_FRAME,
         TRACE_ITEM_LENGTH, TRACE_SIZE,
     },
     instructions::OP_CUSTOM_TRACE_END,
@@ -12,18 +13,23 @@ use ckb_vm_definitions::{
     RISCV_PAGE_SHIFTS,
 };
 use rand::{prelude::RngCore, SeedableRng};
+use std::cmp::min;
 use std::os::raw::c_uchar;
 
 use crate::{
+    coverage::Coverage,
+    debugger::{accesses_memory, memory_access},
     decoder::{build_decoder, Decoder},
+    error::{AccessKind, MemoryFault},
     instructions::{
-        blank_instruction, execute_instruction, extract_opcode, instruction_length,
-        is_basic_block_end_instruction,
+        blank_instruction, execute, execute_instruction, extract_opcode, instruction_length,
+        is_basic_block_end_instruction, Instruction,
     },
-    machine::VERSION0,
+    machine::{budget_progress, enter_budget, leave_budget, RunOutcome, VERSION0},
     memory::{
-        fill_page_data, get_page_indices, memset, round_page_down, round_page_up, FLAG_DIRTY,
-        FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_WRITABLE, FLAG_WXORX_BIT,
+        check_bounds, fill_page_data, get_page_indices, memset, page_out_of_bound,
+        permission_error, round_page_down, round_page_up, FLAG_DIRTY, FLAG_EXECUTABLE,
+        FLAG_FREEZED, FLAG_WRITABLE, FLAG_WXORX_BIT,
     },
     CoreMachine, DefaultMachine, Error, Machine, Memory, SupportMachine, MEMORY_FRAME_SHIFTS,
     RISCV_MAX_MEMORY, RISCV_PAGES, RISCV_PAGESIZE,
@@ -78,6 +84,15 @@ impl CoreMachine for Box<AsmCoreMachine> {
 // in this way.
 #[no_mangle]
 pub extern "C" fn inited_memory(frame_index: u64, machine: &mut AsmCoreMachine) {
+    // Charged by SupportMachine::charge_frames once the instruction touching
+    // the frame is executed, see RET_TOUCHED_FRAME.
+    if machine.frame_cycles != 0 {
+        machine.touched_frames += 1;
+    }
+    init_frame(frame_index, machine);
+}
+
+fn init_frame(frame_index: u64, machine: &mut AsmCoreMachine) {
     let addr_from = (frame_index << MEMORY_FRAME_SHIFTS) as usize;
     let addr_to = ((frame_index + 1) << MEMORY_FRAME_SHIFTS) as usize;
     if machine.chaos_mode != 0 {
@@ -97,10 +112,16 @@ fn check_memory(machine: &mut AsmCoreMachine, page: u64) {
     }
 }
 
-fn check_permission<M: Memory>(memory: &mut M, page: u64, flag: u8) -> Result<(), Error> {
+fn check_permission<M: Memory>(
+    memory: &mut M,
+    page: u64,
+    addr: u64,
+    size: u64,
+    flag: u8,
+) -> Result<(), Error> {
     let page_flag = memory.fetch_flag(page)?;
     if (page_flag & FLAG_WXORX_BIT) != (flag & FLAG_WXORX_BIT) {
-        return Err(Error::MemWriteOnExecutablePage);
+        return Err(permission_error(addr, size, flag));
     }
     Ok(())
 }
@@ -112,11 +133,12 @@ fn check_memory_writable(
     size: usize,
 ) -> Result<(), Error> {
     debug_assert!(size == 1 || size == 2 || size == 4 || size == 8);
+    let fault = MemoryFault::new(addr, size as u64, AccessKind::Write);
     let page = addr >> RISCV_PAGE_SHIFTS;
     if page as usize >= RISCV_PAGES {
-        return Err(Error::MemOutOfBound);
+        return Err(Error::MemOutOfBound(fault));
     }
-    check_permission(machine, page, FLAG_WRITABLE)?;
+    check_permission(machine, page, addr, size as u64, FLAG_WRITABLE)?;
     check_memory(machine, page);
     machine.set_flag(page, FLAG_DIRTY)?;
 
@@ -125,9 +147,9 @@ fn check_memory_writable(
     if page_offset + size > RISCV_PAGESIZE {
         let page = page + 1;
         if page as usize >= RISCV_PAGES {
-            return Err(Error::MemOutOfBound);
+            return Err(Error::MemOutOfBound(fault));
         } else {
-            check_permission(machine, page, FLAG_WRITABLE)?;
+            check_permission(machine, page, addr, size as u64, FLAG_WRITABLE)?;
             check_memory(machine, page);
             machine.set_flag(page, FLAG_DIRTY)?
         }
@@ -143,11 +165,12 @@ fn check_memory_executable(
 ) -> Result<(), Error> {
     debug_assert!(size == 2 || size == 4);
 
+    let fault = MemoryFault::new(addr, size as u64, AccessKind::Execute);
     let page = addr >> RISCV_PAGE_SHIFTS;
     if page as usize >= RISCV_PAGES {
-        return Err(Error::MemOutOfBound);
+        return Err(Error::MemOutOfBound(fault));
     }
-    check_permission(machine, page, FLAG_EXECUTABLE)?;
+    check_permission(machine, page, addr, size as u64, FLAG_EXECUTABLE)?;
     check_memory(machine, page);
 
     // check next page if neccessary
@@ -155,9 +178,9 @@ fn check_memory_executable(
     if page_offset + size > RISCV_PAGESIZE {
         let page = page + 1;
         if page as usize >= RISCV_PAGES {
-            return Err(Error::MemOutOfBound);
+            return Err(Error::MemOutOfBound(fault));
         } else {
-            check_permission(machine, page, FLAG_EXECUTABLE)?;
+            check_permission(machine, page, addr, size as u64, FLAG_EXECUTABLE)?;
             check_memory(machine, page);
         }
     }
@@ -171,9 +194,10 @@ fn check_memory_inited(
     size: usize,
 ) -> Result<(), Error> {
     debug_assert!(size == 1 || size == 2 || size == 4 || size == 8);
+    let fault = MemoryFault::new(addr, size as u64, AccessKind::Read);
     let page = addr >> RISCV_PAGE_SHIFTS;
     if page as usize >= RISCV_PAGES {
-        return Err(Error::MemOutOfBound);
+        return Err(Error::MemOutOfBound(fault));
     }
     check_memory(machine, page);
 
@@ -182,7 +206,7 @@ fn check_memory_inited(
     if page_offset + size > RISCV_PAGESIZE {
         let page = page + 1;
         if page as usize >= RISCV_PAGES {
-            return Err(Error::MemOutOfBound);
+            return Err(Error::MemOutOfBound(fault));
         } else {
             check_memory(machine, page);
         }
@@ -209,15 +233,16 @@ impl Memory for Box<AsmCoreMachine> {
         source: Option<Bytes>,
         offset_from_addr: u64,
     ) -> Result<(), Error> {
+        let fault = MemoryFault::new(addr, size, AccessKind::Write);
         if round_page_down(addr) != addr || round_page_up(size) != size {
-            return Err(Error::MemPageUnalignedAccess);
+            return Err(Error::MemPageUnalignedAccess(fault));
         }
         if addr > RISCV_MAX_MEMORY as u64
             || size > RISCV_MAX_MEMORY as u64
             || addr + size > RISCV_MAX_MEMORY as u64
             || offset_from_addr > size
         {
-            return Err(Error::MemOutOfBound);
+            return Err(Error::MemOutOfBound(fault));
         }
         // We benchmarked the code piece here, using while loop this way is
         // actually faster than a for..in solution. The difference is roughly
@@ -226,7 +251,7 @@ impl Memory for Box<AsmCoreMachine> {
         while current_addr < addr + size {
             let page = current_addr / RISCV_PAGESIZE as u64;
             if self.fetch_flag(page)? & FLAG_FREEZED != 0 {
-                return Err(Error::MemWriteOnFreezedPage);
+                return Err(Error::MemWriteOnFreezedPage(fault));
             }
             current_addr += RISCV_PAGESIZE as u64;
         }
@@ -247,7 +272,7 @@ impl Memory for Box<AsmCoreMachine> {
         if page < RISCV_PAGES as u64 {
             Ok(self.flags[page as usize])
         } else {
-            Err(Error::MemOutOfBound)
+            Err(page_out_of_bound(page, AccessKind::Read))
         }
     }
 
@@ -258,7 +283,7 @@ impl Memory for Box<AsmCoreMachine> {
             self.last_write_page = u64::max_value();
             Ok(())
         } else {
-            Err(Error::MemOutOfBound)
+            Err(page_out_of_bound(page, AccessKind::Write))
         }
     }
 
@@ -269,7 +294,7 @@ impl Memory for Box<AsmCoreMachine> {
             self.last_write_page = u64::max_value();
             Ok(())
         } else {
-            Err(Error::MemOutOfBound)
+            Err(page_out_of_bound(page, AccessKind::Write))
         }
     }
 
@@ -281,9 +306,10 @@ impl Memory for Box<AsmCoreMachine> {
         if value.is_empty() {
             return Ok(());
         }
-        let page_indices = get_page_indices(addr, value.len() as u64)?;
+        let size = value.len() as u64;
+        let page_indices = get_page_indices(addr, size, AccessKind::Write)?;
         for page in page_indices.0..=page_indices.1 {
-            check_permission(self, page, FLAG_WRITABLE)?;
+            check_permission(self, page, addr, size, FLAG_WRITABLE)?;
             check_memory(self, page);
             self.set_flag(page, FLAG_DIRTY)?;
         }
@@ -296,9 +322,9 @@ impl Memory for Box<AsmCoreMachine> {
         if size == 0 {
             return Ok(());
         }
-        let page_indices = get_page_indices(addr, size)?;
+        let page_indices = get_page_indices(addr, size, AccessKind::Write)?;
         for page in page_indices.0..=page_indices.1 {
-            check_permission(self, page, FLAG_WRITABLE)?;
+            check_permission(self, page, addr, size, FLAG_WRITABLE)?;
             check_memory(self, page);
             self.set_flag(page, FLAG_DIRTY)?;
         }
@@ -313,7 +339,7 @@ impl Memory for Box<AsmCoreMachine> {
         if size == 0 {
             return Ok(Bytes::new());
         }
-        let page_indices = get_page_indices(addr, size)?;
+        let page_indices = get_page_indices(addr, size, AccessKind::Read)?;
         for page in page_indices.0..=page_indices.1 {
             check_memory(self, page);
         }
@@ -322,6 +348,28 @@ impl Memory for Box<AsmCoreMachine> {
         ))
     }
 
+    // Frames not initialized yet read as zeros.
+    fn peek_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        if size == 0 {
+            return Ok(Bytes::new());
+        }
+        check_bounds(self, addr, size, AccessKind::Read)?;
+        let mut bytes = self.memory[addr as usize..(addr + size) as usize].to_vec();
+        let mut start = addr;
+        while start < addr + size {
+            let frame = start >> MEMORY_FRAME_SHIFTS;
+            let frame_end = min((frame + 1) << MEMORY_FRAME_SHIFTS, addr + size);
+            if self.frames[frame as usize] == 0 {
+                memset(
+                    &mut bytes[(start - addr) as usize..(frame_end - addr) as usize],
+                    0,
+                );
+            }
+            start = frame_end;
+        }
+        Ok(Bytes::from(bytes))
+    }
+
     fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
         check_memory_executable(self, addr, 2)?;
         Ok(LittleEndian::read_u16(
@@ -407,6 +455,21 @@ impl Memory for Box<AsmCoreMachine> {
     fn set_lr(&mut self, value: &Self::REG) {
         self.load_reservation_address = *value;
     }
+
+    fn touched_frame_indices(&self) -> Vec<u64> {
+        (0..self.frames_size)
+            .filter(|frame| self.frames[*frame as usize] != 0)
+            .collect()
+    }
+
+    fn restore_touched_frames(&mut self, indices: &[u64]) {
+        for frame in indices {
+            if *frame < self.frames_size && self.frames[*frame as usize] == 0 {
+                init_frame(*frame, self);
+                self.frames[*frame as usize] = 1;
+            }
+        }
+    }
 }
 
 impl SupportMachine for Box<AsmCoreMachine> {
@@ -422,6 +485,30 @@ impl SupportMachine for Box<AsmCoreMachine> {
         self.max_cycles
     }
 
+    fn set_max_cycles(&mut self, max_cycles: u64) {
+        self.max_cycles = max_cycles;
+    }
+
+    fn instret(&self) -> u64 {
+        self.instret
+    }
+
+    fn set_instret(&mut self, instret: u64) {
+        self.instret = instret;
+    }
+
+    fn frame_cycles(&self) -> u64 {
+        self.frame_cycles
+    }
+
+    fn set_frame_cycles(&mut self, cycles: u64) {
+        self.frame_cycles = cycles;
+    }
+
+    fn take_touched_frames(&mut self) -> u64 {
+        std::mem::take(&mut self.touched_frames)
+    }
+
     fn reset(&mut self, max_cycles: u64) {
         self.registers = [0; RISCV_GENERAL_REGISTER_NUMBER];
         self.pc = 0;
@@ -430,8 +517,10 @@ impl SupportMachine for Box<AsmCoreMachine> {
             self.traces[i] = Trace::default();
         }
         self.frames = [0; MEMORY_FRAMES];
+        self.touched_frames = 0;
         self.cycles = 0;
         self.max_cycles = max_cycles;
+        self.instret = 0;
         self.reset_signal = 1;
         self.load_reservation_address = u64::MAX;
     }
@@ -465,11 +554,23 @@ extern "C" {
 
 pub struct AsmMachine {
     pub machine: DefaultMachine<Box<AsmCoreMachine>>,
+    coverage: Option<Coverage>,
 }
 
 impl AsmMachine {
     pub fn new(machine: DefaultMachine<Box<AsmCoreMachine>>) -> Self {
-        Self { machine }
+        Self {
+            machine,
+            coverage: None,
+        }
+    }
+
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
     }
 
     pub fn set_max_cycles(&mut self, cycles: u64) {
@@ -484,9 +585,23 @@ impl AsmMachine {
         if self.machine.isa() & ISA_MOP != 0 && self.machine.version() == VERSION0 {
             return Err(Error::InvalidVersion);
         }
+        // Extra cycles are charged per instruction, see CostModel.
+        if self.machine.cost_model().context_aware() {
+            return self.run_by_steps();
+        }
+        if self.coverage.is_some() {
+            return self.run_with_coverage();
+        }
         let mut decoder = build_decoder::<u64>(self.machine.isa(), self.machine.version());
+        let mut resume = self.machine.take_stop();
+        let debugging = !self.machine.breakpoints.is_empty();
+        if debugging {
+            // Traces built before might run across breakpoints.
+            self.clear_traces();
+        }
+        self.machine.inner.pause = PauseFlag::new(self.machine.pause.flag());
         self.machine.set_running(true);
-        while self.machine.running() {
+        'run: while self.machine.running() {
             if self.machine.reset_signal() {
                 decoder.reset_instructions_cache();
             }
@@ -494,16 +609,35 @@ impl AsmMachine {
             match result {
                 RET_DECODE_TRACE => {
                     let pc = *self.machine.pc();
+                    if debugging && self.needs_debug_step(&mut decoder, pc)? {
+                        self.machine.debug_step(&mut decoder, &mut resume)?;
+                        continue;
+                    }
                     let slot = calculate_slot(pc);
                     let mut trace = Trace::default();
                     let mut current_pc = pc;
                     let mut i = 0;
                     while i < TRACE_ITEM_LENGTH {
-                        let instruction = decoder.decode(self.machine.memory_mut(), current_pc)?;
+                        if debugging && i > 0 && self.needs_debug_step(&mut decoder, current_pc)? {
+                            break;
+                        }
+                        let instruction =
+                            match decoder.decode(self.machine.memory_mut(), current_pc) {
+                                Ok(instruction) => instruction,
+                                // The trace ends before instructions failing to
+                                // be decoded, they are trapped only when executed.
+                                Err(_) if i > 0 && self.machine.traps_enabled() => break,
+                                Err(e) => {
+                                    self.machine
+                                        .trap(e)
+                                        .map_err(|e| self.machine.locate_error(current_pc, e))?;
+                                    continue 'run;
+                                }
+                            };
                         let end_instruction = is_basic_block_end_instruction(instruction);
                         current_pc += u64::from(instruction_length(instruction));
                         trace.instructions[i] = instruction;
-                        trace.cycles += self.machine.instruction_cycle_func()(instruction);
+                        trace.cycles += self.machine.cost_model().cycles(instruction);
                         let opcode = extract_opcode(instruction);
                         // Here we are calculating the absolute address used in direct threading
                         // from label offsets.
@@ -525,19 +659,32 @@ impl AsmMachine {
                     };
                     trace.address = pc;
                     trace.length = (current_pc - pc) as u8;
+                    trace.instruction_count = i as u8;
                     self.machine.inner_mut().traces[slot] = trace;
                 }
-                RET_ECALL => self.machine.ecall()?,
+                RET_ECALL => {
+                    let pc = *self.machine.pc() - 4;
+                    self.machine
+                        .ecall()
+                        .and_then(|_| self.machine.charge_frames())
+                        .map_err(|e| self.machine.locate_error(pc, e.with_pc(pc)))?
+                }
                 RET_EBREAK => self.machine.ebreak()?,
                 RET_DYNAMIC_JUMP => (),
                 RET_MAX_CYCLES_EXCEEDED => return Err(Error::CyclesExceeded),
                 RET_CYCLES_OVERFLOW => return Err(Error::CyclesOverflow),
-                RET_OUT_OF_BOUND => return Err(Error::MemOutOfBound),
-                RET_INVALID_PERMISSION => return Err(Error::MemWriteOnExecutablePage),
+                RET_OUT_OF_BOUND | RET_INVALID_PERMISSION => self.trap_memory_fault(result)?,
+                RET_TOUCHED_FRAME => self.step_touched_frame()?,
+                RET_PAUSE => {
+                    self.machine.pause.free();
+                    return Err(Error::Pause);
+                }
                 RET_SLOWPATH => {
                     let pc = *self.machine.pc() - 4;
                     let instruction = decoder.decode(self.machine.memory_mut(), pc)?;
-                    execute_instruction(instruction, &mut self.machine)?;
+                    execute_instruction(instruction, &mut self.machine)
+                        .or_else(|e| self.trap_slowpath(pc, e))?;
+                    self.machine.charge_frames()?;
                 }
                 _ => return Err(Error::Asm(result)),
             }
@@ -545,6 +692,91 @@ impl AsmMachine {
         Ok(self.machine.exit_code())
     }
 
+    // Executes basic blocks one by one with step_trace, so each of them can
+    // be recorded. Breakpoints are not honored.
+    fn run_with_coverage(&mut self) -> Result<i8, Error> {
+        let mut decoder = build_decoder::<u64>(self.machine.isa(), self.machine.version());
+        self.clear_traces();
+        self.machine.set_running(true);
+        while self.machine.running() {
+            if self.machine.reset_signal() {
+                decoder.reset_instructions_cache();
+            }
+            let pc = *self.machine.pc();
+            let length: u64 = self
+                .step_trace(&mut decoder)?
+                .into_iter()
+                .map(|instruction| u64::from(instruction_length(instruction)))
+                .sum();
+            if let Some(coverage) = &mut self.coverage {
+                coverage.record(pc, length);
+            }
+        }
+        Ok(self.machine.exit_code())
+    }
+
+    // Executes instructions one by one with step, for cost models looking at
+    // what each instruction does. Breakpoints are not honored.
+    fn run_by_steps(&mut self) -> Result<i8, Error> {
+        let mut decoder = build_decoder::<u64>(self.machine.isa(), self.machine.version());
+        self.clear_traces();
+        self.machine.set_running(true);
+        while self.machine.running() {
+            if self.machine.reset_signal() {
+                decoder.reset_instructions_cache();
+            }
+            let pc = *self.machine.pc();
+            let instruction = match decoder.decode(self.machine.memory_mut(), pc) {
+                Ok(instruction) => instruction,
+                Err(e) => {
+                    self.machine
+                        .trap(e)
+                        .map_err(|e| self.machine.locate_error(pc, e))?;
+                    continue;
+                }
+            };
+            self.step(&mut decoder)?;
+            if let Some(coverage) = &mut self.coverage {
+                coverage.record(pc, u64::from(instruction_length(instruction)));
+            }
+        }
+        Ok(self.machine.exit_code())
+    }
+
+    // See DefaultMachine::run_with_budget, a basic block costing more than
+    // the budget is executed instruction by instruction.
+    pub fn run_with_budget(&mut self, budget: u64) -> Result<RunOutcome, Error> {
+        let start = budget_progress(&self.machine);
+        let max_cycles = enter_budget(&mut self.machine, budget)?;
+        let result = self.run();
+        let stop = self.machine.stop;
+        match leave_budget(&mut self.machine, max_cycles, stop, result)? {
+            RunOutcome::Paused if budget_progress(&self.machine) == start => {
+                let mut decoder = build_decoder::<u64>(self.machine.isa(), self.machine.version());
+                let pc = *self.machine.pc();
+                let result = self
+                    .step(&mut decoder)
+                    .map_err(|e| self.machine.locate_error(pc, e));
+                self.machine.leave_step(result)
+            }
+            outcome => Ok(outcome),
+        }
+    }
+
+    // When debugging, instructions at breakpoints and instructions accessing
+    // memory watched by watchpoints are never put in traces, so the asm
+    // interpreter returns before them and they can be checked one by one.
+    fn needs_debug_step(&mut self, decoder: &mut Decoder, pc: u64) -> Result<bool, Error> {
+        if self.machine.breakpoints.is_breakpoint(pc) {
+            return Ok(true);
+        }
+        if self.machine.breakpoints.has_watchpoints() {
+            let instruction = decoder.decode(self.machine.memory_mut(), pc)?;
+            return Ok(accesses_memory(instruction));
+        }
+        Ok(false)
+    }
+
     pub fn step(&mut self, decoder: &mut Decoder) -> Result<(), Error> {
         // Decode only one instruction into a trace
         let pc = *self.machine.pc();
@@ -553,7 +785,7 @@ impl AsmMachine {
         let instruction = decoder.decode(self.machine.memory_mut(), pc)?;
         let len = instruction_length(instruction) as u8;
         trace.instructions[0] = instruction;
-        trace.cycles += self.machine.instruction_cycle_func()(instruction);
+        trace.cycles += self.machine.cost_model().cycles(instruction);
         let opcode = extract_opcode(instruction);
         trace.thread[0] = unsafe {
             u64::from(*(ckb_vm_asm_labels as *const u32).offset(opcode as isize))
@@ -566,26 +798,335 @@ impl AsmMachine {
         };
         trace.address = pc;
         trace.length = len;
+        trace.instruction_count = 1;
         self.machine.inner_mut().traces[slot] = trace;
+        self.machine.inner.pause = PauseFlag::new(self.machine.pause.flag());
+        let context = self.machine.cost_context(instruction);
 
         let result = unsafe { ckb_vm_x64_execute(&mut (**self.machine.inner_mut())) };
         match result {
             RET_DECODE_TRACE => (),
-            RET_ECALL => self.machine.ecall()?,
+            RET_ECALL => {
+                let pc = *self.machine.pc() - 4;
+                self.machine
+                    .ecall()
+                    .and_then(|_| self.machine.charge_frames())
+                    .map_err(|e| self.machine.locate_error(pc, e.with_pc(pc)))?
+            }
             RET_EBREAK => self.machine.ebreak()?,
             RET_MAX_CYCLES_EXCEEDED => return Err(Error::CyclesExceeded),
-            RET_OUT_OF_BOUND => return Err(Error::MemOutOfBound),
-            RET_INVALID_PERMISSION => return Err(Error::MemWriteOnExecutablePage),
+            // Like the interpreter, no more cycles are charged for trapped
+            // instructions.
+            RET_OUT_OF_BOUND | RET_INVALID_PERMISSION => {
+                let trapped = self.trap_memory_fault(result);
+                self.machine.inner_mut().traces[slot] = Trace::default();
+                return trapped;
+            }
+            RET_TOUCHED_FRAME => {
+                if let Err(e) = self.step_touched_frame() {
+                    self.machine.inner_mut().traces[slot] = Trace::default();
+                    return Err(e);
+                }
+            }
+            RET_PAUSE => {
+                self.machine.pause.free();
+                return Err(Error::Pause);
+            }
             RET_SLOWPATH => {
                 let pc = *self.machine.pc() - 4;
                 let instruction = decoder.decode(self.machine.memory_mut(), pc)?;
-                execute_instruction(instruction, &mut self.machine)?;
+                if let Err(e) = execute_instruction(instruction, &mut self.machine) {
+                    self.machine.inner_mut().traces[slot] = Trace::default();
+                    return self.trap_slowpath(pc, e);
+                }
+                self.machine.charge_frames()?;
             }
             _ => return Err(Error::Asm(result)),
         }
         self.machine.inner_mut().traces[slot] = Trace::default();
+        if let Some(context) = context {
+            self.machine.charge_extra_cycles(instruction, context)?;
+        }
         Ok(())
     }
+
+    // Executes the basic block at pc as a single trace, and returns its
+    // instructions. The trace is not kept, and max cycles are lowered while
+    // executing it, so the asm interpreter returns right after the block even
+    // if the block jumps back to itself. Traces built by run shall be cleared
+    // before, or they might be executed as well. No instruction is returned
+    // when the first one fails to decode and is trapped.
+    pub fn step_trace(&mut self, decoder: &mut Decoder) -> Result<Vec<Instruction>, Error> {
+        let pc = *self.machine.pc();
+        let slot = calculate_slot(pc);
+        let mut trace = Trace::default();
+        let mut instructions = Vec::new();
+        let mut current_pc = pc;
+        while instructions.len() < TRACE_ITEM_LENGTH {
+            let instruction = match decoder.decode(self.machine.memory_mut(), current_pc) {
+                Ok(instruction) => instruction,
+                // See run.
+                Err(_) if !instructions.is_empty() && self.machine.traps_enabled() => break,
+                Err(e) => {
+                    return self
+                        .machine
+                        .trap(e)
+                        .map(|_| instructions)
+                        .map_err(|e| self.machine.locate_error(current_pc, e))
+                }
+            };
+            current_pc += u64::from(instruction_length(instruction));
+            let i = instructions.len();
+            trace.instructions[i] = instruction;
+            trace.cycles += self.machine.cost_model().cycles(instruction);
+            let opcode = extract_opcode(instruction);
+            trace.thread[i] = unsafe {
+                u64::from(*(ckb_vm_asm_labels as *const u32).offset(opcode as u8 as isize))
+                    + (ckb_vm_asm_labels as *const u32 as u64)
+            };
+            instructions.push(instruction);
+            if is_basic_block_end_instruction(instruction) {
+                break;
+            }
+        }
+        let i = instructions.len();
+        trace.instructions[i] = blank_instruction(OP_CUSTOM_TRACE_END);
+        trace.thread[i] = unsafe {
+            u64::from(*(ckb_vm_asm_labels as *const u32).offset(OP_CUSTOM_TRACE_END as isize))
+                + (ckb_vm_asm_labels as *const u32 as u64)
+        };
+        trace.address = pc;
+        trace.length = (current_pc - pc) as u8;
+        trace.instruction_count = i as u8;
+        let max_cycles = self.machine.max_cycles();
+        let limit = self
+            .machine
+            .cycles()
+            .checked_add(trace.cycles)
+            .filter(|limit| *limit < max_cycles);
+        if let Some(limit) = limit {
+            self.machine.inner.max_cycles = limit;
+        }
+        self.machine.inner_mut().traces[slot] = trace;
+        self.machine.inner.pause = PauseFlag::new(self.machine.pause.flag());
+
+        let result = unsafe { ckb_vm_x64_execute(&mut (**self.machine.inner_mut())) };
+        self.machine.inner.max_cycles = max_cycles;
+        // The instruction the asm interpreter stops at is looked up in the
+        // trace, before it is cleared. Only the instructions up to it are
+        // run, so the block is also split at instructions touching frames.
+        let stopped = match result {
+            RET_OUT_OF_BOUND | RET_INVALID_PERMISSION => {
+                let executed = self.fault_location().map_or(0, |(_, index)| index + 1);
+                Some((executed, self.trap_memory_fault(result)))
+            }
+            RET_TOUCHED_FRAME => {
+                let executed = self.fault_location().map_or(0, |(_, index)| index + 1);
+                let stepped = self.step_touched_frame();
+                Some((executed, stepped))
+            }
+            _ => None,
+        };
+        self.machine.inner_mut().traces[slot] = Trace::default();
+        if let Some((executed, stepped)) = stopped {
+            stepped?;
+            instructions.truncate(executed);
+            return Ok(instructions);
+        }
+        match result {
+            RET_DECODE_TRACE | RET_DYNAMIC_JUMP => (),
+            RET_ECALL => {
+                let pc = *self.machine.pc() - 4;
+                self.machine
+                    .ecall()
+                    .and_then(|_| self.machine.charge_frames())
+                    .map_err(|e| self.machine.locate_error(pc, e.with_pc(pc)))?
+            }
+            RET_EBREAK => self.machine.ebreak()?,
+            // The lowered limit is reached when entering the next block, once
+            // all the cycles of this one are charged.
+            RET_MAX_CYCLES_EXCEEDED
+                if limit.map_or(false, |limit| self.machine.cycles() >= limit) => {}
+            RET_MAX_CYCLES_EXCEEDED => return Err(Error::CyclesExceeded),
+            RET_CYCLES_OVERFLOW => return Err(Error::CyclesOverflow),
+            RET_PAUSE => {
+                self.machine.pause.free();
+                return Err(Error::Pause);
+            }
+            RET_SLOWPATH => {
+                let pc = *self.machine.pc() - 4;
+                let instruction = decoder.decode(self.machine.memory_mut(), pc)?;
+                execute_instruction(instruction, &mut self.machine)
+                    .or_else(|e| self.trap_slowpath(pc, e))?;
+                self.machine.charge_frames()?;
+            }
+            _ => return Err(Error::Asm(result)),
+        }
+        Ok(instructions)
+    }
+
+    // Rebuilds the error of a load or store the asm interpreter failed on.
+    // The interpreter only records where it was reading the arguments of the
+    // trace, which is one slot past the failing instruction, so the
+    // instruction and its pc are found in the trace, and the accessed memory
+    // is computed from the registers, which are left untouched by the failing
+    // instruction. The pc of the machine itself is not restored. A fault
+    // which cannot be located is reported as unexpected, rather than with a
+    // made up location.
+    fn memory_fault(&self, result: u8) -> Error {
+        let inner = &self.machine.inner;
+        let located = self.fault_location().and_then(|(slot, index)| {
+            let trace = &inner.traces[slot];
+            let access = memory_access(trace.instructions[index], &inner.registers)?;
+            Some((trace_pc(trace, index), access))
+        });
+        let (pc, access) = match located {
+            Some(located) => located,
+            None => {
+                return Error::Unexpected(format!(
+                    "The memory fault {} of the asm interpreter is not located",
+                    result
+                ))
+            }
+        };
+        // The asm loop records whether the failing check guarded a load or
+        // a store, the decoded instruction only confirms the address.
+        let kind = match inner.fault_kind {
+            FAULT_KIND_READ => AccessKind::Read,
+            FAULT_KIND_WRITE => AccessKind::Write,
+            fault_kind => {
+                return Error::Unexpected(format!(
+                    "Invalid fault kind {} of the asm interpreter",
+                    fault_kind
+                ))
+            }
+        };
+        let mut fault = MemoryFault::new(access.address, access.size, kind);
+        fault.pc = pc;
+        match (result, kind) {
+            (RET_OUT_OF_BOUND, _) => Error::MemOutOfBound(fault),
+            (RET_INVALID_PERMISSION, AccessKind::Write) => Error::MemWriteOnExecutablePage(fault),
+            _ => Error::Unexpected(format!(
+                "The asm interpreter can't fail a {} access with {}",
+                kind, result
+            )),
+        }
+    }
+
+    // The trace slot and the index in it of the instruction the asm
+    // interpreter failed on, see memory_fault.
+    fn fault_location(&self) -> Option<(usize, usize)> {
+        let inner = &self.machine.inner;
+        let traces = inner.traces.as_ptr() as u64;
+        let trace_size = std::mem::size_of::<Trace>() as u64;
+        let slot = (inner.fault_inst_args.wrapping_sub(traces) / trace_size) as usize;
+        let trace = inner.traces.get(slot)?;
+        let instructions = trace.instructions.as_ptr() as u64;
+        let index = (inner.fault_inst_args.wrapping_sub(instructions) / 8).checked_sub(1)?;
+        if index as usize >= TRACE_ITEM_LENGTH {
+            return None;
+        }
+        Some((slot, index as usize))
+    }
+
+    // Delivers a memory fault of the asm interpreter to the trap handler of
+    // the guest. The cycles and instret of a whole trace are added before
+    // running it, those of the instructions after the failing one are given
+    // back, as well as the instret of the failing one, so both are the same
+    // as the interpreter.
+    fn trap_memory_fault(&mut self, result: u8) -> Result<(), Error> {
+        let error = self.memory_fault(result);
+        let location = self.fault_location();
+        let result = if self.machine.traps_enabled() {
+            if let Some((slot, index)) = location {
+                self.rewind_trace(slot, index);
+            }
+            self.machine.trap(error)
+        } else {
+            Err(error)
+        };
+        result.map_err(|e| match location {
+            Some((slot, index)) => {
+                let pc = trace_pc(&self.machine.inner.traces[slot], index);
+                self.machine.locate_error(pc, e)
+            }
+            None => e,
+        })
+    }
+
+    // Gives back the cycles of the instructions after the one at `index` in
+    // a trace, and the instret of it and those after it, as they are all
+    // added when the trace is entered.
+    fn rewind_trace(&mut self, slot: usize, index: usize) {
+        let (refund, unretired) = {
+            let trace = &self.machine.inner.traces[slot];
+            let cost_model = self.machine.cost_model();
+            let refund: u64 = trace.instructions[index + 1..]
+                .iter()
+                .take_while(|i| extract_opcode(**i) != OP_CUSTOM_TRACE_END)
+                .map(|i| cost_model.cycles(*i))
+                .sum();
+            (refund, u64::from(trace.instruction_count) - index as u64)
+        };
+        let cycles = self.machine.cycles() - refund;
+        self.machine.set_cycles(cycles);
+        let instret = self.machine.instret() - unretired;
+        self.machine.set_instret(instret);
+    }
+
+    // Executes the instruction the asm interpreter stops at, after it
+    // initializes a frame while frames are charged, with the Rust
+    // interpreter. The frame is charged right after the instruction like
+    // DefaultMachine::step does, so both stop at the same instruction when
+    // max cycles are exceeded. The cycles of the instruction itself are
+    // within max cycles, as they are checked when the trace is entered.
+    // Errors are delivered to the trap handler of the guest.
+    fn step_touched_frame(&mut self) -> Result<(), Error> {
+        let (slot, index) = self
+            .fault_location()
+            .ok_or_else(|| Error::Unexpected(String::from("The frame is touched out of traces")))?;
+        self.rewind_trace(slot, index);
+        let trace = &self.machine.inner.traces[slot];
+        let (pc, instruction) = (trace_pc(trace, index), trace.instructions[index]);
+        self.machine.update_pc(pc);
+        self.machine.commit_pc();
+        execute(instruction, &mut self.machine)
+            .map_err(|e| e.with_pc(pc))
+            .and_then(|_| {
+                self.machine.set_instret(self.machine.instret() + 1);
+                self.machine.charge_frames()
+            })
+            .or_else(|e| self.machine.trap(e))
+            .map_err(|e| self.machine.locate_error(pc, e))
+    }
+
+    // Delivers an error of the instruction at pc the asm interpreter leaves
+    // to the Rust one to the trap handler of the guest. The instruction has
+    // been counted as retired when its trace was entered.
+    fn trap_slowpath(&mut self, pc: u64, error: Error) -> Result<(), Error> {
+        self.machine
+            .trap(error.with_pc(pc))
+            .map_err(|e| self.machine.locate_error(pc, e))?;
+        let instret = self.machine.instret() - 1;
+        self.machine.set_instret(instret);
+        Ok(())
+    }
+
+    // Clears traces built before, see step_trace.
+    pub fn clear_traces(&mut self) {
+        for trace in self.machine.inner_mut().traces.iter_mut() {
+            *trace = Trace::default();
+        }
+    }
+}
+
+// The pc of the instruction at `index` in a trace.
+fn trace_pc(trace: &Trace, index: usize) -> u64 {
+    trace.instructions[..index]
+        .iter()
+        .fold(trace.address, |pc, i| {
+            pc + u64::from(instruction_length(*i))
+        })
 }
 
 #[cfg(test)]
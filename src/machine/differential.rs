@@ -0,0 +1,693 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use bytes::Bytes;
+
+use super::{
+    super::{
+        debugger::memory_access,
+        decoder::{build_decoder, Decoder},
+        instructions::{tagged::TaggedInstruction, Instruction},
+        memory::{Memory, FLAG_DIRTY},
+        registers::{A7, REGISTER_ABI_NAMES},
+        syscalls::{SyscallRegistry, Syscalls},
+        Error, ISA_MOP, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS,
+    },
+    asm::AsmMachine,
+    CoreMachine, DefaultMachine, SupportMachine, VERSION0,
+};
+use ckb_vm_definitions::asm::TRACE_ITEM_LENGTH;
+
+// DifferentialMachine runs a program on the Rust interpreter and on the asm
+// interpreter side by side, and stops at the first instruction where they
+// disagree. The Rust interpreter is taken as the reference.
+//
+// Both machines execute a chunk of instructions, then pc, registers, cycles,
+// exit codes, and flags and contents of pages written by the chunk are
+// compared. A chunk is either a trace built by the asm interpreter, or a
+// fixed number of instructions executed one by one. When the machines
+// disagree, or any of them fails, both are rewound to the start of the chunk
+// and the chunk is replayed one instruction at a time to find the divergent
+// instruction. Rewinding restores registers and pages the Rust interpreter
+// wrote, without permission checks. Syscalls are not executed again: the
+// registers, cycles and pages each of them leaves are recorded the first time
+// the chunk runs, and applied instead when it's replayed. Pages written by
+// syscalls are not rewound, nor are memory frames touched by the chunk.
+//
+// Pages are only compared in full when the program exits, so memory written
+// by syscalls, or written by the asm interpreter where the Rust interpreter
+// doesn't write, is reported without the instruction writing it.
+//
+// Both machines must be built with the same ISA, version, cycle function,
+// max cycles and syscalls, and breakpoints are not honored. The syscalls are
+// moved into a SyscallLog of each machine when the DifferentialMachine is
+// created.
+
+/// How often the machines are compared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lockstep {
+    /// After each trace, a basic block of at most TRACE_ITEM_LENGTH
+    /// instructions.
+    Traces,
+    /// After every n instructions.
+    Instructions(u64),
+}
+
+/// A state in which the two machines differ.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Difference {
+    Pc {
+        interpreter: u64,
+        asm: u64,
+    },
+    Register {
+        index: usize,
+        interpreter: u64,
+        asm: u64,
+    },
+    Cycles {
+        interpreter: u64,
+        asm: u64,
+    },
+    /// Exit codes, None for machines still running.
+    Exit {
+        interpreter: Option<i8>,
+        asm: Option<i8>,
+    },
+    /// Outcomes of executing the instruction.
+    Result {
+        interpreter: Result<(), Error>,
+        asm: Result<(), Error>,
+    },
+    PageFlag {
+        page: u64,
+        interpreter: u8,
+        asm: u8,
+    },
+    /// The first differing byte in a page, None for pages that can't be read.
+    Memory {
+        address: u64,
+        interpreter: Option<u8>,
+        asm: Option<u8>,
+    },
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Pc { interpreter, asm } => {
+                write!(f, "pc: 0x{:x} != 0x{:x}", interpreter, asm)
+            }
+            Difference::Register {
+                index,
+                interpreter,
+                asm,
+            } => write!(
+                f,
+                "{}: 0x{:x} != 0x{:x}",
+                REGISTER_ABI_NAMES[*index], interpreter, asm
+            ),
+            Difference::Cycles { interpreter, asm } => {
+                write!(f, "cycles: {} != {}", interpreter, asm)
+            }
+            Difference::Exit { interpreter, asm } => {
+                write!(f, "exit code: {:?} != {:?}", interpreter, asm)
+            }
+            Difference::Result { interpreter, asm } => {
+                write!(f, "result: {:?} != {:?}", interpreter, asm)
+            }
+            Difference::PageFlag {
+                page,
+                interpreter,
+                asm,
+            } => write!(
+                f,
+                "flag of page {}: 0x{:x} != 0x{:x}",
+                page, interpreter, asm
+            ),
+            Difference::Memory {
+                address,
+                interpreter,
+                asm,
+            } => write!(
+                f,
+                "memory at 0x{:x}: {:?} != {:?}",
+                address, interpreter, asm
+            ),
+        }
+    }
+}
+
+/// The first instruction after which the two machines disagree. Both
+/// machines are left in the state right after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub pc: u64,
+    /// None when the instruction leaving the difference is unknown, e.g.
+    /// memory found different when the program exits.
+    pub instruction: Option<Instruction>,
+    /// Cycles before executing the instruction.
+    pub cycles: u64,
+    /// Registers before executing the instruction.
+    pub registers: Vec<u64>,
+    /// Differences after executing the instruction, with the Rust
+    /// interpreter first.
+    pub differences: Vec<Difference>,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "divergence at 0x{:x}", self.pc)?;
+        match self.instruction {
+            Some(instruction) => match TaggedInstruction::try_from(instruction) {
+                Ok(tagged) => writeln!(f, ": {}", tagged)?,
+                Err(_) => writeln!(f, ": 0x{:x}", instruction)?,
+            },
+            None => writeln!(f)?,
+        }
+        writeln!(f, "cycles: {}", self.cycles)?;
+        for (i, name) in REGISTER_ABI_NAMES.iter().enumerate() {
+            write!(f, "{:4}: 0x{:16X}", name, self.registers[i])?;
+            if (i + 1) % 4 == 0 {
+                writeln!(f)?;
+            } else {
+                write!(f, " ")?;
+            }
+        }
+        for difference in &self.differences {
+            writeln!(f, "{}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+// States of both machines before a chunk of instructions.
+struct Checkpoint {
+    pc: u64,
+    registers: Vec<u64>,
+    cycles: u64,
+    instret: u64,
+    lr: u64,
+    // Flags and contents of pages written by the Rust interpreter, as they
+    // were before.
+    pages: BTreeMap<u64, (u8, Bytes)>,
+}
+
+impl Checkpoint {
+    fn new<M: SupportMachine<REG = u64>>(machine: &M) -> Self {
+        Self {
+            pc: *machine.pc(),
+            registers: machine.registers().to_vec(),
+            cycles: machine.cycles(),
+            instret: machine.instret(),
+            lr: *machine.memory().lr(),
+            pages: BTreeMap::new(),
+        }
+    }
+
+    fn restore<M: SupportMachine<REG = u64>>(&self, machine: &mut M) -> Result<(), Error> {
+        for (page, (flag, data)) in &self.pages {
+            restore_page(machine, *page, *flag, data)?;
+        }
+        machine.memory_mut().set_lr(&self.lr);
+        for (i, value) in self.registers.iter().enumerate() {
+            machine.set_register(i, *value);
+        }
+        machine.update_pc(self.pc);
+        machine.commit_pc();
+        machine.set_cycles(self.cycles);
+        machine.set_instret(self.instret);
+        machine.set_running(true);
+        Ok(())
+    }
+}
+
+// Writes a page with init_pages, which skips permission checks, and sets its
+// flag as it is. Frames touched by writing it are not charged.
+fn restore_page<M: SupportMachine>(
+    machine: &mut M,
+    page: u64,
+    flag: u8,
+    data: &Bytes,
+) -> Result<(), Error> {
+    let memory = machine.memory_mut();
+    memory.init_pages(
+        page << RISCV_PAGE_SHIFTS,
+        RISCV_PAGESIZE as u64,
+        flag,
+        Some(data.clone()),
+        0,
+    )?;
+    memory.clear_flag(page, !flag)?;
+    memory.set_flag(page, flag)?;
+    machine.take_touched_frames();
+    Ok(())
+}
+
+// What a syscall leaves, see SyscallLog.
+struct SyscallEffect {
+    result: Result<bool, Error>,
+    registers: Vec<u64>,
+    cycles: u64,
+    // Flags and contents of pages written by the syscall.
+    pages: Vec<(u64, u8, Bytes)>,
+}
+
+#[derive(Default)]
+struct SyscallEffects {
+    effects: Vec<SyscallEffect>,
+    // Index of the next effect to apply.
+    next: usize,
+}
+
+impl SyscallEffects {
+    // Starts recording the syscalls of a new chunk.
+    fn clear(&mut self) {
+        self.effects.clear();
+        self.next = 0;
+    }
+
+    // Applies the recorded effects again from the first one.
+    fn rewind(&mut self) {
+        self.next = 0;
+    }
+}
+
+fn lock(effects: &Mutex<SyscallEffects>) -> Result<MutexGuard<'_, SyscallEffects>, Error> {
+    effects.lock().map_err(|e| Error::Unexpected(e.to_string()))
+}
+
+// Handles the syscalls of a machine in place of its syscall registry and
+// Syscalls modules. A syscall not recorded yet is handled by them, and what it
+// leaves is recorded; a recorded one is applied without being executed again.
+struct SyscallLog<Mac> {
+    registry: SyscallRegistry<Mac>,
+    syscalls: Vec<Box<dyn Syscalls<Mac>>>,
+    effects: Arc<Mutex<SyscallEffects>>,
+}
+
+impl<Mac: SupportMachine<REG = u64> + 'static> SyscallLog<Mac> {
+    // Moves the syscalls of the machine into a log, and returns its effects.
+    fn install(machine: &mut DefaultMachine<Mac>) -> Arc<Mutex<SyscallEffects>> {
+        let effects = Arc::new(Mutex::new(SyscallEffects::default()));
+        let log = SyscallLog {
+            registry: machine.syscall_registry.take_handlers(),
+            syscalls: std::mem::take(&mut machine.syscalls),
+            effects: Arc::clone(&effects),
+        };
+        machine.syscalls.push(Box::new(log));
+        effects
+    }
+
+    fn handle(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        let number = machine.registers()[A7];
+        if self.registry.ecall(number, machine)? {
+            return Ok(true);
+        }
+        for syscall in &mut self.syscalls {
+            if syscall.ecall(machine)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // Handles the syscall, and records its result, registers, cycles and the
+    // pages it writes, which are found by clearing dirty flags beforehand.
+    fn record(&mut self, machine: &mut Mac) -> Result<SyscallEffect, Error> {
+        let pages = (machine.memory().memory_size() / RISCV_PAGESIZE) as u64;
+        let mut dirty = Vec::new();
+        for page in 0..pages {
+            if machine.memory_mut().fetch_flag(page)? & FLAG_DIRTY != 0 {
+                machine.memory_mut().clear_flag(page, FLAG_DIRTY)?;
+                dirty.push(page);
+            }
+        }
+        let result = self.handle(machine);
+        let mut written = Vec::new();
+        for page in 0..pages {
+            let flag = machine.memory_mut().fetch_flag(page)?;
+            if flag & FLAG_DIRTY != 0 {
+                let data = machine
+                    .memory_mut()
+                    .peek_bytes(page << RISCV_PAGE_SHIFTS, RISCV_PAGESIZE as u64)?;
+                written.push((page, flag, data));
+            }
+        }
+        for page in dirty {
+            machine.memory_mut().set_flag(page, FLAG_DIRTY)?;
+        }
+        Ok(SyscallEffect {
+            result,
+            registers: machine.registers().to_vec(),
+            cycles: machine.cycles(),
+            pages: written,
+        })
+    }
+}
+
+impl<Mac: SupportMachine<REG = u64> + 'static> Syscalls<Mac> for SyscallLog<Mac> {
+    fn initialize(&mut self, machine: &mut Mac) -> Result<(), Error> {
+        self.registry.initialize(machine)?;
+        for syscall in &mut self.syscalls {
+            syscall.initialize(machine)?;
+        }
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        let effects = Arc::clone(&self.effects);
+        let mut effects = lock(&effects)?;
+        if effects.next == effects.effects.len() {
+            let effect = self.record(machine)?;
+            effects.effects.push(effect);
+        } else {
+            let effect = &effects.effects[effects.next];
+            for (i, value) in effect.registers.iter().enumerate() {
+                machine.set_register(i, *value);
+            }
+            for (page, flag, data) in &effect.pages {
+                restore_page(machine, *page, *flag, data)?;
+            }
+            machine.set_cycles(effect.cycles);
+        }
+        effects.next += 1;
+        effects.effects[effects.next - 1].result.clone()
+    }
+}
+
+fn exit_code<Inner: SupportMachine>(machine: &DefaultMachine<Inner>) -> Option<i8> {
+    if machine.running() {
+        None
+    } else {
+        Some(machine.exit_code())
+    }
+}
+
+pub struct DifferentialMachine<Inner> {
+    pub interpreter: DefaultMachine<Inner>,
+    pub asm: AsmMachine,
+    lockstep: Lockstep,
+    interpreter_syscalls: Arc<Mutex<SyscallEffects>>,
+    asm_syscalls: Arc<Mutex<SyscallEffects>>,
+}
+
+impl<Inner: SupportMachine<REG = u64> + 'static> DifferentialMachine<Inner> {
+    pub fn new(
+        mut interpreter: DefaultMachine<Inner>,
+        mut asm: AsmMachine,
+        lockstep: Lockstep,
+    ) -> Self {
+        let interpreter_syscalls = SyscallLog::install(&mut interpreter);
+        let asm_syscalls = SyscallLog::install(&mut asm.machine);
+        Self {
+            interpreter,
+            asm,
+            lockstep,
+            interpreter_syscalls,
+            asm_syscalls,
+        }
+    }
+
+    pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
+        self.asm.load_program(program, args)?;
+        self.interpreter.load_program(program, args)
+    }
+
+    /// Runs both machines until the program exits, returns the first
+    /// divergence if there is any. Errors both machines agree on, such as
+    /// CyclesExceeded, are returned as they are.
+    pub fn run(&mut self) -> Result<Option<Divergence>, Error> {
+        let isa = self.interpreter.isa();
+        let version = self.interpreter.version();
+        if isa & ISA_MOP != 0 && version == VERSION0 {
+            return Err(Error::InvalidVersion);
+        }
+        let mut interpreter_decoder = build_decoder::<u64>(isa, version);
+        let mut asm_decoder = build_decoder::<u64>(isa, version);
+        self.asm.clear_traces();
+        self.interpreter.set_running(true);
+        self.asm.machine.set_running(true);
+        while self.interpreter.running() {
+            let mut checkpoint = Checkpoint::new(&self.interpreter);
+            lock(&self.interpreter_syscalls)?.clear();
+            lock(&self.asm_syscalls)?.clear();
+            let asm_result = match self.lockstep {
+                Lockstep::Traces => {
+                    if self.asm.machine.reset_signal() {
+                        asm_decoder.reset_instructions_cache();
+                    }
+                    // An instruction failing to decode is trapped without
+                    // being returned, it's still a step of the interpreter.
+                    self.asm
+                        .step_trace(&mut asm_decoder)
+                        .map(|instructions| instructions.len().max(1) as u64)
+                }
+                Lockstep::Instructions(count) => self.step_asm(&mut asm_decoder, count.max(1)),
+            };
+            let mut matched = false;
+            if let Ok(count) = asm_result {
+                let mut result = Ok(());
+                for _ in 0..count {
+                    if !self.interpreter.running() {
+                        break;
+                    }
+                    result = self.step_interpreter(&mut interpreter_decoder, &mut checkpoint);
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                matched = result.is_ok() && self.differences(&checkpoint)?.is_empty();
+            }
+            if !matched {
+                return self
+                    .replay(&checkpoint, &mut interpreter_decoder, &mut asm_decoder)
+                    .map(Some);
+            }
+        }
+        let differences = self.compare_memory()?;
+        if differences.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Divergence {
+            pc: *self.interpreter.pc(),
+            instruction: None,
+            cycles: self.interpreter.cycles(),
+            registers: self.interpreter.registers().to_vec(),
+            differences,
+        }))
+    }
+
+    fn step_asm(&mut self, decoder: &mut Decoder, count: u64) -> Result<u64, Error> {
+        let mut executed = 0;
+        while executed < count && self.asm.machine.running() {
+            if self.asm.machine.reset_signal() {
+                decoder.reset_instructions_cache();
+            }
+            self.asm
+                .step(decoder)
+                .or_else(|e| self.asm.machine.trap(e))?;
+            executed += 1;
+        }
+        Ok(executed)
+    }
+
+    // Executes an instruction on the Rust interpreter, pages it writes are
+    // saved to the checkpoint first.
+    fn step_interpreter(
+        &mut self,
+        decoder: &mut Decoder,
+        checkpoint: &mut Checkpoint,
+    ) -> Result<(), Error> {
+        if self.interpreter.reset_signal() {
+            decoder.reset_instructions_cache();
+        }
+        let pc = *self.interpreter.pc();
+        let instruction = match decoder.decode(self.interpreter.memory_mut(), pc) {
+            Ok(instruction) => instruction,
+            Err(e) => return self.interpreter.trap(e),
+        };
+        let access = memory_access(instruction, self.interpreter.registers());
+        if let Some(access) = access.filter(|access| access.write) {
+            let first = access.address >> RISCV_PAGE_SHIFTS;
+            let last = access.address.saturating_add(access.size - 1) >> RISCV_PAGE_SHIFTS;
+            for page in first..=last {
+                if checkpoint.pages.contains_key(&page) {
+                    continue;
+                }
+                // Pages out of bound are not written either.
+                let memory = self.interpreter.memory_mut();
+                if let (Ok(flag), Ok(data)) = (
+                    memory.fetch_flag(page),
+                    memory.peek_bytes(page << RISCV_PAGE_SHIFTS, RISCV_PAGESIZE as u64),
+                ) {
+                    checkpoint.pages.insert(page, (flag, data));
+                }
+            }
+        }
+        self.interpreter
+            .step(decoder)
+            .or_else(|e| self.interpreter.trap(e))
+    }
+
+    // Rewinds both machines to the checkpoint, and executes instructions one
+    // by one until they disagree.
+    fn replay(
+        &mut self,
+        checkpoint: &Checkpoint,
+        interpreter_decoder: &mut Decoder,
+        asm_decoder: &mut Decoder,
+    ) -> Result<Divergence, Error> {
+        checkpoint.restore(&mut self.interpreter)?;
+        checkpoint.restore(&mut self.asm.machine)?;
+        lock(&self.interpreter_syscalls)?.rewind();
+        lock(&self.asm_syscalls)?.rewind();
+        // A chunk can't be longer than this, unless it diverges earlier.
+        let limit = match self.lockstep {
+            Lockstep::Traces => TRACE_ITEM_LENGTH as u64,
+            Lockstep::Instructions(count) => count.max(1),
+        };
+        let mut last = None;
+        for _ in 0..limit {
+            if !self.interpreter.running() {
+                break;
+            }
+            let mut step = Checkpoint::new(&self.interpreter);
+            let instruction = interpreter_decoder
+                .decode(self.interpreter.memory_mut(), step.pc)
+                .ok();
+            let interpreter = self.step_interpreter(interpreter_decoder, &mut step);
+            let asm = self.step_asm(asm_decoder, 1).map(|_| ());
+            let mut differences = Vec::new();
+            if interpreter != asm {
+                differences.push(Difference::Result {
+                    interpreter: interpreter.clone(),
+                    asm: asm.clone(),
+                });
+            }
+            if let Err(e) = interpreter.as_ref().and(asm.as_ref()) {
+                if differences.is_empty() {
+                    return Err(e.clone());
+                }
+            } else {
+                differences.extend(self.differences(&step)?);
+            }
+            let divergence = Divergence {
+                pc: step.pc,
+                instruction,
+                cycles: step.cycles,
+                registers: step.registers,
+                differences,
+            };
+            if !divergence.differences.is_empty() {
+                return Ok(divergence);
+            }
+            last = Some(divergence);
+        }
+        // The machines didn't disagree this time, e.g. the difference is
+        // left by the asm interpreter in pages the Rust interpreter doesn't
+        // write. The last instruction is reported with what's different.
+        let mut divergence = last.unwrap_or_else(|| Divergence {
+            pc: checkpoint.pc,
+            instruction: None,
+            cycles: checkpoint.cycles,
+            registers: checkpoint.registers.clone(),
+            differences: Vec::new(),
+        });
+        divergence.differences = self.differences(checkpoint)?;
+        divergence.differences.extend(self.compare_memory()?);
+        Ok(divergence)
+    }
+
+    // Compares states of the two machines, and the pages in the checkpoint.
+    fn differences(&mut self, checkpoint: &Checkpoint) -> Result<Vec<Difference>, Error> {
+        let interpreter = &mut self.interpreter;
+        let asm = &mut self.asm.machine;
+        let mut differences = Vec::new();
+        if interpreter.pc() != asm.pc() {
+            differences.push(Difference::Pc {
+                interpreter: *interpreter.pc(),
+                asm: *asm.pc(),
+            });
+        }
+        for (index, (a, b)) in interpreter
+            .registers()
+            .iter()
+            .zip(asm.registers())
+            .enumerate()
+        {
+            if a != b {
+                differences.push(Difference::Register {
+                    index,
+                    interpreter: *a,
+                    asm: *b,
+                });
+            }
+        }
+        if interpreter.cycles() != asm.cycles() {
+            differences.push(Difference::Cycles {
+                interpreter: interpreter.cycles(),
+                asm: asm.cycles(),
+            });
+        }
+        if exit_code(interpreter) != exit_code(asm) {
+            differences.push(Difference::Exit {
+                interpreter: exit_code(interpreter),
+                asm: exit_code(asm),
+            });
+        }
+        for page in checkpoint.pages.keys() {
+            differences.extend(self.compare_page(*page)?);
+        }
+        Ok(differences)
+    }
+
+    fn compare_page(&mut self, page: u64) -> Result<Option<Difference>, Error> {
+        let interpreter = self.interpreter.memory_mut().fetch_flag(page)?;
+        let asm = self.asm.machine.memory_mut().fetch_flag(page)?;
+        if interpreter != asm {
+            return Ok(Some(Difference::PageFlag {
+                page,
+                interpreter,
+                asm,
+            }));
+        }
+        let address = page << RISCV_PAGE_SHIFTS;
+        let size = RISCV_PAGESIZE as u64;
+        let interpreter = self.interpreter.memory_mut().peek_bytes(address, size).ok();
+        let asm = self.asm.machine.memory_mut().peek_bytes(address, size).ok();
+        let difference = match (interpreter, asm) {
+            (Some(a), Some(b)) => a
+                .iter()
+                .zip(b.iter())
+                .position(|(a, b)| a != b)
+                .map(|offset| Difference::Memory {
+                    address: address + offset as u64,
+                    interpreter: Some(a[offset]),
+                    asm: Some(b[offset]),
+                }),
+            (None, None) => None,
+            (a, b) => Some(Difference::Memory {
+                address,
+                interpreter: a.map(|a| a[0]),
+                asm: b.map(|b| b[0]),
+            }),
+        };
+        Ok(difference)
+    }
+
+    // Compares flags of all pages, and contents of dirty pages.
+    fn compare_memory(&mut self) -> Result<Vec<Difference>, Error> {
+        let pages = (self.interpreter.memory().memory_size() / RISCV_PAGESIZE) as u64;
+        let mut differences = Vec::new();
+        for page in 0..pages {
+            let interpreter = self.interpreter.memory_mut().fetch_flag(page)?;
+            let asm = self.asm.machine.memory_mut().fetch_flag(page)?;
+            if interpreter != asm || interpreter & FLAG_DIRTY != 0 {
+                differences.extend(self.compare_page(page)?);
+            }
+        }
+        Ok(differences)
+    }
+}
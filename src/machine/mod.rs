@@ -1,22 +1,36 @@
 #[cfg(has_asm)]
 pub mod asm;
+#[cfg(has_asm)]
+pub mod differential;
 pub mod elf_adaptor;
 pub mod trace;
+pub mod trap;
 
 use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
 
 use bytes::Bytes;
 use scroll::Pread;
 
-use super::debugger::Debugger;
+use super::cost_model::{CostContext, CostModel};
+use super::debugger::{memory_access, Breakpoints, Debugger, Watchpoint};
 use super::decoder::{build_decoder, Decoder};
-use super::instructions::{execute, Instruction, Register};
-use super::memory::{round_page_down, round_page_up, Memory};
-use super::syscalls::Syscalls;
+#[cfg(feature = "hooks")]
+use super::hook::{Hook, HookAction};
+use super::instructions::{
+    execute, extract_opcode, instruction_length, insts, is_basic_block_end_instruction,
+    Instruction, Register,
+};
+use super::memory::{round_page_down, round_page_up, Memory, FLAG_DIRTY, FLAG_TOUCHED};
+use super::symbolizer::Symbolizer;
+use super::syscalls::{Syscall, SyscallCost, SyscallRegistry, Syscalls, EXIT};
+use super::tracer::{execute_recorded, Tracer};
 use super::{
-    registers::{A0, A7, REGISTER_ABI_NAMES, SP},
-    Error, ISA_MOP, RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY,
+    registers::{A0, A1, A7, REGISTER_ABI_NAMES, SP},
+    Error, ISA_MOP, RISCV_GENERAL_REGISTER_NUMBER, RISCV_MAX_MEMORY, RISCV_PAGE_SHIFTS,
 };
+use trap::{Traps, CSR_READ, CSR_WRITE, MRET};
 
 // Version 0 is the initial launched CKB VM, it is used in CKB Lina mainnet
 pub const VERSION0: u32 = 0;
@@ -28,6 +42,128 @@ pub const VERSION0: u32 = 0;
 // * https://github.com/nervosnetwork/ckb-vm/issues/106
 pub const VERSION1: u32 = 1;
 pub const VERSION2: u32 = 2;
+// Version 3 delivers faults to trap handlers registered by guests, see trap.rs.
+pub const VERSION3: u32 = 3;
+
+// Parses the entry point and program headers of an ELF program, the goblin
+// version used depends on the machine version.
+pub fn parse_elf<R: Register>(
+    program: &Bytes,
+    version: u32,
+) -> Result<(u64, Vec<elf_adaptor::ProgramHeader>), Error> {
+    let (e_entry, program_headers, _) = parse_elf_headers::<R>(program, version, false)?;
+    Ok((e_entry, program_headers))
+}
+
+// Like parse_elf, also parses the section headers for tools inspecting a
+// program. They are left empty when missing or malformed, programs are loaded
+// regardless of them.
+pub fn parse_elf_sections<R: Register>(
+    program: &Bytes,
+    version: u32,
+) -> Result<
+    (
+        u64,
+        Vec<elf_adaptor::ProgramHeader>,
+        Vec<elf_adaptor::SectionHeader>,
+    ),
+    Error,
+> {
+    parse_elf_headers::<R>(program, version, true)
+}
+
+fn parse_elf_headers<R: Register>(
+    program: &Bytes,
+    version: u32,
+    sections: bool,
+) -> Result<
+    (
+        u64,
+        Vec<elf_adaptor::ProgramHeader>,
+        Vec<elf_adaptor::SectionHeader>,
+    ),
+    Error,
+> {
+    // We did not use Elf::parse here to avoid triggering potential bugs in goblin.
+    // * https://github.com/nervosnetwork/ckb-vm/issues/143
+    // Section headers are only parsed when there are some, as a count of 0
+    // makes goblin read the count from the first section header.
+    if version < VERSION1 {
+        use goblin_v023::container::Ctx;
+        use goblin_v023::elf::{program_header::ProgramHeader, Header, SectionHeader};
+        let header = program.pread::<Header>(0)?;
+        let container = header.container().map_err(|_e| Error::ElfBits)?;
+        let endianness = header.endianness().map_err(|_e| Error::ElfBits)?;
+        if R::BITS != if container.is_big() { 64 } else { 32 } {
+            return Err(Error::ElfBits);
+        }
+        let ctx = Ctx::new(container, endianness);
+        let program_headers = ProgramHeader::parse(
+            program,
+            header.e_phoff as usize,
+            header.e_phnum as usize,
+            ctx,
+        )?
+        .iter()
+        .map(elf_adaptor::ProgramHeader::from_v0)
+        .collect();
+        let section_headers = if sections && header.e_shnum > 0 {
+            SectionHeader::parse(
+                program,
+                header.e_shoff as usize,
+                header.e_shnum as usize,
+                ctx,
+            )
+            .map(|headers| {
+                headers
+                    .iter()
+                    .map(elf_adaptor::SectionHeader::from_v0)
+                    .collect()
+            })
+            .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        Ok((header.e_entry, program_headers, section_headers))
+    } else {
+        use goblin_v040::container::Ctx;
+        use goblin_v040::elf::{program_header::ProgramHeader, Header, SectionHeader};
+        let header = program.pread::<Header>(0)?;
+        let container = header.container().map_err(|_e| Error::ElfBits)?;
+        let endianness = header.endianness().map_err(|_e| Error::ElfBits)?;
+        if R::BITS != if container.is_big() { 64 } else { 32 } {
+            return Err(Error::ElfBits);
+        }
+        let ctx = Ctx::new(container, endianness);
+        let program_headers = ProgramHeader::parse(
+            program,
+            header.e_phoff as usize,
+            header.e_phnum as usize,
+            ctx,
+        )?
+        .iter()
+        .map(elf_adaptor::ProgramHeader::from_v1)
+        .collect();
+        let section_headers = if sections && header.e_shnum > 0 {
+            SectionHeader::parse(
+                program,
+                header.e_shoff as usize,
+                header.e_shnum as usize,
+                ctx,
+            )
+            .map(|headers| {
+                headers
+                    .iter()
+                    .map(elf_adaptor::SectionHeader::from_v1)
+                    .collect()
+            })
+            .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        Ok((header.e_entry, program_headers, section_headers))
+    }
+}
 
 /// This is the core part of RISC-V that only deals with data part, it
 /// is extracted from Machine so we can handle lifetime logic in dynamic
@@ -55,6 +191,18 @@ pub trait CoreMachine {
 pub trait Machine: CoreMachine {
     fn ecall(&mut self) -> Result<(), Error>;
     fn ebreak(&mut self) -> Result<(), Error>;
+
+    // Counters read by guests with the Zicsr instructions. While executing
+    // an instruction, the cycle counter includes the instruction itself,
+    // while the instret counter only counts instructions retired before it.
+    // Machines that don't track them report zero.
+    fn cycle_counter(&self) -> u64 {
+        0
+    }
+
+    fn instret_counter(&self) -> u64 {
+        0
+    }
 }
 
 /// This traits extend on top of CoreMachine by adding additional support
@@ -68,6 +216,35 @@ pub trait SupportMachine: CoreMachine {
     fn cycles(&self) -> u64;
     fn set_cycles(&mut self, cycles: u64);
     fn max_cycles(&self) -> u64;
+    // Machines which can't change their max cycles ignore it, and can't be
+    // run with a budget.
+    fn set_max_cycles(&mut self, _cycles: u64) {}
+    // Instructions retired, instructions which fail or are skipped by hooks
+    // are not counted. Machines which don't count them always report 0.
+    fn instret(&self) -> u64 {
+        0
+    }
+    fn set_instret(&mut self, _instret: u64) {}
+
+    // Cycles charged for each memory frame touched for the first time,
+    // including by loading the program, not charged by default. Frames are
+    // only tracked while the charge is not 0, so it is set before loading.
+    // Machines which don't track frames never charge them.
+    fn frame_cycles(&self) -> u64 {
+        0
+    }
+    fn set_frame_cycles(&mut self, _cycles: u64) {}
+    // The number of frames touched for the first time since the last call.
+    fn take_touched_frames(&mut self) -> u64 {
+        0
+    }
+
+    // The state of guest trap handlers, kept in snapshots. Only DefaultMachine
+    // delivers traps, core machines have none.
+    fn trap_state(&self) -> Traps {
+        Traps::default()
+    }
+    fn set_trap_state(&mut self, _traps: Traps) {}
 
     fn running(&self) -> bool;
     fn set_running(&mut self, running: bool);
@@ -97,52 +274,28 @@ pub trait SupportMachine: CoreMachine {
         Ok(())
     }
 
+    // Charges the frames touched since the last call, right after the
+    // instruction or syscall touching them. Unlike add_cycles, the cycles are
+    // kept when max cycles are exceeded, as the frames stay touched.
+    fn charge_frames(&mut self) -> Result<(), Error> {
+        let frames = self.take_touched_frames();
+        if frames == 0 {
+            return Ok(());
+        }
+        let new_cycles = frames
+            .checked_mul(self.frame_cycles())
+            .and_then(|cycles| cycles.checked_add(self.cycles()))
+            .ok_or(Error::CyclesOverflow)?;
+        self.set_cycles(new_cycles);
+        if new_cycles > self.max_cycles() {
+            return Err(Error::CyclesExceeded);
+        }
+        Ok(())
+    }
+
     fn load_elf_inner(&mut self, program: &Bytes, update_pc: bool) -> Result<u64, Error> {
         let version = self.version();
-        // We did not use Elf::parse here to avoid triggering potential bugs in goblin.
-        // * https://github.com/nervosnetwork/ckb-vm/issues/143
-        let (e_entry, program_headers): (u64, Vec<elf_adaptor::ProgramHeader>) =
-            if version < VERSION1 {
-                use goblin_v023::container::Ctx;
-                use goblin_v023::elf::{program_header::ProgramHeader, Header};
-                let header = program.pread::<Header>(0)?;
-                let container = header.container().map_err(|_e| Error::ElfBits)?;
-                let endianness = header.endianness().map_err(|_e| Error::ElfBits)?;
-                if Self::REG::BITS != if container.is_big() { 64 } else { 32 } {
-                    return Err(Error::ElfBits);
-                }
-                let ctx = Ctx::new(container, endianness);
-                let program_headers = ProgramHeader::parse(
-                    program,
-                    header.e_phoff as usize,
-                    header.e_phnum as usize,
-                    ctx,
-                )?
-                .iter()
-                .map(elf_adaptor::ProgramHeader::from_v0)
-                .collect();
-                (header.e_entry, program_headers)
-            } else {
-                use goblin_v040::container::Ctx;
-                use goblin_v040::elf::{program_header::ProgramHeader, Header};
-                let header = program.pread::<Header>(0)?;
-                let container = header.container().map_err(|_e| Error::ElfBits)?;
-                let endianness = header.endianness().map_err(|_e| Error::ElfBits)?;
-                if Self::REG::BITS != if container.is_big() { 64 } else { 32 } {
-                    return Err(Error::ElfBits);
-                }
-                let ctx = Ctx::new(container, endianness);
-                let program_headers = ProgramHeader::parse(
-                    program,
-                    header.e_phoff as usize,
-                    header.e_phnum as usize,
-                    ctx,
-                )?
-                .iter()
-                .map(elf_adaptor::ProgramHeader::from_v1)
-                .collect();
-                (header.e_entry, program_headers)
-            };
+        let (e_entry, program_headers) = parse_elf::<Self::REG>(program, version)?;
         let mut bytes: u64 = 0;
         for program_header in program_headers {
             if program_header.p_type == elf_adaptor::PT_LOAD {
@@ -280,7 +433,10 @@ pub trait SupportMachine: CoreMachine {
     fn code(&self) -> &Bytes;
 }
 
-#[derive(Default)]
+// Cloning a DefaultCoreMachine forks it, the cost of which depends on the
+// memory used: SparseMemory shares pages copy-on-write with the clone, while
+// FlatMemory is deep copied.
+#[derive(Clone, Default)]
 pub struct DefaultCoreMachine<R, M> {
     registers: [R; RISCV_GENERAL_REGISTER_NUMBER],
     pc: R,
@@ -289,6 +445,9 @@ pub struct DefaultCoreMachine<R, M> {
     memory: M,
     cycles: u64,
     max_cycles: u64,
+    instret: u64,
+    // Cycles charged for each memory frame touched for the first time.
+    frame_cycles: u64,
     running: bool,
     isa: u8,
     version: u32,
@@ -349,12 +508,39 @@ impl<R: Register, M: Memory<REG = R>> SupportMachine for DefaultCoreMachine<R, M
         self.max_cycles
     }
 
+    fn set_max_cycles(&mut self, max_cycles: u64) {
+        self.max_cycles = max_cycles;
+    }
+
+    fn instret(&self) -> u64 {
+        self.instret
+    }
+
+    fn set_instret(&mut self, instret: u64) {
+        self.instret = instret;
+    }
+
+    fn frame_cycles(&self) -> u64 {
+        self.frame_cycles
+    }
+
+    fn set_frame_cycles(&mut self, cycles: u64) {
+        self.frame_cycles = cycles;
+        self.memory.track_frames(cycles != 0);
+    }
+
+    fn take_touched_frames(&mut self) -> u64 {
+        self.memory.take_touched_frames()
+    }
+
     fn reset(&mut self, max_cycles: u64) {
         self.registers = Default::default();
         self.pc = Default::default();
         self.memory = M::new_with_memory(self.memory().memory_size());
+        self.memory.track_frames(self.frame_cycles != 0);
         self.cycles = 0;
         self.max_cycles = max_cycles;
+        self.instret = 0;
         self.reset_signal = true;
         self.memory_mut().set_lr(&R::from_u64(u64::MAX));
     }
@@ -401,6 +587,8 @@ impl<R: Register, M: Memory> DefaultCoreMachine<R, M> {
             memory: M::new_with_memory(memory_size),
             cycles: Default::default(),
             max_cycles,
+            instret: 0,
+            frame_cycles: 0,
             running: Default::default(),
             isa,
             version,
@@ -409,10 +597,6 @@ impl<R: Register, M: Memory> DefaultCoreMachine<R, M> {
         }
     }
 
-    pub fn set_max_cycles(&mut self, cycles: u64) {
-        self.max_cycles = cycles;
-    }
-
     pub fn take_memory(self) -> M {
         self.memory
     }
@@ -420,17 +604,126 @@ impl<R: Register, M: Memory> DefaultCoreMachine<R, M> {
 
 pub type InstructionCycleFunc = dyn Fn(Instruction) -> u64 + Send + Sync;
 
+/// The result of running a machine with a cycle budget.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RunOutcome {
+    /// The program exited with the given exit code.
+    Exited(i8),
+    /// The cycle budget is used up before the program exits. The machine is
+    /// left intact, running it again continues from where it stopped.
+    Paused,
+    /// The machine stops before executing the instruction at a breakpoint,
+    /// running it again continues from the breakpoint.
+    Breakpoint(u64),
+    /// The machine stops after executing an instruction which accesses
+    /// memory at `address` covered by a watchpoint.
+    Watchpoint {
+        watchpoint: Watchpoint,
+        address: u64,
+    },
+    /// A hook stops the machine at pc, see HookAction::Stop. Only with the
+    /// hooks feature.
+    Hook(u64),
+}
+
+// Lowers max cycles of the machine so that at most `budget` more cycles can
+// be consumed, and returns the original max cycles which must be restored
+// after running. Fails with Error::Unimplemented if the machine keeps its max
+// cycles, see SupportMachine::set_max_cycles.
+fn enter_budget<M: SupportMachine>(machine: &mut M, budget: u64) -> Result<u64, Error> {
+    let max_cycles = machine.max_cycles();
+    let limit = machine.cycles().saturating_add(budget);
+    if limit < max_cycles {
+        machine.set_max_cycles(limit);
+        if machine.max_cycles() != limit {
+            return Err(Error::Unimplemented);
+        }
+    }
+    Ok(max_cycles)
+}
+
+// Restores max cycles lowered by enter_budget, and tells apart a paused
+// machine from one that really exceeds its max cycles, or one stopped at a
+// breakpoint or watchpoint.
+fn leave_budget<M: SupportMachine>(
+    machine: &mut M,
+    max_cycles: u64,
+    stop: Option<RunOutcome>,
+    result: Result<i8, Error>,
+) -> Result<RunOutcome, Error> {
+    let limit = machine.max_cycles();
+    machine.set_max_cycles(max_cycles);
+    match result {
+        Ok(exit_code) => Ok(RunOutcome::Exited(exit_code)),
+        Err(Error::CyclesExceeded) if limit < max_cycles && machine.cycles() <= max_cycles => {
+            Ok(RunOutcome::Paused)
+        }
+        Err(Error::Pause) => stop.ok_or(Error::Pause),
+        Err(e) => Err(e),
+    }
+}
+
+// Where a machine is and the cycles it has consumed. A run with a budget
+// pausing at the same point has made no progress, as the next instruction,
+// or basic block for the asm machine, costs more than the budget.
+fn budget_progress<M: SupportMachine>(machine: &M) -> (u64, u64) {
+    (machine.pc().to_u64(), machine.cycles())
+}
+
+/// A thread-safe handle to interrupt a running machine. Machines poll it at
+/// basic block boundaries and stop with Error::Pause once interrupted, the
+/// machine is left intact so it can be resumed by running it again.
+#[derive(Clone, Default)]
+pub struct Pause {
+    s: Arc<AtomicU8>,
+}
+
+impl Pause {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn interrupt(&self) {
+        self.s.store(1, Ordering::SeqCst);
+    }
+
+    pub fn has_interrupted(&self) -> bool {
+        self.s.load(Ordering::SeqCst) != 0
+    }
+
+    pub fn free(&self) {
+        self.s.store(0, Ordering::SeqCst);
+    }
+
+    // The shared flag, polled by the asm loop.
+    #[cfg(has_asm)]
+    pub(crate) fn flag(&self) -> Arc<AtomicU8> {
+        Arc::clone(&self.s)
+    }
+}
+
 pub struct DefaultMachine<Inner> {
     inner: Inner,
+    pause: Pause,
 
     // We have run benchmarks on secp256k1 verification, the performance
-    // cost of the Box wrapper here is neglectable, hence we are sticking
-    // with Box solution for simplicity now. Later if this becomes an issue,
+    // cost of the Arc wrapper here is neglectable, hence we are sticking
+    // with Arc solution for simplicity now. Later if this becomes an issue,
     // we can change to static dispatch.
-    instruction_cycle_func: Box<InstructionCycleFunc>,
+    cost_model: Arc<dyn CostModel>,
     debugger: Option<Box<dyn Debugger<Inner>>>,
+    syscall_registry: SyscallRegistry<Inner>,
     syscalls: Vec<Box<dyn Syscalls<Inner>>>,
+    #[cfg(feature = "hooks")]
+    hooks: Vec<Box<dyn Hook<Inner>>>,
     exit_code: i8,
+    breakpoints: Breakpoints,
+    // Why the machine stopped last time, if it was because of breakpoints,
+    // watchpoints or hooks.
+    stop: Option<RunOutcome>,
+    tracer: Option<Tracer>,
+    symbolizer: Option<Symbolizer>,
+    traps: Traps,
 }
 
 impl<Inner: CoreMachine> CoreMachine for DefaultMachine<Inner> {
@@ -487,6 +780,38 @@ impl<Inner: SupportMachine> SupportMachine for DefaultMachine<Inner> {
         self.inner.max_cycles()
     }
 
+    fn set_max_cycles(&mut self, max_cycles: u64) {
+        self.inner.set_max_cycles(max_cycles)
+    }
+
+    fn instret(&self) -> u64 {
+        self.inner.instret()
+    }
+
+    fn set_instret(&mut self, instret: u64) {
+        self.inner.set_instret(instret)
+    }
+
+    fn frame_cycles(&self) -> u64 {
+        self.inner.frame_cycles()
+    }
+
+    fn set_frame_cycles(&mut self, cycles: u64) {
+        self.inner.set_frame_cycles(cycles)
+    }
+
+    fn take_touched_frames(&mut self) -> u64 {
+        self.inner.take_touched_frames()
+    }
+
+    fn trap_state(&self) -> Traps {
+        self.traps.clone()
+    }
+
+    fn set_trap_state(&mut self, traps: Traps) {
+        self.traps = traps;
+    }
+
     fn reset(&mut self, max_cycles: u64) {
         self.inner_mut().reset(max_cycles);
     }
@@ -503,6 +828,11 @@ impl<Inner: SupportMachine> SupportMachine for DefaultMachine<Inner> {
         self.inner.set_running(running);
     }
 
+    fn load_elf(&mut self, program: &Bytes, update_pc: bool) -> Result<u64, Error> {
+        // Lets the inner machine keep the program when it overrides load_elf.
+        self.inner.load_elf(program, update_pc)
+    }
+
     #[cfg(feature = "pprof")]
     fn code(&self) -> &Bytes {
         self.inner.code()
@@ -513,13 +843,16 @@ impl<Inner: SupportMachine> Machine for DefaultMachine<Inner> {
     fn ecall(&mut self) -> Result<(), Error> {
         let code = self.registers()[A7].to_u64();
         match code {
-            93 => {
-                // exit
+            EXIT => {
                 self.exit_code = self.registers()[A0].to_i8();
                 self.set_running(false);
                 Ok(())
             }
+            _ if self.syscall_registry.is_reserved(code) => self.trap_ecall(code),
             _ => {
+                if self.syscall_registry.ecall(code, &mut self.inner)? {
+                    return Ok(());
+                }
                 for syscall in &mut self.syscalls {
                     let processed = syscall.ecall(&mut self.inner)?;
                     if processed {
@@ -543,11 +876,23 @@ impl<Inner: SupportMachine> Machine for DefaultMachine<Inner> {
             Ok(())
         }
     }
+
+    fn cycle_counter(&self) -> u64 {
+        self.cycles()
+    }
+
+    fn instret_counter(&self) -> u64 {
+        self.instret()
+    }
 }
 
 impl<Inner: CoreMachine> Display for DefaultMachine<Inner> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "pc  : 0x{:16X}", self.pc().to_u64())?;
+        let pc = self.pc().to_u64();
+        match &self.symbolizer {
+            Some(symbolizer) => writeln!(f, "pc  : 0x{:16X} in {}", pc, symbolizer.describe(pc))?,
+            None => writeln!(f, "pc  : 0x{:16X}", pc)?,
+        }
         for (i, name) in REGISTER_ABI_NAMES.iter().enumerate() {
             write!(f, "{:4}: 0x{:16X}", name, self.registers()[i].to_u64())?;
             if (i + 1) % 4 == 0 {
@@ -563,6 +908,8 @@ impl<Inner: CoreMachine> Display for DefaultMachine<Inner> {
 impl<Inner: SupportMachine> DefaultMachine<Inner> {
     pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
         let elf_bytes = self.load_elf(program, true)?;
+        self.traps = Traps::default();
+        self.syscall_registry.initialize(&mut self.inner)?;
         for syscall in &mut self.syscalls {
             syscall.initialize(&mut self.inner)?;
         }
@@ -577,6 +924,7 @@ impl<Inner: SupportMachine> DefaultMachine<Inner> {
         if self.inner.version() >= VERSION1 {
             debug_assert!(self.registers()[SP].to_u64() % 16 == 0);
         }
+        self.charge_frames()?;
         let bytes = elf_bytes.checked_add(stack_bytes).ok_or_else(|| {
             Error::Unexpected(String::from(
                 "The bytes count overflowed on loading program",
@@ -585,6 +933,52 @@ impl<Inner: SupportMachine> DefaultMachine<Inner> {
         Ok(bytes)
     }
 
+    pub fn traps(&self) -> &Traps {
+        &self.traps
+    }
+
+    /// If faults are delivered to the trap handler of the guest.
+    pub fn traps_enabled(&self) -> bool {
+        self.version() >= VERSION3 && self.traps.enabled()
+    }
+
+    /// Delivers a fault to the trap handler of the guest, the error is given
+    /// back if it cannot be trapped.
+    pub fn trap(&mut self, error: Error) -> Result<(), Error> {
+        if self.version() < VERSION3 {
+            return Err(error);
+        }
+        let handler = self.traps.deliver(error)?;
+        self.update_pc(Inner::REG::from_u64(handler));
+        self.commit_pc();
+        Ok(())
+    }
+
+    fn trap_ecall(&mut self, code: u64) -> Result<(), Error> {
+        let csr = self.registers()[A0].to_u64();
+        match code {
+            CSR_READ => {
+                let value = self.traps.csr(csr).ok_or(Error::InvalidEcall(code))?;
+                self.set_register(A0, Inner::REG::from_u64(value));
+            }
+            CSR_WRITE => {
+                let value = self.registers()[A1].to_u64();
+                let old = self
+                    .traps
+                    .set_csr(csr, value)
+                    .ok_or(Error::InvalidEcall(code))?;
+                self.set_register(A0, Inner::REG::from_u64(old));
+            }
+            MRET => {
+                self.traps.handling = false;
+                self.update_pc(Inner::REG::from_u64(self.traps.mepc));
+                self.commit_pc();
+            }
+            _ => return Err(Error::InvalidEcall(code)),
+        }
+        Ok(())
+    }
+
     pub fn take_inner(self) -> Inner {
         self.inner
     }
@@ -593,14 +987,139 @@ impl<Inner: SupportMachine> DefaultMachine<Inner> {
         self.exit_code
     }
 
-    pub fn instruction_cycle_func(&self) -> &InstructionCycleFunc {
-        &self.instruction_cycle_func
+    /// The cycles of an instruction charged before executing it, see
+    /// CostModel::cycles. The function shares the cost model and doesn't
+    /// borrow the machine.
+    pub fn instruction_cycle_func(&self) -> Box<InstructionCycleFunc> {
+        let cost_model = Arc::clone(&self.cost_model);
+        Box::new(move |instruction| cost_model.cycles(instruction))
+    }
+
+    pub fn cost_model(&self) -> &dyn CostModel {
+        self.cost_model.as_ref()
     }
 
     pub fn inner_mut(&mut self) -> &mut Inner {
         &mut self.inner
     }
 
+    pub fn pause(&self) -> Pause {
+        self.pause.clone()
+    }
+
+    pub fn set_pause(&mut self, pause: Pause) {
+        self.pause = pause;
+    }
+
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    pub fn symbolizer(&self) -> Option<&Symbolizer> {
+        self.symbolizer.as_ref()
+    }
+
+    pub fn set_symbolizer(&mut self, symbolizer: Symbolizer) {
+        self.symbolizer = Some(symbolizer);
+    }
+
+    // Wraps an error raised by the instruction at pc with its location, if
+    // a symbolizer is attached. Pause and CyclesExceeded are not wrapped, as
+    // they are how run_with_budget stops.
+    pub(crate) fn locate_error(&self, pc: u64, error: Error) -> Error {
+        match (&self.symbolizer, error) {
+            (Some(symbolizer), error)
+                if !matches!(
+                    error,
+                    Error::Pause | Error::CyclesExceeded | Error::Located { .. }
+                ) =>
+            {
+                Error::Located {
+                    error: Box::new(error),
+                    pc,
+                    location: symbolizer.describe(pc),
+                }
+            }
+            (_, error) => error,
+        }
+    }
+
+    // Breakpoints, watchpoints, hooks, the tracer and context aware cost
+    // models require instructions to be executed one by one.
+    fn single_stepping(&self) -> bool {
+        !self.breakpoints.is_empty()
+            || self.has_hooks()
+            || self.tracer.is_some()
+            || self.cost_model.context_aware()
+    }
+
+    // Starts the context of an instruction about to be executed, if the cost
+    // model is context aware.
+    pub(crate) fn cost_context(&mut self, instruction: Instruction) -> Option<CostContext> {
+        if !self.cost_model.context_aware() {
+            return None;
+        }
+        let access = memory_access(instruction, self.registers());
+        let fresh_page = match access {
+            Some(access) => self
+                .memory_mut()
+                .fetch_flag(access.address >> RISCV_PAGE_SHIFTS)
+                .map(|flag| flag & (FLAG_DIRTY | FLAG_TOUCHED) == 0)
+                .unwrap_or(false),
+            None => false,
+        };
+        Some(CostContext {
+            pc: self.pc().to_u64(),
+            branch_taken: None,
+            access,
+            fresh_page,
+        })
+    }
+
+    // Completes the context of an instruction once executed, and charges the
+    // extra cycles the cost model asks for. Like charge_frames, the cycles
+    // are kept when max cycles are exceeded, as the instruction is executed.
+    pub(crate) fn charge_extra_cycles(
+        &mut self,
+        instruction: Instruction,
+        mut context: CostContext,
+    ) -> Result<(), Error> {
+        if matches!(
+            extract_opcode(instruction),
+            insts::OP_BEQ
+                | insts::OP_BNE
+                | insts::OP_BLT
+                | insts::OP_BGE
+                | insts::OP_BLTU
+                | insts::OP_BGEU
+        ) {
+            let next_pc = context.pc + u64::from(instruction_length(instruction));
+            context.branch_taken = Some(self.pc().to_u64() != next_pc);
+        }
+        let cycles = self.cost_model.extra_cycles(instruction, &context);
+        let new_cycles = self
+            .cycles()
+            .checked_add(cycles)
+            .ok_or(Error::CyclesOverflow)?;
+        self.set_cycles(new_cycles);
+        if new_cycles > self.max_cycles() {
+            return Err(Error::CyclesExceeded);
+        }
+        Ok(())
+    }
+
     // This is the most naive way of running the VM, it only decodes each
     // instruction and run it, no optimization is performed here. It might
     // not be practical in production, but it serves as a baseline and
@@ -610,42 +1129,233 @@ impl<Inner: SupportMachine> DefaultMachine<Inner> {
             return Err(Error::InvalidVersion);
         }
         let mut decoder = build_decoder::<Inner::REG>(self.isa(), self.version());
+        let mut resume = self.take_stop();
+        // The pause is polled at basic block boundaries, which include
+        // syscalls interrupting the machine, and faults delivered to traps.
+        let mut block_start = true;
         self.set_running(true);
         while self.running() {
+            if block_start && self.pause.has_interrupted() {
+                self.pause.free();
+                return Err(Error::Pause);
+            }
             if self.reset_signal() {
                 decoder.reset_instructions_cache();
             }
-            self.step(&mut decoder)?;
+            let pc = self.pc().to_u64();
+            let result = if self.breakpoints.is_empty() {
+                self.step_instruction(&mut decoder)
+            } else {
+                self.debug_step(&mut decoder, &mut resume)
+            };
+            block_start = result
+                .as_ref()
+                .map_or(true, |i| is_basic_block_end_instruction(*i));
+            result
+                .map(|_| ())
+                .or_else(|e| self.trap(e))
+                .map_err(|e| self.locate_error(pc, e))?;
         }
         Ok(self.exit_code())
     }
 
+    // Runs the VM until it exits or `budget` cycles are consumed. Unlike
+    // CyclesExceeded, a paused machine keeps all its states, so one can keep
+    // calling this function to run a program in slices. Each call makes
+    // progress: when the next instruction costs more than the budget, it is
+    // executed past the budget.
+    pub fn run_with_budget(&mut self, budget: u64) -> Result<RunOutcome, Error> {
+        let start = budget_progress(self);
+        match self.run_within_budget(budget)? {
+            RunOutcome::Paused if budget_progress(self) == start => {
+                let mut decoder = build_decoder::<Inner::REG>(self.isa(), self.version());
+                let pc = self.pc().to_u64();
+                let result = self
+                    .step(&mut decoder)
+                    .or_else(|e| self.trap(e))
+                    .map_err(|e| self.locate_error(pc, e));
+                self.leave_step(result)
+            }
+            outcome => Ok(outcome),
+        }
+    }
+
+    // Like run_with_budget, but never executes past the budget, so the
+    // machine might pause without making progress.
+    pub(crate) fn run_within_budget(&mut self, budget: u64) -> Result<RunOutcome, Error> {
+        let max_cycles = enter_budget(self, budget)?;
+        let result = self.run();
+        leave_budget(self, max_cycles, self.stop, result)
+    }
+
+    // Tells the outcome of an instruction executed past the budget, see
+    // run_with_budget.
+    pub(crate) fn leave_step(&mut self, result: Result<(), Error>) -> Result<RunOutcome, Error> {
+        match result {
+            Ok(()) if self.running() => Ok(RunOutcome::Paused),
+            Ok(()) => Ok(RunOutcome::Exited(self.exit_code())),
+            Err(Error::Pause) => self.stop.ok_or(Error::Pause),
+            Err(e) => Err(e),
+        }
+    }
+
     pub fn step(&mut self, decoder: &mut Decoder) -> Result<(), Error> {
+        self.step_instruction(decoder).map(|_| ())
+    }
+
+    // Executes an instruction like step, and returns it.
+    fn step_instruction(&mut self, decoder: &mut Decoder) -> Result<Instruction, Error> {
+        let pc = self.pc().to_u64();
         let instruction = {
-            let pc = self.pc().to_u64();
             let memory = self.memory_mut();
             decoder.decode(memory, pc)?
         };
-        let cycles = self.instruction_cycle_func()(instruction);
+        #[cfg(feature = "hooks")]
+        if self.has_hooks() {
+            match self.call_hooks(instruction, true)? {
+                HookAction::Continue => (),
+                HookAction::Skip => {
+                    let next_pc = self
+                        .pc()
+                        .overflowing_add(&Inner::REG::from_u8(instruction_length(instruction)));
+                    self.update_pc(next_pc);
+                    self.commit_pc();
+                    return Ok(instruction);
+                }
+                HookAction::Stop => return Err(self.stop_by_hook()),
+            }
+        }
+        let cycles = self.cost_model.cycles(instruction);
         self.add_cycles(cycles)?;
-        execute(instruction, self)
+        let context = self.cost_context(instruction);
+        match self.tracer.take() {
+            Some(mut tracer) => {
+                let result =
+                    execute_recorded(instruction, self).and_then(|record| tracer.write(&record));
+                self.tracer = Some(tracer);
+                result.map_err(|e| e.with_pc(pc))?
+            }
+            None => execute(instruction, self).map_err(|e| e.with_pc(pc))?,
+        }
+        self.set_instret(self.instret() + 1);
+        self.charge_frames()?;
+        if let Some(context) = context {
+            self.charge_extra_cycles(instruction, context)?;
+        }
+        #[cfg(feature = "hooks")]
+        if self.has_hooks() && self.call_hooks(instruction, false)? == HookAction::Stop {
+            return Err(self.stop_by_hook());
+        }
+        Ok(instruction)
+    }
+
+    #[cfg(feature = "hooks")]
+    fn has_hooks(&self) -> bool {
+        !self.hooks.is_empty()
+    }
+
+    #[cfg(not(feature = "hooks"))]
+    fn has_hooks(&self) -> bool {
+        false
+    }
+
+    // Returns what the first hook not continuing tells to do.
+    #[cfg(feature = "hooks")]
+    fn call_hooks(&mut self, instruction: Instruction, before: bool) -> Result<HookAction, Error> {
+        for hook in &mut self.hooks {
+            let action = if before {
+                hook.before(&mut self.inner, instruction)?
+            } else {
+                hook.after(&mut self.inner, instruction)?
+            };
+            if action != HookAction::Continue {
+                return Ok(action);
+            }
+        }
+        Ok(HookAction::Continue)
+    }
+
+    #[cfg(feature = "hooks")]
+    fn stop_by_hook(&mut self) -> Error {
+        self.stop = Some(RunOutcome::Hook(self.pc().to_u64()));
+        Error::Pause
+    }
+
+    // Returns the breakpoint the machine stopped at last time, so running
+    // the machine again executes the instruction there instead of stopping
+    // at the same breakpoint again.
+    fn take_stop(&mut self) -> Option<u64> {
+        match self.stop.take() {
+            Some(RunOutcome::Breakpoint(pc)) if pc == self.pc().to_u64() => Some(pc),
+            _ => None,
+        }
+    }
+
+    // Executes one instruction while breakpoints or watchpoints are set. A
+    // stopped machine returns Error::Pause, the reason is kept in `stop`.
+    fn debug_step(
+        &mut self,
+        decoder: &mut Decoder,
+        resume: &mut Option<u64>,
+    ) -> Result<Instruction, Error> {
+        let pc = self.pc().to_u64();
+        if self.breakpoints.is_breakpoint(pc) && resume.take() != Some(pc) {
+            self.stop = Some(RunOutcome::Breakpoint(pc));
+            return Err(Error::Pause);
+        }
+        *resume = None;
+        let access = if self.breakpoints.has_watchpoints() {
+            let instruction = decoder.decode(self.memory_mut(), pc)?;
+            memory_access(instruction, self.registers())
+        } else {
+            None
+        };
+        let instruction = self.step_instruction(decoder)?;
+        if let Some(access) = access {
+            if let Some(watchpoint) = self.breakpoints.watchpoint_hit(&access) {
+                self.stop = Some(RunOutcome::Watchpoint {
+                    watchpoint: *watchpoint,
+                    address: access.address.max(watchpoint.address),
+                });
+                return Err(Error::Pause);
+            }
+        }
+        Ok(instruction)
     }
 }
 
 pub struct DefaultMachineBuilder<Inner> {
     inner: Inner,
-    instruction_cycle_func: Box<InstructionCycleFunc>,
+    pause: Pause,
+    cost_model: Arc<dyn CostModel>,
     debugger: Option<Box<dyn Debugger<Inner>>>,
+    syscall_registry: SyscallRegistry<Inner>,
     syscalls: Vec<Box<dyn Syscalls<Inner>>>,
+    #[cfg(feature = "hooks")]
+    hooks: Vec<Box<dyn Hook<Inner>>>,
+    symbolizer: Option<Symbolizer>,
 }
 
-impl<Inner> DefaultMachineBuilder<Inner> {
+impl<Inner: CoreMachine> DefaultMachineBuilder<Inner> {
     pub fn new(inner: Inner) -> Self {
+        let mut syscall_registry = SyscallRegistry::new();
+        if inner.version() >= VERSION3 {
+            for number in trap::SYSCALLS {
+                syscall_registry
+                    .reserve(number)
+                    .expect("reserve trap syscalls in an empty registry");
+            }
+        }
         Self {
             inner,
-            instruction_cycle_func: Box::new(|_| 0),
+            pause: Pause::new(),
+            cost_model: Arc::new(|_: Instruction| 0u64),
             debugger: None,
+            syscall_registry,
             syscalls: vec![],
+            #[cfg(feature = "hooks")]
+            hooks: vec![],
+            symbolizer: None,
         }
     }
 
@@ -653,27 +1363,72 @@ impl<Inner> DefaultMachineBuilder<Inner> {
         mut self,
         instruction_cycle_func: Box<InstructionCycleFunc>,
     ) -> Self {
-        self.instruction_cycle_func = instruction_cycle_func;
+        self.cost_model = Arc::new(instruction_cycle_func);
+        self
+    }
+
+    pub fn cost_model(mut self, cost_model: Box<dyn CostModel>) -> Self {
+        self.cost_model = Arc::from(cost_model);
         self
     }
 
+    /// Adds a legacy syscall module, consulted in order for syscall numbers
+    /// without a handler. Modules charge cycles themselves.
     pub fn syscall(mut self, syscall: Box<dyn Syscalls<Inner>>) -> Self {
         self.syscalls.push(syscall);
         self
     }
 
+    /// Handles syscall `number` with `handler`, the machine charges `cost`
+    /// for it. Fails with Error::DuplicateSyscall if the number is taken by
+    /// another handler or the exit syscall.
+    pub fn syscall_handler(
+        mut self,
+        number: u64,
+        cost: SyscallCost,
+        handler: Box<dyn Syscall<Inner>>,
+    ) -> Result<Self, Error> {
+        self.syscall_registry.register(number, cost, handler)?;
+        Ok(self)
+    }
+
     pub fn debugger(mut self, debugger: Box<dyn Debugger<Inner>>) -> Self {
         self.debugger = Some(debugger);
         self
     }
 
+    #[cfg(feature = "hooks")]
+    pub fn hook(mut self, hook: Box<dyn Hook<Inner>>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    pub fn pause(mut self, pause: Pause) -> Self {
+        self.pause = pause;
+        self
+    }
+
+    pub fn symbolizer(mut self, symbolizer: Symbolizer) -> Self {
+        self.symbolizer = Some(symbolizer);
+        self
+    }
+
     pub fn build(self) -> DefaultMachine<Inner> {
         DefaultMachine {
             inner: self.inner,
-            instruction_cycle_func: self.instruction_cycle_func,
+            pause: self.pause,
+            cost_model: self.cost_model,
             debugger: self.debugger,
+            syscall_registry: self.syscall_registry,
             syscalls: self.syscalls,
+            #[cfg(feature = "hooks")]
+            hooks: self.hooks,
             exit_code: 0,
+            breakpoints: Breakpoints::new(),
+            stop: None,
+            tracer: None,
+            symbolizer: self.symbolizer,
+            traps: Traps::default(),
         }
     }
 }
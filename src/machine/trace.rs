@@ -1,12 +1,14 @@
 use super::{
     super::{
+        coverage::Coverage,
         decoder::build_decoder,
         instructions::{
             execute, instruction_length, is_basic_block_end_instruction, Instruction, Register,
         },
         Error,
     },
-    CoreMachine, DefaultMachine, Machine, SupportMachine,
+    budget_progress, enter_budget, leave_budget, CoreMachine, DefaultMachine, Machine, RunOutcome,
+    SupportMachine,
 };
 use bytes::Bytes;
 
@@ -36,6 +38,7 @@ pub struct TraceMachine<Inner> {
     pub machine: DefaultMachine<Inner>,
 
     traces: Vec<Trace>,
+    coverage: Option<Coverage>,
 }
 
 impl<Inner: SupportMachine> CoreMachine for TraceMachine<Inner> {
@@ -87,6 +90,14 @@ impl<Inner: SupportMachine> Machine for TraceMachine<Inner> {
     fn ebreak(&mut self) -> Result<(), Error> {
         self.machine.ebreak()
     }
+
+    fn cycle_counter(&self) -> u64 {
+        self.machine.cycle_counter()
+    }
+
+    fn instret_counter(&self) -> u64 {
+        self.machine.instret_counter()
+    }
 }
 
 impl<Inner: SupportMachine> TraceMachine<Inner> {
@@ -94,21 +105,35 @@ impl<Inner: SupportMachine> TraceMachine<Inner> {
         Self {
             machine,
             traces: vec![],
+            coverage: None,
         }
     }
 
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
     pub fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
         self.machine.load_program(program, args)
     }
 
     pub fn run(&mut self) -> Result<i8, Error> {
         let mut decoder = build_decoder::<Inner::REG>(self.isa(), self.version());
+        let mut resume = self.machine.take_stop();
         self.machine.set_running(true);
         // For current trace size this is acceptable, however we might want
         // to tweak the code here if we choose to use a larger trace size or
         // larger trace item length.
         self.traces.resize_with(TRACE_SIZE, Trace::default);
-        while self.machine.running() {
+        'run: while self.machine.running() {
+            if self.machine.pause.has_interrupted() {
+                self.machine.pause.free();
+                return Err(Error::Pause);
+            }
             if self.machine.reset_signal() {
                 decoder.reset_instructions_cache();
                 for i in self.traces.iter_mut() {
@@ -116,13 +141,50 @@ impl<Inner: SupportMachine> TraceMachine<Inner> {
                 }
             }
             let pc = self.machine.pc().to_u64();
+            if self.machine.single_stepping() {
+                // Instructions executed one by one are blocks by themselves,
+                // decoded before being executed for their lengths.
+                let mut length = 0;
+                if self.coverage.is_some() {
+                    match decoder.decode(self.machine.memory_mut(), pc) {
+                        Ok(instruction) => length = instruction_length(instruction),
+                        Err(e) => {
+                            self.machine
+                                .trap(e)
+                                .map_err(|e| self.machine.locate_error(pc, e))?;
+                            continue;
+                        }
+                    }
+                }
+                if let Err(e) = self.machine.debug_step(&mut decoder, &mut resume) {
+                    self.machine
+                        .trap(e)
+                        .map_err(|e| self.machine.locate_error(pc, e))?;
+                    continue;
+                }
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.record(pc, u64::from(length));
+                }
+                continue;
+            }
             let slot = calculate_slot(pc);
             if pc != self.traces[slot].address || self.traces[slot].instruction_count == 0 {
                 self.traces[slot] = Trace::default();
                 let mut current_pc = pc;
                 let mut i = 0;
                 while i < TRACE_ITEM_LENGTH {
-                    let instruction = decoder.decode(self.machine.memory_mut(), current_pc)?;
+                    let instruction = match decoder.decode(self.machine.memory_mut(), current_pc) {
+                        Ok(instruction) => instruction,
+                        // The trace ends before instructions failing to be
+                        // decoded, they are trapped only when executed.
+                        Err(_) if i > 0 && self.machine.traps_enabled() => break,
+                        Err(e) => {
+                            self.machine
+                                .trap(e)
+                                .map_err(|e| self.machine.locate_error(current_pc, e))?;
+                            continue 'run;
+                        }
+                    };
                     let end_instruction = is_basic_block_end_instruction(instruction);
                     current_pc += u64::from(instruction_length(instruction));
                     self.traces[slot].instructions[i] = instruction;
@@ -135,15 +197,57 @@ impl<Inner: SupportMachine> TraceMachine<Inner> {
                 self.traces[slot].length = (current_pc - pc) as usize;
                 self.traces[slot].instruction_count = i as u8;
             }
-            for i in 0..self.traces[slot].instruction_count {
-                let i = self.traces[slot].instructions[i as usize];
-                let cycles = self.machine.instruction_cycle_func()(i);
-                self.machine.add_cycles(cycles)?;
-                execute(i, self)?;
+            for index in 0..self.traces[slot].instruction_count as usize {
+                let i = self.traces[slot].instructions[index];
+                let cycles = self.machine.cost_model().cycles(i);
+                let result = self
+                    .machine
+                    .add_cycles(cycles)
+                    .and_then(|_| execute(i, self));
+                if let Err(e) = result {
+                    // The pc of the failing instruction is only computed on errors.
+                    let trace = &self.traces[slot];
+                    let pc = trace.instructions[..index]
+                        .iter()
+                        .fold(trace.address, |pc, i| {
+                            pc + u64::from(instruction_length(*i))
+                        });
+                    self.machine
+                        .trap(e.with_pc(pc))
+                        .map_err(|e| self.machine.locate_error(pc, e))?;
+                    continue 'run;
+                }
+                self.machine.set_instret(self.machine.instret() + 1);
+                self.machine.charge_frames()?;
+            }
+            if let Some(coverage) = &mut self.coverage {
+                let trace = &self.traces[slot];
+                coverage.record(trace.address, trace.length as u64);
             }
         }
         Ok(self.machine.exit_code())
     }
+
+    // See DefaultMachine::run_with_budget.
+    pub fn run_with_budget(&mut self, budget: u64) -> Result<RunOutcome, Error> {
+        let start = budget_progress(&self.machine);
+        let max_cycles = enter_budget(&mut self.machine, budget)?;
+        let result = self.run();
+        let stop = self.machine.stop;
+        match leave_budget(&mut self.machine, max_cycles, stop, result)? {
+            RunOutcome::Paused if budget_progress(&self.machine) == start => {
+                let mut decoder = build_decoder::<Inner::REG>(self.isa(), self.version());
+                let pc = self.machine.pc().to_u64();
+                let result = self
+                    .machine
+                    .step(&mut decoder)
+                    .or_else(|e| self.machine.trap(e))
+                    .map_err(|e| self.machine.locate_error(pc, e));
+                self.machine.leave_step(result)
+            }
+            outcome => Ok(outcome),
+        }
+    }
 }
 
 #[cfg(test)]
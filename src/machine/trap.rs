@@ -0,0 +1,103 @@
+// Guest trap handling, enabled from VERSION3 on. Instead of terminating the
+// VM, illegal instructions and memory faults are delivered to the handler a
+// guest registers in mtvec, following a minimal subset of the RISC-V machine
+// mode trap CSRs. Guests access the trap CSRs with syscalls, the Zicsr
+// instructions only read the cycle, time and instret counters. The syscall
+// numbers are reserved in the syscall registry of VERSION3 machines:
+//
+// * CSR_READ: a0 = the CSR number, returns its value in a0;
+// * CSR_WRITE: a0 = the CSR number, a1 = the new value, returns the old one
+//   in a0;
+// * MRET: returns from the handler to mepc.
+//
+// When a trap is delivered, mepc is the pc of the faulting instruction, mcause
+// and mtval describe the fault, and execution continues at mtvec. Faults are
+// only delivered when mtvec is not zero, and not while a handler is running
+// until it returns with MRET, such faults still terminate the VM.
+use super::super::error::{AccessKind, Error};
+use serde::{Deserialize, Serialize};
+
+pub const CSR_READ: u64 = 2301;
+pub const CSR_WRITE: u64 = 2302;
+pub const MRET: u64 = 2303;
+
+pub const CSR_MTVEC: u64 = 0x305;
+pub const CSR_MEPC: u64 = 0x341;
+pub const CSR_MCAUSE: u64 = 0x342;
+pub const CSR_MTVAL: u64 = 0x343;
+
+pub const CAUSE_INSTRUCTION_ACCESS_FAULT: u64 = 1;
+pub const CAUSE_ILLEGAL_INSTRUCTION: u64 = 2;
+pub const CAUSE_LOAD_ACCESS_FAULT: u64 = 5;
+pub const CAUSE_STORE_ACCESS_FAULT: u64 = 7;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Traps {
+    pub mtvec: u64,
+    pub mepc: u64,
+    pub mcause: u64,
+    pub mtval: u64,
+    // If a handler is running.
+    pub handling: bool,
+}
+
+// Syscalls reserved by VERSION3 machines.
+pub const SYSCALLS: [u64; 3] = [CSR_READ, CSR_WRITE, MRET];
+
+impl Traps {
+    pub fn enabled(&self) -> bool {
+        self.mtvec != 0 && !self.handling
+    }
+
+    pub fn csr(&self, csr: u64) -> Option<u64> {
+        match csr {
+            CSR_MTVEC => Some(self.mtvec),
+            CSR_MEPC => Some(self.mepc),
+            CSR_MCAUSE => Some(self.mcause),
+            CSR_MTVAL => Some(self.mtval),
+            _ => None,
+        }
+    }
+
+    // Returns the old value of the CSR.
+    pub fn set_csr(&mut self, csr: u64, value: u64) -> Option<u64> {
+        let field = match csr {
+            CSR_MTVEC => &mut self.mtvec,
+            CSR_MEPC => &mut self.mepc,
+            CSR_MCAUSE => &mut self.mcause,
+            CSR_MTVAL => &mut self.mtval,
+            _ => return None,
+        };
+        Some(std::mem::replace(field, value))
+    }
+
+    /// Records the trap for a fault, and returns the address of the handler.
+    /// Errors which are not faults, or faults raised while traps are not
+    /// enabled, are given back.
+    pub fn deliver(&mut self, error: Error) -> Result<u64, Error> {
+        if !self.enabled() {
+            return Err(error);
+        }
+        let (epc, cause, tval) = match &error {
+            Error::InvalidInstruction { pc, instruction } => {
+                (*pc, CAUSE_ILLEGAL_INSTRUCTION, u64::from(*instruction))
+            }
+            _ => match error.memory_fault() {
+                Some(fault) => {
+                    let cause = match fault.kind {
+                        AccessKind::Read => CAUSE_LOAD_ACCESS_FAULT,
+                        AccessKind::Write => CAUSE_STORE_ACCESS_FAULT,
+                        AccessKind::Execute => CAUSE_INSTRUCTION_ACCESS_FAULT,
+                    };
+                    (fault.pc, cause, fault.address)
+                }
+                None => return Err(error),
+            },
+        };
+        self.mepc = epc;
+        self.mcause = cause;
+        self.mtval = tval;
+        self.handling = true;
+        Ok(self.mtvec)
+    }
+}
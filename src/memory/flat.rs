@@ -1,5 +1,8 @@
-use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE};
-use super::{fill_page_data, get_page_indices, memset, set_dirty, Memory};
+use super::super::{error::AccessKind, Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE};
+use super::{
+    check_bounds, fill_page_data, get_page_indices, memset, page_out_of_bound, set_dirty, Memory,
+    TouchedFrames,
+};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use bytes::Bytes;
@@ -7,12 +10,16 @@ use std::io::{Cursor, Seek, SeekFrom};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 
+/// Cloning a FlatMemory is a deep copy of the whole buffer, use SparseMemory
+/// to fork machines cheaply.
+#[derive(Clone)]
 pub struct FlatMemory<R> {
     data: Vec<u8>,
     flags: Vec<u8>,
     memory_size: usize,
     riscv_pages: usize,
     load_reservation_address: R,
+    touched_frames: TouchedFrames,
     _inner: PhantomData<R>,
 }
 
@@ -48,6 +55,7 @@ impl<R: Register> Memory for FlatMemory<R> {
             memory_size,
             riscv_pages: memory_size / RISCV_PAGESIZE,
             load_reservation_address: R::from_u64(u64::MAX),
+            touched_frames: TouchedFrames::default(),
             _inner: PhantomData,
         }
     }
@@ -67,7 +75,7 @@ impl<R: Register> Memory for FlatMemory<R> {
         if page < self.riscv_pages as u64 {
             Ok(self.flags[page as usize])
         } else {
-            Err(Error::MemOutOfBound)
+            Err(page_out_of_bound(page, AccessKind::Read))
         }
     }
 
@@ -76,7 +84,7 @@ impl<R: Register> Memory for FlatMemory<R> {
             self.flags[page as usize] |= flag;
             Ok(())
         } else {
-            Err(Error::MemOutOfBound)
+            Err(page_out_of_bound(page, AccessKind::Write))
         }
     }
 
@@ -85,7 +93,7 @@ impl<R: Register> Memory for FlatMemory<R> {
             self.flags[page as usize] &= !flag;
             Ok(())
         } else {
-            Err(Error::MemOutOfBound)
+            Err(page_out_of_bound(page, AccessKind::Write))
         }
     }
 
@@ -94,97 +102,103 @@ impl<R: Register> Memory for FlatMemory<R> {
     }
 
     fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        check_bounds(self, addr, 2, AccessKind::Execute)?;
         self.load16(&Self::REG::from_u64(addr)).map(|v| v.to_u16())
     }
 
     fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
+        check_bounds(self, addr, 4, AccessKind::Execute)?;
         self.load32(&R::from_u64(addr)).map(|v| v.to_u32())
     }
 
     fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
         let addr = addr.to_u64();
-        if addr.checked_add(1).ok_or(Error::MemOutOfBound)? > self.len() as u64 {
-            return Err(Error::MemOutOfBound);
-        }
+        check_bounds(self, addr, 1, AccessKind::Read)?;
         let mut reader = Cursor::new(&self.data);
         reader.seek(SeekFrom::Start(addr as u64))?;
         let v = reader.read_u8()?;
+        self.touched_frames.touch(addr, 1);
         Ok(Self::REG::from_u8(v))
     }
 
     fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
         let addr = addr.to_u64();
-        if addr.checked_add(2).ok_or(Error::MemOutOfBound)? > self.len() as u64 {
-            return Err(Error::MemOutOfBound);
-        }
+        check_bounds(self, addr, 2, AccessKind::Read)?;
         let mut reader = Cursor::new(&self.data);
         reader.seek(SeekFrom::Start(addr as u64))?;
         // NOTE: Base RISC-V ISA is defined as a little-endian memory system.
         let v = reader.read_u16::<LittleEndian>()?;
+        self.touched_frames.touch(addr, 2);
         Ok(Self::REG::from_u16(v))
     }
 
     fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
         let addr = addr.to_u64();
-        if addr.checked_add(4).ok_or(Error::MemOutOfBound)? > self.len() as u64 {
-            return Err(Error::MemOutOfBound);
-        }
+        check_bounds(self, addr, 4, AccessKind::Read)?;
         let mut reader = Cursor::new(&self.data);
         reader.seek(SeekFrom::Start(addr as u64))?;
         // NOTE: Base RISC-V ISA is defined as a little-endian memory system.
         let v = reader.read_u32::<LittleEndian>()?;
+        self.touched_frames.touch(addr, 4);
         Ok(Self::REG::from_u32(v))
     }
 
     fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
         let addr = addr.to_u64();
-        if addr.checked_add(8).ok_or(Error::MemOutOfBound)? > self.len() as u64 {
-            return Err(Error::MemOutOfBound);
-        }
+        check_bounds(self, addr, 8, AccessKind::Read)?;
         let mut reader = Cursor::new(&self.data);
         reader.seek(SeekFrom::Start(addr as u64))?;
         // NOTE: Base RISC-V ISA is defined as a little-endian memory system.
         let v = reader.read_u64::<LittleEndian>()?;
+        self.touched_frames.touch(addr, 8);
         Ok(Self::REG::from_u64(v))
     }
 
     fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
         let addr = addr.to_u64();
-        let page_indices = get_page_indices(addr.to_u64(), 1)?;
+        check_bounds(self, addr, 1, AccessKind::Write)?;
+        let page_indices = get_page_indices(addr, 1, AccessKind::Write)?;
         set_dirty(self, &page_indices)?;
         let mut writer = Cursor::new(&mut self.data);
         writer.seek(SeekFrom::Start(addr as u64))?;
         writer.write_u8(value.to_u8())?;
+        self.touched_frames.touch(addr, 1);
         Ok(())
     }
 
     fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
         let addr = addr.to_u64();
-        let page_indices = get_page_indices(addr.to_u64(), 2)?;
+        check_bounds(self, addr, 2, AccessKind::Write)?;
+        let page_indices = get_page_indices(addr, 2, AccessKind::Write)?;
         set_dirty(self, &page_indices)?;
         let mut writer = Cursor::new(&mut self.data);
         writer.seek(SeekFrom::Start(addr as u64))?;
         writer.write_u16::<LittleEndian>(value.to_u16())?;
+        self.touched_frames.touch(addr, 2);
         Ok(())
     }
 
     fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
         let addr = addr.to_u64();
-        let page_indices = get_page_indices(addr.to_u64(), 4)?;
+        check_bounds(self, addr, 4, AccessKind::Write)?;
+        let page_indices = get_page_indices(addr, 4, AccessKind::Write)?;
         set_dirty(self, &page_indices)?;
         let mut writer = Cursor::new(&mut self.data);
         writer.seek(SeekFrom::Start(addr as u64))?;
         writer.write_u32::<LittleEndian>(value.to_u32())?;
+        self.touched_frames.touch(addr, 4);
         Ok(())
     }
 
     fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
         let addr = addr.to_u64();
-        let page_indices = get_page_indices(addr.to_u64(), 8)?;
+        check_bounds(self, addr, 8, AccessKind::Write)?;
+        let page_indices = get_page_indices(addr, 8, AccessKind::Write)?;
         set_dirty(self, &page_indices)?;
         let mut writer = Cursor::new(&mut self.data);
         writer.seek(SeekFrom::Start(addr as u64))?;
         writer.write_u64::<LittleEndian>(value.to_u64())?;
+        self.touched_frames.touch(addr, 8);
         Ok(())
     }
 
@@ -193,10 +207,12 @@ impl<R: Register> Memory for FlatMemory<R> {
         if size == 0 {
             return Ok(());
         }
-        let page_indices = get_page_indices(addr.to_u64(), size)?;
+        check_bounds(self, addr, size, AccessKind::Write)?;
+        let page_indices = get_page_indices(addr, size, AccessKind::Write)?;
         set_dirty(self, &page_indices)?;
         let slice = &mut self[addr as usize..(addr + size) as usize];
         slice.copy_from_slice(value);
+        self.touched_frames.touch(addr, size);
         Ok(())
     }
 
@@ -204,19 +220,27 @@ impl<R: Register> Memory for FlatMemory<R> {
         if size == 0 {
             return Ok(());
         }
-        let page_indices = get_page_indices(addr.to_u64(), size)?;
+        check_bounds(self, addr, size, AccessKind::Write)?;
+        let page_indices = get_page_indices(addr, size, AccessKind::Write)?;
         set_dirty(self, &page_indices)?;
         memset(&mut self[addr as usize..(addr + size) as usize], value);
+        self.touched_frames.touch(addr, size);
         Ok(())
     }
 
     fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        let bytes = self.peek_bytes(addr, size)?;
+        if size != 0 {
+            self.touched_frames.touch(addr, size);
+        }
+        Ok(bytes)
+    }
+
+    fn peek_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
         if size == 0 {
             return Ok(Bytes::new());
         }
-        if addr.checked_add(size).ok_or(Error::MemOutOfBound)? > self.memory_size() as u64 {
-            return Err(Error::MemOutOfBound);
-        }
+        check_bounds(self, addr, size, AccessKind::Read)?;
         Ok(Bytes::from(
             self[addr as usize..(addr + size) as usize].to_vec(),
         ))
@@ -229,4 +253,24 @@ impl<R: Register> Memory for FlatMemory<R> {
     fn set_lr(&mut self, value: &Self::REG) {
         self.load_reservation_address = value.clone();
     }
+
+    fn track_frames(&mut self, enabled: bool) {
+        self.touched_frames = if enabled {
+            TouchedFrames::new(self.memory_size())
+        } else {
+            TouchedFrames::default()
+        };
+    }
+
+    fn take_touched_frames(&mut self) -> u64 {
+        self.touched_frames.take_count()
+    }
+
+    fn touched_frame_indices(&self) -> Vec<u64> {
+        self.touched_frames.indices()
+    }
+
+    fn restore_touched_frames(&mut self, indices: &[u64]) {
+        self.touched_frames.restore(indices);
+    }
 }
@@ -1,5 +1,6 @@
 use super::{
     bits::{rounddown, roundup},
+    error::{AccessKind, MemoryFault},
     Error, Register, RISCV_PAGESIZE,
 };
 use bytes::Bytes;
@@ -8,11 +9,14 @@ use std::ptr;
 
 pub mod flat;
 pub mod sparse;
+pub mod tracing;
 pub mod wxorx;
 
 pub use ckb_vm_definitions::{
-    memory::{FLAG_DIRTY, FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_WRITABLE, FLAG_WXORX_BIT},
-    MEMORY_FRAME_PAGE_SHIFTS, RISCV_MAX_MEMORY, RISCV_PAGE_SHIFTS,
+    memory::{
+        FLAG_DIRTY, FLAG_EXECUTABLE, FLAG_FREEZED, FLAG_TOUCHED, FLAG_WRITABLE, FLAG_WXORX_BIT,
+    },
+    MEMORY_FRAME_PAGE_SHIFTS, MEMORY_FRAME_SHIFTS, RISCV_MAX_MEMORY, RISCV_PAGE_SHIFTS,
 };
 
 #[inline(always)]
@@ -49,6 +53,12 @@ pub trait Memory {
     fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error>;
     fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error>;
     fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error>;
+    // Reads memory like load_bytes for tools inspecting a machine, without
+    // touching frames or reporting the access. Memories whose load_bytes has
+    // such side effects shall override it.
+    fn peek_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        self.load_bytes(addr, size)
+    }
     fn execute_load16(&mut self, addr: u64) -> Result<u16, Error>;
     fn execute_load32(&mut self, addr: u64) -> Result<u32, Error>;
 
@@ -68,6 +78,85 @@ pub trait Memory {
     // Load reservation address for atomic extension.
     fn lr(&self) -> &Self::REG;
     fn set_lr(&mut self, value: &Self::REG);
+
+    // Tracks memory frames touched for the first time, see
+    // SupportMachine::frame_cycles. A frame is touched when any byte in it is
+    // loaded, stored or fetched, like when the asm machine initializes it.
+    // Nothing is tracked by default.
+    fn track_frames(&mut self, _enabled: bool) {}
+
+    // The number of frames touched for the first time since the last call.
+    fn take_touched_frames(&mut self) -> u64 {
+        0
+    }
+
+    // Indices of the frames touched so far, kept in snapshots. Empty when
+    // frames are not tracked.
+    fn touched_frame_indices(&self) -> Vec<u64> {
+        Vec::new()
+    }
+
+    // Marks frames restored from a snapshot as touched, without counting
+    // them as touched for the first time.
+    fn restore_touched_frames(&mut self, _indices: &[u64]) {}
+}
+
+// Frames touched by a memory, see Memory::track_frames. Nothing is tracked
+// when there are no frames.
+#[derive(Clone, Default)]
+pub(crate) struct TouchedFrames {
+    frames: Vec<bool>,
+    count: u64,
+}
+
+impl TouchedFrames {
+    pub(crate) fn new(memory_size: usize) -> Self {
+        let frames = (memory_size + (1 << MEMORY_FRAME_SHIFTS) - 1) >> MEMORY_FRAME_SHIFTS;
+        Self {
+            frames: vec![false; frames],
+            count: 0,
+        }
+    }
+
+    // Marks frames of the `size` bytes at `addr` as touched, `size` shall
+    // not be 0.
+    #[inline]
+    pub(crate) fn touch(&mut self, addr: u64, size: u64) {
+        if self.frames.is_empty() {
+            return;
+        }
+        let first = addr >> MEMORY_FRAME_SHIFTS;
+        let last = addr.saturating_add(size - 1) >> MEMORY_FRAME_SHIFTS;
+        for frame in first..=last {
+            if let Some(touched) = self.frames.get_mut(frame as usize) {
+                if !*touched {
+                    *touched = true;
+                    self.count += 1;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn take_count(&mut self) -> u64 {
+        std::mem::take(&mut self.count)
+    }
+
+    pub(crate) fn indices(&self) -> Vec<u64> {
+        self.frames
+            .iter()
+            .enumerate()
+            .filter(|(_, touched)| **touched)
+            .map(|(index, _)| index as u64)
+            .collect()
+    }
+
+    pub(crate) fn restore(&mut self, indices: &[u64]) {
+        for index in indices {
+            if let Some(touched) = self.frames.get_mut(*index as usize) {
+                *touched = true;
+            }
+        }
+    }
 }
 
 #[inline(always)]
@@ -98,28 +187,71 @@ pub fn fill_page_data<M: Memory>(
 }
 
 // `size` should be none zero u64
-pub fn get_page_indices(addr: u64, size: u64) -> Result<(u64, u64), Error> {
+pub fn get_page_indices(addr: u64, size: u64, kind: AccessKind) -> Result<(u64, u64), Error> {
     let (addr_end, overflowed) = addr.overflowing_add(size);
-    if overflowed {
-        return Err(Error::MemOutOfBound);
-    }
-    if addr_end > RISCV_MAX_MEMORY as u64 {
-        return Err(Error::MemOutOfBound);
+    if overflowed || addr_end > RISCV_MAX_MEMORY as u64 {
+        return Err(Error::MemOutOfBound(MemoryFault::new(addr, size, kind)));
     }
     let page = addr >> RISCV_PAGE_SHIFTS;
     let page_end = (addr_end - 1) >> RISCV_PAGE_SHIFTS;
     Ok((page, page_end))
 }
 
+// Checks the `size` bytes at `addr` are in memory.
+pub fn check_bounds<M: Memory>(
+    memory: &M,
+    addr: u64,
+    size: u64,
+    kind: AccessKind,
+) -> Result<(), Error> {
+    match addr.checked_add(size) {
+        Some(addr_end) if addr_end <= memory.memory_size() as u64 => Ok(()),
+        _ => Err(Error::MemOutOfBound(MemoryFault::new(addr, size, kind))),
+    }
+}
+
+// The error of flag methods called with a page out of memory.
+pub fn page_out_of_bound(page: u64, kind: AccessKind) -> Error {
+    Error::MemOutOfBound(MemoryFault::new(
+        page << RISCV_PAGE_SHIFTS,
+        RISCV_PAGESIZE as u64,
+        kind,
+    ))
+}
+
+// The error of writing to an executable page, or executing a writable page,
+// `flag` is the permission required by the access.
+pub fn permission_error(addr: u64, size: u64, flag: u8) -> Error {
+    if flag & FLAG_WXORX_BIT == FLAG_EXECUTABLE {
+        Error::MemExecuteOnWritablePage(MemoryFault::new(addr, size, AccessKind::Execute))
+    } else {
+        Error::MemWriteOnExecutablePage(MemoryFault::new(addr, size, AccessKind::Write))
+    }
+}
+
+// Checks pages of the `size` bytes at `addr` one by one, they shall be in
+// memory and have the permission `flag`, which is FLAG_WRITABLE or
+// FLAG_EXECUTABLE. `size` should be none zero u64.
 pub fn check_permission<M: Memory>(
     memory: &mut M,
-    page_indices: &(u64, u64),
+    addr: u64,
+    size: u64,
     flag: u8,
 ) -> Result<(), Error> {
-    for page in page_indices.0..=page_indices.1 {
+    let kind = if flag & FLAG_WXORX_BIT == FLAG_EXECUTABLE {
+        AccessKind::Execute
+    } else {
+        AccessKind::Write
+    };
+    let (page, page_end) = get_page_indices(addr, size, kind)?;
+    let pages = memory.memory_size() as u64 >> RISCV_PAGE_SHIFTS;
+    for page in page..=page_end {
+        if page >= pages {
+            return Err(Error::MemOutOfBound(MemoryFault::new(addr, size, kind)));
+        }
         let page_flag = memory.fetch_flag(page)?;
         if (page_flag & FLAG_WXORX_BIT) != (flag & FLAG_WXORX_BIT) {
-            return Err(Error::MemWriteOnExecutablePage);
+            return Err(permission_error(addr, size, flag));
         }
     }
     Ok(())
@@ -132,6 +264,15 @@ pub fn set_dirty<M: Memory>(memory: &mut M, page_indices: &(u64, u64)) -> Result
     Ok(())
 }
 
+// Clears FLAG_DIRTY of a page, which is marked with FLAG_TOUCHED instead.
+pub fn clear_dirty<M: Memory>(memory: &mut M, page: u64) -> Result<(), Error> {
+    if memory.fetch_flag(page)? & FLAG_DIRTY != 0 {
+        memory.clear_flag(page, FLAG_DIRTY)?;
+        memory.set_flag(page, FLAG_TOUCHED)?;
+    }
+    Ok(())
+}
+
 // Keep this in a central place to allow for future optimization
 #[inline(always)]
 pub fn memset(slice: &mut [u8], value: u8) {
@@ -1,45 +1,68 @@
-use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS};
-use super::{fill_page_data, memset, round_page_down, Memory, Page, FLAG_DIRTY};
+use super::super::{
+    error::AccessKind, Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE, RISCV_PAGE_SHIFTS,
+};
+use super::{
+    check_bounds, fill_page_data, memset, page_out_of_bound, round_page_down, Memory, Page,
+    TouchedFrames, FLAG_DIRTY,
+};
 
 use bytes::Bytes;
 use std::cmp::min;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 const INVALID_PAGE_INDEX: u16 = 0xFFFF;
 
 /// A sparse flat memory implementation, it allocates pages only when requested,
 /// but besides that, it does not permission checking.
+///
+/// Cloning a SparseMemory is cheap: pages are shared between the clones, and
+/// a page is only copied when one of the clones writes to it.
+#[derive(Clone)]
 pub struct SparseMemory<R> {
     // Stores the indices of each page in pages data structure, if a page hasn't
     // been initialized, the corresponding position will be filled with
     // INVALID_PAGE_INDEX. Considering u16 takes 2 bytes, this add an additional
     // of 64KB extra storage cost assuming we have 128MB memory.
     indices: Vec<u16>,
-    pages: Vec<Page>,
+    pages: Vec<Arc<Page>>,
     flags: Vec<u8>,
     memory_size: usize,
     riscv_pages: usize,
     load_reservation_address: R,
+    touched_frames: TouchedFrames,
     _inner: PhantomData<R>,
 }
 
-impl<R> SparseMemory<R> {
-    fn fetch_page(&mut self, aligned_addr: u64) -> Result<&mut Page, Error> {
+impl<R: Register> SparseMemory<R> {
+    fn fetch_page_index(&mut self, aligned_addr: u64, kind: AccessKind) -> Result<usize, Error> {
         let page = aligned_addr / RISCV_PAGESIZE as u64;
         if page >= self.riscv_pages as u64 {
-            return Err(Error::MemOutOfBound);
+            return Err(page_out_of_bound(page, kind));
         }
         let mut index = self.indices[page as usize];
         if index == INVALID_PAGE_INDEX {
-            self.pages.push([0; RISCV_PAGESIZE]);
+            self.pages.push(Arc::new([0; RISCV_PAGESIZE]));
             index = (self.pages.len() - 1) as u16;
             self.indices[page as usize] = index;
         }
-        Ok(&mut self.pages[index as usize])
+        Ok(index as usize)
     }
 
-    fn load(&mut self, addr: u64, bytes: u64) -> Result<u64, Error> {
+    fn fetch_page(&mut self, aligned_addr: u64) -> Result<&Page, Error> {
+        let index = self.fetch_page_index(aligned_addr, AccessKind::Read)?;
+        Ok(&self.pages[index])
+    }
+
+    // Pages shared with clones of this memory are copied before written.
+    fn fetch_page_mut(&mut self, aligned_addr: u64) -> Result<&mut Page, Error> {
+        let index = self.fetch_page_index(aligned_addr, AccessKind::Write)?;
+        Ok(Arc::make_mut(&mut self.pages[index]))
+    }
+
+    fn load(&mut self, addr: u64, bytes: u64, kind: AccessKind) -> Result<u64, Error> {
         debug_assert!(bytes == 1 || bytes == 2 || bytes == 4 || bytes == 8);
+        check_bounds(self, addr, bytes, kind)?;
         let page_addr = round_page_down(addr);
         let first_page_bytes = min(bytes, RISCV_PAGESIZE as u64 - (addr - page_addr));
         let mut shift = 0;
@@ -63,6 +86,7 @@ impl<R> SparseMemory<R> {
                 shift += 8;
             }
         }
+        self.touched_frames.touch(addr, bytes);
         Ok(value)
     }
 }
@@ -84,6 +108,7 @@ impl<R: Register> Memory for SparseMemory<R> {
             memory_size,
             riscv_pages: memory_size / RISCV_PAGESIZE,
             load_reservation_address: R::from_u64(u64::MAX),
+            touched_frames: TouchedFrames::default(),
             _inner: PhantomData,
         }
     }
@@ -103,7 +128,7 @@ impl<R: Register> Memory for SparseMemory<R> {
         if page < self.riscv_pages as u64 {
             Ok(self.flags[page as usize])
         } else {
-            Err(Error::MemOutOfBound)
+            Err(page_out_of_bound(page, AccessKind::Read))
         }
     }
 
@@ -112,7 +137,7 @@ impl<R: Register> Memory for SparseMemory<R> {
             self.flags[page as usize] |= flag;
             Ok(())
         } else {
-            Err(Error::MemOutOfBound)
+            Err(page_out_of_bound(page, AccessKind::Write))
         }
     }
 
@@ -121,7 +146,7 @@ impl<R: Register> Memory for SparseMemory<R> {
             self.flags[page as usize] &= !flag;
             Ok(())
         } else {
-            Err(Error::MemOutOfBound)
+            Err(page_out_of_bound(page, AccessKind::Write))
         }
     }
 
@@ -130,39 +155,49 @@ impl<R: Register> Memory for SparseMemory<R> {
     }
 
     fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
-        let v = self.load(addr.to_u64(), 1).map(|v| v as u8)?;
+        let v = self
+            .load(addr.to_u64(), 1, AccessKind::Read)
+            .map(|v| v as u8)?;
         Ok(Self::REG::from_u8(v))
     }
 
     fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
-        let v = self.load(addr.to_u64(), 2).map(|v| v as u16)?;
+        let v = self
+            .load(addr.to_u64(), 2, AccessKind::Read)
+            .map(|v| v as u16)?;
         Ok(Self::REG::from_u16(v))
     }
 
     fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
-        let v = self.load(addr.to_u64(), 4).map(|v| v as u32)?;
+        let v = self
+            .load(addr.to_u64(), 4, AccessKind::Read)
+            .map(|v| v as u32)?;
         Ok(Self::REG::from_u32(v))
     }
 
     fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
-        let v = self.load(addr.to_u64(), 8)?;
+        let v = self.load(addr.to_u64(), 8, AccessKind::Read)?;
         Ok(Self::REG::from_u64(v))
     }
 
     fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
-        self.load(addr, 2).map(|v| v as u16)
+        self.load(addr, 2, AccessKind::Execute).map(|v| v as u16)
     }
 
     fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
-        self.load(addr, 4).map(|v| v as u32)
+        self.load(addr, 4, AccessKind::Execute).map(|v| v as u32)
     }
 
     fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        if value.is_empty() {
+            return Ok(());
+        }
+        check_bounds(self, addr, value.len() as u64, AccessKind::Write)?;
         let mut remaining_data = value;
         let mut current_page_addr = round_page_down(addr);
         let mut current_page_offset = addr - current_page_addr;
         while !remaining_data.is_empty() {
-            let page = self.fetch_page(current_page_addr)?;
+            let page = self.fetch_page_mut(current_page_addr)?;
             let bytes = min(
                 RISCV_PAGESIZE as u64 - current_page_offset,
                 remaining_data.len() as u64,
@@ -176,15 +211,20 @@ impl<R: Register> Memory for SparseMemory<R> {
             current_page_addr += RISCV_PAGESIZE as u64;
             current_page_offset = 0;
         }
+        self.touched_frames.touch(addr, value.len() as u64);
         Ok(())
     }
 
     fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        if size == 0 {
+            return Ok(());
+        }
+        check_bounds(self, addr, size, AccessKind::Write)?;
         let mut current_page_addr = round_page_down(addr);
         let mut current_page_offset = addr - current_page_addr;
         let mut remaining_size = size;
         while remaining_size > 0 {
-            let page = self.fetch_page(current_page_addr)?;
+            let page = self.fetch_page_mut(current_page_addr)?;
             let bytes = min(RISCV_PAGESIZE as u64 - current_page_offset, remaining_size);
             memset(
                 &mut page[current_page_offset as usize..(current_page_offset + bytes) as usize],
@@ -196,16 +236,23 @@ impl<R: Register> Memory for SparseMemory<R> {
             current_page_addr += RISCV_PAGESIZE as u64;
             current_page_offset = 0;
         }
+        self.touched_frames.touch(addr, size);
         Ok(())
     }
 
     fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        let bytes = self.peek_bytes(addr, size)?;
+        if size != 0 {
+            self.touched_frames.touch(addr, size);
+        }
+        Ok(bytes)
+    }
+
+    fn peek_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
         if size == 0 {
             return Ok(Bytes::new());
         }
-        if addr.checked_add(size).ok_or(Error::MemOutOfBound)? > self.memory_size() as u64 {
-            return Err(Error::MemOutOfBound);
-        }
+        check_bounds(self, addr, size, AccessKind::Read)?;
         let mut current_page_addr = round_page_down(addr);
         let mut current_page_offset = addr - current_page_addr;
         let mut need_read_len = size;
@@ -272,4 +319,24 @@ impl<R: Register> Memory for SparseMemory<R> {
     fn set_lr(&mut self, value: &Self::REG) {
         self.load_reservation_address = value.clone();
     }
+
+    fn track_frames(&mut self, enabled: bool) {
+        self.touched_frames = if enabled {
+            TouchedFrames::new(self.memory_size())
+        } else {
+            TouchedFrames::default()
+        };
+    }
+
+    fn take_touched_frames(&mut self) -> u64 {
+        self.touched_frames.take_count()
+    }
+
+    fn touched_frame_indices(&self) -> Vec<u64> {
+        self.touched_frames.indices()
+    }
+
+    fn restore_touched_frames(&mut self, indices: &[u64]) {
+        self.touched_frames.restore(indices);
+    }
 }
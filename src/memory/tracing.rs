@@ -0,0 +1,277 @@
+#[cfg(feature = "hooks")]
+use super::super::{
+    hook::{Hook, HookAction},
+    instructions::Instruction,
+    machine::SupportMachine,
+};
+use super::super::{Error, Register, RISCV_MAX_MEMORY};
+use super::Memory;
+
+use bytes::Bytes;
+use std::sync::Arc;
+
+// TracingMemory reports every load and store done through it to a callback,
+// and counts them. Instruction fetches are not reported. Loads and stores of
+// the program are reported with the pc of the instruction doing them, which
+// is kept up to date by registering TracePc as a hook of the machine, with
+// the hooks feature; those done by syscalls come with the pc of the ecall.
+//
+// Accesses are reported after they succeed, an error returned by the
+// callback fails the access, which makes it possible to stop a machine at
+// the access like a watchpoint.
+
+/// A load or a store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryEvent<'a> {
+    pub pc: u64,
+    pub address: u64,
+    pub write: bool,
+    /// Bytes loaded or stored.
+    pub data: &'a [u8],
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub loads: u64,
+    pub stores: u64,
+    pub bytes_loaded: u64,
+    pub bytes_stored: u64,
+}
+
+pub type MemoryCallback = dyn Fn(&MemoryEvent) -> Result<(), Error> + Send + Sync;
+
+#[derive(Clone)]
+pub struct TracingMemory<M: Memory> {
+    inner: M,
+    pc: u64,
+    callback: Option<Arc<MemoryCallback>>,
+    stats: MemoryStats,
+}
+
+impl<M: Memory> TracingMemory<M> {
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    pub fn set_callback(&mut self, callback: Arc<MemoryCallback>) {
+        self.callback = Some(callback);
+    }
+
+    pub fn pc(&self) -> u64 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u64) {
+        self.pc = pc;
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = MemoryStats::default();
+    }
+
+    fn report(&mut self, address: u64, write: bool, data: &[u8]) -> Result<(), Error> {
+        let size = data.len() as u64;
+        if write {
+            self.stats.stores += 1;
+            self.stats.bytes_stored += size;
+        } else {
+            self.stats.loads += 1;
+            self.stats.bytes_loaded += size;
+        }
+        match &self.callback {
+            Some(callback) => callback(&MemoryEvent {
+                pc: self.pc,
+                address,
+                write,
+                data,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn report_value(
+        &mut self,
+        address: &M::REG,
+        write: bool,
+        value: &M::REG,
+        size: usize,
+    ) -> Result<(), Error> {
+        let bytes = value.to_u64().to_le_bytes();
+        self.report(address.to_u64(), write, &bytes[..size])
+    }
+}
+
+impl<M: Memory> Memory for TracingMemory<M> {
+    type REG = M::REG;
+
+    fn new() -> Self {
+        Self::new_with_memory(RISCV_MAX_MEMORY)
+    }
+
+    fn new_with_memory(memory_size: usize) -> Self {
+        Self {
+            inner: M::new_with_memory(memory_size),
+            pc: 0,
+            callback: None,
+            stats: MemoryStats::default(),
+        }
+    }
+
+    fn init_pages(
+        &mut self,
+        addr: u64,
+        size: u64,
+        flags: u8,
+        source: Option<Bytes>,
+        offset_from_addr: u64,
+    ) -> Result<(), Error> {
+        self.inner
+            .init_pages(addr, size, flags, source, offset_from_addr)
+    }
+
+    fn fetch_flag(&mut self, page: u64) -> Result<u8, Error> {
+        self.inner.fetch_flag(page)
+    }
+
+    fn set_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.set_flag(page, flag)
+    }
+
+    fn clear_flag(&mut self, page: u64, flag: u8) -> Result<(), Error> {
+        self.inner.clear_flag(page, flag)
+    }
+
+    fn memory_size(&self) -> usize {
+        self.inner.memory_size()
+    }
+
+    fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
+        self.inner.execute_load16(addr)
+    }
+
+    fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
+        self.inner.execute_load32(addr)
+    }
+
+    fn load8(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let value = self.inner.load8(addr)?;
+        self.report_value(addr, false, &value, 1)?;
+        Ok(value)
+    }
+
+    fn load16(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let value = self.inner.load16(addr)?;
+        self.report_value(addr, false, &value, 2)?;
+        Ok(value)
+    }
+
+    fn load32(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let value = self.inner.load32(addr)?;
+        self.report_value(addr, false, &value, 4)?;
+        Ok(value)
+    }
+
+    fn load64(&mut self, addr: &Self::REG) -> Result<Self::REG, Error> {
+        let value = self.inner.load64(addr)?;
+        self.report_value(addr, false, &value, 8)?;
+        Ok(value)
+    }
+
+    fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.inner.store8(addr, value)?;
+        self.report_value(addr, true, value, 1)
+    }
+
+    fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.inner.store16(addr, value)?;
+        self.report_value(addr, true, value, 2)
+    }
+
+    fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.inner.store32(addr, value)?;
+        self.report_value(addr, true, value, 4)
+    }
+
+    fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
+        self.inner.store64(addr, value)?;
+        self.report_value(addr, true, value, 8)
+    }
+
+    fn store_bytes(&mut self, addr: u64, value: &[u8]) -> Result<(), Error> {
+        self.inner.store_bytes(addr, value)?;
+        if value.is_empty() {
+            return Ok(());
+        }
+        self.report(addr, true, value)
+    }
+
+    fn store_byte(&mut self, addr: u64, size: u64, value: u8) -> Result<(), Error> {
+        self.inner.store_byte(addr, size, value)?;
+        if size == 0 {
+            return Ok(());
+        }
+        self.report(addr, true, &vec![value; size as usize])
+    }
+
+    fn load_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        let bytes = self.inner.load_bytes(addr, size)?;
+        if !bytes.is_empty() {
+            self.report(addr, false, &bytes)?;
+        }
+        Ok(bytes)
+    }
+
+    fn peek_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        self.inner.peek_bytes(addr, size)
+    }
+
+    fn lr(&self) -> &Self::REG {
+        self.inner.lr()
+    }
+
+    fn set_lr(&mut self, value: &Self::REG) {
+        self.inner.set_lr(value);
+    }
+
+    fn track_frames(&mut self, enabled: bool) {
+        self.inner.track_frames(enabled);
+    }
+
+    fn take_touched_frames(&mut self) -> u64 {
+        self.inner.take_touched_frames()
+    }
+
+    fn touched_frame_indices(&self) -> Vec<u64> {
+        self.inner.touched_frame_indices()
+    }
+
+    fn restore_touched_frames(&mut self, indices: &[u64]) {
+        self.inner.restore_touched_frames(indices);
+    }
+}
+
+/// A hook keeping the pc reported by TracingMemory up to date, see
+/// DefaultMachineBuilder::hook.
+#[cfg(feature = "hooks")]
+pub struct TracePc;
+
+#[cfg(feature = "hooks")]
+impl<M, Mac> Hook<Mac> for TracePc
+where
+    M: Memory,
+    Mac: SupportMachine<MEM = TracingMemory<M>>,
+{
+    fn before(
+        &mut self,
+        machine: &mut Mac,
+        _instruction: Instruction,
+    ) -> Result<HookAction, Error> {
+        let pc = machine.pc().to_u64();
+        machine.memory_mut().set_pc(pc);
+        Ok(HookAction::Continue)
+    }
+}
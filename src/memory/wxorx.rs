@@ -1,11 +1,15 @@
-use super::super::{Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE};
+use super::super::{
+    error::{AccessKind, MemoryFault},
+    Error, Register, RISCV_MAX_MEMORY, RISCV_PAGESIZE,
+};
 use super::{
-    check_permission, get_page_indices, round_page_down, round_page_up, Memory, FLAG_EXECUTABLE,
-    FLAG_FREEZED, FLAG_WRITABLE,
+    check_permission, round_page_down, round_page_up, Memory, FLAG_EXECUTABLE, FLAG_FREEZED,
+    FLAG_WRITABLE,
 };
 
 use bytes::Bytes;
 
+#[derive(Clone)]
 pub struct WXorXMemory<M: Memory> {
     inner: M,
 }
@@ -37,8 +41,9 @@ impl<M: Memory> Memory for WXorXMemory<M> {
         source: Option<Bytes>,
         offset_from_addr: u64,
     ) -> Result<(), Error> {
+        let fault = MemoryFault::new(addr, size, AccessKind::Write);
         if round_page_down(addr) != addr || round_page_up(size) != size {
-            return Err(Error::MemPageUnalignedAccess);
+            return Err(Error::MemPageUnalignedAccess(fault));
         }
 
         if addr > self.memory_size() as u64
@@ -46,12 +51,12 @@ impl<M: Memory> Memory for WXorXMemory<M> {
             || addr + size > self.memory_size() as u64
             || offset_from_addr > size
         {
-            return Err(Error::MemOutOfBound);
+            return Err(Error::MemOutOfBound(fault));
         }
         for page_addr in (addr..addr + size).step_by(RISCV_PAGESIZE) {
             let page = page_addr / RISCV_PAGESIZE as u64;
             if self.fetch_flag(page)? & FLAG_FREEZED != 0 {
-                return Err(Error::MemWriteOnFreezedPage);
+                return Err(Error::MemWriteOnFreezedPage(fault));
             }
             self.set_flag(page, flags)?;
         }
@@ -76,14 +81,12 @@ impl<M: Memory> Memory for WXorXMemory<M> {
     }
 
     fn execute_load16(&mut self, addr: u64) -> Result<u16, Error> {
-        let page_indices = get_page_indices(addr, 2)?;
-        check_permission(self, &page_indices, FLAG_EXECUTABLE)?;
+        check_permission(self, addr, 2, FLAG_EXECUTABLE)?;
         self.inner.execute_load16(addr)
     }
 
     fn execute_load32(&mut self, addr: u64) -> Result<u32, Error> {
-        let page_indices = get_page_indices(addr, 4)?;
-        check_permission(self, &page_indices, FLAG_EXECUTABLE)?;
+        check_permission(self, addr, 4, FLAG_EXECUTABLE)?;
         self.inner.execute_load32(addr)
     }
 
@@ -104,26 +107,22 @@ impl<M: Memory> Memory for WXorXMemory<M> {
     }
 
     fn store8(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
-        let page_indices = get_page_indices(addr.to_u64(), 1)?;
-        check_permission(self, &page_indices, FLAG_WRITABLE)?;
+        check_permission(self, addr.to_u64(), 1, FLAG_WRITABLE)?;
         self.inner.store8(addr, value)
     }
 
     fn store16(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
-        let page_indices = get_page_indices(addr.to_u64(), 2)?;
-        check_permission(self, &page_indices, FLAG_WRITABLE)?;
+        check_permission(self, addr.to_u64(), 2, FLAG_WRITABLE)?;
         self.inner.store16(addr, value)
     }
 
     fn store32(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
-        let page_indices = get_page_indices(addr.to_u64(), 4)?;
-        check_permission(self, &page_indices, FLAG_WRITABLE)?;
+        check_permission(self, addr.to_u64(), 4, FLAG_WRITABLE)?;
         self.inner.store32(addr, value)
     }
 
     fn store64(&mut self, addr: &Self::REG, value: &Self::REG) -> Result<(), Error> {
-        let page_indices = get_page_indices(addr.to_u64(), 8)?;
-        check_permission(self, &page_indices, FLAG_WRITABLE)?;
+        check_permission(self, addr.to_u64(), 8, FLAG_WRITABLE)?;
         self.inner.store64(addr, value)
     }
 
@@ -131,8 +130,7 @@ impl<M: Memory> Memory for WXorXMemory<M> {
         if value.is_empty() {
             return Ok(());
         }
-        let page_indices = get_page_indices(addr, value.len() as u64)?;
-        check_permission(self, &page_indices, FLAG_WRITABLE)?;
+        check_permission(self, addr, value.len() as u64, FLAG_WRITABLE)?;
         self.inner.store_bytes(addr, value)
     }
 
@@ -140,8 +138,7 @@ impl<M: Memory> Memory for WXorXMemory<M> {
         if size == 0 {
             return Ok(());
         }
-        let page_indices = get_page_indices(addr, size)?;
-        check_permission(self, &page_indices, FLAG_WRITABLE)?;
+        check_permission(self, addr, size, FLAG_WRITABLE)?;
         self.inner.store_byte(addr, size, value)
     }
 
@@ -150,6 +147,10 @@ impl<M: Memory> Memory for WXorXMemory<M> {
         self.inner.load_bytes(addr, size)
     }
 
+    fn peek_bytes(&mut self, addr: u64, size: u64) -> Result<Bytes, Error> {
+        self.inner.peek_bytes(addr, size)
+    }
+
     fn lr(&self) -> &Self::REG {
         self.inner.lr()
     }
@@ -157,4 +158,20 @@ impl<M: Memory> Memory for WXorXMemory<M> {
     fn set_lr(&mut self, value: &Self::REG) {
         self.inner.set_lr(value);
     }
+
+    fn track_frames(&mut self, enabled: bool) {
+        self.inner.track_frames(enabled);
+    }
+
+    fn take_touched_frames(&mut self) -> u64 {
+        self.inner.take_touched_frames()
+    }
+
+    fn touched_frame_indices(&self) -> Vec<u64> {
+        self.inner.touched_frame_indices()
+    }
+
+    fn restore_touched_frames(&mut self, indices: &[u64]) {
+        self.inner.restore_touched_frames(indices);
+    }
 }
@@ -0,0 +1,238 @@
+use crate::decoder::build_decoder;
+#[cfg(has_asm)]
+use crate::instructions::instruction_length;
+use crate::instructions::{extract_opcode, insts, Instruction, Itype, Register, Utype};
+#[cfg(has_asm)]
+use crate::machine::asm::AsmMachine;
+use crate::machine::{DefaultMachine, VERSION0};
+use crate::symbolizer::Symbolizer;
+use crate::{Bytes, CoreMachine, Error, SupportMachine, ISA_MOP};
+use ckb_vm_definitions::registers::{RA, ZERO};
+use std::collections::HashMap;
+use std::io::Write;
+
+// Profiler attributes cycles consumed by a program to the functions in its
+// ELF symbol table, resolved by a Symbolizer, and keeps them apart by call
+// stack, so the result can be rendered as a flamegraph.
+//
+// The call stack is tracked from the instructions executed: a jal or jalr
+// writing the return address to ra is a call, a jalr jumping to ra without
+// linking is a return, and a jump without linking to the start of another
+// function is a tail call, which replaces the function on top of the stack.
+// Code built without following the calling convention confuses the profiler,
+// but never the program.
+//
+// With the Rust interpreter, instructions are observed one by one. With
+// AsmMachine, the asm interpreter returns after each basic block, so cycles
+// are sampled at trace boundaries, and only the last instruction of a block
+// may change the call stack.
+//
+// The output is in the folded stack format, one line for each call stack:
+// function names from the outermost to the innermost joined by ';', then a
+// space and the cycles consumed there. Tools such as flamegraph.pl and
+// inferno-flamegraph accept it directly.
+
+const UNKNOWN_FUNCTION: &str = "??";
+
+struct Frame {
+    parent: Option<usize>,
+    // Address of the function, None for code without a symbol.
+    function: Option<u64>,
+    cycles: u64,
+    children: HashMap<Option<u64>, usize>,
+}
+
+enum Transfer {
+    Call,
+    Return,
+    Jump,
+}
+
+fn classify(instruction: Instruction) -> Option<Transfer> {
+    let (rd, rs1) = match extract_opcode(instruction) {
+        insts::OP_JAL => (Utype(instruction).rd(), None),
+        insts::OP_JALR_VERSION0 | insts::OP_JALR_VERSION1 => {
+            let i = Itype(instruction);
+            (i.rd(), Some(i.rs1()))
+        }
+        insts::OP_FAR_JUMP_REL | insts::OP_FAR_JUMP_ABS => (RA, None),
+        _ => return None,
+    };
+    match (rd, rs1) {
+        (RA, _) => Some(Transfer::Call),
+        (ZERO, Some(RA)) => Some(Transfer::Return),
+        (ZERO, _) => Some(Transfer::Jump),
+        _ => None,
+    }
+}
+
+pub struct Profiler {
+    symbolizer: Symbolizer,
+    frames: Vec<Frame>,
+    roots: HashMap<Option<u64>, usize>,
+    current: Option<usize>,
+}
+
+impl Profiler {
+    pub fn new(program: &Bytes) -> Result<Self, Error> {
+        Ok(Self {
+            symbolizer: Symbolizer::new(program)?,
+            frames: Vec::new(),
+            roots: HashMap::new(),
+            current: None,
+        })
+    }
+
+    /// Builds a profiler for the program loaded in an interpreter machine.
+    /// AsmCoreMachine doesn't keep the program, use `new` with it instead.
+    pub fn from_machine<M: SupportMachine>(machine: &M) -> Result<Self, Error> {
+        Self::new(machine.code())
+    }
+
+    fn function_at(&self, pc: u64) -> Option<u64> {
+        self.symbolizer
+            .function(pc)
+            .map(|function| function.address)
+    }
+
+    /// Name of the function containing pc.
+    pub fn function_name(&self, pc: u64) -> &str {
+        self.name(self.function_at(pc))
+    }
+
+    fn name(&self, function: Option<u64>) -> &str {
+        function
+            .and_then(|address| self.symbolizer.function(address))
+            .map_or(UNKNOWN_FUNCTION, |function| function.name.as_str())
+    }
+
+    fn frame(&mut self, parent: Option<usize>, function: Option<u64>) -> usize {
+        let next = self.frames.len();
+        let children = match parent {
+            Some(parent) => &mut self.frames[parent].children,
+            None => &mut self.roots,
+        };
+        let index = *children.entry(function).or_insert(next);
+        if index == next {
+            self.frames.push(Frame {
+                parent,
+                function,
+                cycles: 0,
+                children: HashMap::new(),
+            });
+        }
+        index
+    }
+
+    /// Attributes the cycles of an instruction executed at pc, and updates
+    /// the call stack with the pc after executing it.
+    pub fn record(&mut self, pc: u64, instruction: Instruction, cycles: u64, next_pc: u64) {
+        let current = match self.current {
+            Some(current) => current,
+            None => self.frame(None, self.function_at(pc)),
+        };
+        self.frames[current].cycles += cycles;
+        self.current = Some(current);
+        match classify(instruction) {
+            Some(Transfer::Call) => {
+                self.current = Some(self.frame(Some(current), self.function_at(next_pc)));
+            }
+            Some(Transfer::Return) => {
+                if let Some(parent) = self.frames[current].parent {
+                    self.current = Some(parent);
+                }
+            }
+            Some(Transfer::Jump) => {
+                let function = self.function_at(next_pc);
+                let tail_call =
+                    function == Some(next_pc) && function != self.frames[current].function;
+                if tail_call {
+                    let parent = self.frames[current].parent;
+                    self.current = Some(self.frame(parent, function));
+                }
+            }
+            None => (),
+        }
+    }
+
+    /// Runs the machine until it exits like DefaultMachine::run, profiling
+    /// each instruction. Breakpoints are not honored.
+    pub fn run<Inner: SupportMachine>(
+        &mut self,
+        machine: &mut DefaultMachine<Inner>,
+    ) -> Result<i8, Error> {
+        if machine.isa() & ISA_MOP != 0 && machine.version() == VERSION0 {
+            return Err(Error::InvalidVersion);
+        }
+        let mut decoder = build_decoder::<Inner::REG>(machine.isa(), machine.version());
+        let pause = machine.pause();
+        machine.set_running(true);
+        while machine.running() {
+            if pause.has_interrupted() {
+                pause.free();
+                return Err(Error::Pause);
+            }
+            if machine.reset_signal() {
+                decoder.reset_instructions_cache();
+            }
+            let pc = machine.pc().to_u64();
+            let instruction = decoder.decode(machine.memory_mut(), pc)?;
+            let cycles = machine.cost_model().cycles(instruction);
+            machine.step(&mut decoder)?;
+            self.record(pc, instruction, cycles, machine.pc().to_u64());
+        }
+        Ok(machine.exit_code())
+    }
+
+    /// Runs the machine until it exits like AsmMachine::run, profiling each
+    /// basic block. Breakpoints are not honored.
+    #[cfg(has_asm)]
+    pub fn run_asm(&mut self, machine: &mut AsmMachine) -> Result<i8, Error> {
+        if machine.machine.isa() & ISA_MOP != 0 && machine.machine.version() == VERSION0 {
+            return Err(Error::InvalidVersion);
+        }
+        let mut decoder = build_decoder::<u64>(machine.machine.isa(), machine.machine.version());
+        machine.clear_traces();
+        machine.machine.set_running(true);
+        while machine.machine.running() {
+            if machine.machine.reset_signal() {
+                decoder.reset_instructions_cache();
+            }
+            let mut pc = *machine.machine.pc();
+            let instructions = machine.step_trace(&mut decoder)?;
+            // No instruction is returned when the first one fails to decode
+            // and is trapped.
+            let last = instructions.len().saturating_sub(1);
+            for (i, instruction) in instructions.into_iter().enumerate() {
+                let cycles = machine.machine.cost_model().cycles(instruction);
+                // Only the last instruction of a block may jump.
+                let next_pc = if i == last {
+                    *machine.machine.pc()
+                } else {
+                    pc + u64::from(instruction_length(instruction))
+                };
+                self.record(pc, instruction, cycles, next_pc);
+                pc = next_pc;
+            }
+        }
+        Ok(machine.machine.exit_code())
+    }
+
+    /// Writes cycles in the folded stack format.
+    pub fn write_folded<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        for (index, frame) in self.frames.iter().enumerate() {
+            if frame.cycles == 0 {
+                continue;
+            }
+            let mut names = Vec::new();
+            let mut current = Some(index);
+            while let Some(index) = current {
+                names.push(self.name(self.frames[index].function));
+                current = self.frames[index].parent;
+            }
+            names.reverse();
+            writeln!(writer, "{} {}", names.join(";"), frame.cycles)?;
+        }
+        Ok(())
+    }
+}
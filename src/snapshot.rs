@@ -1,11 +1,14 @@
 use crate::instructions::Register;
+use crate::machine::trap::Traps;
 use crate::memory::Memory;
-use crate::memory::FLAG_DIRTY;
+use crate::memory::{clear_dirty, FLAG_DIRTY};
 use crate::{
-    CoreMachine, Error, RISCV_GENERAL_REGISTER_NUMBER, RISCV_PAGES, RISCV_PAGESIZE,
+    CoreMachine, Error, SupportMachine, RISCV_GENERAL_REGISTER_NUMBER, RISCV_PAGES, RISCV_PAGESIZE,
     RISCV_PAGE_SHIFTS,
 };
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 // Snapshot provides a mechanism for suspending and resuming a virtual machine.
 //
@@ -18,6 +21,8 @@ use serde::{Deserialize, Serialize};
 //   - machine.version
 //   - machine.pc
 //   - machine.registers
+//   - the state of trap handlers, see DefaultMachine::trap
+//   - the indices of touched memory frames, see SupportMachine::frame_cycles
 //
 // For memory, the situation becomes more complicated. Every memory page has
 // page flag where each page flag stores a optional FLAG_DIRTY. When this page
@@ -26,6 +31,48 @@ use serde::{Deserialize, Serialize};
 // clean up all dirty flags, so after the program terminates, all pages marked
 // as dirty are the pages that have been modified by the program. We only store
 // these pages in the snapshot.
+//
+// Long running programs might be suspended many times, to avoid storing the
+// same unchanged pages over and over again, incremental snapshots are also
+// supported. `make_delta_snapshot` only records pages dirtied since the
+// previous snapshot, and then clears all dirty flags. A machine is restored
+// by applying the whole chain of snapshots in order with `resume_chain`, the
+// first one being a full snapshot. `compact_snapshots` merges a chain into a
+// single equivalent snapshot. Note clearing dirty flags breaks the tracking
+// done by Snapshot2Context, the two shall not be used on the same machine.
+//
+// A snapshot can be persisted with `Snapshot::encode`, which produces a stable
+// little endian binary format:
+//
+//   magic                  8 bytes, "CKBVMSNP"
+//   format version         u32, SNAPSHOT_FORMAT_VERSION
+//   machine version        u32
+//   pc                     u64
+//   registers              u64 * RISCV_GENERAL_REGISTER_NUMBER
+//   mtvec, mepc            u64 * 2
+//   mcause, mtval          u64 * 2
+//   trap handling          u8, 1 if a trap handler is running
+//   touched frame count    u64
+//   touched frame indices  u64 * touched frame count
+//   page count             u64
+//   header checksum        u32, CRC32 of all the fields above
+//   pages, each of them being:
+//     page index           u64
+//     page flag            u8
+//     page kind            u8, PAGE_KIND_ZERO or PAGE_KIND_DATA
+//     page checksum        u32, CRC32 of index, flag, kind and content
+//     content              RISCV_PAGESIZE bytes, only for PAGE_KIND_DATA
+//
+// Pages containing only zeros are stored without content. Format version 1
+// has neither the trap handler state nor touched frames, `Snapshot::decode`
+// still accepts it. Data of other format versions, corrupted data and
+// trailing bytes are rejected with Error::SnapshotDecodeError.
+
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"CKBVMSNP";
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 2;
+
+const PAGE_KIND_ZERO: u8 = 0;
+const PAGE_KIND_DATA: u8 = 1;
 
 #[derive(Default, Deserialize, Serialize)]
 pub struct Snapshot {
@@ -35,19 +82,218 @@ pub struct Snapshot {
     pub page_indices: Vec<u64>,
     pub page_flags: Vec<u8>,
     pub pages: Vec<Vec<u8>>,
+    // Missing in snapshots made before traps were delivered.
+    #[serde(default)]
+    pub traps: Traps,
+    // Missing in snapshots made before frames were charged.
+    #[serde(default)]
+    pub touched_frames: Vec<u64>,
+}
+
+impl Snapshot {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(
+            Self::header_size()
+                + 8 * self.touched_frames.len()
+                + self.pages.len() * (RISCV_PAGESIZE + Self::page_header_size()),
+        );
+        data.extend_from_slice(SNAPSHOT_MAGIC);
+        // Writing to a Vec never fails.
+        data.write_u32::<LittleEndian>(SNAPSHOT_FORMAT_VERSION)
+            .unwrap();
+        data.write_u32::<LittleEndian>(self.version).unwrap();
+        data.write_u64::<LittleEndian>(self.pc).unwrap();
+        for register in &self.registers {
+            data.write_u64::<LittleEndian>(*register).unwrap();
+        }
+        for csr in [
+            self.traps.mtvec,
+            self.traps.mepc,
+            self.traps.mcause,
+            self.traps.mtval,
+        ] {
+            data.write_u64::<LittleEndian>(csr).unwrap();
+        }
+        data.push(self.traps.handling as u8);
+        data.write_u64::<LittleEndian>(self.touched_frames.len() as u64)
+            .unwrap();
+        for frame in &self.touched_frames {
+            data.write_u64::<LittleEndian>(*frame).unwrap();
+        }
+        data.write_u64::<LittleEndian>(self.page_indices.len() as u64)
+            .unwrap();
+        let checksum = crc32(&data);
+        data.write_u32::<LittleEndian>(checksum).unwrap();
+        for ((index, flag), page) in self
+            .page_indices
+            .iter()
+            .zip(self.page_flags.iter())
+            .zip(self.pages.iter())
+        {
+            let start = data.len();
+            data.write_u64::<LittleEndian>(*index).unwrap();
+            data.push(*flag);
+            if page.iter().all(|b| *b == 0) {
+                data.push(PAGE_KIND_ZERO);
+                let checksum = crc32(&data[start..]);
+                data.write_u32::<LittleEndian>(checksum).unwrap();
+            } else {
+                data.push(PAGE_KIND_DATA);
+                let checksum = crc32_update(crc32(&data[start..]), page);
+                data.write_u32::<LittleEndian>(checksum).unwrap();
+                data.extend_from_slice(page);
+            }
+        }
+        data
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Snapshot, Error> {
+        if data.len() < Self::header_size_v1() {
+            return Err(decode_error("unexpected end of data"));
+        }
+        if &data[0..8] != SNAPSHOT_MAGIC {
+            return Err(decode_error("invalid magic"));
+        }
+        let mut reader = &data[8..];
+        let format_version = read_u32(&mut reader)?;
+        if format_version != 1 && format_version != SNAPSHOT_FORMAT_VERSION {
+            return Err(Error::SnapshotDecodeError(format!(
+                "unsupported format version {}",
+                format_version
+            )));
+        }
+        let mut snap = Snapshot {
+            version: read_u32(&mut reader)?,
+            pc: read_u64(&mut reader)?,
+            ..Default::default()
+        };
+        for register in snap.registers.iter_mut() {
+            *register = read_u64(&mut reader)?;
+        }
+        if format_version >= 2 {
+            snap.traps.mtvec = read_u64(&mut reader)?;
+            snap.traps.mepc = read_u64(&mut reader)?;
+            snap.traps.mcause = read_u64(&mut reader)?;
+            snap.traps.mtval = read_u64(&mut reader)?;
+            snap.traps.handling = match read_u8(&mut reader)? {
+                0 => false,
+                1 => true,
+                _ => return Err(decode_error("invalid trap handling")),
+            };
+            let frame_count = read_u64(&mut reader)?;
+            for _ in 0..frame_count {
+                snap.touched_frames.push(read_u64(&mut reader)?);
+            }
+        }
+        let page_count = read_u64(&mut reader)?;
+        let header_size = data.len() - reader.len();
+        let checksum = read_u32(&mut reader)?;
+        if checksum != crc32(&data[0..header_size]) {
+            return Err(decode_error("header checksum mismatch"));
+        }
+        for _ in 0..page_count {
+            let record = reader;
+            let index = read_u64(&mut reader)?;
+            let flag = read_u8(&mut reader)?;
+            let kind = read_u8(&mut reader)?;
+            let checksum = read_u32(&mut reader)?;
+            if index >= RISCV_PAGES as u64 {
+                return Err(decode_error("page index out of bound"));
+            }
+            let mut expected = crc32(&record[0..10]);
+            let page = match kind {
+                PAGE_KIND_ZERO => vec![0; RISCV_PAGESIZE],
+                PAGE_KIND_DATA => {
+                    if reader.len() < RISCV_PAGESIZE {
+                        return Err(decode_error("unexpected end of data"));
+                    }
+                    let (page, rest) = reader.split_at(RISCV_PAGESIZE);
+                    reader = rest;
+                    expected = crc32_update(expected, page);
+                    page.to_vec()
+                }
+                _ => return Err(decode_error("invalid page kind")),
+            };
+            if checksum != expected {
+                return Err(decode_error("page checksum mismatch"));
+            }
+            snap.page_indices.push(index);
+            snap.page_flags.push(flag);
+            snap.pages.push(page);
+        }
+        if !reader.is_empty() {
+            return Err(decode_error("trailing data"));
+        }
+        Ok(snap)
+    }
+
+    // The header size without touched frames.
+    fn header_size() -> usize {
+        Self::header_size_v1() + 8 * 4 + 1 + 8
+    }
+
+    fn header_size_v1() -> usize {
+        8 + 4 + 4 + 8 + 8 * RISCV_GENERAL_REGISTER_NUMBER + 8 + 4
+    }
+
+    fn page_header_size() -> usize {
+        8 + 1 + 1 + 4
+    }
+}
+
+fn decode_error(reason: &str) -> Error {
+    Error::SnapshotDecodeError(reason.to_string())
 }
 
-pub fn make_snapshot<T: CoreMachine>(machine: &mut T) -> Result<Snapshot, Error> {
+fn read_u8(reader: &mut &[u8]) -> Result<u8, Error> {
+    reader
+        .read_u8()
+        .map_err(|_| decode_error("unexpected end of data"))
+}
+
+fn read_u32(reader: &mut &[u8]) -> Result<u32, Error> {
+    reader
+        .read_u32::<LittleEndian>()
+        .map_err(|_| decode_error("unexpected end of data"))
+}
+
+fn read_u64(reader: &mut &[u8]) -> Result<u64, Error> {
+    reader
+        .read_u64::<LittleEndian>()
+        .map_err(|_| decode_error("unexpected end of data"))
+}
+
+// CRC-32 (IEEE 802.3), as used by zlib and gzip.
+fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+pub fn make_snapshot<T: SupportMachine>(machine: &mut T) -> Result<Snapshot, Error> {
     let mut snap = Snapshot {
         version: machine.version(),
         pc: machine.pc().to_u64(),
+        traps: machine.trap_state(),
+        touched_frames: machine.memory().touched_frame_indices(),
         ..Default::default()
     };
     for (i, v) in machine.registers().iter().enumerate() {
         snap.registers[i] = v.to_u64();
     }
 
-    for i in 0..RISCV_PAGES {
+    let pages = machine.memory().memory_size() / RISCV_PAGESIZE;
+    for i in 0..pages {
         let flag = machine.memory_mut().fetch_flag(i as u64)?;
         if flag & FLAG_DIRTY != 0 {
             let addr_from = i << RISCV_PAGE_SHIFTS;
@@ -78,7 +324,7 @@ pub fn make_snapshot<T: CoreMachine>(machine: &mut T) -> Result<Snapshot, Error>
     Ok(snap)
 }
 
-pub fn resume<T: CoreMachine>(machine: &mut T, snapshot: &Snapshot) -> Result<(), Error> {
+pub fn resume<T: SupportMachine>(machine: &mut T, snapshot: &Snapshot) -> Result<(), Error> {
     if machine.version() != snapshot.version {
         return Err(Error::InvalidVersion);
     }
@@ -87,6 +333,21 @@ pub fn resume<T: CoreMachine>(machine: &mut T, snapshot: &Snapshot) -> Result<()
     }
     machine.update_pc(T::REG::from_u64(snapshot.pc));
     machine.commit_pc();
+    machine.set_trap_state(snapshot.traps.clone());
+    resume_pages(machine, snapshot)?;
+    // The frames are charged by the machine making the snapshot.
+    machine
+        .memory_mut()
+        .restore_touched_frames(&snapshot.touched_frames);
+    machine.take_touched_frames();
+    Ok(())
+}
+
+// Restores only the memory pages of a snapshot.
+pub(crate) fn resume_pages<T: CoreMachine>(
+    machine: &mut T,
+    snapshot: &Snapshot,
+) -> Result<(), Error> {
     for i in 0..snapshot.page_indices.len() {
         let page_index = snapshot.page_indices[i];
         let page_flag = snapshot.page_flags[i];
@@ -98,3 +359,68 @@ pub fn resume<T: CoreMachine>(machine: &mut T, snapshot: &Snapshot) -> Result<()
 
     Ok(())
 }
+
+// Creates a snapshot containing pages dirtied since the previous delta
+// snapshot (or since the machine is created if there is none), then clears
+// dirty flags so the next one only contains newly modified pages.
+pub fn make_delta_snapshot<T: SupportMachine>(machine: &mut T) -> Result<Snapshot, Error> {
+    let snap = make_snapshot(machine)?;
+    clear_dirty_flags(machine)?;
+    Ok(snap)
+}
+
+pub fn clear_dirty_flags<T: CoreMachine>(machine: &mut T) -> Result<(), Error> {
+    let pages = machine.memory().memory_size() / RISCV_PAGESIZE;
+    for i in 0..pages {
+        clear_dirty(machine.memory_mut(), i as u64)?;
+    }
+    Ok(())
+}
+
+// Restores a chain of snapshots created by make_delta_snapshot, in the order
+// they are created. Dirty flags are cleared afterwards, so the next delta
+// snapshot continues the chain.
+pub fn resume_chain<T: SupportMachine>(
+    machine: &mut T,
+    snapshots: &[Snapshot],
+) -> Result<(), Error> {
+    for snapshot in snapshots {
+        resume(machine, snapshot)?;
+    }
+    clear_dirty_flags(machine)
+}
+
+// Merges a chain of snapshots into one, resuming from the result is the same
+// as resuming from the whole chain.
+pub fn compact_snapshots(snapshots: &[Snapshot]) -> Result<Snapshot, Error> {
+    let last = match snapshots.last() {
+        Some(last) => last,
+        None => return Ok(Snapshot::default()),
+    };
+    let mut pages = BTreeMap::new();
+    for snapshot in snapshots {
+        if snapshot.version != last.version {
+            return Err(Error::InvalidVersion);
+        }
+        for i in 0..snapshot.page_indices.len() {
+            pages.insert(
+                snapshot.page_indices[i],
+                (snapshot.page_flags[i], &snapshot.pages[i]),
+            );
+        }
+    }
+    let mut snap = Snapshot {
+        version: last.version,
+        registers: last.registers,
+        pc: last.pc,
+        traps: last.traps.clone(),
+        touched_frames: last.touched_frames.clone(),
+        ..Default::default()
+    };
+    for (page_index, (page_flag, page)) in pages {
+        snap.page_indices.push(page_index);
+        snap.page_flags.push(page_flag);
+        snap.pages.push(page.clone());
+    }
+    Ok(snap)
+}
@@ -0,0 +1,273 @@
+use crate::machine::trap::Traps;
+use crate::machine::{elf_adaptor, parse_elf};
+use crate::memory::{clear_dirty, round_page_down, Memory, FLAG_DIRTY};
+use crate::{
+    Error, Register, SupportMachine, RISCV_GENERAL_REGISTER_NUMBER, RISCV_PAGESIZE,
+    RISCV_PAGE_SHIFTS,
+};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::cmp::min;
+use std::collections::HashMap;
+
+// Snapshot2 is a self-contained snapshot format. Unlike Snapshot, a machine
+// can be restored from Snapshot2 without loading the program beforehand, and
+// the full machine state, including cycles, max cycles, instret, the load
+// reservation address, the trap handler state and touched frames, is captured.
+//
+// Memory pages whose content is a range of some data known to the host (such
+// as the program itself), padded with zeros, need not be stored in the
+// snapshot. They are tracked by Snapshot2Context and recorded as a reference
+// to a range of data identified by an ID, the content of such pages is
+// fetched from a DataSource when the snapshot is resumed. Once a tracked page gets written,
+// it is marked as dirty and its content is stored in the snapshot instead.
+
+pub trait DataSource<I: Clone + PartialEq> {
+    // Loads `length` bytes starting from `offset` in the data identified by
+    // `id`, None is returned when such data cannot be found.
+    fn load_data(&self, id: &I, offset: u64, length: u64) -> Option<Bytes>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Snapshot2<I: Clone + PartialEq> {
+    // (address, flag, id, source offset, source length), address might not be
+    // page aligned, the rest of the page is filled with zeros.
+    pub pages_from_source: Vec<(u64, u8, I, u64, u64)>,
+    // (address, flag, content)
+    pub dirty_pages: Vec<(u64, u8, Vec<u8>)>,
+    pub version: u32,
+    pub registers: [u64; RISCV_GENERAL_REGISTER_NUMBER],
+    pub pc: u64,
+    pub cycles: u64,
+    pub max_cycles: u64,
+    pub load_reservation_address: u64,
+    // Missing in snapshots made before instret was counted.
+    #[serde(default)]
+    pub instret: u64,
+    // Missing in snapshots made before traps were delivered.
+    #[serde(default)]
+    pub traps: Traps,
+    // Missing in snapshots made before frames were charged.
+    #[serde(default)]
+    pub touched_frames: Vec<u64>,
+}
+
+pub struct Snapshot2Context<I: Clone + PartialEq, D: DataSource<I>> {
+    // page index -> (address, id, offset in source, length)
+    pages: HashMap<u64, (u64, I, u64, u64)>,
+    data_source: D,
+}
+
+impl<I: Clone + PartialEq, D: DataSource<I>> Snapshot2Context<I, D> {
+    pub fn new(data_source: D) -> Self {
+        Self {
+            pages: HashMap::default(),
+            data_source,
+        }
+    }
+
+    pub fn data_source(&self) -> &D {
+        &self.data_source
+    }
+
+    // Marks the pages of a loaded program as coming from the data source.
+    // `program` must be the data identified by `id` starting at `offset`, and
+    // it must have been loaded into the machine via `load_program` or
+    // `load_elf`.
+    pub fn mark_program<M: SupportMachine>(
+        &mut self,
+        machine: &mut M,
+        program: &Bytes,
+        id: &I,
+        offset: u64,
+    ) -> Result<(), Error> {
+        let (_, program_headers) = parse_elf::<M::REG>(program, machine.version())?;
+        for program_header in program_headers {
+            if program_header.p_type != elf_adaptor::PT_LOAD {
+                continue;
+            }
+            let aligned_start = round_page_down(program_header.p_vaddr);
+            let segment_end = program_header
+                .p_vaddr
+                .checked_add(program_header.p_memsz)
+                .ok_or(Error::ElfSegmentAddrOrSizeError)?;
+            // Later segments sharing a page with earlier ones overwrite the
+            // whole page when loaded.
+            for page_addr in (aligned_start..segment_end).step_by(RISCV_PAGESIZE) {
+                self.pages.remove(&(page_addr >> RISCV_PAGE_SHIFTS));
+            }
+            self.track_range(
+                machine,
+                program_header.p_vaddr,
+                id,
+                offset + program_header.p_offset,
+                program_header.p_filesz,
+            )?;
+        }
+        Ok(())
+    }
+
+    // Loads `length` bytes from the data source into machine memory starting
+    // at `addr`, the pages are tracked so they are not stored in snapshots.
+    // The rest of a partially filled page must be zero. Returns the number of
+    // bytes written.
+    pub fn store_bytes<M: SupportMachine>(
+        &mut self,
+        machine: &mut M,
+        addr: u64,
+        id: &I,
+        offset: u64,
+        length: u64,
+    ) -> Result<u64, Error> {
+        let data = self
+            .data_source
+            .load_data(id, offset, length)
+            .ok_or(Error::SnapshotDataLoadError)?;
+        machine.memory_mut().store_bytes(addr, &data)?;
+        self.track_range(machine, addr, id, offset, data.len() as u64)?;
+        Ok(data.len() as u64)
+    }
+
+    fn track_range<M: SupportMachine>(
+        &mut self,
+        machine: &mut M,
+        addr: u64,
+        id: &I,
+        offset: u64,
+        length: u64,
+    ) -> Result<(), Error> {
+        let end = addr
+            .checked_add(length)
+            .ok_or(Error::ElfSegmentAddrOrSizeError)?;
+        let mut start = addr;
+        while start < end {
+            let next_page = round_page_down(start) + RISCV_PAGESIZE as u64;
+            let page_end = min(next_page, end);
+            self.track_page(machine, start, id, offset + start - addr, page_end - start)?;
+            start = page_end;
+        }
+        Ok(())
+    }
+
+    fn track_page<M: SupportMachine>(
+        &mut self,
+        machine: &mut M,
+        addr: u64,
+        id: &I,
+        offset: u64,
+        length: u64,
+    ) -> Result<(), Error> {
+        let page = addr >> RISCV_PAGE_SHIFTS;
+        clear_dirty(machine.memory_mut(), page)?;
+        self.pages.insert(page, (addr, id.clone(), offset, length));
+        Ok(())
+    }
+
+    pub fn make_snapshot<M: SupportMachine>(&self, machine: &mut M) -> Result<Snapshot2<I>, Error> {
+        let mut snap = Snapshot2 {
+            pages_from_source: vec![],
+            dirty_pages: vec![],
+            version: machine.version(),
+            registers: [0; RISCV_GENERAL_REGISTER_NUMBER],
+            pc: machine.pc().to_u64(),
+            cycles: machine.cycles(),
+            max_cycles: machine.max_cycles(),
+            load_reservation_address: machine.memory().lr().to_u64(),
+            instret: machine.instret(),
+            traps: machine.trap_state(),
+            touched_frames: machine.memory().touched_frame_indices(),
+        };
+        for (i, v) in machine.registers().iter().enumerate() {
+            snap.registers[i] = v.to_u64();
+        }
+        let pages = (machine.memory().memory_size() / RISCV_PAGESIZE) as u64;
+        for page in 0..pages {
+            let flag = machine.memory_mut().fetch_flag(page)?;
+            let addr = page << RISCV_PAGE_SHIFTS;
+            match self.pages.get(&page) {
+                Some((address, id, offset, length)) if flag & FLAG_DIRTY == 0 => {
+                    snap.pages_from_source
+                        .push((*address, flag, id.clone(), *offset, *length));
+                }
+                _ => {
+                    if flag != 0 {
+                        let content = machine
+                            .memory_mut()
+                            .load_bytes(addr, RISCV_PAGESIZE as u64)?;
+                        snap.dirty_pages.push((addr, flag, content.to_vec()));
+                    }
+                }
+            }
+        }
+        Ok(snap)
+    }
+
+    // Restores a snapshot into a freshly created machine, no program needs to
+    // be loaded beforehand.
+    pub fn resume<M: SupportMachine>(
+        &mut self,
+        machine: &mut M,
+        snapshot: &Snapshot2<I>,
+    ) -> Result<(), Error> {
+        if machine.version() != snapshot.version {
+            return Err(Error::InvalidVersion);
+        }
+        self.pages.clear();
+        for (address, flag, id, offset, length) in &snapshot.pages_from_source {
+            let data = self
+                .data_source
+                .load_data(id, *offset, *length)
+                .ok_or(Error::SnapshotDataLoadError)?;
+            let page_addr = round_page_down(*address);
+            let start = (*address - page_addr) as usize;
+            if data.len() as u64 != *length || start + data.len() > RISCV_PAGESIZE {
+                return Err(Error::SnapshotDataLoadError);
+            }
+            let mut content = vec![0; RISCV_PAGESIZE];
+            content[start..start + data.len()].copy_from_slice(&data);
+            restore_page(machine, page_addr, *flag, &content)?;
+            self.track_page(machine, *address, id, *offset, *length)?;
+        }
+        for (address, flag, content) in &snapshot.dirty_pages {
+            restore_page(machine, *address, *flag, content)?;
+        }
+        // Frames are charged in the restored cycles already.
+        machine
+            .memory_mut()
+            .restore_touched_frames(&snapshot.touched_frames);
+        machine.take_touched_frames();
+        for (i, v) in snapshot.registers.iter().enumerate() {
+            machine.set_register(i, M::REG::from_u64(*v));
+        }
+        machine.update_pc(M::REG::from_u64(snapshot.pc));
+        machine.commit_pc();
+        machine.set_cycles(snapshot.cycles);
+        machine.set_max_cycles(snapshot.max_cycles);
+        machine.set_instret(snapshot.instret);
+        machine.set_trap_state(snapshot.traps.clone());
+        machine
+            .memory_mut()
+            .set_lr(&M::REG::from_u64(snapshot.load_reservation_address));
+        Ok(())
+    }
+}
+
+fn restore_page<M: SupportMachine>(
+    machine: &mut M,
+    address: u64,
+    flag: u8,
+    content: &[u8],
+) -> Result<(), Error> {
+    machine.memory_mut().init_pages(
+        address,
+        RISCV_PAGESIZE as u64,
+        flag,
+        Some(Bytes::copy_from_slice(content)),
+        0,
+    )?;
+    // Not all memories keep flags in init_pages, and storing the content
+    // always marks the page as dirty, hence flags are reset here.
+    let page = address >> RISCV_PAGE_SHIFTS;
+    machine.memory_mut().clear_flag(page, !flag)?;
+    machine.memory_mut().set_flag(page, flag)
+}
@@ -0,0 +1,714 @@
+use crate::machine::{trace::TraceMachine, DefaultMachine, Pause, RunOutcome, SupportMachine};
+use crate::registers::{A0, A1, A2, A3, A4, A7};
+use crate::syscalls::SyscallCost;
+use crate::{CoreMachine, Error, Memory, Register, Syscalls};
+use bytes::Bytes;
+use std::cmp::min;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[cfg(has_asm)]
+use crate::machine::asm::{AsmCoreMachine, AsmMachine};
+
+// Spawn lets a guest start child programs and talk to them through pipes. A
+// Scheduler owns all the machines of a spawn tree, each of them has a
+// SpawnSyscalls installed. Syscalls that cannot be finished by the calling
+// machine alone (spawning a child, or waiting for data or a child to exit)
+// interrupt the machine, which is then resumed by the scheduler once the
+// request is fulfilled.
+//
+// Only one machine runs at a time. The scheduler always picks the runnable
+// machine that has consumed the least cycles (the smaller ID wins a tie) and
+// runs it for at most `slice_cycles` cycles, so the execution is fully
+// deterministic. Cycles consumed by all machines are summed up and checked
+// against a single max cycles limit.
+//
+// Each syscall charges the calling machine the base cycles of the
+// scheduler's SyscallCost, and its per byte cycles for the bytes read or
+// written through a pipe, or loaded for a child on SPAWN (the program and
+// its arguments). Syscall cycles are not checked against the time slice, a
+// syscall failing half way would be executed again when the machine is
+// resumed. Instead the scheduler checks the cycles of all machines after
+// each slice, so a machine can overrun its slice by the cycles of one
+// syscall.
+//
+// A child machine failing with an error other than running out of cycles is
+// terminated alone: its fds are closed and WAIT for it returns WAIT_FAILURE.
+// Only errors of the root machine are returned by the scheduler.
+//
+// Syscalls, arguments are passed in A0 to A4, and the result is returned in
+// A0:
+//   SPAWN(program index, argc, argv, fds, pid address)
+//     fds is an array of file descriptors terminated by 0, their ownership
+//     is transferred to the child. The pid of the child is written to the
+//     pid address.
+//   WAIT(pid, exit code address)
+//     Blocks until the machine exits, its exit code is written as a u64.
+//   PROCESS_ID()
+//     Returns the pid of the current machine, the root machine has pid 0.
+//   PIPE(fds address)
+//     Creates a pipe, the read fd and the write fd are written as u64s.
+//   WRITE(fd, buffer, length address)
+//     Blocks until some bytes can be written, the number of bytes written
+//     is stored back to length address.
+//   READ(fd, buffer, length address)
+//     Blocks until some bytes can be read, the number of bytes read is stored
+//     back to length address. 0 is stored when the write end is closed.
+//   INHERITED_FDS(buffer, length address)
+//     Copies the fds passed from the parent to buffer, at most the u64 at
+//     length address fds are copied, the total number of fds is stored back.
+//   CLOSE(fd)
+
+pub const SPAWN: u64 = 2601;
+pub const WAIT: u64 = 2602;
+pub const PROCESS_ID: u64 = 2603;
+pub const PIPE: u64 = 2604;
+pub const WRITE: u64 = 2605;
+pub const READ: u64 = 2606;
+pub const INHERITED_FDS: u64 = 2607;
+pub const CLOSE: u64 = 2608;
+
+pub const SUCCESS: u64 = 0;
+pub const INVALID_ARGUMENT: u64 = 1;
+pub const INVALID_FD: u64 = 2;
+pub const OTHER_END_CLOSED: u64 = 3;
+pub const MAX_MACHINES_SPAWNED: u64 = 4;
+pub const WAIT_FAILURE: u64 = 5;
+
+pub const ROOT_PID: u64 = 0;
+pub const PIPE_CAPACITY: usize = 65536;
+pub const DEFAULT_SLICE_CYCLES: u64 = 1_000_000;
+pub const DEFAULT_MAX_MACHINES: usize = 16;
+pub const DEFAULT_SYSCALL_CYCLES: u64 = 500;
+pub const DEFAULT_BYTE_CYCLES: u64 = 1;
+
+// Read ends are even, write ends are odd, fd 0 is reserved as terminator.
+const FIRST_FD: u64 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Request {
+    Spawn {
+        program: u64,
+        argc: u64,
+        argv: u64,
+        fds: u64,
+        pid_addr: u64,
+    },
+    Wait {
+        pid: u64,
+        code_addr: u64,
+    },
+    Read {
+        fd: u64,
+        buffer: u64,
+        length_addr: u64,
+    },
+    Write {
+        fd: u64,
+        buffer: u64,
+        length_addr: u64,
+    },
+}
+
+#[derive(Default)]
+struct SpawnState {
+    next_pid: u64,
+    next_fd: u64,
+    // fd -> owner pid
+    fds: BTreeMap<u64, u64>,
+    // read fd -> buffered data
+    pipes: BTreeMap<u64, VecDeque<u8>>,
+    inherited_fds: BTreeMap<u64, Vec<u64>>,
+    exit_codes: BTreeMap<u64, i8>,
+    failed: BTreeSet<u64>,
+    blocked: BTreeMap<u64, Request>,
+    spawn_request: Option<(u64, Request)>,
+}
+
+impl SpawnState {
+    fn new() -> Self {
+        Self {
+            next_pid: ROOT_PID + 1,
+            next_fd: FIRST_FD,
+            ..Default::default()
+        }
+    }
+
+    fn owns(&self, pid: u64, fd: u64) -> bool {
+        self.fds.get(&fd) == Some(&pid)
+    }
+
+    fn close_fd(&mut self, fd: u64) {
+        self.fds.remove(&fd);
+        let read_fd = fd & !1;
+        if !self.fds.contains_key(&read_fd) && !self.fds.contains_key(&(read_fd | 1)) {
+            self.pipes.remove(&read_fd);
+        }
+    }
+
+    fn close_all(&mut self, pid: u64) {
+        let fds: Vec<u64> = self
+            .fds
+            .iter()
+            .filter(|(_, owner)| **owner == pid)
+            .map(|(fd, _)| *fd)
+            .collect();
+        for fd in fds {
+            self.close_fd(fd);
+        }
+    }
+
+    // Tries to fulfill a wait, read or write request, returns false when the
+    // request shall be blocked. Bytes read or written are charged
+    // `byte_cycles` each.
+    fn try_complete<M: SupportMachine>(
+        &mut self,
+        pid: u64,
+        request: &Request,
+        machine: &mut M,
+        byte_cycles: u64,
+    ) -> Result<bool, Error> {
+        let code = match request {
+            Request::Wait {
+                pid: target,
+                code_addr,
+            } => {
+                if let Some(exit_code) = self.exit_codes.get(target) {
+                    store_u64(machine, *code_addr, *exit_code as i64 as u64)?;
+                    SUCCESS
+                } else if *target == pid || *target >= self.next_pid || self.failed.contains(target)
+                {
+                    WAIT_FAILURE
+                } else {
+                    return Ok(false);
+                }
+            }
+            Request::Read {
+                fd,
+                buffer,
+                length_addr,
+            } => {
+                if fd % 2 != 0 || !self.owns(pid, *fd) {
+                    INVALID_FD
+                } else {
+                    let length = load_u64(machine, *length_addr)?;
+                    let write_end_open = self.fds.contains_key(&(fd + 1));
+                    let data = self.pipes.entry(*fd).or_default();
+                    if data.is_empty() && write_end_open && length > 0 {
+                        return Ok(false);
+                    }
+                    let n = min(length, data.len() as u64) as usize;
+                    machine.add_cycles_no_checking(byte_cycles.saturating_mul(n as u64))?;
+                    let bytes: Vec<u8> = data.drain(..n).collect();
+                    machine.memory_mut().store_bytes(*buffer, &bytes)?;
+                    store_u64(machine, *length_addr, n as u64)?;
+                    SUCCESS
+                }
+            }
+            Request::Write {
+                fd,
+                buffer,
+                length_addr,
+            } => {
+                if fd % 2 != 1 || !self.owns(pid, *fd) {
+                    INVALID_FD
+                } else if !self.fds.contains_key(&(fd - 1)) {
+                    OTHER_END_CLOSED
+                } else {
+                    let length = load_u64(machine, *length_addr)?;
+                    let data = self.pipes.entry(fd - 1).or_default();
+                    let n = min(length, (PIPE_CAPACITY - data.len()) as u64);
+                    if n == 0 && length > 0 {
+                        return Ok(false);
+                    }
+                    machine.add_cycles_no_checking(byte_cycles.saturating_mul(n))?;
+                    let bytes = machine.memory_mut().load_bytes(*buffer, n)?;
+                    data.extend(bytes.iter());
+                    store_u64(machine, *length_addr, n)?;
+                    SUCCESS
+                }
+            }
+            Request::Spawn { .. } => unreachable!(),
+        };
+        machine.set_register(A0, M::REG::from_u64(code));
+        Ok(true)
+    }
+}
+
+fn lock(state: &Mutex<SpawnState>) -> Result<MutexGuard<'_, SpawnState>, Error> {
+    state.lock().map_err(|e| Error::Unexpected(e.to_string()))
+}
+
+/// Syscalls of the spawn subsystem, created by Scheduler for each machine.
+pub struct SpawnSyscalls {
+    pid: u64,
+    state: Arc<Mutex<SpawnState>>,
+    pause: Pause,
+    cost: SyscallCost,
+}
+
+impl SpawnSyscalls {
+    pub fn pid(&self) -> u64 {
+        self.pid
+    }
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for SpawnSyscalls {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        let code = machine.registers()[A7].to_u64();
+        if !(SPAWN..=CLOSE).contains(&code) {
+            return Ok(false);
+        }
+        machine.add_cycles_no_checking(self.cost.base)?;
+        let arg = |i: usize| machine.registers()[i].to_u64();
+        let mut state = lock(&self.state)?;
+        let result = match code {
+            SPAWN => {
+                let request = Request::Spawn {
+                    program: arg(A0),
+                    argc: arg(A1),
+                    argv: arg(A2),
+                    fds: arg(A3),
+                    pid_addr: arg(A4),
+                };
+                state.spawn_request = Some((self.pid, request));
+                self.pause.interrupt();
+                return Ok(true);
+            }
+            WAIT | READ | WRITE => {
+                let request = match code {
+                    WAIT => Request::Wait {
+                        pid: arg(A0),
+                        code_addr: arg(A1),
+                    },
+                    READ => Request::Read {
+                        fd: arg(A0),
+                        buffer: arg(A1),
+                        length_addr: arg(A2),
+                    },
+                    _ => Request::Write {
+                        fd: arg(A0),
+                        buffer: arg(A1),
+                        length_addr: arg(A2),
+                    },
+                };
+                if !state.try_complete(self.pid, &request, machine, self.cost.per_byte)? {
+                    state.blocked.insert(self.pid, request);
+                    self.pause.interrupt();
+                }
+                return Ok(true);
+            }
+            PROCESS_ID => self.pid,
+            PIPE => {
+                let fds_addr = arg(A0);
+                let read_fd = state.next_fd;
+                store_u64(machine, fds_addr, read_fd)?;
+                store_u64(machine, fds_addr + 8, read_fd + 1)?;
+                state.next_fd += 2;
+                state.fds.insert(read_fd, self.pid);
+                state.fds.insert(read_fd + 1, self.pid);
+                state.pipes.insert(read_fd, VecDeque::new());
+                SUCCESS
+            }
+            INHERITED_FDS => {
+                let buffer = arg(A0);
+                let length_addr = arg(A1);
+                let fds = state
+                    .inherited_fds
+                    .get(&self.pid)
+                    .cloned()
+                    .unwrap_or_default();
+                let length = load_u64(machine, length_addr)?;
+                for (i, fd) in fds.iter().take(length as usize).enumerate() {
+                    store_u64(machine, buffer + i as u64 * 8, *fd)?;
+                }
+                store_u64(machine, length_addr, fds.len() as u64)?;
+                SUCCESS
+            }
+            CLOSE => {
+                let fd = arg(A0);
+                if state.owns(self.pid, fd) {
+                    state.close_fd(fd);
+                    SUCCESS
+                } else {
+                    INVALID_FD
+                }
+            }
+            _ => unreachable!(),
+        };
+        machine.set_register(A0, Mac::REG::from_u64(result));
+        Ok(true)
+    }
+}
+
+/// Machines that can be managed by Scheduler.
+pub trait SpawnMachine {
+    type Inner: SupportMachine;
+
+    fn inner_mut(&mut self) -> &mut Self::Inner;
+    fn set_pause(&mut self, pause: Pause);
+    fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error>;
+    fn run_with_budget(&mut self, budget: u64) -> Result<RunOutcome, Error>;
+}
+
+impl<Inner: SupportMachine> SpawnMachine for DefaultMachine<Inner> {
+    type Inner = Self;
+
+    fn inner_mut(&mut self) -> &mut Self {
+        self
+    }
+
+    fn set_pause(&mut self, pause: Pause) {
+        DefaultMachine::set_pause(self, pause)
+    }
+
+    fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
+        DefaultMachine::load_program(self, program, args)
+    }
+
+    fn run_with_budget(&mut self, budget: u64) -> Result<RunOutcome, Error> {
+        DefaultMachine::run_with_budget(self, budget)
+    }
+}
+
+impl<Inner: SupportMachine> SpawnMachine for TraceMachine<Inner> {
+    type Inner = DefaultMachine<Inner>;
+
+    fn inner_mut(&mut self) -> &mut Self::Inner {
+        &mut self.machine
+    }
+
+    fn set_pause(&mut self, pause: Pause) {
+        self.machine.set_pause(pause)
+    }
+
+    fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
+        TraceMachine::load_program(self, program, args)
+    }
+
+    fn run_with_budget(&mut self, budget: u64) -> Result<RunOutcome, Error> {
+        TraceMachine::run_with_budget(self, budget)
+    }
+}
+
+#[cfg(has_asm)]
+impl SpawnMachine for AsmMachine {
+    type Inner = DefaultMachine<Box<AsmCoreMachine>>;
+
+    fn inner_mut(&mut self) -> &mut Self::Inner {
+        &mut self.machine
+    }
+
+    fn set_pause(&mut self, pause: Pause) {
+        self.machine.set_pause(pause)
+    }
+
+    fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
+        AsmMachine::load_program(self, program, args)
+    }
+
+    fn run_with_budget(&mut self, budget: u64) -> Result<RunOutcome, Error> {
+        AsmMachine::run_with_budget(self, budget)
+    }
+}
+
+pub type MachineFactory<M> = Box<dyn Fn(SpawnSyscalls) -> M>;
+
+/// Runs a tree of machines spawned from a root program. `programs` can be
+/// spawned by index, the root machine runs `programs[0]`. The factory builds
+/// a fresh machine with the given SpawnSyscalls installed.
+pub struct Scheduler<M: SpawnMachine> {
+    programs: Vec<Bytes>,
+    factory: MachineFactory<M>,
+    max_cycles: u64,
+    slice_cycles: u64,
+    max_machines: usize,
+    syscall_cost: SyscallCost,
+    machines: BTreeMap<u64, M>,
+    state: Arc<Mutex<SpawnState>>,
+    pause: Pause,
+    exited_cycles: u64,
+}
+
+impl<M: SpawnMachine> Scheduler<M> {
+    pub fn new(programs: Vec<Bytes>, max_cycles: u64, factory: MachineFactory<M>) -> Self {
+        Self {
+            programs,
+            factory,
+            max_cycles,
+            slice_cycles: DEFAULT_SLICE_CYCLES,
+            max_machines: DEFAULT_MAX_MACHINES,
+            syscall_cost: SyscallCost::new(DEFAULT_SYSCALL_CYCLES, DEFAULT_BYTE_CYCLES),
+            machines: BTreeMap::new(),
+            state: Arc::new(Mutex::new(SpawnState::new())),
+            pause: Pause::new(),
+            exited_cycles: 0,
+        }
+    }
+
+    pub fn slice_cycles(mut self, slice_cycles: u64) -> Self {
+        assert!(slice_cycles > 0);
+        self.slice_cycles = slice_cycles;
+        self
+    }
+
+    pub fn max_machines(mut self, max_machines: usize) -> Self {
+        self.max_machines = max_machines;
+        self
+    }
+
+    /// Cycles charged for each spawn syscall, and for each byte transferred
+    /// or loaded by it.
+    pub fn syscall_cost(mut self, syscall_cost: SyscallCost) -> Self {
+        self.syscall_cost = syscall_cost;
+        self
+    }
+
+    /// Cycles consumed by all machines, including the exited ones.
+    pub fn consumed_cycles(&mut self) -> u64 {
+        self.machines
+            .values_mut()
+            .fold(self.exited_cycles, |sum, machine| {
+                sum + machine.inner_mut().cycles()
+            })
+    }
+
+    /// Runs the root program until it exits, returns its exit code.
+    pub fn run(&mut self, args: &[Bytes]) -> Result<i8, Error> {
+        let root = self.create_machine(ROOT_PID, 0, args)?;
+        self.machines.insert(ROOT_PID, root);
+        loop {
+            let pid = self.next_runnable()?;
+            let consumed = self.consumed_cycles();
+            if consumed >= self.max_cycles {
+                return Err(Error::CyclesExceeded);
+            }
+            let budget = min(self.slice_cycles, self.max_cycles - consumed);
+            let machine = self.machines.get_mut(&pid).expect("runnable machine");
+            match machine.run_with_budget(budget) {
+                Ok(RunOutcome::Exited(exit_code)) => {
+                    if pid == ROOT_PID {
+                        if self.consumed_cycles() > self.max_cycles {
+                            return Err(Error::CyclesExceeded);
+                        }
+                        return Ok(exit_code);
+                    }
+                    let cycles = machine.inner_mut().cycles();
+                    self.machines.remove(&pid);
+                    self.exited_cycles += cycles;
+                    let mut state = lock(&self.state)?;
+                    state.close_all(pid);
+                    state.exit_codes.insert(pid, exit_code);
+                }
+                Ok(RunOutcome::Paused) | Err(Error::Pause) => {
+                    let request = lock(&self.state)?.spawn_request.take();
+                    if let Some((parent, request)) = request {
+                        if let Err(e) = self.spawn(parent, request) {
+                            self.fail(parent, e)?;
+                        }
+                    }
+                }
+                Ok(outcome) => {
+                    return Err(Error::Unexpected(format!(
+                        "Machine {} stopped by {:?}",
+                        pid, outcome
+                    )))
+                }
+                Err(e) => self.fail(pid, e)?,
+            }
+            self.unblock()?;
+        }
+    }
+
+    // Terminates a child machine failing with an error. The error is
+    // returned instead for the root machine, or when cycles run out, which
+    // applies to all machines.
+    fn fail(&mut self, pid: u64, error: Error) -> Result<(), Error> {
+        if pid == ROOT_PID || error == Error::CyclesExceeded {
+            return Err(error);
+        }
+        if let Some(mut machine) = self.machines.remove(&pid) {
+            self.exited_cycles += machine.inner_mut().cycles();
+        }
+        let mut state = lock(&self.state)?;
+        state.close_all(pid);
+        state.blocked.remove(&pid);
+        state.failed.insert(pid);
+        Ok(())
+    }
+
+    fn next_runnable(&mut self) -> Result<u64, Error> {
+        let blocked: Vec<u64> = lock(&self.state)?.blocked.keys().cloned().collect();
+        self.machines
+            .iter_mut()
+            .filter(|(pid, _)| !blocked.contains(pid))
+            .map(|(pid, machine)| (machine.inner_mut().cycles(), *pid))
+            .min()
+            .map(|(_, pid)| pid)
+            .ok_or(Error::SpawnDeadlock)
+    }
+
+    fn unblock(&mut self) -> Result<(), Error> {
+        // Fulfilling a request might unblock others, e.g. a read frees room
+        // for a blocked write, or a failing machine closes its fds.
+        loop {
+            let mut progressed = false;
+            let blocked: Vec<(u64, Request)> = lock(&self.state)?
+                .blocked
+                .iter()
+                .map(|(k, v)| (*k, v.clone()))
+                .collect();
+            for (pid, request) in blocked {
+                let machine = self.machines.get_mut(&pid).expect("blocked machine");
+                let completed = lock(&self.state)?.try_complete(
+                    pid,
+                    &request,
+                    machine.inner_mut(),
+                    self.syscall_cost.per_byte,
+                );
+                match completed {
+                    Ok(true) => {
+                        lock(&self.state)?.blocked.remove(&pid);
+                        progressed = true;
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        self.fail(pid, e)?;
+                        progressed = true;
+                    }
+                }
+            }
+            if !progressed {
+                return Ok(());
+            }
+        }
+    }
+
+    fn spawn(&mut self, parent: u64, request: Request) -> Result<(), Error> {
+        let (program, argc, argv, fds_addr, pid_addr) = match request {
+            Request::Spawn {
+                program,
+                argc,
+                argv,
+                fds,
+                pid_addr,
+            } => (program, argc, argv, fds, pid_addr),
+            _ => unreachable!(),
+        };
+        let machine = self
+            .machines
+            .get_mut(&parent)
+            .expect("parent machine")
+            .inner_mut();
+        let mut args = Vec::new();
+        for i in 0..argc {
+            let addr = load_u64(machine, argv + i * 8)?;
+            args.push(load_c_string(machine, addr)?);
+        }
+        let mut fds = Vec::new();
+        if fds_addr != 0 {
+            loop {
+                let fd = load_u64(machine, fds_addr + fds.len() as u64 * 8)?;
+                if fd == 0 {
+                    break;
+                }
+                fds.push(fd);
+            }
+        }
+
+        let (code, pid) = self.try_spawn(parent, program, &args, fds)?;
+        let machine = self
+            .machines
+            .get_mut(&parent)
+            .expect("parent machine")
+            .inner_mut();
+        if let Some(pid) = pid {
+            let loaded = args
+                .iter()
+                .fold(self.programs[program as usize].len(), |sum, arg| {
+                    sum + arg.len()
+                });
+            machine
+                .add_cycles_no_checking(self.syscall_cost.per_byte.saturating_mul(loaded as u64))?;
+            store_u64(machine, pid_addr, pid)?;
+        }
+        machine.set_register(A0, <M::Inner as CoreMachine>::REG::from_u64(code));
+        Ok(())
+    }
+
+    fn try_spawn(
+        &mut self,
+        parent: u64,
+        program: u64,
+        args: &[Bytes],
+        fds: Vec<u64>,
+    ) -> Result<(u64, Option<u64>), Error> {
+        if program >= self.programs.len() as u64 {
+            return Ok((INVALID_ARGUMENT, None));
+        }
+        if self.machines.len() >= self.max_machines {
+            return Ok((MAX_MACHINES_SPAWNED, None));
+        }
+        let pid = {
+            let mut state = lock(&self.state)?;
+            if fds.iter().any(|fd| !state.owns(parent, *fd)) {
+                return Ok((INVALID_FD, None));
+            }
+            let pid = state.next_pid;
+            state.next_pid += 1;
+            pid
+        };
+        let child = self.create_machine(pid, program, args)?;
+        self.machines.insert(pid, child);
+        let mut state = lock(&self.state)?;
+        for fd in &fds {
+            state.fds.insert(*fd, pid);
+        }
+        state.inherited_fds.insert(pid, fds);
+        Ok((SUCCESS, Some(pid)))
+    }
+
+    fn create_machine(&mut self, pid: u64, program: u64, args: &[Bytes]) -> Result<M, Error> {
+        let mut machine = (self.factory)(SpawnSyscalls {
+            pid,
+            state: Arc::clone(&self.state),
+            pause: self.pause.clone(),
+            cost: self.syscall_cost,
+        });
+        machine.set_pause(self.pause.clone());
+        machine.inner_mut().set_max_cycles(self.max_cycles);
+        machine.load_program(&self.programs[program as usize], args)?;
+        Ok(machine)
+    }
+}
+
+fn load_c_string<M: SupportMachine>(machine: &mut M, addr: u64) -> Result<Bytes, Error> {
+    let mut buffer = Vec::new();
+    loop {
+        let byte = machine
+            .memory_mut()
+            .load8(&M::REG::from_u64(addr + buffer.len() as u64))?
+            .to_u8();
+        if byte == 0 {
+            return Ok(buffer.into());
+        }
+        buffer.push(byte);
+    }
+}
+
+fn load_u64<M: SupportMachine>(machine: &mut M, addr: u64) -> Result<u64, Error> {
+    Ok(machine
+        .memory_mut()
+        .load64(&M::REG::from_u64(addr))?
+        .to_u64())
+}
+
+fn store_u64<M: SupportMachine>(machine: &mut M, addr: u64, value: u64) -> Result<(), Error> {
+    machine
+        .memory_mut()
+        .store64(&M::REG::from_u64(addr), &M::REG::from_u64(value))
+}
@@ -0,0 +1,520 @@
+use crate::{Bytes, Error};
+use byteorder::{ByteOrder, LittleEndian};
+use goblin_v040::elf::{section_header::SHF_COMPRESSED, sym::STT_FUNC, Elf};
+use std::collections::HashMap;
+
+// Symbolizer maps guest addresses to the functions containing them, using
+// the ELF symbol table, and to source files and lines, using the DWARF line
+// number programs in .debug_line. Programs built without debug info are only
+// resolved to functions, and stripped programs to nothing.
+//
+// Line number programs of DWARF versions 2 to 5 are supported, in both the
+// 32-bit and the 64-bit formats. Compressed debug sections are ignored. File
+// paths are kept relative to the compilation directory.
+//
+// A symbolizer attached to a DefaultMachine is used in its Display output,
+// and errors stopping the Rust interpreters are wrapped in Error::Located.
+
+const UNKNOWN: &str = "??";
+
+// Extended opcodes.
+const DW_LNE_END_SEQUENCE: u8 = 0x01;
+const DW_LNE_SET_ADDRESS: u8 = 0x02;
+const DW_LNE_DEFINE_FILE: u8 = 0x03;
+// Standard opcodes.
+const DW_LNS_COPY: u8 = 0x01;
+const DW_LNS_ADVANCE_PC: u8 = 0x02;
+const DW_LNS_ADVANCE_LINE: u8 = 0x03;
+const DW_LNS_SET_FILE: u8 = 0x04;
+const DW_LNS_SET_COLUMN: u8 = 0x05;
+const DW_LNS_NEGATE_STMT: u8 = 0x06;
+const DW_LNS_SET_BASIC_BLOCK: u8 = 0x07;
+const DW_LNS_CONST_ADD_PC: u8 = 0x08;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 0x09;
+// Entry formats of DWARF 5.
+const DW_LNCT_PATH: u64 = 0x01;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 0x02;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+
+/// A function in the ELF symbol table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+    pub address: u64,
+    pub size: u64,
+    pub name: String,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Row {
+    address: u64,
+    // Index into Symbolizer::files, None if the file is not defined.
+    file: Option<usize>,
+    line: u64,
+}
+
+// Rows of a sequence of contiguous instructions, sorted by address.
+#[derive(Debug)]
+struct Sequence {
+    start: u64,
+    end: u64,
+    rows: Vec<Row>,
+}
+
+#[derive(Debug, Default)]
+pub struct Symbolizer {
+    // Sorted by address.
+    functions: Vec<Function>,
+    files: Vec<String>,
+    file_indices: HashMap<String, usize>,
+    // Sorted by start address.
+    sequences: Vec<Sequence>,
+}
+
+impl Symbolizer {
+    pub fn new(program: &Bytes) -> Result<Self, Error> {
+        let elf = Elf::parse(program)?;
+        let mut functions: Vec<Function> = elf
+            .syms
+            .iter()
+            .filter(|sym| sym.st_type() == STT_FUNC && sym.st_value != 0)
+            .filter_map(|sym| {
+                elf.strtab.get_at(sym.st_name).map(|name| Function {
+                    address: sym.st_value,
+                    size: sym.st_size,
+                    name: name.to_string(),
+                })
+            })
+            .collect();
+        functions.sort_by_key(|function| function.address);
+
+        let section = |name: &str| -> Result<&[u8], Error> {
+            let header = elf.section_headers.iter().find(|header| {
+                elf.shdr_strtab.get_at(header.sh_name) == Some(name)
+                    && header.sh_flags & u64::from(SHF_COMPRESSED) == 0
+            });
+            match header {
+                Some(header) => program
+                    .get(header.sh_offset as usize..)
+                    .and_then(|data| data.get(..header.sh_size as usize))
+                    .ok_or_else(|| dwarf_error("section out of bound")),
+                None => Ok(&[]),
+            }
+        };
+        let strings = Strings {
+            line_str: section(".debug_line_str")?,
+            str: section(".debug_str")?,
+        };
+        let mut symbolizer = Self {
+            functions,
+            ..Default::default()
+        };
+        let mut reader = Reader::new(section(".debug_line")?);
+        while !reader.is_empty() {
+            symbolizer.parse_unit(&mut reader, &strings)?;
+        }
+        symbolizer.sequences.sort_by_key(|sequence| sequence.start);
+        Ok(symbolizer)
+    }
+
+    /// The function containing pc.
+    pub fn function(&self, pc: u64) -> Option<&Function> {
+        let index = self
+            .functions
+            .partition_point(|function| function.address <= pc)
+            .checked_sub(1)?;
+        let function = &self.functions[index];
+        // Functions without sizes extend to the next function.
+        if function.size == 0 || pc < function.address + function.size {
+            Some(function)
+        } else {
+            None
+        }
+    }
+
+    /// The source file and line of the instruction at pc.
+    pub fn line(&self, pc: u64) -> Option<(&str, u64)> {
+        let index = self
+            .sequences
+            .partition_point(|sequence| sequence.start <= pc)
+            .checked_sub(1)?;
+        let sequence = &self.sequences[index];
+        if pc >= sequence.end {
+            return None;
+        }
+        let index = sequence
+            .rows
+            .partition_point(|row| row.address <= pc)
+            .checked_sub(1)?;
+        let row = sequence.rows[index];
+        let file = row.file.map_or(UNKNOWN, |file| &self.files[file]);
+        Some((file, row.line))
+    }
+
+    /// Functions in the symbol table, sorted by address.
+    pub fn functions(&self) -> &[Function] {
+        &self.functions
+    }
+
+    /// Addresses starting a source line, with their files and lines, sorted
+    /// by address within each sequence. Rows in undefined files are skipped.
+    pub fn lines(&self) -> impl Iterator<Item = (u64, &str, u64)> + '_ {
+        self.sequences.iter().flat_map(move |sequence| {
+            sequence.rows.iter().filter_map(move |row| {
+                row.file
+                    .map(|file| (row.address, self.files[file].as_str(), row.line))
+            })
+        })
+    }
+
+    /// Describes pc like "leaf+0x4 at leaf.c:12", as far as the program
+    /// tells, or "??" if nothing is known.
+    pub fn describe(&self, pc: u64) -> String {
+        let mut description = match self.function(pc) {
+            Some(function) if pc == function.address => function.name.clone(),
+            Some(function) => format!("{}+0x{:x}", function.name, pc - function.address),
+            None => UNKNOWN.to_string(),
+        };
+        if let Some((file, line)) = self.line(pc) {
+            description.push_str(&format!(" at {}:{}", file, line));
+        }
+        description
+    }
+
+    fn intern(&mut self, path: String) -> usize {
+        if let Some(index) = self.file_indices.get(&path) {
+            return *index;
+        }
+        self.files.push(path.clone());
+        self.file_indices.insert(path, self.files.len() - 1);
+        self.files.len() - 1
+    }
+
+    fn parse_unit(&mut self, reader: &mut Reader, strings: &Strings) -> Result<(), Error> {
+        let mut offset_size = 4;
+        let mut unit_length = u64::from(reader.u32()?);
+        if unit_length == 0xffff_ffff {
+            offset_size = 8;
+            unit_length = reader.u64()?;
+        }
+        let mut unit = Reader::new(reader.bytes(unit_length)?);
+        let version = unit.u16()?;
+        if !(2..=5).contains(&version) {
+            return Err(dwarf_error(&format!("unsupported version {}", version)));
+        }
+        if version >= 5 {
+            // Address size and segment selector size.
+            unit.bytes(2)?;
+        }
+        let header_length = unit.offset(offset_size)?;
+        let mut program = unit.clone();
+        program.bytes(header_length)?;
+
+        let minimum_instruction_length = u64::from(unit.u8()?);
+        if version >= 4 {
+            // Maximum operations per instruction, only used by VLIW.
+            unit.u8()?;
+        }
+        // Default is_stmt.
+        unit.u8()?;
+        let line_base = unit.u8()? as i8;
+        let line_range = unit.u8()?;
+        let opcode_base = unit.u8()?;
+        if line_range == 0 || opcode_base == 0 {
+            return Err(dwarf_error("invalid header"));
+        }
+        let standard_opcode_lengths = unit.bytes(u64::from(opcode_base - 1))?;
+
+        // Paths indexed by file numbers used in the program.
+        let (directories, paths) = if version >= 5 {
+            let directories = parse_entries(&mut unit, offset_size, strings)?
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect::<Vec<_>>();
+            let paths = parse_entries(&mut unit, offset_size, strings)?
+                .into_iter()
+                .map(|(path, directory)| Some(join(&directories, directory, path)))
+                .collect();
+            (directories, paths)
+        } else {
+            // The compilation directory is implicitly the first one.
+            let mut directories = vec![String::new()];
+            loop {
+                let directory = unit.cstr()?;
+                if directory.is_empty() {
+                    break;
+                }
+                directories.push(directory);
+            }
+            let mut paths = vec![None];
+            loop {
+                let path = unit.cstr()?;
+                if path.is_empty() {
+                    break;
+                }
+                let directory = unit.uleb()?;
+                // Modification time and length.
+                unit.uleb()?;
+                unit.uleb()?;
+                paths.push(Some(join(&directories, directory, path)));
+            }
+            (directories, paths)
+        };
+        let mut files: Vec<Option<usize>> = paths
+            .into_iter()
+            .map(|path| path.map(|path| self.intern(path)))
+            .collect();
+
+        let mut address = 0;
+        let mut file = 1;
+        let mut line = 1u64;
+        let mut rows = Vec::new();
+        while !program.is_empty() {
+            let opcode = program.u8()?;
+            if opcode >= opcode_base {
+                let adjusted = opcode - opcode_base;
+                address += u64::from(adjusted / line_range) * minimum_instruction_length;
+                line = line
+                    .wrapping_add((i64::from(line_base) + i64::from(adjusted % line_range)) as u64);
+                rows.push(Row {
+                    address,
+                    file: files.get(file as usize).copied().flatten(),
+                    line,
+                });
+                continue;
+            }
+            match opcode {
+                0 => {
+                    let length = program.uleb()?;
+                    let mut extended = Reader::new(program.bytes(length)?);
+                    match extended.u8()? {
+                        DW_LNE_END_SEQUENCE => {
+                            if let Some(first) = rows.first() {
+                                self.sequences.push(Sequence {
+                                    start: first.address,
+                                    end: address,
+                                    rows: std::mem::take(&mut rows),
+                                });
+                            }
+                            address = 0;
+                            file = 1;
+                            line = 1;
+                        }
+                        DW_LNE_SET_ADDRESS => {
+                            address = extended.offset(length - 1)?;
+                        }
+                        DW_LNE_DEFINE_FILE => {
+                            let path = extended.cstr()?;
+                            let directory = extended.uleb()?;
+                            let path = join(&directories, directory, path);
+                            files.push(Some(self.intern(path)));
+                        }
+                        // Discriminators and vendor extensions.
+                        _ => (),
+                    }
+                }
+                DW_LNS_COPY => rows.push(Row {
+                    address,
+                    file: files.get(file as usize).copied().flatten(),
+                    line,
+                }),
+                DW_LNS_ADVANCE_PC => {
+                    address += program.uleb()? * minimum_instruction_length;
+                }
+                DW_LNS_ADVANCE_LINE => line = line.wrapping_add(program.sleb()? as u64),
+                DW_LNS_SET_FILE => file = program.uleb()?,
+                DW_LNS_SET_COLUMN => {
+                    program.uleb()?;
+                }
+                DW_LNS_NEGATE_STMT | DW_LNS_SET_BASIC_BLOCK => (),
+                DW_LNS_CONST_ADD_PC => {
+                    address +=
+                        u64::from((255 - opcode_base) / line_range) * minimum_instruction_length;
+                }
+                DW_LNS_FIXED_ADVANCE_PC => address += u64::from(program.u16()?),
+                // Other standard opcodes only set registers not tracked here.
+                _ => {
+                    for _ in 0..standard_opcode_lengths[opcode as usize - 1] {
+                        program.uleb()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Strings<'a> {
+    line_str: &'a [u8],
+    str: &'a [u8],
+}
+
+// Parses directory or file name entries of DWARF 5, returns paths and their
+// directory indices.
+fn parse_entries(
+    reader: &mut Reader,
+    offset_size: u64,
+    strings: &Strings,
+) -> Result<Vec<(String, u64)>, Error> {
+    let format_count = reader.u8()?;
+    let mut formats = Vec::with_capacity(format_count as usize);
+    for _ in 0..format_count {
+        formats.push((reader.uleb()?, reader.uleb()?));
+    }
+    let count = reader.uleb()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let mut path = String::new();
+        let mut directory = 0;
+        for (content, form) in &formats {
+            let mut value = 0;
+            match *form {
+                DW_FORM_STRING => path = reader.cstr()?,
+                DW_FORM_LINE_STRP | DW_FORM_STRP => {
+                    let section = if *form == DW_FORM_LINE_STRP {
+                        strings.line_str
+                    } else {
+                        strings.str
+                    };
+                    let offset = reader.offset(offset_size)?;
+                    let mut strings = Reader::new(section);
+                    strings.bytes(offset)?;
+                    path = strings.cstr()?;
+                }
+                DW_FORM_DATA1 => value = u64::from(reader.u8()?),
+                DW_FORM_DATA2 => value = u64::from(reader.u16()?),
+                DW_FORM_DATA4 => value = u64::from(reader.u32()?),
+                DW_FORM_DATA8 => value = reader.u64()?,
+                DW_FORM_UDATA => value = reader.uleb()?,
+                DW_FORM_DATA16 => {
+                    reader.bytes(16)?;
+                }
+                DW_FORM_BLOCK => {
+                    let length = reader.uleb()?;
+                    reader.bytes(length)?;
+                }
+                _ => return Err(dwarf_error(&format!("unsupported form 0x{:x}", form))),
+            }
+            match *content {
+                DW_LNCT_PATH => (),
+                DW_LNCT_DIRECTORY_INDEX => directory = value,
+                _ => (),
+            }
+        }
+        entries.push((path, directory));
+    }
+    Ok(entries)
+}
+
+// Joins a path with its directory, the compilation directory being omitted.
+fn join(directories: &[String], directory: u64, path: String) -> String {
+    match directories.get(directory as usize) {
+        Some(name) if directory != 0 && !path.starts_with('/') => format!("{}/{}", name, path),
+        _ => path,
+    }
+}
+
+fn dwarf_error(message: &str) -> Error {
+    Error::ElfParseError(format!("dwarf: {}", message))
+}
+
+#[derive(Clone)]
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, length: u64) -> Result<&'a [u8], Error> {
+        if length > self.data.len() as u64 {
+            return Err(dwarf_error("unexpected end of section"));
+        }
+        let (bytes, rest) = self.data.split_at(length as usize);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(LittleEndian::read_u16(self.bytes(2)?))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(LittleEndian::read_u32(self.bytes(4)?))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(LittleEndian::read_u64(self.bytes(8)?))
+    }
+
+    // Reads an offset or an address of 4 or 8 bytes.
+    fn offset(&mut self, size: u64) -> Result<u64, Error> {
+        match size {
+            4 => Ok(u64::from(self.u32()?)),
+            8 => self.u64(),
+            _ => Err(dwarf_error(&format!("unsupported size {}", size))),
+        }
+    }
+
+    fn uleb(&mut self) -> Result<u64, Error> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= u64::from(byte & 0x7f) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn sleb(&mut self) -> Result<i64, Error> {
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= i64::from(byte & 0x7f) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    fn cstr(&mut self) -> Result<String, Error> {
+        let length = self
+            .data
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(|| dwarf_error("unterminated string"))?;
+        let string = String::from_utf8_lossy(&self.data[..length]).into_owned();
+        self.data = &self.data[length + 1..];
+        Ok(string)
+    }
+}
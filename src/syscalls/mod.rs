@@ -1,5 +1,6 @@
 use super::Error;
 use crate::machine::SupportMachine;
+use std::collections::{BTreeMap, BTreeSet};
 
 pub trait Syscalls<Mac: SupportMachine>: Send + Sync {
     fn initialize(&mut self, machine: &mut Mac) -> Result<(), Error>;
@@ -8,3 +9,138 @@ pub trait Syscalls<Mac: SupportMachine>: Send + Sync {
     // the next syscall module to process.
     fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error>;
 }
+
+/// The exit syscall, handled by DefaultMachine itself.
+pub const EXIT: u64 = 93;
+
+/// Cycles charged by the machine for a syscall in a SyscallRegistry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyscallCost {
+    /// Charged before the syscall is handled.
+    pub base: u64,
+    /// Charged for each byte the syscall reports, after it is handled.
+    pub per_byte: u64,
+}
+
+impl SyscallCost {
+    pub fn new(base: u64, per_byte: u64) -> Self {
+        Self { base, per_byte }
+    }
+}
+
+/// The handler of a single syscall number. Unlike Syscalls, handlers never
+/// charge cycles themselves, they return the number of bytes processed and
+/// the machine charges the SyscallCost declared for them.
+pub trait Syscall<Mac: SupportMachine>: Send + Sync {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<u64, Error>;
+}
+
+impl<Mac, F> Syscall<Mac> for F
+where
+    Mac: SupportMachine,
+    F: FnMut(&mut Mac) -> Result<u64, Error> + Send + Sync,
+{
+    fn ecall(&mut self, machine: &mut Mac) -> Result<u64, Error> {
+        self(machine)
+    }
+}
+
+// Syscall handlers indexed by their numbers. DefaultMachine looks up the
+// registry first, and only falls back to its Syscalls modules, in the order
+// they are added, for numbers not registered here. Numbers of syscalls the
+// machine handles itself, such as exit, are reserved and have no handler.
+pub struct SyscallRegistry<Mac> {
+    handlers: BTreeMap<u64, (SyscallCost, Box<dyn Syscall<Mac>>)>,
+    reserved: BTreeSet<u64>,
+}
+
+impl<Mac> Default for SyscallRegistry<Mac> {
+    fn default() -> Self {
+        Self {
+            handlers: BTreeMap::new(),
+            reserved: BTreeSet::from([EXIT]),
+        }
+    }
+}
+
+impl<Mac> SyscallRegistry<Mac> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails with Error::DuplicateSyscall when the number is already taken,
+    /// including by a reserved syscall.
+    pub fn register(
+        &mut self,
+        number: u64,
+        cost: SyscallCost,
+        handler: Box<dyn Syscall<Mac>>,
+    ) -> Result<(), Error> {
+        if self.is_taken(number) {
+            return Err(Error::DuplicateSyscall(number));
+        }
+        self.handlers.insert(number, (cost, handler));
+        Ok(())
+    }
+
+    /// Reserves the number of a syscall handled by the machine itself, fails
+    /// with Error::DuplicateSyscall when the number is already taken.
+    pub fn reserve(&mut self, number: u64) -> Result<(), Error> {
+        if self.is_taken(number) {
+            return Err(Error::DuplicateSyscall(number));
+        }
+        self.reserved.insert(number);
+        Ok(())
+    }
+
+    fn is_taken(&self, number: u64) -> bool {
+        self.reserved.contains(&number) || self.handlers.contains_key(&number)
+    }
+
+    pub fn contains(&self, number: u64) -> bool {
+        self.handlers.contains_key(&number)
+    }
+
+    pub fn is_reserved(&self, number: u64) -> bool {
+        self.reserved.contains(&number)
+    }
+
+    // Moves the handlers to a new registry, the reserved numbers are kept by
+    // both of them.
+    pub(crate) fn take_handlers(&mut self) -> Self {
+        Self {
+            handlers: std::mem::take(&mut self.handlers),
+            reserved: self.reserved.clone(),
+        }
+    }
+
+    pub fn cost(&self, number: u64) -> Option<SyscallCost> {
+        self.handlers.get(&number).map(|(cost, _)| *cost)
+    }
+}
+
+impl<Mac: SupportMachine> SyscallRegistry<Mac> {
+    pub fn initialize(&mut self, machine: &mut Mac) -> Result<(), Error> {
+        for (_, handler) in self.handlers.values_mut() {
+            handler.initialize(machine)?;
+        }
+        Ok(())
+    }
+
+    /// Handles the syscall and charges its cycles, returns false if the
+    /// number is not registered.
+    pub fn ecall(&mut self, number: u64, machine: &mut Mac) -> Result<bool, Error> {
+        let (cost, handler) = match self.handlers.get_mut(&number) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        machine.add_cycles(cost.base)?;
+        let bytes = handler.ecall(machine)?;
+        machine.add_cycles(cost.per_byte.saturating_mul(bytes))?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,228 @@
+use crate::instructions::Register;
+use crate::machine::{DefaultMachine, RunOutcome, SupportMachine};
+use crate::snapshot::{make_delta_snapshot, make_snapshot, resume_chain, resume_pages, Snapshot};
+use crate::syscalls::Syscalls;
+use crate::{Bytes, Error};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// Time travel lets one inspect the state of a program at any earlier cycle
+// count, typically to step backwards from a failure found after running for
+// a long time.
+//
+// TimeTravel::record runs a program while taking a checkpoint every
+// `checkpoint_interval` cycles. Checkpoints are delta snapshots (see
+// snapshot.rs) kept in memory, so each of them only contains the pages
+// modified since the previous one. Syscalls are not deterministic in
+// general, so their effects are recorded as well: before a syscall, pages
+// dirtied so far are moved into the snapshot chain, so the pages dirtied
+// by the syscall can be told apart and saved together with the registers
+// and cycles after it.
+//
+// TimeTravel::travel rebuilds a machine by restoring the nearest checkpoint
+// before the requested cycle count, then replays the program forward to it.
+// During replaying, recorded syscall effects are applied instead of invoking
+// the real syscalls.
+//
+// Machines are built by a factory, which is called with the syscalls to
+// install, once for recording and once for every travel. They must be built
+// with the same ISA, version and cycle function, and without other syscalls
+// or breakpoints. The dirty flags of the recording machine are reset at each
+// checkpoint, so the machine can't be used with Snapshot2Context.
+
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 10_000_000;
+
+pub type MachineFactory<Inner> = Box<dyn Fn(TimeTravelSyscalls<Inner>) -> DefaultMachine<Inner>>;
+
+struct SyscallEffect {
+    result: Result<bool, Error>,
+    cycles: u64,
+    // Registers after the syscall, and pages modified by it.
+    snapshot: Snapshot,
+}
+
+#[derive(Default)]
+struct Recording {
+    // Delta snapshots, including the ones taken before syscalls.
+    chain: Vec<Snapshot>,
+    effects: Vec<SyscallEffect>,
+}
+
+struct Checkpoint {
+    cycles: u64,
+    instret: u64,
+    // Number of syscalls invoked before the checkpoint.
+    syscalls: usize,
+    // Number of snapshots in the chain to restore the checkpoint.
+    chain_length: usize,
+}
+
+fn lock(state: &Mutex<Recording>) -> Result<MutexGuard<'_, Recording>, Error> {
+    state.lock().map_err(|e| Error::Unexpected(e.to_string()))
+}
+
+enum Mode<Inner> {
+    Record(Vec<Box<dyn Syscalls<Inner>>>),
+    // Index of the next syscall effect to apply.
+    Replay(usize),
+}
+
+/// Syscalls installed in machines built for time travel. When recording,
+/// they forward ecalls to the real syscalls and record their effects. When
+/// replaying, they apply recorded effects.
+pub struct TimeTravelSyscalls<Inner> {
+    state: Arc<Mutex<Recording>>,
+    mode: Mode<Inner>,
+}
+
+impl<Inner: SupportMachine> Syscalls<Inner> for TimeTravelSyscalls<Inner> {
+    fn initialize(&mut self, machine: &mut Inner) -> Result<(), Error> {
+        if let Mode::Record(syscalls) = &mut self.mode {
+            for syscall in syscalls {
+                syscall.initialize(machine)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Inner) -> Result<bool, Error> {
+        match &mut self.mode {
+            Mode::Record(syscalls) => {
+                let snapshot = make_delta_snapshot(machine)?;
+                lock(&self.state)?.chain.push(snapshot);
+                let mut result = Ok(false);
+                for syscall in syscalls.iter_mut() {
+                    result = syscall.ecall(machine);
+                    if result != Ok(false) {
+                        break;
+                    }
+                }
+                let effect = SyscallEffect {
+                    result: result.clone(),
+                    cycles: machine.cycles(),
+                    snapshot: make_snapshot(machine)?,
+                };
+                lock(&self.state)?.effects.push(effect);
+                result
+            }
+            Mode::Replay(next) => {
+                let state = lock(&self.state)?;
+                let effect = state.effects.get(*next).ok_or_else(|| {
+                    Error::Unexpected(format!("Syscall {} is not recorded", next))
+                })?;
+                *next += 1;
+                // The pc is not restored, it still points to the ecall.
+                for (i, v) in effect.snapshot.registers.iter().enumerate() {
+                    machine.set_register(i, Inner::REG::from_u64(*v));
+                }
+                resume_pages(machine, &effect.snapshot)?;
+                machine.set_cycles(effect.cycles);
+                effect.result.clone()
+            }
+        }
+    }
+}
+
+pub struct TimeTravel<Inner> {
+    factory: MachineFactory<Inner>,
+    checkpoint_interval: u64,
+    state: Arc<Mutex<Recording>>,
+    checkpoints: Vec<Checkpoint>,
+    end_cycles: u64,
+}
+
+impl<Inner: SupportMachine> TimeTravel<Inner> {
+    pub fn new(factory: MachineFactory<Inner>) -> Self {
+        Self {
+            factory,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            state: Arc::new(Mutex::new(Recording::default())),
+            checkpoints: Vec::new(),
+            end_cycles: 0,
+        }
+    }
+
+    pub fn checkpoint_interval(mut self, checkpoint_interval: u64) -> Self {
+        self.checkpoint_interval = checkpoint_interval;
+        self
+    }
+
+    /// Runs a program until it exits or fails, recording checkpoints and
+    /// syscall effects. A previous recording is discarded.
+    pub fn record(
+        &mut self,
+        program: &Bytes,
+        args: &[Bytes],
+        syscalls: Vec<Box<dyn Syscalls<Inner>>>,
+    ) -> Result<i8, Error> {
+        *lock(&self.state)? = Recording::default();
+        self.checkpoints.clear();
+        self.end_cycles = 0;
+        let mut machine = (self.factory)(TimeTravelSyscalls {
+            state: Arc::clone(&self.state),
+            mode: Mode::Record(syscalls),
+        });
+        machine.load_program(program, args)?;
+        self.checkpoint(&mut machine)?;
+        loop {
+            let result = machine.run_with_budget(self.checkpoint_interval);
+            self.end_cycles = machine.cycles();
+            match result? {
+                RunOutcome::Exited(exit_code) => return Ok(exit_code),
+                _ => self.checkpoint(&mut machine)?,
+            }
+        }
+    }
+
+    fn checkpoint(&mut self, machine: &mut DefaultMachine<Inner>) -> Result<(), Error> {
+        let snapshot = make_delta_snapshot(machine)?;
+        let mut state = lock(&self.state)?;
+        state.chain.push(snapshot);
+        self.checkpoints.push(Checkpoint {
+            cycles: machine.cycles(),
+            instret: machine.instret(),
+            syscalls: state.effects.len(),
+            chain_length: state.chain.len(),
+        });
+        Ok(())
+    }
+
+    /// Cycles consumed when the recorded program exits or fails.
+    pub fn end_cycles(&self) -> u64 {
+        self.end_cycles
+    }
+
+    /// Cycle counts at which checkpoints are taken.
+    pub fn checkpoint_cycles(&self) -> Vec<u64> {
+        self.checkpoints.iter().map(|c| c.cycles).collect()
+    }
+
+    /// Rebuilds the machine at `cycles`, i.e. after executing all the
+    /// instructions fitting in that many cycles. Replaying to the point
+    /// where the recorded program fails returns the same error.
+    pub fn travel(&self, cycles: u64) -> Result<DefaultMachine<Inner>, Error> {
+        if cycles > self.end_cycles {
+            return Err(Error::Unexpected(format!(
+                "Cycles {} are beyond the recording",
+                cycles
+            )));
+        }
+        let checkpoint = self
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|c| c.cycles <= cycles)
+            .ok_or_else(|| Error::Unexpected("No checkpoint is recorded".to_string()))?;
+        let mut machine = (self.factory)(TimeTravelSyscalls {
+            state: Arc::clone(&self.state),
+            mode: Mode::Replay(checkpoint.syscalls),
+        });
+        resume_chain(
+            &mut machine,
+            &lock(&self.state)?.chain[..checkpoint.chain_length],
+        )?;
+        machine.set_cycles(checkpoint.cycles);
+        machine.set_instret(checkpoint.instret);
+        machine.run_within_budget(cycles - checkpoint.cycles)?;
+        Ok(machine)
+    }
+}
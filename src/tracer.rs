@@ -0,0 +1,346 @@
+use crate::debugger::memory_access;
+use crate::decoder::{build_decoder, Decoder};
+use crate::instructions::{execute, Instruction, Register};
+use crate::machine::{DefaultMachine, Machine};
+use crate::memory::Memory;
+use crate::{CoreMachine, Error, SupportMachine};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{ErrorKind, Read, Write};
+
+// Tracer records every instruction retired by a machine into a log, which
+// can be audited later, or verified against a fresh machine to find the
+// first instruction where two executions diverge.
+//
+// A tracer is installed with DefaultMachine::set_tracer. Instructions are then
+// executed one by one, a TraceMachine falls back to stepping like
+// DefaultMachine. Instructions failing with an error are not recorded.
+//
+// For each instruction, the log contains the pc, the decoded instruction,
+// cycles after executing it, registers whose values are changed, and memory
+// read or written by load, store and atomic instructions. Memory modified by
+// syscalls is not recorded, the verifying machine is expected to be built
+// with the same syscalls.
+//
+// The log is in a compact little endian binary format, where varints are
+// unsigned LEB128 integers:
+//
+//   magic                  8 bytes, "CKBVMTRC"
+//   format version         u32, TRACE_FORMAT_VERSION
+//   records, each of them being:
+//     pc                   varint
+//     instruction          varint
+//     cycles               varint, delta from cycles of the previous record
+//     register count       u8
+//     registers, each of them being:
+//       index              u8
+//       value              varint
+//     memory count         u8
+//     memory effects, each of them being:
+//       kind               u8, size in bytes, the highest bit is set for writes
+//       address            varint
+//       value              varint, the little endian value of the bytes
+
+pub const TRACE_MAGIC: &[u8; 8] = b"CKBVMTRC";
+pub const TRACE_FORMAT_VERSION: u32 = 1;
+
+const MEMORY_WRITE_BIT: u8 = 0x80;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryEffect {
+    pub address: u64,
+    pub size: u64,
+    pub write: bool,
+    pub value: u64,
+}
+
+/// A retired instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u64,
+    pub instruction: Instruction,
+    pub cycles: u64,
+    pub register_writes: Vec<(usize, u64)>,
+    pub memory_effects: Vec<MemoryEffect>,
+}
+
+// Reads memory without side effects on the machine. None is returned for
+// memory the instruction can't access, which it fails on by itself.
+fn load_value<M: CoreMachine>(machine: &mut M, address: u64, size: u64) -> Option<u64> {
+    let bytes = machine.memory_mut().peek_bytes(address, size).ok()?;
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0u64, |value, byte| (value << 8) | u64::from(*byte)),
+    )
+}
+
+// Executes an instruction like `execute`, and records its effects. Cycles of
+// the instruction shall be added before.
+pub(crate) fn execute_recorded<M: Machine + SupportMachine>(
+    instruction: Instruction,
+    machine: &mut M,
+) -> Result<TraceRecord, Error> {
+    let pc = machine.pc().to_u64();
+    let registers: Vec<u64> = machine.registers().iter().map(|r| r.to_u64()).collect();
+    let access = memory_access(instruction, machine.registers());
+    let mut memory_effects = Vec::new();
+    if let Some(access) = access.filter(|access| access.read) {
+        if let Some(value) = load_value(machine, access.address, access.size) {
+            memory_effects.push(MemoryEffect {
+                address: access.address,
+                size: access.size,
+                write: false,
+                value,
+            });
+        }
+    }
+    execute(instruction, machine)?;
+    if let Some(access) = access.filter(|access| access.write) {
+        if let Some(value) = load_value(machine, access.address, access.size) {
+            memory_effects.push(MemoryEffect {
+                address: access.address,
+                size: access.size,
+                write: true,
+                value,
+            });
+        }
+    }
+    let register_writes = machine
+        .registers()
+        .iter()
+        .zip(registers)
+        .enumerate()
+        .filter(|(_, (after, before))| after.to_u64() != *before)
+        .map(|(i, (after, _))| (i, after.to_u64()))
+        .collect();
+    Ok(TraceRecord {
+        pc,
+        instruction,
+        cycles: machine.cycles(),
+        register_writes,
+        memory_effects,
+    })
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> Result<(), Error> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            writer.write_u8(byte)?;
+            return Ok(());
+        }
+        writer.write_u8(byte | 0x80)?;
+    }
+}
+
+fn decode_error(e: std::io::Error) -> Error {
+    Error::TraceDecodeError(e.to_string())
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = reader.read_u8().map_err(decode_error)?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::TraceDecodeError("varint is too long".to_string()))
+}
+
+pub struct Tracer {
+    writer: Box<dyn Write + Send + Sync>,
+    cycles: u64,
+}
+
+impl Tracer {
+    pub fn new(mut writer: Box<dyn Write + Send + Sync>) -> Result<Self, Error> {
+        writer.write_all(TRACE_MAGIC)?;
+        writer.write_u32::<LittleEndian>(TRACE_FORMAT_VERSION)?;
+        Ok(Self { writer, cycles: 0 })
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> Result<(), Error> {
+        let writer = &mut self.writer;
+        write_varint(writer, record.pc)?;
+        write_varint(writer, record.instruction)?;
+        write_varint(writer, record.cycles.wrapping_sub(self.cycles))?;
+        writer.write_u8(record.register_writes.len() as u8)?;
+        for (index, value) in &record.register_writes {
+            writer.write_u8(*index as u8)?;
+            write_varint(writer, *value)?;
+        }
+        writer.write_u8(record.memory_effects.len() as u8)?;
+        for effect in &record.memory_effects {
+            let mut kind = effect.size as u8;
+            if effect.write {
+                kind |= MEMORY_WRITE_BIT;
+            }
+            writer.write_u8(kind)?;
+            write_varint(writer, effect.address)?;
+            write_varint(writer, effect.value)?;
+        }
+        self.cycles = record.cycles;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads records from a log written by Tracer.
+pub struct TraceReader<R> {
+    reader: R,
+    cycles: u64,
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).map_err(decode_error)?;
+        if &magic != TRACE_MAGIC {
+            return Err(Error::TraceDecodeError("invalid magic".to_string()));
+        }
+        let version = reader.read_u32::<LittleEndian>().map_err(decode_error)?;
+        if version != TRACE_FORMAT_VERSION {
+            return Err(Error::TraceDecodeError(format!(
+                "unsupported format version {}",
+                version
+            )));
+        }
+        Ok(Self { reader, cycles: 0 })
+    }
+
+    fn read_record(&mut self, first: u8) -> Result<TraceRecord, Error> {
+        let reader = &mut self.reader;
+        let pc = read_varint(&mut (&[first][..]).chain(&mut *reader))?;
+        let instruction = read_varint(reader)?;
+        let cycles = self.cycles.wrapping_add(read_varint(reader)?);
+        let count = reader.read_u8().map_err(decode_error)?;
+        let mut register_writes = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let index = reader.read_u8().map_err(decode_error)? as usize;
+            register_writes.push((index, read_varint(reader)?));
+        }
+        let count = reader.read_u8().map_err(decode_error)?;
+        let mut memory_effects = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let kind = reader.read_u8().map_err(decode_error)?;
+            let address = read_varint(reader)?;
+            let value = read_varint(reader)?;
+            memory_effects.push(MemoryEffect {
+                address,
+                size: u64::from(kind & !MEMORY_WRITE_BIT),
+                write: kind & MEMORY_WRITE_BIT != 0,
+                value,
+            });
+        }
+        self.cycles = cycles;
+        Ok(TraceRecord {
+            pc,
+            instruction,
+            cycles,
+            register_writes,
+            memory_effects,
+        })
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = Result<TraceRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The log may only end at a record boundary.
+        let first = match self.reader.read_u8() {
+            Ok(byte) => byte,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return None,
+            Err(e) => return Some(Err(decode_error(e))),
+        };
+        Some(self.read_record(first))
+    }
+}
+
+/// The first difference between a log and a replaying machine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// Index of the diverging record.
+    pub index: u64,
+    /// The record in the log, None if the log ends before the machine stops.
+    pub expected: Option<TraceRecord>,
+    /// The record produced by the machine, None if the machine exits before
+    /// the log ends.
+    pub actual: Option<TraceRecord>,
+}
+
+// Executes an instruction like DefaultMachine::step, and records it.
+fn execute_step<Inner: SupportMachine>(
+    machine: &mut DefaultMachine<Inner>,
+    decoder: &mut Decoder,
+) -> Result<TraceRecord, Error> {
+    let pc = machine.pc().to_u64();
+    let instruction = decoder.decode(machine.memory_mut(), pc)?;
+    let cycles = machine.cost_model().cycles(instruction);
+    machine.add_cycles(cycles)?;
+    let context = machine.cost_context(instruction);
+    let record = execute_recorded(instruction, machine).map_err(|e| e.with_pc(pc))?;
+    machine.set_instret(machine.instret() + 1);
+    machine.charge_frames()?;
+    if let Some(context) = context {
+        machine.charge_extra_cycles(instruction, context)?;
+    }
+    Ok(record)
+}
+
+// Steps the machine until an instruction is retired. Failing instructions are
+// not recorded, their errors are trapped like DefaultMachine::run does.
+fn step_recorded<Inner: SupportMachine>(
+    machine: &mut DefaultMachine<Inner>,
+    decoder: &mut Decoder,
+) -> Result<Option<TraceRecord>, Error> {
+    while machine.running() {
+        if machine.reset_signal() {
+            decoder.reset_instructions_cache();
+        }
+        match execute_step(machine, decoder) {
+            Ok(record) => return Ok(Some(record)),
+            Err(e) => machine.trap(e)?,
+        }
+    }
+    Ok(None)
+}
+
+/// Replays a log against a fresh machine with the program loaded, returns
+/// the first divergence if there is any. Errors the machine can't trap are
+/// returned.
+pub fn verify<Inner: SupportMachine, R: Read>(
+    machine: &mut DefaultMachine<Inner>,
+    reader: TraceReader<R>,
+) -> Result<Option<Divergence>, Error> {
+    let mut decoder = build_decoder::<Inner::REG>(machine.isa(), machine.version());
+    machine.set_running(true);
+    let mut index = 0;
+    for expected in reader {
+        let expected = expected?;
+        let actual = step_recorded(machine, &mut decoder)?;
+        if actual.as_ref() != Some(&expected) {
+            return Ok(Some(Divergence {
+                index,
+                expected: Some(expected),
+                actual,
+            }));
+        }
+        index += 1;
+    }
+    Ok(
+        step_recorded(machine, &mut decoder)?.map(|actual| Divergence {
+            index,
+            expected: None,
+            actual: Some(actual),
+        }),
+    )
+}
@@ -7,10 +7,13 @@ riscv64-unknown-elf-as -o amo_write_permission.o amo_write_permission.S && riscv
 riscv64-unknown-elf-gcc -o argv_null_test argv_null_test.c
 riscv64-unknown-elf-as -march=rv64imc -o cadd_hints.o cadd_hints.S && riscv64-unknown-elf-ld -o cadd_hints cadd_hints.o && rm cadd_hints.o
 riscv64-unknown-elf-as -o ckbforks.o ckbforks.S && riscv64-unknown-elf-ld -o ckbforks ckbforks.o && rm ckbforks.o
+riscv64-unknown-elf-as -march=rv64im -g --gdwarf-5 -o coverage.o coverage.S && riscv64-unknown-elf-ld -o coverage coverage.o && rm coverage.o
+riscv64-unknown-elf-as -march=rv64im -g --gdwarf-5 -o syscall_replay.o syscall_replay.S && riscv64-unknown-elf-ld -o syscall_replay syscall_replay.o && rm syscall_replay.o
 # TODO: clzw_bug
 # SKIP: decoder_instructions_cache_pc_out_of_bound_timeout
 riscv64-unknown-elf-as -o ebreak.o ebreak.S && riscv64-unknown-elf-ld -o ebreak64 ebreak.o && rm ebreak.o
 # SKIP: flat_crash_64
+riscv64-unknown-elf-as -o gdb_target.o gdb_target.S && riscv64-unknown-elf-ld -o gdb_target gdb_target.o && rm gdb_target.o
 # SKIP: goblin_overflow_elf
 # SKIP: invalid_file_offset64*
 riscv64-unknown-elf-as -o invalid_read.o invalid_read.S && riscv64-unknown-elf-ld -o invalid_read64 invalid_read.o && rm invalid_read.o
@@ -20,6 +23,7 @@ riscv64-unknown-elf-as -o jump0.o jump0.S && riscv64-unknown-elf-ld -o jump0_64
 # SKIP: load_elf_crash_64
 # SKIP: load_elf_section_crash_64
 # SKIP: load_malformed_elf_crash_64
+riscv64-unknown-elf-as -march=rv64im -g --gdwarf-5 -o memory_access.o memory_access.S && riscv64-unknown-elf-ld -o memory_access memory_access.o && rm memory_access.o
 # SKIP: minimal
 riscv64-unknown-elf-as -o misaligned_jump.o misaligned_jump.S && riscv64-unknown-elf-ld -o misaligned_jump64 misaligned_jump.o && rm misaligned_jump.o
 riscv64-unknown-elf-as -o mop_adc.o mop_adc.S && riscv64-unknown-elf-ld -o mop_adc mop_adc.o && rm mop_adc.o
@@ -41,6 +45,7 @@ riscv64-unknown-elf-as -o mulw.o mulw.S && riscv64-unknown-elf-ld -o mulw64 mulw
 # SKIP: op_rvc_srai_crash_32
 # SKIP: op_rvc_srli_crash_32
 # TODO: pcnt
+riscv64-unknown-elf-as -o pprof_target.o pprof_target.S && riscv64-unknown-elf-ld -o pprof_target pprof_target.o && rm pprof_target.o
 riscv64-unknown-elf-as -o read_at_boundary.o read_at_boundary.S && riscv64-unknown-elf-ld -o read_at_boundary64 read_at_boundary.o && rm read_at_boundary.o
 riscv64-unknown-elf-as -o read_memory.o read_memory.S && riscv64-unknown-elf-ld -o read_memory read_memory.o && rm read_memory.o
 riscv64-unknown-elf-gcc -o reset_callee reset_callee.c
@@ -52,7 +57,17 @@ riscv64-unknown-elf-as -o sc_after_sc.o sc_after_sc.S && riscv64-unknown-elf-ld
 # SKIP: simple
 riscv64-unknown-elf-gcc -o simple64 simple.c
 riscv64-unknown-elf-as -o sp_alignment_test.o sp_alignment_test.S && riscv64-unknown-elf-ld -o sp_alignment_test sp_alignment_test.o && rm sp_alignment_test.o
+riscv64-unknown-elf-as -o spawn_bad_child.o spawn_bad_child.S && riscv64-unknown-elf-ld -o spawn_bad_child spawn_bad_child.o && rm spawn_bad_child.o
+riscv64-unknown-elf-as -o spawn_child.o spawn_child.S && riscv64-unknown-elf-ld -o spawn_child spawn_child.o && rm spawn_child.o
+riscv64-unknown-elf-as -o spawn_deadlock.o spawn_deadlock.S && riscv64-unknown-elf-ld -o spawn_deadlock spawn_deadlock.o && rm spawn_deadlock.o
+riscv64-unknown-elf-as -o spawn_parent.o spawn_parent.S && riscv64-unknown-elf-ld -o spawn_parent spawn_parent.o && rm spawn_parent.o
+riscv64-unknown-elf-as -march=rv64im -g --gdwarf-5 -o symbolizer.o symbolizer.S && riscv64-unknown-elf-ld -o symbolizer symbolizer.o && rm symbolizer.o
+riscv64-unknown-elf-as -march=rv64im -g --gdwarf-4 -o symbolizer.o symbolizer.S && riscv64-unknown-elf-ld -o symbolizer_dwarf4 symbolizer.o && rm symbolizer.o
 riscv64-unknown-elf-as -o syscall.o syscall.S && riscv64-unknown-elf-ld -o syscall64 syscall.o && rm syscall.o
+riscv64-unknown-elf-as -march=rv64im -o trap.o trap.S && riscv64-unknown-elf-ld -o trap trap.o && rm trap.o
+riscv64-unknown-elf-as -march=rv64im_zicsr -o csr.o csr.S && riscv64-unknown-elf-ld -o csr csr.o && rm csr.o
+riscv64-unknown-elf-as -march=rv64im -o frame_touch.o frame_touch.S && riscv64-unknown-elf-ld -o frame_touch frame_touch.o && rm frame_touch.o
+riscv64-unknown-elf-as -o time_travel.o time_travel.S && riscv64-unknown-elf-ld -o time_travel time_travel.o && rm time_travel.o
 riscv64-unknown-elf-as -o trace.o trace.S && riscv64-unknown-elf-ld -o trace64 trace.o && rm trace.o
 # SKIP: unaligned64
 riscv64-unknown-elf-gcc -o writable_page writable_page.c && riscv64-unknown-elf-objdump -h writable_page > writable_page.dump
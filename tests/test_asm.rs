@@ -1,11 +1,14 @@
 #![cfg(has_asm)]
 use ckb_vm::cost_model::constant_cycles;
 use ckb_vm::decoder::build_decoder;
+use ckb_vm::error::{AccessKind, MemoryFault};
 use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
 use ckb_vm::machine::{CoreMachine, VERSION0, VERSION1};
 use ckb_vm::memory::Memory;
 use ckb_vm::registers::{A0, A1, A2, A3, A4, A5, A7};
-use ckb_vm::{Debugger, DefaultMachineBuilder, Error, Register, SupportMachine, Syscalls, ISA_IMC};
+use ckb_vm::{
+    Debugger, DefaultMachineBuilder, Error, Pause, Register, SupportMachine, Syscalls, ISA_IMC,
+};
 use std::fs;
 use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::Arc;
@@ -148,7 +151,10 @@ pub fn test_asm_trace() {
         .unwrap();
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::MemWriteOnExecutablePage));
+    assert!(matches!(
+        result.err(),
+        Some(Error::MemWriteOnExecutablePage(_))
+    ));
 }
 
 #[test]
@@ -162,7 +168,9 @@ pub fn test_asm_jump0() {
         .unwrap();
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::MemWriteOnExecutablePage));
+    // Unmapped pages are writable.
+    let fault = MemoryFault::new(0, 4, AccessKind::Execute);
+    assert_eq!(result.err(), Some(Error::MemExecuteOnWritablePage(fault)));
 }
 
 #[test]
@@ -178,7 +186,7 @@ pub fn test_asm_write_large_address() {
         .unwrap();
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::MemOutOfBound));
+    assert!(matches!(result.err(), Some(Error::MemOutOfBound(_))));
 }
 
 #[test]
@@ -219,7 +227,7 @@ pub fn test_invalid_read64() {
         .unwrap();
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::MemOutOfBound));
+    assert!(matches!(result.err(), Some(Error::MemOutOfBound(_))));
 }
 
 #[test]
@@ -232,7 +240,9 @@ pub fn test_asm_load_elf_crash_64() {
         .load_program(&buffer, &vec!["load_elf_crash_64".into()])
         .unwrap();
     let result = machine.run();
-    assert_eq!(result.err(), Some(Error::MemWriteOnExecutablePage));
+    let mut fault = MemoryFault::new(65736, 4, AccessKind::Execute);
+    fault.pc = 65736;
+    assert_eq!(result.err(), Some(Error::MemExecuteOnWritablePage(fault)));
 }
 
 #[test]
@@ -245,7 +255,7 @@ pub fn test_asm_wxorx_crash_64() {
         .load_program(&buffer, &vec!["wxorx_crash_64".into()])
         .unwrap();
     let result = machine.run();
-    assert_eq!(result.err(), Some(Error::MemOutOfBound));
+    assert!(matches!(result.err(), Some(Error::MemOutOfBound(_))));
 }
 
 #[test]
@@ -382,7 +392,7 @@ pub fn test_decoder_instructions_cache_pc_out_of_bound_timeout() {
         .unwrap();
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.unwrap_err(), Error::MemOutOfBound);
+    assert!(matches!(result.unwrap_err(), Error::MemOutOfBound(_)));
 }
 
 pub fn test_asm_step() {
@@ -435,3 +445,77 @@ fn test_zero_address() {
     assert!(result.is_ok());
     assert_eq!(result.unwrap(), 0);
 }
+
+pub struct InterruptSyscall {
+    pause: Pause,
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for InterruptSyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        let processed = CustomSyscall {}.ecall(machine)?;
+        if processed {
+            self.pause.interrupt();
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+#[test]
+pub fn test_asm_pause() {
+    let buffer = fs::read("tests/programs/syscall64").unwrap().into();
+    let pause = Pause::new();
+    let asm_core = AsmCoreMachine::new(ISA_IMC, VERSION0, u64::max_value());
+    let core = DefaultMachineBuilder::new(asm_core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .syscall(Box::new(InterruptSyscall {
+            pause: pause.clone(),
+        }))
+        .pause(pause.clone())
+        .build();
+    let mut machine = AsmMachine::new(core);
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+
+    pause.interrupt();
+    let result = machine.run();
+    assert_eq!(result.unwrap_err(), Error::Pause);
+    assert_eq!(machine.machine.cycles(), 0);
+    assert!(!pause.has_interrupted());
+
+    let result = machine.run();
+    assert_eq!(result.unwrap_err(), Error::Pause);
+    assert_eq!(machine.machine.registers()[A0], 39);
+    let cycles = machine.machine.cycles();
+
+    let result = machine.run();
+    assert_eq!(result.unwrap(), 39);
+    assert!(machine.machine.cycles() > cycles);
+}
+
+#[test]
+pub fn test_asm_pause_thread_safe() {
+    let buffer = fs::read("tests/programs/alloc_many").unwrap().into();
+    let asm_core = AsmCoreMachine::new(ISA_IMC, VERSION0, u64::max_value());
+    let core = DefaultMachineBuilder::new(asm_core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let mut machine = AsmMachine::new(core);
+    machine
+        .load_program(&buffer, &vec!["alloc_many".into()])
+        .unwrap();
+    let pause = machine.machine.pause();
+    pause.interrupt();
+    let thread_join_handle = thread::spawn(move || {
+        let result = machine.run();
+        assert_eq!(result.unwrap_err(), Error::Pause);
+        let result = machine.run();
+        assert_eq!(result.unwrap(), 0);
+    });
+    thread_join_handle.join().unwrap();
+}
@@ -0,0 +1,177 @@
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::debugger::{Breakpoints, WatchKind, Watchpoint};
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, VERSION1};
+use ckb_vm::{
+    CoreMachine, Error, RunOutcome, SparseMemory, SupportMachine, TraceMachine, WXorXMemory,
+    ISA_IMC,
+};
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+// Addresses in tests/programs/gdb_target.
+const ENTRY: u64 = 0x11158;
+const SD: u64 = 0x11164;
+const LD: u64 = 0x11168;
+const EBREAK: u64 = 0x1116c;
+const VALUE: u64 = 0x12178;
+
+trait Debuggee {
+    fn breakpoints_mut(&mut self) -> &mut Breakpoints;
+    fn current_pc(&self) -> u64;
+    fn total_cycles(&self) -> u64;
+    fn run_with_budget(&mut self, budget: u64) -> Result<RunOutcome, Error>;
+}
+
+impl Debuggee for DefaultMachine<Core> {
+    fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        DefaultMachine::breakpoints_mut(self)
+    }
+
+    fn current_pc(&self) -> u64 {
+        *CoreMachine::pc(self)
+    }
+
+    fn total_cycles(&self) -> u64 {
+        SupportMachine::cycles(self)
+    }
+
+    fn run_with_budget(&mut self, budget: u64) -> Result<RunOutcome, Error> {
+        DefaultMachine::run_with_budget(self, budget)
+    }
+}
+
+impl Debuggee for TraceMachine<Core> {
+    fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        self.machine.breakpoints_mut()
+    }
+
+    fn current_pc(&self) -> u64 {
+        *self.machine.pc()
+    }
+
+    fn total_cycles(&self) -> u64 {
+        self.machine.cycles()
+    }
+
+    fn run_with_budget(&mut self, budget: u64) -> Result<RunOutcome, Error> {
+        TraceMachine::run_with_budget(self, budget)
+    }
+}
+
+#[cfg(has_asm)]
+impl Debuggee for AsmMachine {
+    fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        self.machine.breakpoints_mut()
+    }
+
+    fn current_pc(&self) -> u64 {
+        *self.machine.pc()
+    }
+
+    fn total_cycles(&self) -> u64 {
+        self.machine.cycles()
+    }
+
+    fn run_with_budget(&mut self, budget: u64) -> Result<RunOutcome, Error> {
+        AsmMachine::run_with_budget(self, budget)
+    }
+}
+
+fn load_program() -> Bytes {
+    std::fs::read("tests/programs/gdb_target").unwrap().into()
+}
+
+fn build_machine() -> DefaultMachine<Core> {
+    let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    machine
+        .load_program(&load_program(), &["gdb_target".into()])
+        .unwrap();
+    machine
+}
+
+fn debug<M: Debuggee>(machine: &mut M) {
+    let write = Watchpoint {
+        kind: WatchKind::Write,
+        address: VALUE,
+        size: 8,
+    };
+    let read = Watchpoint {
+        kind: WatchKind::Read,
+        address: VALUE + 4,
+        size: 4,
+    };
+
+    machine.breakpoints_mut().insert_breakpoint(ENTRY);
+    machine.breakpoints_mut().insert_breakpoint(SD);
+    assert_eq!(
+        machine.run_with_budget(u64::MAX).unwrap(),
+        RunOutcome::Breakpoint(ENTRY)
+    );
+    assert_eq!(machine.total_cycles(), 0);
+    assert_eq!(
+        machine.run_with_budget(u64::MAX).unwrap(),
+        RunOutcome::Breakpoint(SD)
+    );
+    assert_eq!(machine.current_pc(), SD);
+
+    machine.breakpoints_mut().insert_watchpoint(write);
+    machine.breakpoints_mut().insert_watchpoint(read);
+    assert_eq!(
+        machine.run_with_budget(u64::MAX).unwrap(),
+        RunOutcome::Watchpoint {
+            watchpoint: write,
+            address: VALUE,
+        }
+    );
+    assert_eq!(machine.current_pc(), LD);
+    assert_eq!(
+        machine.run_with_budget(u64::MAX).unwrap(),
+        RunOutcome::Watchpoint {
+            watchpoint: read,
+            address: VALUE + 4,
+        }
+    );
+    assert_eq!(machine.current_pc(), EBREAK);
+
+    machine.breakpoints_mut().clear();
+    assert_eq!(
+        machine.run_with_budget(u64::MAX).unwrap(),
+        RunOutcome::Exited(42)
+    );
+}
+
+#[test]
+fn test_breakpoints() {
+    debug(&mut build_machine());
+    debug(&mut TraceMachine::new(build_machine()));
+}
+
+#[cfg(has_asm)]
+#[test]
+fn test_breakpoints_asm() {
+    let core = AsmCoreMachine::new(ISA_IMC, VERSION1, u64::MAX);
+    let core = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let mut machine = AsmMachine::new(core);
+    machine
+        .load_program(&load_program(), &["gdb_target".into()])
+        .unwrap();
+    debug(&mut machine);
+}
+
+#[test]
+fn test_breakpoint_pauses_run() {
+    let mut machine = build_machine();
+    machine.breakpoints_mut().insert_breakpoint(EBREAK);
+    assert_eq!(machine.run().unwrap_err(), Error::Pause);
+    assert_eq!(*CoreMachine::pc(&machine), EBREAK);
+    // Running again continues from the breakpoint.
+    assert_eq!(machine.run().unwrap(), 42);
+}
@@ -0,0 +1,165 @@
+use bytes::Bytes;
+use ckb_vm::cost_model::{CostContext, CostModel};
+use ckb_vm::machine::{
+    DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, RunOutcome, VERSION1,
+};
+use ckb_vm::memory::FLAG_DIRTY;
+use ckb_vm::snapshot::make_delta_snapshot;
+use ckb_vm::{
+    CoreMachine, Instruction, Memory, SparseMemory, SupportMachine, TraceMachine, WXorXMemory,
+    ISA_IMC, RISCV_PAGE_SHIFTS,
+};
+use std::sync::{Arc, Mutex};
+
+// Address of the buffer written in tests/programs/memory_access.
+const BUFFER: u64 = 0x12180;
+
+// Charges a cycle for each instruction, plus 1 for branches not taken, 10
+// for branches taken and 100 for accesses to fresh pages.
+#[derive(Default)]
+struct ContextModel {
+    contexts: Arc<Mutex<Vec<CostContext>>>,
+}
+
+impl CostModel for ContextModel {
+    fn cycles(&self, _: Instruction) -> u64 {
+        1
+    }
+
+    fn context_aware(&self) -> bool {
+        true
+    }
+
+    fn extra_cycles(&self, _: Instruction, context: &CostContext) -> u64 {
+        self.contexts.lock().unwrap().push(*context);
+        let branch = match context.branch_taken {
+            Some(true) => 10,
+            Some(false) => 1,
+            None => 0,
+        };
+        let access = if context.fresh_page { 100 } else { 0 };
+        branch + access
+    }
+}
+
+fn build_machine<M: SupportMachine>(core: M, path: &str, model: ContextModel) -> DefaultMachine<M> {
+    let mut machine = DefaultMachineBuilder::new(core)
+        .cost_model(Box::new(model))
+        .build();
+    let program: Bytes = std::fs::read(path).unwrap().into();
+    machine.load_program(&program, &["main".into()]).unwrap();
+    // Loading the program dirties the buffer.
+    machine
+        .memory_mut()
+        .clear_flag(BUFFER >> RISCV_PAGE_SHIFTS, FLAG_DIRTY)
+        .unwrap();
+    machine
+}
+
+fn new_core() -> DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>> {
+    DefaultCoreMachine::new(ISA_IMC, VERSION1, u64::MAX)
+}
+
+#[test]
+fn test_cost_model_branches() {
+    // check in tests/programs/coverage is called twice, its branch is never
+    // taken.
+    let model = ContextModel::default();
+    let contexts = Arc::clone(&model.contexts);
+    let mut machine = build_machine(new_core(), "tests/programs/coverage", model);
+    assert_eq!(machine.run().unwrap(), 0);
+    assert_eq!(machine.cycles(), 12 + 2);
+    let branches: Vec<_> = contexts
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|context| context.branch_taken.map(|taken| (context.pc, taken)))
+        .collect();
+    assert_eq!(branches, vec![(0x11138, false), (0x11138, false)]);
+
+    let mut machine = TraceMachine::new(build_machine(
+        new_core(),
+        "tests/programs/coverage",
+        ContextModel::default(),
+    ));
+    assert_eq!(machine.run().unwrap(), 0);
+    assert_eq!(machine.machine.cycles(), 12 + 2);
+}
+
+#[test]
+fn test_cost_model_fresh_pages() {
+    // Only the first store to the buffer touches a fresh page.
+    let model = ContextModel::default();
+    let contexts = Arc::clone(&model.contexts);
+    let mut machine = build_machine(new_core(), "tests/programs/memory_access", model);
+    assert_eq!(machine.run().unwrap(), 0x12);
+    assert_eq!(machine.cycles(), 10 + 100);
+    let accesses: Vec<_> = contexts
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|context| {
+            context
+                .access
+                .map(|access| (access.address, context.fresh_page))
+        })
+        .collect();
+    assert_eq!(
+        accesses,
+        vec![
+            (BUFFER, true),
+            (BUFFER + 8, false),
+            (BUFFER + 1, false),
+            (BUFFER, false)
+        ]
+    );
+}
+
+#[test]
+fn test_cost_model_fresh_pages_delta_snapshots() {
+    // Delta snapshots clear dirty flags, pages written before are still not
+    // fresh, and cycles exceeding a slice are kept.
+    let mut machine = build_machine(
+        new_core(),
+        "tests/programs/memory_access",
+        ContextModel::default(),
+    );
+    loop {
+        match machine.run_with_budget(1).unwrap() {
+            RunOutcome::Exited(exit_code) => {
+                assert_eq!(exit_code, 0x12);
+                break;
+            }
+            outcome => assert_eq!(outcome, RunOutcome::Paused),
+        }
+        make_delta_snapshot(&mut machine).unwrap();
+    }
+    assert_eq!(machine.cycles(), 10 + 100);
+}
+
+// AsmMachine executes instructions one by one for context aware models, and
+// charges the same cycles as the interpreter.
+#[cfg(has_asm)]
+#[test]
+fn test_cost_model_asm() {
+    use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+
+    for path in [
+        "tests/programs/coverage",
+        "tests/programs/memory_access",
+        "tests/programs/frame_touch",
+    ] {
+        let model = ContextModel::default();
+        let contexts = Arc::clone(&model.contexts);
+        let mut machine = build_machine(new_core(), path, model);
+        let result = machine.run();
+
+        let model = ContextModel::default();
+        let asm_contexts = Arc::clone(&model.contexts);
+        let core = AsmCoreMachine::new(ISA_IMC, VERSION1, u64::MAX);
+        let mut asm = AsmMachine::new(build_machine(core, path, model));
+        assert_eq!(asm.run(), result);
+        assert_eq!(asm.machine.cycles(), machine.cycles());
+        assert_eq!(*asm_contexts.lock().unwrap(), *contexts.lock().unwrap());
+    }
+}
@@ -0,0 +1,61 @@
+use bytes::Bytes;
+use ckb_vm::cost_model::{estimate_cycles, CostTable};
+use ckb_vm::instructions::{blank_instruction, insts};
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachineBuilder, VERSION1};
+use ckb_vm::{SparseMemory, SupportMachine, WXorXMemory, ISA_IMC};
+
+fn run_cycles(table: CostTable) -> u64 {
+    let core =
+        DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(table.into_cycle_func())
+        .build();
+    let program: Bytes = std::fs::read("tests/programs/coverage").unwrap().into();
+    machine
+        .load_program(&program, &["coverage".into()])
+        .unwrap();
+    assert_eq!(machine.run().unwrap(), 0);
+    machine.cycles()
+}
+
+#[test]
+fn test_cost_table_default() {
+    let table = CostTable::default();
+    for op in insts::MINIMAL_OPCODE..=insts::MAXIMUM_OPCODE {
+        assert_eq!(table.get(op), estimate_cycles(blank_instruction(op)));
+    }
+    // 5 li, 2 jal, 2 bltz, 2 ret and an ecall.
+    assert_eq!(run_cycles(table), 5 + 2 * 3 + 2 * 3 + 2 * 3 + 500);
+    assert_eq!(run_cycles(CostTable::new(1)), 12);
+}
+
+#[test]
+fn test_cost_table_serde() {
+    let table = CostTable::default();
+    let json = serde_json::to_string(&table).unwrap();
+    assert!(json.contains("\"ECALL\":500"));
+    assert_eq!(serde_json::from_str::<CostTable>(&json).unwrap(), table);
+
+    let table: CostTable =
+        serde_json::from_str(r#"{"default": 2, "cycles": {"ECALL": 10, "JAL": 7}}"#).unwrap();
+    assert_eq!(table.get(insts::OP_ADDI), 2);
+    assert_eq!(table.get(insts::OP_ECALL), 10);
+    assert_eq!(table.get(insts::OP_JAL), 7);
+    let mut expected = CostTable::new(2);
+    expected.set(insts::OP_ECALL, 10);
+    expected.set(insts::OP_JAL, 7);
+    assert_eq!(table, expected);
+}
+
+#[test]
+fn test_cost_table_invalid() {
+    let error = serde_json::from_str::<CostTable>(r#"{"default": 1, "cycles": {"MOV": 1}}"#)
+        .unwrap_err()
+        .to_string();
+    assert!(error.starts_with("cost table error: unknown instruction opcode MOV"));
+
+    let error = serde_json::from_str::<CostTable>(r#"{"cycles": {"ADD": 1}}"#)
+        .unwrap_err()
+        .to_string();
+    assert!(error.starts_with("cost table error: missing cycles of UNLOADED"));
+}
@@ -0,0 +1,132 @@
+use bytes::Bytes;
+use ckb_vm::coverage::Coverage;
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachineBuilder, VERSION1, VERSION3};
+use ckb_vm::symbolizer::Symbolizer;
+use ckb_vm::{SparseMemory, TraceMachine, WXorXMemory, ISA_IMC};
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+// check is called twice, its branch for negative arguments is never taken,
+// and unused is never called.
+const EXPECTED: &str = "TN:
+SF:coverage.S
+FN:7,_start
+FN:17,check
+FN:27,unused
+FNDA:1,_start
+FNDA:2,check
+FNDA:0,unused
+FNF:3
+FNH:2
+DA:7,1
+DA:8,1
+DA:9,1
+DA:10,1
+DA:11,1
+DA:12,1
+DA:17,2
+DA:18,2
+DA:19,2
+DA:21,0
+DA:22,0
+DA:27,0
+DA:28,0
+LF:13
+LH:9
+end_of_record
+";
+
+fn load_program() -> Bytes {
+    std::fs::read("tests/programs/coverage").unwrap().into()
+}
+
+fn lcov(coverage: &Coverage) -> String {
+    let symbolizer = Symbolizer::new(&load_program()).unwrap();
+    let mut output = Vec::new();
+    coverage.write_lcov(&symbolizer, &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+fn run_trace() -> Coverage {
+    let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = TraceMachine::new(DefaultMachineBuilder::new(core).build());
+    machine
+        .load_program(&load_program(), &["coverage".into()])
+        .unwrap();
+    machine.set_coverage(Coverage::new());
+    assert_eq!(machine.run().unwrap(), 0);
+    machine.take_coverage().unwrap()
+}
+
+#[test]
+fn test_coverage() {
+    let coverage = run_trace();
+    assert_eq!(
+        coverage.blocks().collect::<Vec<_>>(),
+        vec![
+            (0x11120, 8, 1),
+            (0x11128, 8, 1),
+            (0x11130, 8, 1),
+            (0x11138, 4, 2),
+            (0x1113c, 8, 2),
+        ]
+    );
+    assert_eq!(coverage.hits(0x11140), 2);
+    assert_eq!(coverage.hits(0x11144), 0);
+    assert_eq!(lcov(&coverage), EXPECTED);
+
+    let mut merged = Coverage::new();
+    merged.merge(&coverage);
+    merged.merge(&run_trace());
+    assert_eq!(merged.hits(0x11138), 4);
+}
+
+#[test]
+fn test_coverage_single_stepping() {
+    // Instructions executed one by one are recorded as blocks by themselves.
+    let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = TraceMachine::new(DefaultMachineBuilder::new(core).build());
+    machine
+        .load_program(&load_program(), &["coverage".into()])
+        .unwrap();
+    machine.machine.breakpoints_mut().insert_breakpoint(0x11000);
+    machine.set_coverage(Coverage::new());
+    assert_eq!(machine.run().unwrap(), 0);
+    let coverage = machine.take_coverage().unwrap();
+    assert_eq!(coverage.blocks().count(), 9);
+    assert_eq!(lcov(&coverage), EXPECTED);
+}
+
+#[test]
+fn test_coverage_single_stepping_trap() {
+    // tests/programs/trap fails to decode the instruction at 0x11144, which
+    // is trapped without being recorded.
+    let core = Core::new(ISA_IMC, VERSION3, u64::MAX);
+    let mut machine = TraceMachine::new(DefaultMachineBuilder::new(core).build());
+    let program: Bytes = std::fs::read("tests/programs/trap").unwrap().into();
+    machine.load_program(&program, &["trap".into()]).unwrap();
+    machine.machine.breakpoints_mut().insert_breakpoint(0x11000);
+    machine.set_coverage(Coverage::new());
+    assert_eq!(machine.run().unwrap(), 3 * 16 + 5 + 2 + 7);
+    let coverage = machine.take_coverage().unwrap();
+    assert_eq!(coverage.hits(0x11140), 0);
+    assert_eq!(coverage.hits(0x11144), 0);
+    assert_eq!(coverage.hits(0x11146), 1);
+}
+
+#[cfg(has_asm)]
+#[test]
+fn test_coverage_asm() {
+    let core = AsmCoreMachine::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = AsmMachine::new(DefaultMachineBuilder::new(core).build());
+    machine
+        .load_program(&load_program(), &["coverage".into()])
+        .unwrap();
+    machine.set_coverage(Coverage::new());
+    assert_eq!(machine.run().unwrap(), 0);
+    let coverage = machine.take_coverage().unwrap();
+    assert_eq!(coverage, run_trace());
+    assert_eq!(lcov(&coverage), EXPECTED);
+}
@@ -0,0 +1,161 @@
+#![cfg(has_asm)]
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::instructions::{extract_opcode, insts, Instruction};
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::differential::{Difference, DifferentialMachine, Lockstep};
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachineBuilder, VERSION1};
+use ckb_vm::registers::A0;
+use ckb_vm::syscalls::{Syscall, SyscallCost};
+use ckb_vm::{
+    CoreMachine, Error, Memory, SparseMemory, SupportMachine, WXorXMemory, ISA_B, ISA_IMC,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+// Syscall 1111 stores how many times it has been called at a0, and counts
+// the calls of all machines.
+fn count_calls<Mac: SupportMachine<REG = u64> + 'static>(
+    calls: Arc<AtomicU64>,
+) -> Box<dyn Syscall<Mac>> {
+    let mut count = 0u64;
+    Box::new(move |machine: &mut Mac| {
+        count += 1;
+        calls.fetch_add(1, Ordering::SeqCst);
+        let address = machine.registers()[A0];
+        machine.memory_mut().store8(&address, &count)?;
+        Ok(0)
+    })
+}
+
+fn build_machine(
+    path: &str,
+    lockstep: Lockstep,
+    max_cycles: u64,
+    asm_cycles: fn(Instruction) -> u64,
+    calls: &Arc<AtomicU64>,
+) -> DifferentialMachine<Core> {
+    let core = Core::new(ISA_IMC | ISA_B, VERSION1, max_cycles);
+    let interpreter = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .syscall_handler(1111, SyscallCost::default(), count_calls(calls.clone()))
+        .unwrap()
+        .build();
+    let core = AsmCoreMachine::new(ISA_IMC | ISA_B, VERSION1, max_cycles);
+    let asm = AsmMachine::new(
+        DefaultMachineBuilder::new(core)
+            .instruction_cycle_func(Box::new(asm_cycles))
+            .syscall_handler(1111, SyscallCost::default(), count_calls(calls.clone()))
+            .unwrap()
+            .build(),
+    );
+    let mut machine = DifferentialMachine::new(interpreter, asm, lockstep);
+    let program: Bytes = std::fs::read(path).unwrap().into();
+    machine.load_program(&program, &["main".into()]).unwrap();
+    machine
+}
+
+// Charges one more cycle for branches, as an asm interpreter going wrong.
+fn wrong_cycles(instruction: Instruction) -> u64 {
+    match extract_opcode(instruction) {
+        insts::OP_BLT => constant_cycles(instruction) + 1,
+        _ => constant_cycles(instruction),
+    }
+}
+
+#[test]
+fn test_differential() {
+    for lockstep in [
+        Lockstep::Traces,
+        Lockstep::Instructions(1),
+        Lockstep::Instructions(7),
+    ] {
+        for path in [
+            "tests/programs/simple64",
+            "tests/programs/clzw_bug",
+            "tests/programs/orc_bug",
+            "tests/programs/sbinvi_aot_load_imm_bug",
+        ] {
+            let mut machine =
+                build_machine(path, lockstep, u64::MAX, constant_cycles, &Arc::default());
+            assert_eq!(machine.run().unwrap(), None);
+            assert_eq!(machine.interpreter.exit_code(), 0);
+        }
+    }
+}
+
+#[test]
+fn test_differential_divergence() {
+    // check in tests/programs/coverage starts with a bltz.
+    for lockstep in [
+        Lockstep::Traces,
+        Lockstep::Instructions(1),
+        Lockstep::Instructions(4),
+    ] {
+        let mut machine = build_machine(
+            "tests/programs/coverage",
+            lockstep,
+            u64::MAX,
+            wrong_cycles,
+            &Arc::default(),
+        );
+        let divergence = machine.run().unwrap().unwrap();
+        assert_eq!(divergence.pc, 0x11138);
+        assert_eq!(
+            extract_opcode(divergence.instruction.unwrap()),
+            insts::OP_BLT
+        );
+        assert_eq!(divergence.cycles, 2);
+        assert_eq!(
+            divergence.differences,
+            vec![Difference::Cycles {
+                interpreter: 3,
+                asm: 4
+            }]
+        );
+        assert!(divergence
+            .to_string()
+            .starts_with("divergence at 0x11138: blt a0,zero,12\n"));
+    }
+}
+
+#[test]
+fn test_differential_agreed_error() {
+    let mut machine = build_machine(
+        "tests/programs/simple64",
+        Lockstep::Traces,
+        10,
+        constant_cycles,
+        &Arc::default(),
+    );
+    assert_eq!(machine.run().unwrap_err(), Error::CyclesExceeded);
+}
+
+#[test]
+fn test_differential_syscall_replay() {
+    // The chunk is replayed for the bltz after the syscall, which is not
+    // called again by either machine.
+    let calls = Arc::default();
+    let mut machine = build_machine(
+        "tests/programs/syscall_replay",
+        Lockstep::Instructions(100),
+        u64::MAX,
+        wrong_cycles,
+        &calls,
+    );
+    let divergence = machine.run().unwrap().unwrap();
+    assert_eq!(divergence.pc, 0x11134);
+    assert_eq!(divergence.cycles, 5);
+    assert_eq!(
+        divergence.differences,
+        vec![Difference::Cycles {
+            interpreter: 6,
+            asm: 7
+        }]
+    );
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert_eq!(machine.interpreter.registers()[A0], 1);
+    assert_eq!(machine.asm.machine.registers()[A0], 1);
+}
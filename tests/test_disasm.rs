@@ -0,0 +1,152 @@
+use bytes::Bytes;
+use ckb_vm::disasm::{disassemble, format_instruction, mnemonic, write_disassembly};
+use ckb_vm::instructions::{extract_opcode, insts, Rtype, Utype};
+use ckb_vm::machine::VERSION2;
+use ckb_vm::{ISA_IMC, ISA_MOP, ISA_ZICSR};
+
+fn load_program(name: &str) -> Bytes {
+    std::fs::read(format!("tests/programs/{}", name))
+        .unwrap()
+        .into()
+}
+
+const COVERAGE: &str = "
+Disassembly at 0x11120:
+
+0000000000011120 <_start>:
+   11120:\t00300513          \taddi a0, zero, 3
+   11124:\t014000ef          \tjal ra, 0x11138 <check>
+   11128:\t00500513          \taddi a0, zero, 5
+   1112c:\t00c000ef          \tjal ra, 0x11138 <check>
+   11130:\t05d00893          \taddi a7, zero, 93
+   11134:\t00000073          \tecall
+
+0000000000011138 <check>:
+   11138:\t00054663          \tblt a0, zero, 0x11144 <check+0xc>
+   1113c:\t00000513          \taddi a0, zero, 0
+   11140:\t00008067          \tjalr zero, 0(ra)
+   11144:\t00100513          \taddi a0, zero, 1
+   11148:\t00008067          \tjalr zero, 0(ra)
+
+000000000001114c <unused>:
+   1114c:\t00200513          \taddi a0, zero, 2
+   11150:\t00008067          \tjalr zero, 0(ra)
+";
+
+#[test]
+fn test_disasm_write() {
+    let mut output = Vec::new();
+    write_disassembly::<u64, _>(&load_program("coverage"), ISA_IMC, VERSION2, &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), COVERAGE);
+}
+
+#[test]
+fn test_disasm_csr() {
+    let program = load_program("csr");
+    let instructions = disassemble::<u64>(&program, ISA_IMC | ISA_ZICSR, VERSION2).unwrap();
+    assert_eq!(instructions.len(), 11);
+    let text = |index: usize| {
+        let disassembled = &instructions[index];
+        format_instruction(
+            disassembled.instruction.unwrap(),
+            disassembled.address,
+            None,
+        )
+    };
+    assert_eq!(text(0), "csrr s0, instret");
+    assert_eq!(text(1), "csrr s1, cycle");
+    assert_eq!(text(4), "bne t0, zero, 0x1112c");
+    assert_eq!(text(7), "csrr s4, time");
+
+    // Without Zicsr, the reads are left as raw bits.
+    let instructions = disassemble::<u64>(&program, ISA_IMC, VERSION2).unwrap();
+    assert_eq!(instructions[0].address, 0x11120);
+    assert_eq!(instructions[0].bits, vec![0xc0202473]);
+    assert_eq!(instructions[0].instruction, None);
+    let mut output = Vec::new();
+    write_disassembly::<u64, _>(&program, ISA_IMC, VERSION2, &mut output).unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .contains("   11120:\tc0202473          \t.4byte 0xc0202473\n"));
+}
+
+#[test]
+fn test_disasm_mop() {
+    let program = load_program("mop_far_jump");
+    let instructions = disassemble::<u64>(&program, ISA_IMC | ISA_MOP, VERSION2).unwrap();
+    // auipc + jalr and c.lui + jalr are fused.
+    assert_eq!(instructions.len(), 8);
+    let far_jumps = [
+        (
+            0x10078,
+            vec![0x00000097, 0x008080e7],
+            "far_jump_rel ra, 0x10080",
+            ["auipc", "jalr"],
+        ),
+        (
+            0x10080,
+            vec![0x60c1, 0x090080e7],
+            "far_jump_abs ra, 0x10090",
+            ["lui", "jalr"],
+        ),
+    ];
+    for (disassembled, (address, bits, text, fused)) in instructions.iter().zip(far_jumps) {
+        assert_eq!(disassembled.address, address);
+        assert_eq!(disassembled.bits, bits);
+        assert_eq!(
+            format_instruction(disassembled.instruction.unwrap(), address, None),
+            text
+        );
+        let fused: Vec<String> = fused.iter().map(|name| name.to_string()).collect();
+        let mnemonics: Vec<String> = disassembled
+            .fused
+            .iter()
+            .map(|i| mnemonic(extract_opcode(*i)))
+            .collect();
+        assert_eq!(mnemonics, fused);
+    }
+    assert_eq!(instructions[2].address, 0x10086);
+    assert!(instructions[2].fused.is_empty());
+
+    let mut output = Vec::new();
+    write_disassembly::<u64, _>(&program, ISA_IMC | ISA_MOP, VERSION2, &mut output).unwrap();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .contains("   10080:\t60c1 090080e7     \tfar_jump_abs ra, 0x10090\t# fused: lui, jalr\n"));
+}
+
+#[test]
+fn test_disasm_goblin_overflow_elf() {
+    // This test case only guarantee that section headers of malformed programs
+    // are parsed without crashing.
+    let _ = disassemble::<u64>(&load_program("goblin_overflow_elf"), ISA_IMC, VERSION2);
+}
+
+#[test]
+fn test_disasm_format() {
+    let format = |instruction| format_instruction(instruction, 0x1000, None);
+    assert_eq!(
+        format(Rtype::new(insts::OP_AMOADD_W, 10, 11, 12).0),
+        "amoadd.w a0, a2, (a1)"
+    );
+    assert_eq!(
+        format(Rtype::new(insts::OP_LR_D, 10, 11, 0).0),
+        "lr.d a0, (a1)"
+    );
+    assert_eq!(
+        format(Rtype::new(insts::OP_SEXTB, 10, 11, 0).0),
+        "sext.b a0, a1"
+    );
+    assert_eq!(
+        format(Rtype::new(insts::OP_SH1ADDUW, 10, 11, 12).0),
+        "sh1add.uw a0, a1, a2"
+    );
+    assert_eq!(
+        format(Utype::new(insts::OP_LUI, 10, 0x12345000).0),
+        "lui a0, 0x12345"
+    );
+    assert_eq!(
+        format(Utype::new_s(insts::OP_JAL, 1, -0x10).0),
+        "jal ra, 0xff0"
+    );
+}
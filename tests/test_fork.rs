@@ -0,0 +1,114 @@
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachineBuilder, VERSION1};
+use ckb_vm::memory::{flat::FlatMemory, sparse::SparseMemory, wxorx::WXorXMemory, Memory};
+use ckb_vm::{CoreMachine, SupportMachine, ISA_IMC};
+
+const EXPECTED_CYCLES: u64 = 8126917;
+// An address on the stack, which is written by alloc_many.
+const STACK_ADDR: u64 = 0x3ff000;
+
+fn load_program() -> Bytes {
+    std::fs::read("tests/programs/alloc_many").unwrap().into()
+}
+
+fn fork_and_run<M>()
+where
+    M: Memory<REG = u64> + Clone,
+{
+    let core = DefaultCoreMachine::<u64, M>::new(ISA_IMC, VERSION1, EXPECTED_CYCLES);
+    let mut parent = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    parent
+        .load_program(&load_program(), &["alloc_many".into()])
+        .unwrap();
+    let original = parent.memory_mut().load64(&STACK_ADDR).unwrap();
+
+    for _ in 0..4 {
+        let core = parent.inner_mut().clone();
+        let mut child = DefaultMachineBuilder::new(core)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .build();
+        assert_eq!(child.pc(), parent.pc());
+        assert_eq!(child.registers(), parent.registers());
+        assert_eq!(child.run().unwrap(), 0);
+        assert_eq!(child.cycles(), EXPECTED_CYCLES);
+        // Writes in a child are not visible to the parent.
+        child
+            .memory_mut()
+            .store64(&STACK_ADDR, &0x1234_5678)
+            .unwrap();
+        assert_eq!(parent.memory_mut().load64(&STACK_ADDR).unwrap(), original);
+    }
+
+    assert_eq!(parent.cycles(), 0);
+    assert_eq!(parent.run().unwrap(), 0);
+    assert_eq!(parent.cycles(), EXPECTED_CYCLES);
+}
+
+#[test]
+fn test_fork_sparse_memory() {
+    fork_and_run::<SparseMemory<u64>>();
+    fork_and_run::<WXorXMemory<SparseMemory<u64>>>();
+}
+
+#[test]
+fn test_fork_flat_memory() {
+    fork_and_run::<FlatMemory<u64>>();
+    fork_and_run::<WXorXMemory<FlatMemory<u64>>>();
+}
+
+#[test]
+fn test_fork_sparse_memory_shares_pages() {
+    let mut parent = SparseMemory::<u64>::new();
+    parent.store64(&0x1000, &1).unwrap();
+    let mut child = parent.clone();
+    assert_eq!(child.load64(&0x1000).unwrap(), 1);
+    child.store64(&0x1000, &2).unwrap();
+    parent.store64(&0x2000, &3).unwrap();
+    assert_eq!(parent.load64(&0x1000).unwrap(), 1);
+    assert_eq!(child.load64(&0x1000).unwrap(), 2);
+    assert_eq!(child.load64(&0x2000).unwrap(), 0);
+}
+
+#[cfg(has_asm)]
+#[test]
+fn test_fork_asm() {
+    let asm_core = AsmCoreMachine::new(ISA_IMC, VERSION1, EXPECTED_CYCLES);
+    let core = DefaultMachineBuilder::new(asm_core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let mut parent = AsmMachine::new(core);
+    parent
+        .load_program(&load_program(), &["alloc_many".into()])
+        .unwrap();
+    let original = parent.machine.memory_mut().load64(&STACK_ADDR).unwrap();
+
+    for _ in 0..4 {
+        let core = parent.machine.inner_mut().clone();
+        let core = DefaultMachineBuilder::new(core)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .build();
+        let mut child = AsmMachine::new(core);
+        assert_eq!(child.machine.pc(), parent.machine.pc());
+        assert_eq!(child.machine.registers(), parent.machine.registers());
+        assert_eq!(child.run().unwrap(), 0);
+        assert_eq!(child.machine.cycles(), EXPECTED_CYCLES);
+        child
+            .machine
+            .memory_mut()
+            .store64(&STACK_ADDR, &0x1234_5678)
+            .unwrap();
+        assert_eq!(
+            parent.machine.memory_mut().load64(&STACK_ADDR).unwrap(),
+            original
+        );
+    }
+
+    assert_eq!(parent.machine.cycles(), 0);
+    assert_eq!(parent.run().unwrap(), 0);
+    assert_eq!(parent.machine.cycles(), EXPECTED_CYCLES);
+}
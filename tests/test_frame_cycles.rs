@@ -0,0 +1,206 @@
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::machine::{
+    trace::TraceMachine, DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, RunOutcome,
+    VERSION1,
+};
+use ckb_vm::snapshot2::{DataSource, Snapshot2Context};
+use ckb_vm::{
+    CoreMachine, Error, FlatMemory, Memory, SparseMemory, SupportMachine, WXorXMemory, ISA_IMC,
+};
+
+// tests/programs/memory_access runs 10 instructions, its code and data share
+// the first frame, and its arguments are pushed to the last frame.
+const INSTRUCTIONS: u64 = 10;
+const FRAMES: u64 = 2;
+
+// tests/programs/frame_touch also has its code and stack in 2 frames, the
+// store at TOUCH_PC, its 3rd instruction, touches another one.
+const TOUCH_PC: u64 = 0x11128;
+const TOUCH_INSTRUCTIONS: u64 = 6;
+
+// How a run ends, with the pc, instret and cycles the machine stops at.
+type Stop = (Result<i8, Error>, u64, u64, u64);
+
+fn run_cycles<M: Memory<REG = u64>>(frame_cycles: u64, max_cycles: u64) -> Result<u64, Error> {
+    let mut core = DefaultCoreMachine::<u64, WXorXMemory<M>>::new(ISA_IMC, VERSION1, max_cycles);
+    core.set_frame_cycles(frame_cycles);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let program: Bytes = std::fs::read("tests/programs/memory_access")
+        .unwrap()
+        .into();
+    machine
+        .load_program(&program, &["memory_access".into()])
+        .unwrap();
+    assert_eq!(machine.cycles(), FRAMES * frame_cycles);
+    assert_eq!(machine.run()?, 0x12);
+    Ok(machine.cycles())
+}
+
+#[test]
+fn test_frame_cycles() {
+    let cycles = INSTRUCTIONS + FRAMES * 1000;
+    assert_eq!(run_cycles::<SparseMemory<u64>>(1000, u64::MAX), Ok(cycles));
+    assert_eq!(run_cycles::<FlatMemory<u64>>(1000, u64::MAX), Ok(cycles));
+    assert_eq!(
+        run_cycles::<SparseMemory<u64>>(0, u64::MAX),
+        Ok(INSTRUCTIONS)
+    );
+}
+
+#[test]
+fn test_frame_cycles_exceeded() {
+    let max_cycles = INSTRUCTIONS + FRAMES * 1000 - 1;
+    assert_eq!(
+        run_cycles::<SparseMemory<u64>>(1000, max_cycles),
+        Err(Error::CyclesExceeded)
+    );
+    assert_eq!(
+        run_cycles::<FlatMemory<u64>>(1000, max_cycles),
+        Err(Error::CyclesExceeded)
+    );
+}
+
+#[cfg(has_asm)]
+#[test]
+fn test_frame_cycles_asm() {
+    use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+
+    let mut core = AsmCoreMachine::new(ISA_IMC, VERSION1, u64::MAX);
+    core.set_frame_cycles(1000);
+    let core = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let mut machine = AsmMachine::new(core);
+    let program: Bytes = std::fs::read("tests/programs/memory_access")
+        .unwrap()
+        .into();
+    machine
+        .load_program(&program, &["memory_access".into()])
+        .unwrap();
+    assert_eq!(machine.run().unwrap(), 0x12);
+    assert_eq!(machine.machine.cycles(), INSTRUCTIONS + FRAMES * 1000);
+}
+
+fn run_touch(max_cycles: u64, trace: bool) -> Stop {
+    let mut core = DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(
+        ISA_IMC, VERSION1, max_cycles,
+    );
+    core.set_frame_cycles(1000);
+    let machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let program: Bytes = std::fs::read("tests/programs/frame_touch").unwrap().into();
+    if trace {
+        let mut machine = TraceMachine::new(machine);
+        machine
+            .load_program(&program, &["frame_touch".into()])
+            .unwrap();
+        let result = machine.run();
+        let machine = &machine.machine;
+        (result, *machine.pc(), machine.instret(), machine.cycles())
+    } else {
+        let mut machine = machine;
+        machine
+            .load_program(&program, &["frame_touch".into()])
+            .unwrap();
+        let result = machine.run();
+        (result, *machine.pc(), machine.instret(), machine.cycles())
+    }
+}
+
+#[cfg(has_asm)]
+fn run_touch_asm(max_cycles: u64) -> Stop {
+    use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+
+    let mut core = AsmCoreMachine::new(ISA_IMC, VERSION1, max_cycles);
+    core.set_frame_cycles(1000);
+    let core = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let mut machine = AsmMachine::new(core);
+    let program: Bytes = std::fs::read("tests/programs/frame_touch").unwrap().into();
+    machine
+        .load_program(&program, &["frame_touch".into()])
+        .unwrap();
+    let result = machine.run();
+    let machine = &machine.machine;
+    (result, *machine.pc(), machine.instret(), machine.cycles())
+}
+
+// A frame is charged right after the instruction touching it, and checked
+// against max cycles there, by all the machines.
+#[test]
+fn test_frame_cycles_exceeded_where_touched() {
+    let loaded = FRAMES * 1000;
+    // The asm machine charges the cycles of all 6 instructions when entering
+    // their trace, so only the frame can exceed max cycles within them.
+    for max_cycles in [loaded + TOUCH_INSTRUCTIONS, loaded + 3 + 999] {
+        let stop = run_touch(max_cycles, false);
+        assert_eq!(
+            stop,
+            (
+                Err(Error::CyclesExceeded),
+                TOUCH_PC + 4,
+                3,
+                loaded + 3 + 1000
+            )
+        );
+        assert_eq!(run_touch(max_cycles, true), stop);
+        #[cfg(has_asm)]
+        assert_eq!(run_touch_asm(max_cycles), stop);
+    }
+
+    let max_cycles = loaded + TOUCH_INSTRUCTIONS + 1000;
+    let stop = run_touch(max_cycles, false);
+    assert_eq!(stop, (Ok(0), TOUCH_PC + 16, TOUCH_INSTRUCTIONS, max_cycles));
+    assert_eq!(run_touch(max_cycles, true), stop);
+    #[cfg(has_asm)]
+    assert_eq!(run_touch_asm(max_cycles), stop);
+
+    // The exit itself exceeds max cycles, which the asm machine finds when
+    // entering the trace of the instructions after the store.
+    let max_cycles = loaded + TOUCH_INSTRUCTIONS + 1000 - 1;
+    assert_eq!(run_touch(max_cycles, false).0, Err(Error::CyclesExceeded));
+    assert_eq!(run_touch(max_cycles, true).0, Err(Error::CyclesExceeded));
+    #[cfg(has_asm)]
+    assert_eq!(run_touch_asm(max_cycles).0, Err(Error::CyclesExceeded));
+}
+
+struct NoSource;
+
+impl DataSource<u64> for NoSource {
+    fn load_data(&self, _id: &u64, _offset: u64, _length: u64) -> Option<Bytes> {
+        None
+    }
+}
+
+// Frames touched before a snapshot are not charged again after resuming.
+#[test]
+fn test_frame_cycles_resume() {
+    type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+    let build = || {
+        let mut core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+        core.set_frame_cycles(1000);
+        DefaultMachineBuilder::new(core)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .build()
+    };
+    let mut machine: DefaultMachine<Core> = build();
+    let program: Bytes = std::fs::read("tests/programs/frame_touch").unwrap().into();
+    machine
+        .load_program(&program, &["frame_touch".into()])
+        .unwrap();
+    // Stops before the store touching another frame.
+    assert_eq!(machine.run_with_budget(2), Ok(RunOutcome::Paused));
+    let mut context = Snapshot2Context::new(NoSource);
+    let snapshot = context.make_snapshot(&mut machine).unwrap();
+    assert_eq!(snapshot.touched_frames.len() as u64, FRAMES);
+
+    let mut resumed: DefaultMachine<Core> = build();
+    context.resume(&mut resumed, &snapshot).unwrap();
+    assert_eq!(resumed.run(), Ok(0));
+    assert_eq!(resumed.cycles(), FRAMES * 1000 + TOUCH_INSTRUCTIONS + 1000);
+}
@@ -0,0 +1,169 @@
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::debugger::gdb::GdbStub;
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachineBuilder, VERSION1};
+use ckb_vm::{Error, SparseMemory, TraceMachine, WXorXMemory, ISA_IMC};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+// A minimal GDB client.
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn read_byte(&mut self) -> u8 {
+        let mut buf = [0u8; 1];
+        self.stream.read_exact(&mut buf).unwrap();
+        buf[0]
+    }
+
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        let packet = format!("${}#{:02x}", data, checksum);
+        self.stream.write_all(packet.as_bytes()).unwrap();
+        assert_eq!(self.read_byte(), b'+');
+    }
+
+    fn recv(&mut self) -> String {
+        assert_eq!(self.read_byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            let byte = self.read_byte();
+            if byte == b'#' {
+                break;
+            }
+            data.push(byte);
+        }
+        let checksum = [self.read_byte(), self.read_byte()];
+        let expected = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        assert_eq!(
+            std::str::from_utf8(&checksum).unwrap(),
+            format!("{:02x}", expected)
+        );
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.recv()
+    }
+}
+
+fn connect() -> (GdbStub<TcpStream>, Client) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    (GdbStub::new(stream), Client { stream: client })
+}
+
+fn load_program() -> bytes::Bytes {
+    std::fs::read("tests/programs/gdb_target").unwrap().into()
+}
+
+#[test]
+fn test_gdb_serve() {
+    let (mut stub, mut client) = connect();
+    let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+    let machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let mut machine = TraceMachine::new(machine);
+    machine
+        .load_program(&load_program(), &["gdb_target".into()])
+        .unwrap();
+
+    let gdb = thread::spawn(move || {
+        assert_eq!(client.request("?"), "S05");
+        assert!(client
+            .request("qSupported:swbreak+;hwbreak+")
+            .contains("qXfer:features:read+"));
+        let xml = client.request("qXfer:features:read:target.xml:0,fff");
+        assert!(xml.starts_with("l<?xml"));
+        assert!(xml.contains("riscv:rv64"));
+        assert_eq!(client.request("p20"), "5811010000000000");
+
+        // Single step over la.
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p20"), "6011010000000000");
+        assert_eq!(client.request("p5"), "7821010000000000");
+
+        // Restart from the entry and continue to a breakpoint.
+        assert_eq!(client.request("P20=5811010000000000"), "OK");
+        assert_eq!(client.request("Z0,11164,4"), "OK");
+        assert_eq!(client.request("c"), "T05swbreak:;");
+        assert_eq!(client.request("p20"), "6411010000000000");
+        assert_eq!(client.request("z0,11164,4"), "OK");
+
+        // Watchpoints.
+        assert_eq!(client.request("Z2,12178,8"), "OK");
+        assert_eq!(client.request("c"), "T05watch:12178;");
+        assert_eq!(client.request("m12178,8"), "2a00000000000000");
+        assert_eq!(client.request("Z3,12178,8"), "OK");
+        assert_eq!(client.request("c"), "T05rwatch:12178;");
+        assert_eq!(client.request("m400000,8"), "E14");
+
+        // Continue to the ebreak, then change the exit code.
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p7"), "2a00000000000000");
+        assert_eq!(client.request("P7=0700000000000000"), "OK");
+        assert_eq!(client.request("c"), "W07");
+    });
+
+    let result = stub.serve(&mut machine.machine);
+    gdb.join().unwrap();
+    assert_eq!(result.unwrap(), 7);
+}
+
+#[test]
+fn test_gdb_kill() {
+    let (mut stub, mut client) = connect();
+    let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    machine
+        .load_program(&load_program(), &["gdb_target".into()])
+        .unwrap();
+
+    let gdb = thread::spawn(move || {
+        assert_eq!(client.request("?"), "S05");
+        client.send("k");
+    });
+
+    let result = stub.serve(&mut machine);
+    gdb.join().unwrap();
+    assert_eq!(
+        result.unwrap_err(),
+        Error::External("killed by gdb".to_string())
+    );
+}
+
+#[test]
+fn test_gdb_debugger() {
+    let (stub, mut client) = connect();
+    let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .debugger(Box::new(stub))
+        .build();
+    machine
+        .load_program(&load_program(), &["gdb_target".into()])
+        .unwrap();
+
+    // GDB gets the control when the ebreak is executed, before the pc moves.
+    let gdb = thread::spawn(move || {
+        assert_eq!(client.request("?"), "S05");
+        assert_eq!(client.request("p20"), "6c11010000000000");
+        assert_eq!(client.request("P7=0b00000000000000"), "OK");
+        client.send("c");
+    });
+
+    let result = machine.run();
+    gdb.join().unwrap();
+    assert_eq!(result.unwrap(), 11);
+}
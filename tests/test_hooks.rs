@@ -0,0 +1,120 @@
+#![cfg(feature = "hooks")]
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, VERSION1};
+use ckb_vm::{
+    CoreMachine, Error, Hook, HookAction, Instruction, RunOutcome, SparseMemory, SupportMachine,
+    TraceMachine, WXorXMemory, ISA_IMC,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+// Addresses in tests/programs/coverage, which executes 12 instructions.
+const LI_A0_0: u64 = 0x1113c;
+const ECALL: u64 = 0x11134;
+
+#[derive(Default)]
+struct Counter {
+    before: Arc<AtomicU64>,
+    after: Arc<AtomicU64>,
+}
+
+impl Hook<Core> for Counter {
+    fn before(&mut self, _: &mut Core, _: Instruction) -> Result<HookAction, Error> {
+        self.before.fetch_add(1, Ordering::SeqCst);
+        Ok(HookAction::Continue)
+    }
+
+    fn after(&mut self, _: &mut Core, _: Instruction) -> Result<HookAction, Error> {
+        self.after.fetch_add(1, Ordering::SeqCst);
+        Ok(HookAction::Continue)
+    }
+}
+
+// Skips the instruction at pc.
+struct Veto(u64);
+
+impl Hook<Core> for Veto {
+    fn before(&mut self, machine: &mut Core, _: Instruction) -> Result<HookAction, Error> {
+        if *machine.pc() == self.0 {
+            Ok(HookAction::Skip)
+        } else {
+            Ok(HookAction::Continue)
+        }
+    }
+}
+
+// Stops before the instruction at pc once.
+struct StopOnce(Option<u64>);
+
+impl Hook<Core> for StopOnce {
+    fn before(&mut self, machine: &mut Core, _: Instruction) -> Result<HookAction, Error> {
+        if Some(*machine.pc()) == self.0 {
+            self.0 = None;
+            Ok(HookAction::Stop)
+        } else {
+            Ok(HookAction::Continue)
+        }
+    }
+}
+
+fn build_machine(hooks: Vec<Box<dyn Hook<Core>>>) -> DefaultMachine<Core> {
+    let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut builder =
+        DefaultMachineBuilder::new(core).instruction_cycle_func(Box::new(constant_cycles));
+    for hook in hooks {
+        builder = builder.hook(hook);
+    }
+    let mut machine = builder.build();
+    let program: Bytes = std::fs::read("tests/programs/coverage").unwrap().into();
+    machine
+        .load_program(&program, &["coverage".into()])
+        .unwrap();
+    machine
+}
+
+#[test]
+fn test_hooks() {
+    let counter = Counter::default();
+    let (before, after) = (Arc::clone(&counter.before), Arc::clone(&counter.after));
+    let mut machine = build_machine(vec![Box::new(counter)]);
+    assert_eq!(machine.run().unwrap(), 0);
+    assert_eq!(before.load(Ordering::SeqCst), 12);
+    assert_eq!(after.load(Ordering::SeqCst), 12);
+
+    let counter = Counter::default();
+    let (before, after) = (Arc::clone(&counter.before), Arc::clone(&counter.after));
+    let mut machine = TraceMachine::new(build_machine(vec![Box::new(counter)]));
+    assert_eq!(machine.run().unwrap(), 0);
+    assert_eq!(before.load(Ordering::SeqCst), 12);
+    assert_eq!(after.load(Ordering::SeqCst), 12);
+}
+
+#[test]
+fn test_hooks_veto() {
+    // check returns its argument, and vetoed instructions are not charged.
+    let counter = Counter::default();
+    let after = Arc::clone(&counter.after);
+    let mut machine = build_machine(vec![Box::new(Veto(LI_A0_0)), Box::new(counter)]);
+    assert_eq!(machine.run().unwrap(), 5);
+    assert_eq!(machine.cycles(), 10);
+    assert_eq!(after.load(Ordering::SeqCst), 10);
+}
+
+#[test]
+fn test_hooks_stop() {
+    let mut machine = build_machine(vec![Box::new(StopOnce(Some(ECALL)))]);
+    assert_eq!(
+        machine.run_with_budget(100).unwrap(),
+        RunOutcome::Hook(ECALL)
+    );
+    assert_eq!(machine.cycles(), 11);
+    assert_eq!(machine.run_with_budget(100).unwrap(), RunOutcome::Exited(0));
+
+    let mut machine = TraceMachine::new(build_machine(vec![Box::new(StopOnce(Some(ECALL)))]));
+    assert_eq!(machine.run().unwrap_err(), Error::Pause);
+    assert_eq!(*machine.machine.pc(), ECALL);
+    assert_eq!(machine.run().unwrap(), 0);
+}
@@ -0,0 +1,67 @@
+use bytes::Bytes;
+use ckb_vm::error::{AccessKind, MemoryFault};
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, VERSION1};
+use ckb_vm::{Error, FlatMemory, Memory, SparseMemory, TraceMachine, WXorXMemory, ISA_IMC};
+
+// tests/programs/trace64 stores a halfword to its own code.
+const FAULT: MemoryFault = MemoryFault {
+    pc: 0x10094,
+    address: 0x10096,
+    size: 2,
+    kind: AccessKind::Write,
+};
+
+fn build_machine<M: Memory<REG = u64>>() -> DefaultMachine<DefaultCoreMachine<u64, WXorXMemory<M>>>
+{
+    let core = DefaultCoreMachine::<u64, WXorXMemory<M>>::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core).build();
+    let program: Bytes = std::fs::read("tests/programs/trace64").unwrap().into();
+    machine.load_program(&program, &["trace64".into()]).unwrap();
+    machine
+}
+
+#[test]
+fn test_memory_fault() {
+    let expected = Err(Error::MemWriteOnExecutablePage(FAULT));
+    assert_eq!(build_machine::<SparseMemory<u64>>().run(), expected);
+    assert_eq!(build_machine::<FlatMemory<u64>>().run(), expected);
+    assert_eq!(
+        TraceMachine::new(build_machine::<SparseMemory<u64>>()).run(),
+        expected
+    );
+
+    let error = build_machine::<SparseMemory<u64>>().run().unwrap_err();
+    assert_eq!(error.memory_fault(), Some(&FAULT));
+    assert_eq!(
+        error.to_string(),
+        "memory error: write on executable page, write of 2 bytes at 0x10096, pc=0x10094"
+    );
+}
+
+#[test]
+fn test_memory_fault_out_of_bound() {
+    let mut memory = WXorXMemory::<SparseMemory<u64>>::new_with_memory(1 << 20);
+    let expected = MemoryFault::new((1 << 20) - 4, 8, AccessKind::Write);
+    assert_eq!(
+        memory.store64(&((1 << 20) - 4), &0),
+        Err(Error::MemOutOfBound(expected))
+    );
+    let expected = MemoryFault::new((1 << 20) - 4, 8, AccessKind::Read);
+    assert_eq!(
+        memory.load64(&((1 << 20) - 4)),
+        Err(Error::MemOutOfBound(expected))
+    );
+}
+
+#[cfg(has_asm)]
+#[test]
+fn test_memory_fault_asm() {
+    use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+
+    let core = AsmCoreMachine::new(ISA_IMC, VERSION1, u64::MAX);
+    let core = DefaultMachineBuilder::new(core).build();
+    let mut machine = AsmMachine::new(core);
+    let program: Bytes = std::fs::read("tests/programs/trace64").unwrap().into();
+    machine.load_program(&program, &["trace64".into()]).unwrap();
+    assert_eq!(machine.run(), Err(Error::MemWriteOnExecutablePage(FAULT)));
+}
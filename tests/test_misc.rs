@@ -1,9 +1,10 @@
 use ckb_vm::cost_model::constant_cycles;
-use ckb_vm::machine::VERSION0;
+use ckb_vm::error::{AccessKind, MemoryFault};
+use ckb_vm::machine::{trace::TraceMachine, VERSION0};
 use ckb_vm::registers::{A0, A1, A2, A3, A4, A5, A7};
 use ckb_vm::{
     run, CoreMachine, Debugger, DefaultCoreMachine, DefaultMachineBuilder, Error, FlatMemory,
-    Memory, Register, SparseMemory, SupportMachine, Syscalls, WXorXMemory, ISA_IMC,
+    Memory, Pause, Register, SparseMemory, SupportMachine, Syscalls, WXorXMemory, ISA_IMC,
     RISCV_MAX_MEMORY, RISCV_PAGESIZE,
 };
 #[cfg(has_asm)]
@@ -109,7 +110,10 @@ pub fn test_trace() {
     let buffer = fs::read("tests/programs/trace64").unwrap().into();
     let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["trace64".into()], RISCV_MAX_MEMORY);
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::MemWriteOnExecutablePage));
+    assert!(matches!(
+        result.err(),
+        Some(Error::MemWriteOnExecutablePage(_))
+    ));
 }
 
 #[test]
@@ -117,7 +121,9 @@ pub fn test_jump0() {
     let buffer = fs::read("tests/programs/jump0_64").unwrap().into();
     let result = run::<u64, SparseMemory<u64>>(&buffer, &vec!["jump0_64".into()], RISCV_MAX_MEMORY);
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::MemWriteOnExecutablePage));
+    // Unmapped pages are writable.
+    let fault = MemoryFault::new(0, 4, AccessKind::Execute);
+    assert_eq!(result.err(), Some(Error::MemExecuteOnWritablePage(fault)));
 }
 
 #[test]
@@ -159,7 +165,8 @@ pub fn test_op_rvc_srli_crash_32() {
         &vec!["op_rvc_srli_crash_32".into()],
         RISCV_MAX_MEMORY,
     );
-    assert_eq!(result.err(), Some(Error::MemWriteOnExecutablePage));
+    let fault = MemoryFault::new(0, 4, AccessKind::Execute);
+    assert_eq!(result.err(), Some(Error::MemExecuteOnWritablePage(fault)));
 }
 
 #[test]
@@ -195,7 +202,9 @@ pub fn test_load_elf_crash_64() {
     let buffer = fs::read("tests/programs/load_elf_crash_64").unwrap().into();
     let result =
         run::<u64, SparseMemory<u64>>(&buffer, &vec!["load_elf_crash_64".into()], RISCV_MAX_MEMORY);
-    assert_eq!(result.err(), Some(Error::MemWriteOnExecutablePage));
+    let mut fault = MemoryFault::new(65736, 4, AccessKind::Execute);
+    fault.pc = 65736;
+    assert_eq!(result.err(), Some(Error::MemExecuteOnWritablePage(fault)));
 }
 
 #[test]
@@ -203,7 +212,7 @@ pub fn test_wxorx_crash_64() {
     let buffer = fs::read("tests/programs/wxorx_crash_64").unwrap().into();
     let result =
         run::<u64, SparseMemory<u64>>(&buffer, &vec!["wxorx_crash_64".into()], RISCV_MAX_MEMORY);
-    assert_eq!(result.err(), Some(Error::MemOutOfBound));
+    assert!(matches!(result.err(), Some(Error::MemOutOfBound(_))));
 }
 
 #[test]
@@ -213,7 +222,7 @@ pub fn test_flat_crash_64() {
         DefaultCoreMachine::<u64, FlatMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
     let mut machine = DefaultMachineBuilder::new(core_machine).build();
     let result = machine.load_program(&buffer, &vec!["flat_crash_64".into()]);
-    assert_eq!(result.err(), Some(Error::MemOutOfBound));
+    assert!(matches!(result.err(), Some(Error::MemOutOfBound(_))));
 }
 
 #[test]
@@ -303,7 +312,7 @@ fn assert_memory_load_bytes<R: Rng, M: Memory>(
     };
     let ret = memory.load_bytes(addr, outofbound_size as u64);
     assert!(ret.is_err());
-    assert_eq!(ret.err().unwrap(), Error::MemOutOfBound);
+    assert!(matches!(ret.err().unwrap(), Error::MemOutOfBound(_)));
 
     // address out of bound
     let ret = memory.load_bytes(
@@ -314,7 +323,7 @@ fn assert_memory_load_bytes<R: Rng, M: Memory>(
         assert!(ret.is_ok())
     } else {
         assert!(ret.is_err());
-        assert_eq!(ret.err().unwrap(), Error::MemOutOfBound);
+        assert!(matches!(ret.err().unwrap(), Error::MemOutOfBound(_)));
     }
 
     // addr + size is overflow
@@ -323,7 +332,7 @@ fn assert_memory_load_bytes<R: Rng, M: Memory>(
         assert!(ret.is_ok());
     } else {
         assert!(ret.is_err());
-        assert_eq!(ret.err().unwrap(), Error::MemOutOfBound);
+        assert!(matches!(ret.err().unwrap(), Error::MemOutOfBound(_)));
     }
 }
 
@@ -425,3 +434,89 @@ pub fn test_outofcycles_in_syscall() {
     assert_eq!(machine.cycles(), 108);
     assert_eq!(machine.registers()[A0], 39);
 }
+
+pub struct InterruptSyscall {
+    pause: Pause,
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for InterruptSyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        let code = &machine.registers()[A7];
+        if code.to_i32() != 1111 {
+            return Ok(false);
+        }
+        let result = machine.registers()[A0]
+            .overflowing_add(&machine.registers()[A1])
+            .overflowing_add(&machine.registers()[A2])
+            .overflowing_add(&machine.registers()[A3])
+            .overflowing_add(&machine.registers()[A4])
+            .overflowing_add(&machine.registers()[A5]);
+        machine.set_register(A0, result);
+        self.pause.interrupt();
+        Ok(true)
+    }
+}
+
+#[test]
+pub fn test_pause() {
+    let buffer = fs::read("tests/programs/syscall64").unwrap().into();
+    let pause = Pause::new();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = DefaultMachineBuilder::new(core_machine)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .syscall(Box::new(InterruptSyscall {
+            pause: pause.clone(),
+        }))
+        .pause(pause.clone())
+        .build();
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+
+    pause.interrupt();
+    let result = machine.run();
+    assert_eq!(result.unwrap_err(), Error::Pause);
+    assert_eq!(machine.cycles(), 0);
+    assert!(!pause.has_interrupted());
+
+    let result = machine.run();
+    assert_eq!(result.unwrap_err(), Error::Pause);
+    assert_eq!(machine.registers()[A0], 39);
+    let cycles = machine.cycles();
+
+    let result = machine.run();
+    assert_eq!(result.unwrap(), 39);
+    assert!(machine.cycles() > cycles);
+}
+
+#[test]
+pub fn test_pause_trace() {
+    let buffer = fs::read("tests/programs/syscall64").unwrap().into();
+    let pause = Pause::new();
+    let core_machine =
+        DefaultCoreMachine::<u64, SparseMemory<u64>>::new(ISA_IMC, VERSION0, u64::max_value());
+    let mut machine = TraceMachine::new(
+        DefaultMachineBuilder::new(core_machine)
+            .instruction_cycle_func(Box::new(constant_cycles))
+            .syscall(Box::new(InterruptSyscall {
+                pause: pause.clone(),
+            }))
+            .pause(pause)
+            .build(),
+    );
+    machine
+        .load_program(&buffer, &vec!["syscall".into()])
+        .unwrap();
+
+    let result = machine.run();
+    assert_eq!(result.unwrap_err(), Error::Pause);
+    assert_eq!(machine.registers()[A0], 39);
+
+    let result = machine.run();
+    assert_eq!(result.unwrap(), 39);
+}
@@ -384,17 +384,17 @@ pub fn test_mop_wide_div_zero() {
 pub fn test_mop_jump_rel_version1_bug() {
     let mut machine = machine_build::int_v1_imcb("tests/programs/mop_jump_rel_version1_bug");
     let ret = machine.run();
-    assert_eq!(ret, Err(Error::MemOutOfBound));
+    assert!(matches!(ret, Err(Error::MemOutOfBound(_))));
     assert_eq!(*machine.pc(), 0xffffffff8000f878);
 
     let mut machine = machine_build::int_v1_mop("tests/programs/mop_jump_rel_version1_bug", vec![]);
     let ret = machine.run();
-    assert_eq!(ret, Err(Error::MemOutOfBound));
+    assert!(matches!(ret, Err(Error::MemOutOfBound(_))));
     assert_eq!(*machine.pc(), 0x8000f878);
 
     let mut machine = machine_build::int_mop("tests/programs/mop_jump_rel_version1_bug", vec![], 2);
     let ret = machine.run();
-    assert_eq!(ret, Err(Error::MemOutOfBound));
+    assert!(matches!(ret, Err(Error::MemOutOfBound(_))));
     assert_eq!(*machine.pc(), 0xffffffff8000f878);
 
     #[cfg(has_asm)]
@@ -402,13 +402,13 @@ pub fn test_mop_jump_rel_version1_bug() {
         let mut machine_asm =
             machine_build::asm_v1_mop("tests/programs/mop_jump_rel_version1_bug", vec![]);
         let ret_asm = machine_asm.run();
-        assert_eq!(ret_asm, Err(Error::MemOutOfBound));
+        assert!(matches!(ret_asm, Err(Error::MemOutOfBound(_))));
         assert_eq!(*machine_asm.machine.pc(), 0x8000f878);
 
         let mut machine_asm =
             machine_build::asm_mop("tests/programs/mop_jump_rel_version1_bug", vec![], 2);
         let ret_asm = machine_asm.run();
-        assert_eq!(ret_asm, Err(Error::MemOutOfBound));
+        assert!(matches!(ret_asm, Err(Error::MemOutOfBound(_))));
         assert_eq!(*machine_asm.machine.pc(), 0xffffffff8000f878);
     }
 }
@@ -418,7 +418,7 @@ pub fn test_mop_jump_rel_version1_reg_not_updated_bug() {
     let mut machine =
         machine_build::int_v1_imcb("tests/programs/mop_jump_rel_version1_reg_not_updated_bug");
     let ret = machine.run();
-    assert_eq!(ret, Err(Error::MemOutOfBound));
+    assert!(matches!(ret, Err(Error::MemOutOfBound(_))));
     assert_eq!(machine.registers()[A0], 67174520);
 
     let mut machine = machine_build::int_v1_mop(
@@ -426,7 +426,7 @@ pub fn test_mop_jump_rel_version1_reg_not_updated_bug() {
         vec![],
     );
     let ret = machine.run();
-    assert_eq!(ret, Err(Error::MemOutOfBound));
+    assert!(matches!(ret, Err(Error::MemOutOfBound(_))));
     assert_eq!(machine.registers()[A0], 0);
 
     let mut machine = machine_build::int_mop(
@@ -435,7 +435,7 @@ pub fn test_mop_jump_rel_version1_reg_not_updated_bug() {
         2,
     );
     let ret = machine.run();
-    assert_eq!(ret, Err(Error::MemOutOfBound));
+    assert!(matches!(ret, Err(Error::MemOutOfBound(_))));
     assert_eq!(machine.registers()[A0], 67174520);
 
     #[cfg(has_asm)]
@@ -445,7 +445,7 @@ pub fn test_mop_jump_rel_version1_reg_not_updated_bug() {
             vec![],
         );
         let ret_asm = machine_asm.run();
-        assert_eq!(ret_asm, Err(Error::MemOutOfBound));
+        assert!(matches!(ret_asm, Err(Error::MemOutOfBound(_))));
         assert_eq!(machine_asm.machine.registers()[A0], 0);
 
         let mut machine_asm = machine_build::asm_mop(
@@ -454,7 +454,7 @@ pub fn test_mop_jump_rel_version1_reg_not_updated_bug() {
             2,
         );
         let ret_asm = machine_asm.run();
-        assert_eq!(ret_asm, Err(Error::MemOutOfBound));
+        assert!(matches!(ret_asm, Err(Error::MemOutOfBound(_))));
         assert_eq!(machine_asm.machine.registers()[A0], 67174520);
     }
 }
@@ -464,7 +464,7 @@ pub fn test_mop_jump_abs_version1_reg_not_updated_bug() {
     let mut machine =
         machine_build::int_v1_imcb("tests/programs/mop_jump_abs_version1_reg_not_updated_bug");
     let ret = machine.run();
-    assert_eq!(ret, Err(Error::MemOutOfBound));
+    assert!(matches!(ret, Err(Error::MemOutOfBound(_))));
     assert_eq!(machine.registers()[A0], 67108864);
 
     let mut machine = machine_build::int_v1_mop(
@@ -472,7 +472,7 @@ pub fn test_mop_jump_abs_version1_reg_not_updated_bug() {
         vec![],
     );
     let ret = machine.run();
-    assert_eq!(ret, Err(Error::MemOutOfBound));
+    assert!(matches!(ret, Err(Error::MemOutOfBound(_))));
     assert_eq!(machine.registers()[A0], 0);
 
     let mut machine = machine_build::int_mop(
@@ -481,7 +481,7 @@ pub fn test_mop_jump_abs_version1_reg_not_updated_bug() {
         2,
     );
     let ret = machine.run();
-    assert_eq!(ret, Err(Error::MemOutOfBound));
+    assert!(matches!(ret, Err(Error::MemOutOfBound(_))));
     assert_eq!(machine.registers()[A0], 67108864);
 
     #[cfg(has_asm)]
@@ -491,7 +491,7 @@ pub fn test_mop_jump_abs_version1_reg_not_updated_bug() {
             vec![],
         );
         let ret_asm = machine_asm.run();
-        assert_eq!(ret_asm, Err(Error::MemOutOfBound));
+        assert!(matches!(ret_asm, Err(Error::MemOutOfBound(_))));
         assert_eq!(machine_asm.machine.registers()[A0], 0);
 
         let mut machine_asm = machine_build::asm_mop(
@@ -500,7 +500,7 @@ pub fn test_mop_jump_abs_version1_reg_not_updated_bug() {
             2,
         );
         let ret_asm = machine_asm.run();
-        assert_eq!(ret_asm, Err(Error::MemOutOfBound));
+        assert!(matches!(ret_asm, Err(Error::MemOutOfBound(_))));
         assert_eq!(machine_asm.machine.registers()[A0], 67108864);
     }
 }
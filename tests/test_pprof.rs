@@ -0,0 +1,77 @@
+#![cfg(feature = "pprof")]
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, VERSION1};
+use ckb_vm::pprof::Profiler;
+use ckb_vm::{SparseMemory, SupportMachine, WXorXMemory, ISA_IMC};
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+// leaf consumes 22 cycles on each call, it's called twice by outer, tail
+// called by outer, and called once by _start.
+const EXPECTED: &str = "_start 5\n_start;outer 7\n_start;outer;leaf 44\n_start;leaf 44\n";
+
+fn load_program() -> Bytes {
+    std::fs::read("tests/programs/pprof_target").unwrap().into()
+}
+
+fn folded(profiler: &Profiler) -> String {
+    let mut output = Vec::new();
+    profiler.write_folded(&mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn test_pprof() {
+    let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine: DefaultMachine<Core> = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    machine
+        .load_program(&load_program(), &["pprof_target".into()])
+        .unwrap();
+    let mut profiler = Profiler::from_machine(&machine).unwrap();
+    assert_eq!(profiler.run(&mut machine).unwrap(), 0);
+    assert_eq!(machine.cycles(), 100);
+    assert_eq!(folded(&profiler), EXPECTED);
+    assert_eq!(profiler.function_name(0), "??");
+}
+
+#[cfg(has_asm)]
+#[test]
+fn test_pprof_asm() {
+    let core = AsmCoreMachine::new(ISA_IMC, VERSION1, u64::MAX);
+    let core = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let mut machine = AsmMachine::new(core);
+    let program = load_program();
+    machine
+        .load_program(&program, &["pprof_target".into()])
+        .unwrap();
+    let mut profiler = Profiler::new(&program).unwrap();
+    assert_eq!(profiler.run_asm(&mut machine).unwrap(), 0);
+    assert_eq!(machine.machine.cycles(), 100);
+    assert_eq!(folded(&profiler), EXPECTED);
+}
+
+#[cfg(has_asm)]
+#[test]
+fn test_pprof_asm_max_cycles() {
+    let core = AsmCoreMachine::new(ISA_IMC, VERSION1, 50);
+    let core = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let mut machine = AsmMachine::new(core);
+    let program = load_program();
+    machine
+        .load_program(&program, &["pprof_target".into()])
+        .unwrap();
+    let mut profiler = Profiler::new(&program).unwrap();
+    assert_eq!(
+        profiler.run_asm(&mut machine).unwrap_err(),
+        ckb_vm::Error::CyclesExceeded
+    );
+}
@@ -4,9 +4,13 @@ use bytes::Bytes;
 use ckb_vm::cost_model::constant_cycles;
 use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
 use ckb_vm::machine::trace::TraceMachine;
-use ckb_vm::machine::{DefaultCoreMachine, DefaultMachine, SupportMachine, VERSION0, VERSION1};
+use ckb_vm::machine::{
+    DefaultCoreMachine, DefaultMachine, RunOutcome, SupportMachine, VERSION0, VERSION1,
+};
 use ckb_vm::memory::{sparse::SparseMemory, wxorx::WXorXMemory};
-use ckb_vm::snapshot::{make_snapshot, resume, Snapshot};
+use ckb_vm::snapshot::{
+    compact_snapshots, make_delta_snapshot, make_snapshot, resume, resume_chain, Snapshot,
+};
 use ckb_vm::{DefaultMachineBuilder, Error, ISA_IMC};
 use std::fs::File;
 use std::io::Read;
@@ -189,6 +193,231 @@ pub fn resume_interpreter_with_trace_2_asm_inner(version: u32, except_cycles: u6
     assert_eq!(cycles1 + cycles2, except_cycles);
 }
 
+#[test]
+fn test_run_with_budget() {
+    run_with_budget(MachineTy::Asm, VERSION1, 8126917);
+    run_with_budget(MachineTy::Asm, VERSION0, 8126917);
+    run_with_budget(MachineTy::Interpreter, VERSION1, 8126917);
+    run_with_budget(MachineTy::Interpreter, VERSION0, 8126917);
+    run_with_budget(MachineTy::InterpreterWithTrace, VERSION1, 8126917);
+    run_with_budget(MachineTy::InterpreterWithTrace, VERSION0, 8126917);
+}
+
+#[test]
+fn test_run_with_budget_exceeds_max_cycles() {
+    run_with_budget_exceeds_max_cycles(MachineTy::Asm, 8126917);
+    run_with_budget_exceeds_max_cycles(MachineTy::Interpreter, 8126917);
+    run_with_budget_exceeds_max_cycles(MachineTy::InterpreterWithTrace, 8126917);
+}
+
+#[test]
+fn test_run_with_budget_makes_progress() {
+    run_with_budget_makes_progress(MachineTy::Asm, 8126917);
+    run_with_budget_makes_progress(MachineTy::Interpreter, 8126917);
+    run_with_budget_makes_progress(MachineTy::InterpreterWithTrace, 8126917);
+}
+
+fn run_with_budget_makes_progress(ty: MachineTy, except_cycles: u64) {
+    let buffer = load_program();
+
+    let mut machine = ty.build(VERSION1, except_cycles);
+    machine
+        .load_program(&buffer, &vec!["alloc_many".into()])
+        .unwrap();
+    // No instruction fits in the budget, each slice executes one past it.
+    for _ in 0..100 {
+        let cycles = machine.cycles();
+        assert_eq!(machine.run_with_budget(0).unwrap(), RunOutcome::Paused);
+        assert!(machine.cycles() > cycles);
+        assert_eq!(machine.max_cycles(), except_cycles);
+    }
+    assert_eq!(
+        machine.run_with_budget(except_cycles).unwrap(),
+        RunOutcome::Exited(0)
+    );
+    assert_eq!(machine.cycles(), except_cycles);
+}
+
+fn run_with_budget(ty: MachineTy, version: u32, except_cycles: u64) {
+    let buffer = load_program();
+
+    let mut machine = ty.build(version, except_cycles);
+    machine
+        .load_program(&buffer, &vec!["alloc_many".into()])
+        .unwrap();
+    let mut slices = 0;
+    loop {
+        let cycles = machine.cycles();
+        match machine.run_with_budget(1000000).unwrap() {
+            RunOutcome::Exited(exit_code) => {
+                assert_eq!(exit_code, 0);
+                break;
+            }
+            RunOutcome::Paused => {
+                assert!(machine.cycles() <= cycles + 1000000);
+                assert_eq!(machine.max_cycles(), except_cycles);
+                slices += 1;
+            }
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+    }
+    assert_eq!(slices, 8);
+    assert_eq!(machine.cycles(), except_cycles);
+}
+
+fn run_with_budget_exceeds_max_cycles(ty: MachineTy, except_cycles: u64) {
+    let buffer = load_program();
+
+    let mut machine = ty.build(VERSION1, except_cycles - 30);
+    machine
+        .load_program(&buffer, &vec!["alloc_many".into()])
+        .unwrap();
+    assert_eq!(
+        machine.run_with_budget(except_cycles - 1000).unwrap(),
+        RunOutcome::Paused
+    );
+    let result = machine.run_with_budget(except_cycles);
+    assert_eq!(result.unwrap_err(), Error::CyclesExceeded);
+}
+
+#[test]
+fn test_resume_encoded_snapshot() {
+    let buffer = load_program();
+    let except_cycles = 8126917;
+
+    let mut machine1 = MachineTy::Asm.build(VERSION1, except_cycles - 30);
+    machine1
+        .load_program(&buffer, &vec!["alloc_many".into()])
+        .unwrap();
+    let result1 = machine1.run();
+    let cycles1 = machine1.cycles();
+    assert_eq!(result1.unwrap_err(), Error::CyclesExceeded);
+    let snapshot = machine1.snapshot().unwrap();
+    let encoded = snapshot.encode();
+    // Pages containing only zeros are stored without content.
+    assert!(encoded.len() < snapshot.pages.len() * 4096);
+
+    let decoded = Snapshot::decode(&encoded).unwrap();
+    assert_eq!(decoded.version, snapshot.version);
+    assert_eq!(decoded.registers, snapshot.registers);
+    assert_eq!(decoded.pc, snapshot.pc);
+    assert_eq!(decoded.page_indices, snapshot.page_indices);
+    assert_eq!(decoded.page_flags, snapshot.page_flags);
+    assert_eq!(decoded.pages, snapshot.pages);
+
+    let mut machine2 = MachineTy::Interpreter.build(VERSION1, 40);
+    machine2.resume(&decoded).unwrap();
+    let result2 = machine2.run();
+    let cycles2 = machine2.cycles();
+    assert_eq!(result2.unwrap(), 0);
+    assert_eq!(cycles1 + cycles2, except_cycles);
+}
+
+#[test]
+fn test_decode_invalid_snapshot() {
+    let mut snapshot = Snapshot {
+        version: VERSION1,
+        pc: 0x1000,
+        ..Default::default()
+    };
+    snapshot.registers[2] = 0x2000;
+    snapshot.page_indices = vec![1, 2];
+    snapshot.page_flags = vec![4, 4];
+    snapshot.pages = vec![vec![0; 4096], vec![0x55; 4096]];
+    let encoded = snapshot.encode();
+    assert!(Snapshot::decode(&encoded).is_ok());
+
+    let is_decode_error =
+        |data: &[u8]| matches!(Snapshot::decode(data), Err(Error::SnapshotDecodeError(_)));
+    // Bad magic
+    let mut data = encoded.clone();
+    data[0] ^= 1;
+    assert!(is_decode_error(&data));
+    // Unsupported format version
+    let mut data = encoded.clone();
+    data[8] += 1;
+    assert!(is_decode_error(&data));
+    // Corrupted header
+    let mut data = encoded.clone();
+    data[20] ^= 1;
+    assert!(is_decode_error(&data));
+    // Corrupted page content
+    let mut data = encoded.clone();
+    let last = data.len() - 1;
+    data[last] ^= 1;
+    assert!(is_decode_error(&data));
+    // Truncated
+    assert!(is_decode_error(&encoded[..encoded.len() - 1]));
+    assert!(is_decode_error(&encoded[..10]));
+    // Trailing data
+    let mut data = encoded.clone();
+    data.push(0);
+    assert!(is_decode_error(&data));
+}
+
+#[test]
+fn test_resume_delta_snapshots() {
+    resume_delta_snapshots(MachineTy::Asm, MachineTy::Interpreter, 8126917);
+    resume_delta_snapshots(MachineTy::Interpreter, MachineTy::Asm, 8126917);
+}
+
+fn resume_delta_snapshots(from: MachineTy, to: MachineTy, except_cycles: u64) {
+    let buffer = load_program();
+
+    let mut machine1 = from.build(VERSION1, except_cycles);
+    machine1
+        .load_program(&buffer, &vec!["alloc_many".into()])
+        .unwrap();
+    let mut snapshots = vec![];
+    for _ in 0..3 {
+        assert_eq!(
+            machine1.run_with_budget(1000000).unwrap(),
+            RunOutcome::Paused
+        );
+        snapshots.push(machine1.delta_snapshot().unwrap());
+    }
+    let cycles1 = machine1.cycles();
+    // Nothing is dirtied between two consecutive delta snapshots.
+    assert!(machine1.delta_snapshot().unwrap().pages.is_empty());
+
+    let compacted = compact_snapshots(&snapshots).unwrap();
+    let total_pages: usize = snapshots.iter().map(|s| s.pages.len()).sum();
+    assert!(compacted.pages.len() < total_pages);
+    assert_eq!(compacted.pc, snapshots[2].pc);
+    assert_eq!(compacted.registers, snapshots[2].registers);
+
+    let mut machine2 = to.build(VERSION1, except_cycles - cycles1);
+    machine2.resume_chain(&snapshots).unwrap();
+    // The restored pages are not dirty, later delta snapshots continue the
+    // chain.
+    assert!(machine2.delta_snapshot().unwrap().pages.is_empty());
+    assert_eq!(machine2.run().unwrap(), 0);
+    assert_eq!(cycles1 + machine2.cycles(), except_cycles);
+
+    let mut machine3 = from.build(VERSION1, except_cycles - cycles1);
+    machine3.resume(&compacted).unwrap();
+    assert_eq!(machine3.run().unwrap(), 0);
+    assert_eq!(cycles1 + machine3.cycles(), except_cycles);
+}
+
+#[test]
+fn test_compact_snapshots_version_mismatch() {
+    let snapshots = vec![
+        Snapshot {
+            version: VERSION0,
+            ..Default::default()
+        },
+        Snapshot {
+            version: VERSION1,
+            ..Default::default()
+        },
+    ];
+    assert!(matches!(
+        compact_snapshots(&snapshots),
+        Err(Error::InvalidVersion)
+    ));
+}
+
 fn load_program() -> Bytes {
     let mut file = File::open("tests/programs/alloc_many").unwrap();
     let mut buffer = Vec::new();
@@ -196,6 +425,7 @@ fn load_program() -> Bytes {
     buffer.into()
 }
 
+#[derive(Clone, Copy)]
 enum MachineTy {
     Asm,
     Interpreter,
@@ -267,6 +497,24 @@ impl Machine {
         }
     }
 
+    fn run_with_budget(&mut self, budget: u64) -> Result<RunOutcome, Error> {
+        use Machine::*;
+        match self {
+            Asm(inner) => inner.run_with_budget(budget),
+            Interpreter(inner) => inner.run_with_budget(budget),
+            InterpreterWithTrace(inner) => inner.run_with_budget(budget),
+        }
+    }
+
+    fn max_cycles(&self) -> u64 {
+        use Machine::*;
+        match self {
+            Asm(inner) => inner.machine.max_cycles(),
+            Interpreter(inner) => inner.max_cycles(),
+            InterpreterWithTrace(inner) => inner.machine.max_cycles(),
+        }
+    }
+
     fn cycles(&self) -> u64 {
         use Machine::*;
         match self {
@@ -285,6 +533,24 @@ impl Machine {
         }
     }
 
+    fn delta_snapshot(&mut self) -> Result<Snapshot, Error> {
+        use Machine::*;
+        match self {
+            Asm(inner) => make_delta_snapshot(&mut inner.machine),
+            Interpreter(inner) => make_delta_snapshot(inner),
+            InterpreterWithTrace(inner) => make_delta_snapshot(&mut inner.machine),
+        }
+    }
+
+    fn resume_chain(&mut self, snaps: &[Snapshot]) -> Result<(), Error> {
+        use Machine::*;
+        match self {
+            Asm(inner) => resume_chain(&mut inner.machine, snaps),
+            Interpreter(inner) => resume_chain(inner, snaps),
+            InterpreterWithTrace(inner) => resume_chain(&mut inner.machine, snaps),
+        }
+    }
+
     fn resume(&mut self, snap: &Snapshot) -> Result<(), Error> {
         use Machine::*;
         match self {
@@ -0,0 +1,273 @@
+#![cfg(has_asm)]
+pub mod machine_build;
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::trace::TraceMachine;
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachine, SupportMachine, VERSION0, VERSION1};
+use ckb_vm::memory::{sparse::SparseMemory, wxorx::WXorXMemory};
+use ckb_vm::snapshot2::{DataSource, Snapshot2, Snapshot2Context};
+use ckb_vm::{DefaultMachineBuilder, Error, ISA_IMC};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+const PROGRAM_ID: u64 = 0x1234;
+
+#[test]
+fn test_resume2_interpreter_with_trace_2_asm() {
+    resume_machine(MachineTy::InterpreterWithTrace, MachineTy::Asm, VERSION1);
+    resume_machine(MachineTy::InterpreterWithTrace, MachineTy::Asm, VERSION0);
+}
+
+#[test]
+fn test_resume2_interpreter_2_asm() {
+    resume_machine(MachineTy::Interpreter, MachineTy::Asm, VERSION1);
+    resume_machine(MachineTy::Interpreter, MachineTy::Asm, VERSION0);
+}
+
+#[test]
+fn test_resume2_interpreter_2_interpreter() {
+    resume_machine(MachineTy::Interpreter, MachineTy::Interpreter, VERSION1);
+    resume_machine(MachineTy::Interpreter, MachineTy::Interpreter, VERSION0);
+}
+
+#[test]
+fn test_resume2_asm_2_interpreter() {
+    resume_machine(MachineTy::Asm, MachineTy::Interpreter, VERSION1);
+    resume_machine(MachineTy::Asm, MachineTy::Interpreter, VERSION0);
+}
+
+#[test]
+fn test_resume2_asm_2_asm() {
+    resume_machine(MachineTy::Asm, MachineTy::Asm, VERSION1);
+    resume_machine(MachineTy::Asm, MachineTy::Asm, VERSION0);
+}
+
+#[test]
+fn test_resume2_references_program() {
+    let program = load_program();
+    let mut context = Snapshot2Context::new(TestSource::new(&program));
+    let mut machine = MachineTy::Asm.build(VERSION1, 1000000);
+    machine
+        .load_program(&program, &["alloc_many".into()])
+        .unwrap();
+    machine.mark_program(&mut context, &program).unwrap();
+    let result = machine.run();
+    assert_eq!(result.unwrap_err(), Error::CyclesExceeded);
+    let snapshot = machine.snapshot(&context).unwrap();
+    assert!(!snapshot.pages_from_source.is_empty());
+    for (_, _, id, _, _) in &snapshot.pages_from_source {
+        assert_eq!(*id, PROGRAM_ID);
+    }
+    assert_eq!(snapshot.cycles, machine.cycles());
+    assert_eq!(snapshot.max_cycles, 1000000);
+}
+
+#[test]
+fn test_resume2_missing_data() {
+    let program = load_program();
+    let mut context = Snapshot2Context::new(TestSource::new(&program));
+    let mut machine = MachineTy::Interpreter.build(VERSION1, 1000000);
+    machine
+        .load_program(&program, &["alloc_many".into()])
+        .unwrap();
+    machine.mark_program(&mut context, &program).unwrap();
+    let snapshot = machine.snapshot(&context).unwrap();
+
+    let mut context2 = Snapshot2Context::new(TestSource(HashMap::default()));
+    let mut machine2 = MachineTy::Interpreter.build(VERSION1, 1000000);
+    let result = machine2.resume(&mut context2, &snapshot);
+    assert_eq!(result.unwrap_err(), Error::SnapshotDataLoadError);
+}
+
+fn resume_machine(from: MachineTy, to: MachineTy, version: u32) {
+    let program = load_program();
+    let except_cycles = 8126917;
+
+    let mut context1 = Snapshot2Context::new(TestSource::new(&program));
+    let mut machine1 = from.build(version, except_cycles - 30);
+    machine1
+        .load_program(&program, &["alloc_many".into()])
+        .unwrap();
+    machine1.mark_program(&mut context1, &program).unwrap();
+    let result1 = machine1.run();
+    assert_eq!(result1.unwrap_err(), Error::CyclesExceeded);
+    let snapshot: Snapshot2<u64> = machine1.snapshot(&context1).unwrap();
+
+    // The program is never loaded into the new machine, its content comes
+    // from the data source.
+    let mut context2 = Snapshot2Context::new(TestSource::new(&program));
+    let mut machine2 = to.build(version, 0);
+    machine2.resume(&mut context2, &snapshot).unwrap();
+    assert_eq!(machine2.cycles(), snapshot.cycles);
+    assert_eq!(machine2.max_cycles(), except_cycles - 30);
+    machine2.set_max_cycles(except_cycles);
+    let result2 = machine2.run();
+    assert_eq!(result2.unwrap(), 0);
+    assert_eq!(machine2.cycles(), except_cycles);
+}
+
+struct TestSource(HashMap<u64, Bytes>);
+
+impl TestSource {
+    fn new(program: &Bytes) -> Self {
+        let mut data = HashMap::default();
+        data.insert(PROGRAM_ID, program.clone());
+        Self(data)
+    }
+}
+
+impl DataSource<u64> for TestSource {
+    fn load_data(&self, id: &u64, offset: u64, length: u64) -> Option<Bytes> {
+        self.0.get(id).and_then(|data| {
+            let end = offset.checked_add(length)?;
+            if end > data.len() as u64 {
+                return None;
+            }
+            Some(data.slice(offset as usize..end as usize))
+        })
+    }
+}
+
+fn load_program() -> Bytes {
+    let mut file = File::open("tests/programs/alloc_many").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+    buffer.into()
+}
+
+enum MachineTy {
+    Asm,
+    Interpreter,
+    InterpreterWithTrace,
+}
+
+impl MachineTy {
+    fn build(self, version: u32, max_cycles: u64) -> Machine {
+        match self {
+            MachineTy::Asm => {
+                let asm_core = AsmCoreMachine::new(ISA_IMC, version, max_cycles);
+                let core = DefaultMachineBuilder::<Box<AsmCoreMachine>>::new(asm_core)
+                    .instruction_cycle_func(Box::new(constant_cycles))
+                    .build();
+                Machine::Asm(AsmMachine::new(core))
+            }
+            MachineTy::Interpreter => {
+                let core_machine = DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(
+                    ISA_IMC, version, max_cycles,
+                );
+                Machine::Interpreter(
+                    DefaultMachineBuilder::new(core_machine)
+                        .instruction_cycle_func(Box::new(constant_cycles))
+                        .build(),
+                )
+            }
+            MachineTy::InterpreterWithTrace => {
+                let core_machine = DefaultCoreMachine::<u64, WXorXMemory<SparseMemory<u64>>>::new(
+                    ISA_IMC, version, max_cycles,
+                );
+                Machine::InterpreterWithTrace(TraceMachine::new(
+                    DefaultMachineBuilder::new(core_machine)
+                        .instruction_cycle_func(Box::new(constant_cycles))
+                        .build(),
+                ))
+            }
+        }
+    }
+}
+
+type InterpreterCore = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+enum Machine {
+    Asm(AsmMachine),
+    Interpreter(DefaultMachine<InterpreterCore>),
+    InterpreterWithTrace(TraceMachine<InterpreterCore>),
+}
+
+impl Machine {
+    fn load_program(&mut self, program: &Bytes, args: &[Bytes]) -> Result<u64, Error> {
+        use Machine::*;
+        match self {
+            Asm(inner) => inner.load_program(program, args),
+            Interpreter(inner) => inner.load_program(program, args),
+            InterpreterWithTrace(inner) => inner.load_program(program, args),
+        }
+    }
+
+    fn run(&mut self) -> Result<i8, Error> {
+        use Machine::*;
+        match self {
+            Asm(inner) => inner.run(),
+            Interpreter(inner) => inner.run(),
+            InterpreterWithTrace(inner) => inner.run(),
+        }
+    }
+
+    fn cycles(&self) -> u64 {
+        use Machine::*;
+        match self {
+            Asm(inner) => inner.machine.cycles(),
+            Interpreter(inner) => inner.cycles(),
+            InterpreterWithTrace(inner) => inner.machine.cycles(),
+        }
+    }
+
+    fn max_cycles(&self) -> u64 {
+        use Machine::*;
+        match self {
+            Asm(inner) => inner.machine.max_cycles(),
+            Interpreter(inner) => inner.max_cycles(),
+            InterpreterWithTrace(inner) => inner.machine.max_cycles(),
+        }
+    }
+
+    fn set_max_cycles(&mut self, cycles: u64) {
+        use Machine::*;
+        match self {
+            Asm(inner) => inner.machine.set_max_cycles(cycles),
+            Interpreter(inner) => inner.set_max_cycles(cycles),
+            InterpreterWithTrace(inner) => inner.machine.set_max_cycles(cycles),
+        }
+    }
+
+    fn mark_program(
+        &mut self,
+        context: &mut Snapshot2Context<u64, TestSource>,
+        program: &Bytes,
+    ) -> Result<(), Error> {
+        use Machine::*;
+        match self {
+            Asm(inner) => context.mark_program(&mut inner.machine, program, &PROGRAM_ID, 0),
+            Interpreter(inner) => context.mark_program(inner, program, &PROGRAM_ID, 0),
+            InterpreterWithTrace(inner) => {
+                context.mark_program(&mut inner.machine, program, &PROGRAM_ID, 0)
+            }
+        }
+    }
+
+    fn snapshot(
+        &mut self,
+        context: &Snapshot2Context<u64, TestSource>,
+    ) -> Result<Snapshot2<u64>, Error> {
+        use Machine::*;
+        match self {
+            Asm(inner) => context.make_snapshot(&mut inner.machine),
+            Interpreter(inner) => context.make_snapshot(inner),
+            InterpreterWithTrace(inner) => context.make_snapshot(&mut inner.machine),
+        }
+    }
+
+    fn resume(
+        &mut self,
+        context: &mut Snapshot2Context<u64, TestSource>,
+        snapshot: &Snapshot2<u64>,
+    ) -> Result<(), Error> {
+        use Machine::*;
+        match self {
+            Asm(inner) => context.resume(&mut inner.machine, snapshot),
+            Interpreter(inner) => context.resume(inner, snapshot),
+            InterpreterWithTrace(inner) => context.resume(&mut inner.machine, snapshot),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, VERSION1};
+use ckb_vm::spawn::{Scheduler, SpawnMachine};
+use ckb_vm::syscalls::SyscallCost;
+use ckb_vm::{Error, SparseMemory, TraceMachine, WXorXMemory, ISA_IMC};
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+fn load_programs(names: &[&str]) -> Vec<Bytes> {
+    names
+        .iter()
+        .map(|name| {
+            std::fs::read(format!("tests/programs/{}", name))
+                .unwrap()
+                .into()
+        })
+        .collect()
+}
+
+fn interpreter_scheduler(names: &[&str], max_cycles: u64) -> Scheduler<DefaultMachine<Core>> {
+    Scheduler::new(
+        load_programs(names),
+        max_cycles,
+        Box::new(|syscalls| {
+            let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+            DefaultMachineBuilder::new(core)
+                .instruction_cycle_func(Box::new(constant_cycles))
+                .syscall(Box::new(syscalls))
+                .build()
+        }),
+    )
+}
+
+fn trace_scheduler(names: &[&str], max_cycles: u64) -> Scheduler<TraceMachine<Core>> {
+    Scheduler::new(
+        load_programs(names),
+        max_cycles,
+        Box::new(|syscalls| {
+            let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+            TraceMachine::new(
+                DefaultMachineBuilder::new(core)
+                    .instruction_cycle_func(Box::new(constant_cycles))
+                    .syscall(Box::new(syscalls))
+                    .build(),
+            )
+        }),
+    )
+}
+
+#[cfg(has_asm)]
+fn asm_scheduler(names: &[&str], max_cycles: u64) -> Scheduler<AsmMachine> {
+    Scheduler::new(
+        load_programs(names),
+        max_cycles,
+        Box::new(|syscalls| {
+            let core = AsmCoreMachine::new(ISA_IMC, VERSION1, u64::MAX);
+            AsmMachine::new(
+                DefaultMachineBuilder::new(core)
+                    .instruction_cycle_func(Box::new(constant_cycles))
+                    .syscall(Box::new(syscalls))
+                    .build(),
+            )
+        }),
+    )
+}
+
+fn run_spawn_parent<M: SpawnMachine>(mut scheduler: Scheduler<M>) -> u64 {
+    let result = scheduler.run(&["parent".into()]);
+    assert_eq!(result.unwrap(), 0);
+    scheduler.consumed_cycles()
+}
+
+#[test]
+fn test_spawn_pipe() {
+    let programs = ["spawn_parent", "spawn_child"];
+    let cycles = run_spawn_parent(interpreter_scheduler(&programs, 100000));
+    assert!(cycles > 0);
+    // Scheduling does not depend on the time slice or the machine type.
+    assert_eq!(
+        run_spawn_parent(interpreter_scheduler(&programs, 100000).slice_cycles(3)),
+        cycles
+    );
+    assert_eq!(run_spawn_parent(trace_scheduler(&programs, 100000)), cycles);
+    #[cfg(has_asm)]
+    assert_eq!(run_spawn_parent(asm_scheduler(&programs, 100000)), cycles);
+}
+
+#[test]
+fn test_spawn_cycles_exceeded() {
+    let programs = ["spawn_parent", "spawn_child"];
+    let cycles = run_spawn_parent(interpreter_scheduler(&programs, 100000));
+    let mut scheduler = interpreter_scheduler(&programs, cycles - 1).slice_cycles(5);
+    assert_eq!(
+        scheduler.run(&["parent".into()]).unwrap_err(),
+        Error::CyclesExceeded
+    );
+    assert!(scheduler.consumed_cycles() < cycles);
+    assert!(scheduler.consumed_cycles() > 0);
+    assert_eq!(
+        run_spawn_parent(interpreter_scheduler(&programs, cycles)),
+        cycles
+    );
+}
+
+#[test]
+fn test_spawn_syscall_cost() {
+    let programs = ["spawn_parent", "spawn_child"];
+    let cost = |base, per_byte| {
+        run_spawn_parent(
+            interpreter_scheduler(&programs, 100000).syscall_cost(SyscallCost::new(base, per_byte)),
+        )
+    };
+    let cycles = cost(0, 0);
+    assert!(run_spawn_parent(interpreter_scheduler(&programs, 100000)) > cycles);
+    // The child program and its argument are loaded, then the argument is
+    // written to and read from the pipe.
+    let loaded = load_programs(&["spawn_child"])[0].len() as u64 + 5;
+    assert_eq!(cost(0, 1), cycles + loaded + 5 + 5);
+}
+
+#[test]
+fn test_spawn_child_failure() {
+    // The child fails on a bad pointer, so the pipe is closed before anything
+    // is written, and the parent exits with 1.
+    let mut scheduler = interpreter_scheduler(&["spawn_parent", "spawn_bad_child"], 100000);
+    assert_eq!(scheduler.run(&["parent".into()]).unwrap(), 1);
+    #[cfg(has_asm)]
+    {
+        let mut scheduler = asm_scheduler(&["spawn_parent", "spawn_bad_child"], 100000);
+        assert_eq!(scheduler.run(&["parent".into()]).unwrap(), 1);
+    }
+}
+
+#[test]
+fn test_spawn_max_machines() {
+    let mut scheduler =
+        interpreter_scheduler(&["spawn_parent", "spawn_child"], 100000).max_machines(1);
+    // Spawning fails, the parent exits with 1.
+    assert_eq!(scheduler.run(&["parent".into()]).unwrap(), 1);
+}
+
+#[test]
+fn test_spawn_deadlock() {
+    let mut scheduler = interpreter_scheduler(&["spawn_deadlock"], 100000);
+    assert_eq!(
+        scheduler.run(&["deadlock".into()]).unwrap_err(),
+        Error::SpawnDeadlock
+    );
+    #[cfg(has_asm)]
+    {
+        let mut scheduler = asm_scheduler(&["spawn_deadlock"], 100000);
+        assert_eq!(
+            scheduler.run(&["deadlock".into()]).unwrap_err(),
+            Error::SpawnDeadlock
+        );
+    }
+}
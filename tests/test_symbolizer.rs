@@ -0,0 +1,100 @@
+use bytes::Bytes;
+use ckb_vm::error::{AccessKind, MemoryFault};
+#[cfg(has_asm)]
+use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, VERSION1};
+use ckb_vm::symbolizer::Symbolizer;
+use ckb_vm::{Error, SparseMemory, TraceMachine, WXorXMemory, ISA_IMC};
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+// Addresses in tests/programs/symbolizer.
+const START: u64 = 0x11120;
+const FAULT: u64 = 0x1112c;
+const LD: u64 = 0x11134;
+const END: u64 = 0x1113c;
+
+fn load_program(name: &str) -> Bytes {
+    std::fs::read(format!("tests/programs/{}", name))
+        .unwrap()
+        .into()
+}
+
+fn build_machine() -> DefaultMachine<Core> {
+    let program = load_program("symbolizer");
+    let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .symbolizer(Symbolizer::new(&program).unwrap())
+        .build();
+    machine
+        .load_program(&program, &["symbolizer".into()])
+        .unwrap();
+    machine
+}
+
+#[test]
+fn test_symbolizer() {
+    // DWARF 5 and DWARF 4 line tables.
+    for name in ["symbolizer", "symbolizer_dwarf4"] {
+        let symbolizer = Symbolizer::new(&load_program(name)).unwrap();
+        assert_eq!(symbolizer.function(START).unwrap().name, "_start");
+        assert_eq!(symbolizer.function(LD).unwrap().address, FAULT);
+        assert!(symbolizer.function(END).is_none());
+        assert_eq!(symbolizer.line(START), Some(("symbolizer.S", 6)));
+        assert_eq!(symbolizer.line(LD), Some(("symbolizer.S", 14)));
+        assert_eq!(symbolizer.line(LD + 2), Some(("symbolizer.S", 14)));
+        assert_eq!(symbolizer.line(END), None);
+        assert_eq!(symbolizer.describe(FAULT), "fault at symbolizer.S:13");
+        assert_eq!(symbolizer.describe(LD), "fault+0x8 at symbolizer.S:14");
+        assert_eq!(symbolizer.describe(END), "??");
+    }
+
+    // Programs without debug info are resolved to functions only.
+    let symbolizer = Symbolizer::new(&load_program("pprof_target")).unwrap();
+    assert_eq!(symbolizer.describe(START), "_start");
+    assert!(symbolizer.line(START).is_none());
+}
+
+#[test]
+fn test_symbolized_error() {
+    let expected = "memory error: out of bound, read of 8 bytes at 0x7ffffff0, pc=0x11134 \
+                    at 0x11134 in fault+0x8 at symbolizer.S:14";
+    let error = build_machine().run().unwrap_err();
+    assert_eq!(error.to_string(), expected);
+    let fault = MemoryFault {
+        pc: LD,
+        address: 0x7ffffff0,
+        size: 8,
+        kind: AccessKind::Read,
+    };
+    assert_eq!(error.unlocated(), &Error::MemOutOfBound(fault));
+
+    let error = TraceMachine::new(build_machine()).run().unwrap_err();
+    assert_eq!(error.to_string(), expected);
+
+    let mut machine = build_machine();
+    machine.run().unwrap_err();
+    assert!(machine
+        .to_string()
+        .starts_with("pc  : 0x           11138 in fault+0xc at symbolizer.S:15\n"));
+}
+
+#[cfg(has_asm)]
+#[test]
+fn test_asm_symbolized_error() {
+    let program = load_program("symbolizer");
+    let core = AsmCoreMachine::new(ISA_IMC, VERSION1, u64::MAX);
+    let core = DefaultMachineBuilder::new(core)
+        .symbolizer(Symbolizer::new(&program).unwrap())
+        .build();
+    let mut machine = AsmMachine::new(core);
+    machine
+        .load_program(&program, &["symbolizer".into()])
+        .unwrap();
+    let error = machine.run().unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "memory error: out of bound, read of 8 bytes at 0x7ffffff0, pc=0x11134 \
+         at 0x11134 in fault+0x8 at symbolizer.S:14"
+    );
+}
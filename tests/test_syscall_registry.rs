@@ -0,0 +1,106 @@
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, VERSION0};
+use ckb_vm::registers::{A0, A1, A2, A3, A4, A5, A7};
+use ckb_vm::syscalls::{SyscallCost, EXIT};
+use ckb_vm::{CoreMachine, Error, Register, SparseMemory, SupportMachine, Syscalls, ISA_IMC};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+type Core = DefaultCoreMachine<u64, SparseMemory<u64>>;
+type Builder = DefaultMachineBuilder<Core>;
+
+// tests/programs/syscall64 calls syscall 1111 with 6 arguments summing up to
+// 39, and exits with the result.
+const SUM: u64 = 1111;
+
+fn sum(machine: &mut Core) -> Result<u64, Error> {
+    let result = [A0, A1, A2, A3, A4, A5]
+        .iter()
+        .fold(0, |sum, i| sum + machine.registers()[*i].to_u64());
+    machine.set_register(A0, result);
+    // Pretends to process a byte per argument.
+    Ok(6)
+}
+
+// Charges 1000 cycles itself, and always returns 0.
+struct LegacySyscall;
+
+impl<Mac: SupportMachine> Syscalls<Mac> for LegacySyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != SUM {
+            return Ok(false);
+        }
+        machine.add_cycles(1000)?;
+        machine.set_register(A0, Mac::REG::zero());
+        Ok(true)
+    }
+}
+
+fn builder(max_cycles: u64) -> Builder {
+    let core = Core::new(ISA_IMC, VERSION0, max_cycles);
+    DefaultMachineBuilder::new(core).instruction_cycle_func(Box::new(constant_cycles))
+}
+
+fn run(mut machine: DefaultMachine<Core>) -> Result<(i8, u64), Error> {
+    let program: Bytes = std::fs::read("tests/programs/syscall64").unwrap().into();
+    machine.load_program(&program, &["syscall".into()])?;
+    let exit_code = machine.run()?;
+    Ok((exit_code, machine.cycles()))
+}
+
+#[test]
+fn test_syscall_registry() {
+    let (exit_code, legacy_cycles) =
+        run(builder(u64::MAX).syscall(Box::new(LegacySyscall)).build()).unwrap();
+    assert_eq!(exit_code, 0);
+
+    // Handlers take precedence over legacy modules, and the machine charges
+    // their costs.
+    let machine = builder(u64::MAX)
+        .syscall(Box::new(LegacySyscall))
+        .syscall_handler(SUM, SyscallCost::new(100, 5), Box::new(sum))
+        .unwrap()
+        .build();
+    assert_eq!(run(machine), Ok((39, legacy_cycles - 1000 + 100 + 6 * 5)));
+}
+
+#[test]
+fn test_syscall_registry_cycles_exceeded() {
+    let (_, cycles) = run(builder(u64::MAX)
+        .syscall_handler(SUM, SyscallCost::new(100, 0), Box::new(sum))
+        .unwrap()
+        .build())
+    .unwrap();
+
+    // The base cost is charged before the handler runs, fewer than 100
+    // cycles are spent after it.
+    let called = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&called);
+    let handler = move |machine: &mut Core| {
+        flag.store(true, Ordering::SeqCst);
+        sum(machine)
+    };
+    let machine = builder(cycles - 100)
+        .syscall_handler(SUM, SyscallCost::new(100, 0), Box::new(handler))
+        .unwrap()
+        .build();
+    assert_eq!(run(machine), Err(Error::CyclesExceeded));
+    assert!(!called.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_syscall_registry_duplicates() {
+    let result = builder(u64::MAX)
+        .syscall_handler(SUM, SyscallCost::default(), Box::new(sum))
+        .unwrap()
+        .syscall_handler(SUM, SyscallCost::default(), Box::new(sum));
+    assert_eq!(result.err(), Some(Error::DuplicateSyscall(SUM)));
+
+    let result = builder(u64::MAX).syscall_handler(EXIT, SyscallCost::default(), Box::new(sum));
+    assert_eq!(result.err(), Some(Error::DuplicateSyscall(EXIT)));
+}
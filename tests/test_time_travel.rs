@@ -0,0 +1,108 @@
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, VERSION1};
+use ckb_vm::registers::{A0, A7};
+use ckb_vm::time_travel::TimeTravel;
+use ckb_vm::{
+    CoreMachine, Error, Memory, Register, SparseMemory, SupportMachine, Syscalls, WXorXMemory,
+    ISA_IMC,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+// Address of buffer in tests/programs/time_travel.
+const BUFFER: u64 = 0x12188;
+
+// Returns a different value on each call.
+struct CounterSyscall {
+    counter: Arc<AtomicU64>,
+}
+
+impl<Mac: SupportMachine> Syscalls<Mac> for CounterSyscall {
+    fn initialize(&mut self, _machine: &mut Mac) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn ecall(&mut self, machine: &mut Mac) -> Result<bool, Error> {
+        if machine.registers()[A7].to_u64() != 1111 {
+            return Ok(false);
+        }
+        let value = self.counter.fetch_add(1, Ordering::SeqCst) + 1;
+        let address = machine.registers()[A0].clone();
+        machine
+            .memory_mut()
+            .store64(&address, &Mac::REG::from_u64(value))?;
+        machine.set_register(A0, Mac::REG::from_u64(value));
+        machine.add_cycles_no_checking(10)?;
+        Ok(true)
+    }
+}
+
+fn load_program() -> Bytes {
+    std::fs::read("tests/programs/time_travel").unwrap().into()
+}
+
+fn build_machine<S: Syscalls<Core> + 'static>(syscalls: S) -> DefaultMachine<Core> {
+    let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+    DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .syscall(Box::new(syscalls))
+        .build()
+}
+
+// Runs the program from the start for `cycles` cycles. Unlike
+// run_with_budget, max cycles never let an instruction run past them.
+fn run_to(cycles: u64) -> DefaultMachine<Core> {
+    let mut machine = build_machine(CounterSyscall {
+        counter: Arc::new(AtomicU64::new(0)),
+    });
+    machine
+        .load_program(&load_program(), &["time_travel".into()])
+        .unwrap();
+    machine.set_max_cycles(cycles);
+    match machine.run() {
+        Ok(_) | Err(Error::CyclesExceeded) => (),
+        Err(e) => panic!("unexpected error {:?}", e),
+    }
+    machine
+}
+
+#[test]
+fn test_time_travel() {
+    let counter = Arc::new(AtomicU64::new(0));
+    let mut time_travel = TimeTravel::new(Box::new(build_machine)).checkpoint_interval(200);
+    let result = time_travel.record(
+        &load_program(),
+        &["time_travel".into()],
+        vec![Box::new(CounterSyscall {
+            counter: Arc::clone(&counter),
+        })],
+    );
+    // The sum of 1..=100 read from memory and returned, truncated.
+    let exit_code = result.unwrap();
+    assert_eq!(exit_code, (5050 * 2 % 128) as i8);
+    assert_eq!(counter.load(Ordering::SeqCst), 100);
+    let end_cycles = time_travel.end_cycles();
+    assert!(time_travel.checkpoint_cycles().len() > 5);
+
+    for cycles in (0..end_cycles).step_by(37).chain([end_cycles - 1]) {
+        let mut expected = run_to(cycles);
+        let mut machine = time_travel.travel(cycles).unwrap();
+        assert_eq!(machine.cycles(), expected.cycles());
+        assert_eq!(machine.pc(), expected.pc());
+        assert_eq!(machine.registers(), expected.registers());
+        assert_eq!(
+            machine.memory_mut().load64(&BUFFER).unwrap(),
+            expected.memory_mut().load64(&BUFFER).unwrap()
+        );
+    }
+    // Syscalls are replayed from the recording.
+    assert_eq!(counter.load(Ordering::SeqCst), 100);
+
+    let machine = time_travel.travel(end_cycles).unwrap();
+    assert!(!machine.running());
+    assert_eq!(machine.exit_code(), exit_code);
+    assert!(time_travel.travel(end_cycles + 1).is_err());
+}
@@ -0,0 +1,173 @@
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::machine::trap::CSR_WRITE;
+use ckb_vm::machine::{
+    DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, VERSION1, VERSION2, VERSION3,
+};
+use ckb_vm::tracer::{verify, MemoryEffect, TraceReader, TraceRecord, Tracer};
+use ckb_vm::{Error, SparseMemory, SupportMachine, TraceMachine, WXorXMemory, ISA_IMC};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+// Address of value in tests/programs/gdb_target.
+const VALUE: u64 = 0x12178;
+
+fn build_machine() -> DefaultMachine<Core> {
+    let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let program: Bytes = std::fs::read("tests/programs/gdb_target").unwrap().into();
+    machine
+        .load_program(&program, &["gdb_target".into()])
+        .unwrap();
+    machine
+}
+
+// tests/programs/trap traps three times, see test_trap.rs.
+fn build_trap_machine(version: u32) -> DefaultMachine<Core> {
+    let core = Core::new(ISA_IMC, version, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let program: Bytes = std::fs::read("tests/programs/trap").unwrap().into();
+    machine.load_program(&program, &["trap".into()]).unwrap();
+    machine
+}
+
+fn log_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ckb_vm_test_tracer_{}.log", name))
+}
+
+fn tracer(path: &PathBuf) -> Tracer {
+    Tracer::new(Box::new(BufWriter::new(File::create(path).unwrap()))).unwrap()
+}
+
+fn read_log(path: &PathBuf) -> Vec<TraceRecord> {
+    TraceReader::new(File::open(path).unwrap())
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+fn write_log(path: &PathBuf, records: &[TraceRecord]) {
+    let mut tracer = tracer(path);
+    for record in records {
+        tracer.write(record).unwrap();
+    }
+    tracer.flush().unwrap();
+}
+
+#[test]
+fn test_tracer() {
+    let path = log_path("default");
+    let mut machine = build_machine();
+    machine.set_tracer(tracer(&path));
+    assert_eq!(machine.run().unwrap(), 42);
+    machine.take_tracer().unwrap().flush().unwrap();
+
+    let records = read_log(&path);
+    assert_eq!(records.len(), 9);
+    assert_eq!(records[0].pc, 0x11158);
+    assert_eq!(records[8].cycles, 9);
+    // sd t1, 0(t0)
+    assert_eq!(
+        records[3].memory_effects,
+        vec![MemoryEffect {
+            address: VALUE,
+            size: 8,
+            write: true,
+            value: 42,
+        }]
+    );
+    assert!(records[3].register_writes.is_empty());
+    // ld t2, 0(t0)
+    assert!(!records[4].memory_effects[0].write);
+    assert_eq!(records[4].register_writes, vec![(7, 42)]);
+
+    // TraceMachine produces the same log.
+    let trace_path = log_path("trace");
+    let mut machine = TraceMachine::new(build_machine());
+    machine.machine.set_tracer(tracer(&trace_path));
+    assert_eq!(machine.run().unwrap(), 42);
+    machine.machine.take_tracer().unwrap().flush().unwrap();
+    assert_eq!(read_log(&trace_path), records);
+
+    let reader = TraceReader::new(File::open(&path).unwrap()).unwrap();
+    assert_eq!(verify(&mut build_machine(), reader).unwrap(), None);
+}
+
+#[test]
+fn test_tracer_divergence() {
+    let path = log_path("divergence");
+    let mut machine = build_machine();
+    machine.set_tracer(tracer(&path));
+    machine.run().unwrap();
+    machine.take_tracer().unwrap().flush().unwrap();
+    let records = read_log(&path);
+
+    let mut tampered = records.clone();
+    tampered[4].register_writes = vec![(7, 43)];
+    write_log(&path, &tampered);
+    let reader = TraceReader::new(File::open(&path).unwrap()).unwrap();
+    let divergence = verify(&mut build_machine(), reader).unwrap().unwrap();
+    assert_eq!(divergence.index, 4);
+    assert_eq!(divergence.expected, Some(tampered[4].clone()));
+    assert_eq!(divergence.actual, Some(records[4].clone()));
+
+    // The log ends early.
+    write_log(&path, &records[..5]);
+    let reader = TraceReader::new(File::open(&path).unwrap()).unwrap();
+    let divergence = verify(&mut build_machine(), reader).unwrap().unwrap();
+    assert_eq!(divergence.index, 5);
+    assert_eq!(divergence.expected, None);
+    assert_eq!(divergence.actual, Some(records[5].clone()));
+
+    // The machine exits early.
+    let mut extended = records.clone();
+    extended.push(records[8].clone());
+    write_log(&path, &extended);
+    let reader = TraceReader::new(File::open(&path).unwrap()).unwrap();
+    let divergence = verify(&mut build_machine(), reader).unwrap().unwrap();
+    assert_eq!(divergence.index, 9);
+    assert_eq!(divergence.actual, None);
+}
+
+#[test]
+fn test_tracer_trap() {
+    let path = log_path("trap");
+    let mut machine = build_trap_machine(VERSION3);
+    machine.set_tracer(tracer(&path));
+    let exit_code = machine.run().unwrap();
+    machine.take_tracer().unwrap().flush().unwrap();
+    // Trapped instructions are not recorded.
+    let records = read_log(&path);
+    assert_eq!(records.len() as u64, machine.instret());
+
+    let reader = TraceReader::new(File::open(&path).unwrap()).unwrap();
+    let mut replaying = build_trap_machine(VERSION3);
+    assert_eq!(verify(&mut replaying, reader).unwrap(), None);
+    assert_eq!(replaying.exit_code(), exit_code);
+
+    // Errors which can't be trapped are returned.
+    let reader = TraceReader::new(File::open(&path).unwrap()).unwrap();
+    assert_eq!(
+        verify(&mut build_trap_machine(VERSION2), reader).unwrap_err(),
+        Error::InvalidEcall(CSR_WRITE)
+    );
+}
+
+#[test]
+fn test_trace_reader_invalid_log() {
+    assert!(matches!(
+        TraceReader::new(&b"CKBVMSNP\x01\x00\x00\x00"[..]),
+        Err(Error::TraceDecodeError(_))
+    ));
+    // A truncated record.
+    let reader = TraceReader::new(&b"CKBVMTRC\x01\x00\x00\x00\x80"[..]).unwrap();
+    let records: Vec<_> = reader.collect();
+    assert!(matches!(records[..], [Err(Error::TraceDecodeError(_))]));
+}
@@ -0,0 +1,91 @@
+#![cfg(feature = "hooks")]
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, VERSION1};
+use ckb_vm::memory::tracing::{MemoryEvent, MemoryStats, TracePc};
+use ckb_vm::{CoreMachine, Error, SparseMemory, TracingMemory, WXorXMemory, ISA_IMC};
+use std::sync::{Arc, Mutex};
+
+type Core = DefaultCoreMachine<u64, TracingMemory<WXorXMemory<SparseMemory<u64>>>>;
+
+// Addresses in tests/programs/memory_access.
+const SD: u64 = 0x11168;
+const SW: u64 = 0x1116c;
+const LBU: u64 = 0x11170;
+const LD: u64 = 0x11174;
+const BUFFER: u64 = 0x12180;
+
+type Event = (u64, u64, bool, Vec<u8>);
+
+fn build_machine(events: &Arc<Mutex<Vec<Event>>>) -> DefaultMachine<Core> {
+    let core = Core::new(ISA_IMC, VERSION1, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .hook(Box::new(TracePc))
+        .build();
+    let program: Bytes = std::fs::read("tests/programs/memory_access")
+        .unwrap()
+        .into();
+    machine
+        .load_program(&program, &["memory_access".into()])
+        .unwrap();
+    // Forgets the stores done to set up the stack.
+    machine.memory_mut().reset_stats();
+    let events = Arc::clone(events);
+    machine
+        .memory_mut()
+        .set_callback(Arc::new(move |event: &MemoryEvent| {
+            events.lock().unwrap().push((
+                event.pc,
+                event.address,
+                event.write,
+                event.data.to_vec(),
+            ));
+            Ok(())
+        }));
+    machine
+}
+
+#[test]
+fn test_tracing_memory() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut machine = build_machine(&events);
+    assert_eq!(machine.run().unwrap(), 0x12);
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            (SD, BUFFER, true, vec![0x34, 0x12, 0, 0, 0, 0, 0, 0]),
+            (SW, BUFFER + 8, true, vec![0x34, 0x12, 0, 0]),
+            (LBU, BUFFER + 1, false, vec![0x12]),
+            (LD, BUFFER, false, vec![0x34, 0x12, 0, 0, 0, 0, 0, 0]),
+        ]
+    );
+    assert_eq!(
+        machine.memory_mut().stats(),
+        MemoryStats {
+            loads: 2,
+            stores: 2,
+            bytes_loaded: 9,
+            bytes_stored: 12,
+        }
+    );
+}
+
+#[test]
+fn test_tracing_memory_watchpoint() {
+    let mut machine = build_machine(&Arc::new(Mutex::new(Vec::new())));
+    machine
+        .memory_mut()
+        .set_callback(Arc::new(|event: &MemoryEvent| {
+            if event.write && event.address == BUFFER + 8 {
+                return Err(Error::Unexpected("watchpoint".into()));
+            }
+            Ok(())
+        }));
+    assert_eq!(
+        machine.run().unwrap_err(),
+        Error::Unexpected("watchpoint".into())
+    );
+    assert_eq!(machine.memory_mut().pc(), SW);
+    assert_eq!(machine.memory_mut().stats().stores, 2);
+}
@@ -0,0 +1,131 @@
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::machine::trap::{Traps, CAUSE_STORE_ACCESS_FAULT, CSR_WRITE};
+use ckb_vm::machine::{
+    DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, RunOutcome, VERSION2, VERSION3,
+};
+use ckb_vm::snapshot::{make_snapshot, resume, Snapshot};
+use ckb_vm::snapshot2::{DataSource, Snapshot2Context};
+use ckb_vm::syscalls::SyscallCost;
+use ckb_vm::{Error, SparseMemory, SupportMachine, TraceMachine, WXorXMemory, ISA_IMC};
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+// tests/programs/trap traps on a load, an illegal instruction and a store,
+// and exits with the number of traps * 16 + the sum of their causes.
+const EXIT_CODE: i8 = 3 * 16 + 5 + 2 + 7;
+// The last trap, storing to _start at 0x1114e. The handler moves mepc past
+// the store before returning.
+const STORE: Traps = Traps {
+    mtvec: 0x11162,
+    mepc: 0x11152,
+    mcause: CAUSE_STORE_ACCESS_FAULT,
+    mtval: 0x11120,
+    handling: false,
+};
+
+fn load_program() -> Bytes {
+    std::fs::read("tests/programs/trap").unwrap().into()
+}
+
+// A machine without the program loaded, snapshots are resumed into it.
+fn new_machine(version: u32) -> DefaultMachine<Core> {
+    let core = Core::new(ISA_IMC, version, u64::MAX);
+    DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build()
+}
+
+fn build_machine(version: u32) -> DefaultMachine<Core> {
+    let mut machine = new_machine(version);
+    machine
+        .load_program(&load_program(), &["trap".into()])
+        .unwrap();
+    machine
+}
+
+#[test]
+fn test_trap() {
+    let mut machine = build_machine(VERSION3);
+    assert_eq!(machine.run(), Ok(EXIT_CODE));
+    assert_eq!(machine.traps(), &STORE);
+    let cycles = machine.cycles();
+
+    let mut machine = TraceMachine::new(build_machine(VERSION3));
+    assert_eq!(machine.run(), Ok(EXIT_CODE));
+    assert_eq!(machine.machine.traps(), &STORE);
+    assert_eq!(machine.machine.cycles(), cycles);
+}
+
+#[test]
+fn test_trap_before_version3() {
+    let mut machine = build_machine(VERSION2);
+    assert_eq!(machine.run(), Err(Error::InvalidEcall(CSR_WRITE)));
+}
+
+#[test]
+fn test_trap_syscalls_reserved() {
+    let handler = |_: &mut Core| Ok(0);
+    let builder = DefaultMachineBuilder::new(Core::new(ISA_IMC, VERSION3, u64::MAX));
+    let result = builder.syscall_handler(CSR_WRITE, SyscallCost::default(), Box::new(handler));
+    assert_eq!(result.err(), Some(Error::DuplicateSyscall(CSR_WRITE)));
+
+    let builder = DefaultMachineBuilder::new(Core::new(ISA_IMC, VERSION2, u64::MAX));
+    let result = builder.syscall_handler(CSR_WRITE, SyscallCost::default(), Box::new(handler));
+    assert!(result.is_ok());
+}
+
+struct NoSource;
+
+impl DataSource<u64> for NoSource {
+    fn load_data(&self, _id: &u64, _offset: u64, _length: u64) -> Option<Bytes> {
+        None
+    }
+}
+
+#[test]
+fn test_trap_resume() {
+    let mut machine = build_machine(VERSION3);
+    // Stops once the handler is registered, before the first fault.
+    assert_eq!(machine.run_with_budget(7), Ok(RunOutcome::Paused));
+    assert_eq!(machine.traps().mtvec, STORE.mtvec);
+
+    let snapshot = make_snapshot(&mut machine).unwrap();
+    let snapshot = Snapshot::decode(&snapshot.encode()).unwrap();
+    assert_eq!(&snapshot.traps, machine.traps());
+    let mut resumed = new_machine(VERSION3);
+    resume(&mut resumed, &snapshot).unwrap();
+    assert_eq!(resumed.run(), Ok(EXIT_CODE));
+    assert_eq!(resumed.traps(), &STORE);
+
+    let mut context = Snapshot2Context::new(NoSource);
+    let snapshot = context.make_snapshot(&mut machine).unwrap();
+    let mut resumed = new_machine(VERSION3);
+    context.resume(&mut resumed, &snapshot).unwrap();
+    assert_eq!(resumed.run(), Ok(EXIT_CODE));
+    assert_eq!(resumed.traps(), &STORE);
+}
+
+#[cfg(has_asm)]
+#[test]
+fn test_trap_asm() {
+    use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+
+    let cycles = {
+        let mut machine = build_machine(VERSION3);
+        machine.run().unwrap();
+        machine.cycles()
+    };
+    let core = AsmCoreMachine::new(ISA_IMC, VERSION3, u64::MAX);
+    let core = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let mut machine = AsmMachine::new(core);
+    machine
+        .load_program(&load_program(), &["trap".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(EXIT_CODE));
+    assert_eq!(machine.machine.traps(), &STORE);
+    // Instructions after the faulting one in a trace are not charged.
+    assert_eq!(machine.machine.cycles(), cycles);
+}
@@ -74,7 +74,7 @@ pub fn test_rust_version0_read_at_boundary() {
     let mut machine = create_rust_machine("read_at_boundary64".to_string(), VERSION0);
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::MemOutOfBound));
+    assert!(matches!(result.err(), Some(Error::MemOutOfBound(_))));
 }
 
 #[test]
@@ -170,7 +170,7 @@ pub fn test_asm_version0_read_at_boundary() {
     let mut machine = create_asm_machine("read_at_boundary64".to_string(), VERSION0);
     let result = machine.run();
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::MemOutOfBound));
+    assert!(matches!(result.err(), Some(Error::MemOutOfBound(_))));
 }
 
 #[test]
@@ -240,7 +240,10 @@ pub fn test_rust_version0_unaligned64() {
         DefaultMachineBuilder::<DefaultCoreMachine<u64, Mem>>::new(core_machine).build();
     let result = machine.load_program(&buffer, &vec![program.into()]);
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::MemWriteOnExecutablePage));
+    assert!(matches!(
+        result.err(),
+        Some(Error::MemWriteOnExecutablePage(_))
+    ));
 }
 
 #[test]
@@ -262,7 +265,10 @@ pub fn test_asm_version0_unaligned64() {
     let mut machine = AsmMachine::new(core);
     let result = machine.load_program(&buffer, &vec![program.into()]);
     assert!(result.is_err());
-    assert_eq!(result.err(), Some(Error::MemWriteOnExecutablePage));
+    assert!(matches!(
+        result.err(),
+        Some(Error::MemWriteOnExecutablePage(_))
+    ));
 }
 
 #[test]
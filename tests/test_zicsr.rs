@@ -0,0 +1,103 @@
+use bytes::Bytes;
+use ckb_vm::cost_model::constant_cycles;
+use ckb_vm::instructions::{extract_opcode, insts, zicsr, Itype};
+use ckb_vm::machine::{DefaultCoreMachine, DefaultMachine, DefaultMachineBuilder, VERSION2};
+use ckb_vm::registers::{S0, S1, S2, S3, S4};
+use ckb_vm::{
+    CoreMachine, Error, SparseMemory, SupportMachine, TraceMachine, WXorXMemory, ISA_IMC, ISA_ZICSR,
+};
+
+type Core = DefaultCoreMachine<u64, WXorXMemory<SparseMemory<u64>>>;
+
+// Counters read by tests/programs/csr with one cycle per instruction: instret
+// and cycle before and after a loop of 2000 instructions, then time.
+const COUNTERS: [u64; 5] = [0, 2, 2003, 2005, 2006 >> zicsr::TIME_SHIFTS];
+const INSTRET: u64 = 2009;
+
+fn load_program() -> Bytes {
+    std::fs::read("tests/programs/csr").unwrap().into()
+}
+
+fn build_machine(isa: u8) -> DefaultMachine<Core> {
+    let core = Core::new(isa, VERSION2, u64::MAX);
+    let mut machine = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    machine
+        .load_program(&load_program(), &["csr".into()])
+        .unwrap();
+    machine
+}
+
+fn counters<M: CoreMachine<REG = u64>>(machine: &M) -> Vec<u64> {
+    [S0, S1, S2, S3, S4]
+        .iter()
+        .map(|r| machine.registers()[*r])
+        .collect()
+}
+
+#[test]
+fn test_zicsr() {
+    let mut machine = build_machine(ISA_IMC | ISA_ZICSR);
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(counters(&machine), COUNTERS);
+    assert_eq!(machine.instret(), INSTRET);
+    assert_eq!(machine.cycles(), INSTRET);
+
+    let mut machine = TraceMachine::new(build_machine(ISA_IMC | ISA_ZICSR));
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(counters(&machine.machine), COUNTERS);
+    assert_eq!(machine.machine.instret(), INSTRET);
+}
+
+#[test]
+fn test_zicsr_disabled() {
+    let mut machine = build_machine(ISA_IMC);
+    assert_eq!(
+        machine.run(),
+        Err(Error::InvalidInstruction {
+            pc: 0x11120,
+            instruction: 0xc0202473,
+        })
+    );
+}
+
+#[test]
+fn test_zicsr_decode() {
+    let decode = |bits| zicsr::factory::<u64>(bits, VERSION2);
+    // rdcycle a0
+    let i = decode(0xc0002573).unwrap();
+    assert_eq!(extract_opcode(i), insts::OP_CSRR);
+    assert_eq!(Itype(i).rd(), 10);
+    assert_eq!(Itype(i).immediate_u(), zicsr::CSR_CYCLE);
+    // csrrsi a0, instret, 0
+    assert!(decode(0xc0206573).is_some());
+    // csrw cycle, a0
+    assert!(decode(0xc0051073).is_none());
+    // csrrs a0, cycle, a1
+    assert!(decode(0xc005a573).is_none());
+    // csrr a0, mstatus
+    assert!(decode(0x30002573).is_none());
+    // rdcycleh a0, only on 32-bit machines
+    assert!(decode(0xc8002573).is_none());
+    assert!(zicsr::factory::<u32>(0xc8002573, VERSION2).is_some());
+}
+
+#[cfg(has_asm)]
+#[test]
+fn test_zicsr_asm() {
+    use ckb_vm::machine::asm::{AsmCoreMachine, AsmMachine};
+
+    let core = AsmCoreMachine::new(ISA_IMC | ISA_ZICSR, VERSION2, u64::MAX);
+    let core = DefaultMachineBuilder::new(core)
+        .instruction_cycle_func(Box::new(constant_cycles))
+        .build();
+    let mut machine = AsmMachine::new(core);
+    machine
+        .load_program(&load_program(), &["csr".into()])
+        .unwrap();
+    assert_eq!(machine.run(), Ok(0));
+    assert_eq!(counters(&machine.machine), COUNTERS);
+    assert_eq!(machine.machine.instret(), INSTRET);
+    assert_eq!(machine.machine.cycles(), INSTRET);
+}